@@ -0,0 +1,245 @@
+use crate::commands::{from_op, TuringOp};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// The tag byte of a `DataType::BINARY` value, the data type of the values set by a `BatchQuery`
+const BINARY_DATA_TYPE: u8 = 0x28;
+
+/// ### A field operation of a batch
+/// `data_type` is the tag byte of the data type of the value
+/// ```rust
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub enum BatchOperation {
+///     Get { document: String, key: Vec<u8> },
+///     Set { document: String, key: Vec<u8>, data_type: u8, value: Vec<u8> },
+///     Modify { document: String, key: Vec<u8>, data_type: u8, value: Vec<u8> },
+///     Remove { document: String, key: Vec<u8> },
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BatchOperation {
+    /// Read the contents of a field
+    Get {
+        /// The document of the field
+        document: String,
+        /// The key of the field
+        key: Vec<u8>,
+    },
+    /// Insert a field, failing if it already exists
+    Set {
+        /// The document of the field
+        document: String,
+        /// The key of the field
+        key: Vec<u8>,
+        /// The tag byte of the data type of the value
+        data_type: u8,
+        /// The value of the field
+        value: Vec<u8>,
+    },
+    /// Replace the value of a field that exists
+    Modify {
+        /// The document of the field
+        document: String,
+        /// The key of the field
+        key: Vec<u8>,
+        /// The tag byte of the data type of the value
+        data_type: u8,
+        /// The new value of the field
+        value: Vec<u8>,
+    },
+    /// Remove a field that exists
+    Remove {
+        /// The document of the field
+        document: String,
+        /// The key of the field
+        key: Vec<u8>,
+    },
+}
+
+/// ### The result of an operation of a batch
+/// The results arrive in the order of the operations as a `DbOps::FieldContents`
+/// holding a `Vec<BatchResult>` serialized with bincode
+/// ```rust
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub enum BatchResult {
+///     Contents(Vec<u8>),
+///     Inserted,
+///     Modified,
+///     Removed,
+///     Failed(String),
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BatchResult {
+    /// The contents of the field read by a `Get`
+    Contents(Vec<u8>),
+    /// The field of a `Set` was inserted
+    Inserted,
+    /// The field of a `Modify` was changed
+    Modified,
+    /// The field of a `Remove` was removed
+    Removed,
+    /// The operation failed with the error
+    Failed(String),
+}
+
+/// ### Sends many field operations across the documents of a database in a single query
+/// The operations are applied in order. Without `atomic` a failed operation does not stop the others,
+/// with `atomic` the operations are only written if all of them succeed
+/// and otherwise the server answers with the first error
+/// ```rust
+/// #[derive(Debug, Serialize, Clone, Default)]
+/// pub struct BatchQuery {
+///     db: String,
+///     atomic: bool,
+///     operations: Vec<BatchOperation>,
+/// }
+/// ```
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct BatchQuery {
+    db: String,
+    atomic: bool,
+    operations: Vec<BatchOperation>,
+}
+
+impl BatchQuery {
+    /// ### Initialize a new empty batch
+    /// #### Usage
+    /// ```rust
+    /// use crate::BatchQuery;
+    ///
+    /// BatchQuery::new()
+    /// ```
+    pub fn new() -> Self {
+        Self {
+            db: Default::default(),
+            atomic: Default::default(),
+            operations: Default::default(),
+        }
+    }
+    /// ### Add a database name
+    /// #### Usage
+    /// ```rust
+    /// use crate::BatchQuery;
+    ///
+    /// let mut foo = BatchQuery::new();
+    /// foo.db("db_name");
+    /// ```
+    pub fn db(&mut self, name: &str) -> &mut Self {
+        self.db = name.into();
+
+        self
+    }
+    /// ### Write the operations only if all of them succeed
+    /// #### Usage
+    /// ```rust
+    /// use crate::BatchQuery;
+    ///
+    /// let mut foo = BatchQuery::new();
+    /// foo
+    ///   .db("db_name")
+    ///   .atomic(true);
+    /// ```
+    pub fn atomic(&mut self, atomic: bool) -> &mut Self {
+        self.atomic = atomic;
+
+        self
+    }
+    /// ### Read the contents of a field
+    /// #### Usage
+    /// ```rust
+    /// use crate::BatchQuery;
+    ///
+    /// let mut foo = BatchQuery::new();
+    /// foo
+    ///   .db("db_name")
+    ///   .get("document_name", b"field_name");
+    /// ```
+    pub fn get(&mut self, document: &str, key: &[u8]) -> &mut Self {
+        self.operations.push(BatchOperation::Get {
+            document: document.into(),
+            key: key.into(),
+        });
+
+        self
+    }
+    /// ### Insert a field
+    /// #### Usage
+    /// ```rust
+    /// use crate::BatchQuery;
+    ///
+    /// let mut foo = BatchQuery::new();
+    /// foo
+    ///   .db("db_name")
+    ///   .set("document_name", b"field_name", b"value");
+    /// ```
+    pub fn set(&mut self, document: &str, key: &[u8], value: &[u8]) -> &mut Self {
+        self.operations.push(BatchOperation::Set {
+            document: document.into(),
+            key: key.into(),
+            data_type: BINARY_DATA_TYPE,
+            value: value.into(),
+        });
+
+        self
+    }
+    /// ### Replace the value of a field
+    /// #### Usage
+    /// ```rust
+    /// use crate::BatchQuery;
+    ///
+    /// let mut foo = BatchQuery::new();
+    /// foo
+    ///   .db("db_name")
+    ///   .modify("document_name", b"field_name", b"new_value");
+    /// ```
+    pub fn modify(&mut self, document: &str, key: &[u8], value: &[u8]) -> &mut Self {
+        self.operations.push(BatchOperation::Modify {
+            document: document.into(),
+            key: key.into(),
+            data_type: BINARY_DATA_TYPE,
+            value: value.into(),
+        });
+
+        self
+    }
+    /// ### Remove a field
+    /// #### Usage
+    /// ```rust
+    /// use crate::BatchQuery;
+    ///
+    /// let mut foo = BatchQuery::new();
+    /// foo
+    ///   .db("db_name")
+    ///   .remove("document_name", b"field_name");
+    /// ```
+    pub fn remove(&mut self, document: &str, key: &[u8]) -> &mut Self {
+        self.operations.push(BatchOperation::Remove {
+            document: document.into(),
+            key: key.into(),
+        });
+
+        self
+    }
+    /// ### Send the operations as a single query
+    /// #### Usage
+    /// ```rust
+    /// use crate::BatchQuery;
+    ///
+    /// let mut foo = BatchQuery::new();
+    /// foo
+    ///   .db("db_name")
+    ///   .atomic(true)
+    ///   .set("users", b"alice", b"admin")
+    ///   .remove("invites", b"alice")
+    ///   .batch()
+    /// ```
+    pub fn batch(&self) -> Result<Vec<u8>> {
+        let mut packet = from_op(&TuringOp::Batch).to_vec();
+
+        let data = bincode::serialize::<Self>(self)?;
+        packet.extend_from_slice(&data);
+
+        Ok(packet)
+    }
+}
@@ -0,0 +1,218 @@
+use crate::commands::{from_op, TuringOp};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// The tag byte of a `DataType::BINARY` value, the data type of a new `BulkRecord`
+const BINARY_DATA_TYPE: u8 = 0x28;
+
+/// ### A field written by a bulk insert
+/// `data_type` is the tag byte of the data type of the value
+/// ```rust
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct BulkRecord {
+///     pub document: String,
+///     pub key: Vec<u8>,
+///     pub data_type: u8,
+///     pub value: Vec<u8>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BulkRecord {
+    /// The document the field is written to
+    pub document: String,
+    /// The key of the field
+    pub key: Vec<u8>,
+    /// The tag byte of the data type of the value
+    pub data_type: u8,
+    /// The value of the field
+    pub value: Vec<u8>,
+}
+
+impl BulkRecord {
+    /// ### A field holding binary data
+    /// #### Usage
+    /// ```rust
+    /// use crate::BulkRecord;
+    ///
+    /// BulkRecord::new("document_name", b"field_name", b"value")
+    /// ```
+    pub fn new(document: &str, key: &[u8], value: &[u8]) -> Self {
+        Self {
+            document: document.into(),
+            key: key.into(),
+            data_type: BINARY_DATA_TYPE,
+            value: value.into(),
+        }
+    }
+}
+
+/// A record that the server did not write, with its position in the whole bulk insert
+/// ```rust
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct BulkRejection {
+///     pub index: u64,
+///     pub document: String,
+///     pub key: Vec<u8>,
+///     pub error: String,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BulkRejection {
+    /// The position of the record
+    pub index: u64,
+    /// The document of the record
+    pub document: String,
+    /// The key of the record
+    pub key: Vec<u8>,
+    /// Why the record was rejected
+    pub error: String,
+}
+
+/// ### The progress of a bulk insert
+/// Arrives as a `DbOps::FieldContents` holding the progress serialized with bincode
+/// and counts every record sent since the bulk insert started
+/// ```rust
+/// #[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct BulkProgress {
+///     pub received: u64,
+///     pub inserted: u64,
+///     pub rejected: Vec<BulkRejection>,
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BulkProgress {
+    /// The number of records received
+    pub received: u64,
+    /// The number of records written
+    pub inserted: u64,
+    /// The records that were not written
+    pub rejected: Vec<BulkRejection>,
+}
+
+/// ### Streams many fields into a database for a fast initial ingestion
+/// The packet of `start()` dedicates the connection to the bulk insert.
+/// It is followed by any number of `chunk()` packets and ends with the packet of `finish()`,
+/// after which the server flushes the database and the connection takes other queries again.
+/// The server answers every packet with the `BulkProgress` of the whole bulk insert,
+/// so wait for the answer before sending the next chunk.
+/// Without `check_existing` the server overwrites the fields that already exist
+/// instead of rejecting them, which is only safe for new documents
+/// ```rust
+/// #[derive(Debug, Serialize, Clone)]
+/// pub struct BulkInsertQuery {
+///     db: String,
+///     check_existing: bool,
+///     batch_size: Option<usize>,
+/// }
+/// ```
+#[derive(Debug, Serialize, Clone)]
+pub struct BulkInsertQuery {
+    db: String,
+    check_existing: bool,
+    batch_size: Option<usize>,
+}
+
+impl Default for BulkInsertQuery {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BulkInsertQuery {
+    /// ### Initialize a new bulk insert that rejects the fields that already exist
+    /// #### Usage
+    /// ```rust
+    /// use crate::BulkInsertQuery;
+    ///
+    /// BulkInsertQuery::new()
+    /// ```
+    pub fn new() -> Self {
+        Self {
+            db: Default::default(),
+            check_existing: true,
+            batch_size: Default::default(),
+        }
+    }
+    /// ### Add a database name
+    /// #### Usage
+    /// ```rust
+    /// use crate::BulkInsertQuery;
+    ///
+    /// let mut foo = BulkInsertQuery::new();
+    /// foo.db("db_name");
+    /// ```
+    pub fn db(&mut self, name: &str) -> &mut Self {
+        self.db = name.into();
+
+        self
+    }
+    /// ### Choose whether the fields that already exist are rejected or overwritten
+    /// #### Usage
+    /// ```rust
+    /// use crate::BulkInsertQuery;
+    ///
+    /// let mut foo = BulkInsertQuery::new();
+    /// foo
+    ///   .db("db_name")
+    ///   .check_existing(false);
+    /// ```
+    pub fn check_existing(&mut self, check_existing: bool) -> &mut Self {
+        self.check_existing = check_existing;
+
+        self
+    }
+    /// ### Set the number of records the server writes at a time
+    /// #### Usage
+    /// ```rust
+    /// use crate::BulkInsertQuery;
+    ///
+    /// let mut foo = BulkInsertQuery::new();
+    /// foo
+    ///   .db("db_name")
+    ///   .batch_size(50_000);
+    /// ```
+    pub fn batch_size(&mut self, batch_size: usize) -> &mut Self {
+        self.batch_size = Some(batch_size);
+
+        self
+    }
+    /// ### Start the bulk insert
+    /// #### Usage
+    /// ```rust
+    /// use crate::BulkInsertQuery;
+    ///
+    /// let mut foo = BulkInsertQuery::new();
+    /// foo
+    ///   .db("db_name")
+    ///   .start()
+    /// ```
+    pub fn start(&self) -> Result<Vec<u8>> {
+        let mut packet = from_op(&TuringOp::BulkInsert).to_vec();
+
+        let data = bincode::serialize::<Self>(self)?;
+        packet.extend_from_slice(&data);
+
+        Ok(packet)
+    }
+    /// ### Send the next records of a started bulk insert
+    /// A chunk cannot be larger than 16MB
+    /// #### Usage
+    /// ```rust
+    /// use crate::{BulkInsertQuery, BulkRecord};
+    ///
+    /// BulkInsertQuery::chunk(&[BulkRecord::new("document_name", b"field_name", b"value")])
+    /// ```
+    pub fn chunk(records: &[BulkRecord]) -> Result<Vec<u8>> {
+        Ok(bincode::serialize::<[BulkRecord]>(records)?)
+    }
+    /// ### End a started bulk insert
+    /// #### Usage
+    /// ```rust
+    /// use crate::BulkInsertQuery;
+    ///
+    /// BulkInsertQuery::finish()
+    /// ```
+    pub fn finish() -> Result<Vec<u8>> {
+        BulkInsertQuery::chunk(&[])
+    }
+}
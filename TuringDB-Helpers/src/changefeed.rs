@@ -0,0 +1,163 @@
+use crate::commands::{from_op, FieldData, TuringOp};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// The kind of change applied to a field
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChangeKind {
+    /// The field was inserted
+    Insert,
+    /// The value of the field was changed
+    Modify,
+    /// The field was removed
+    Remove,
+    /// The field was removed once it outlived its time-to-live
+    Expire,
+}
+
+/// ### A change to a field pushed by the server to a subscribed client
+/// Each event arrives as a `DbOps::FieldContents` holding the event serialized with bincode.
+/// Keep the `position` of the last event received to resume the subscription after reconnecting
+/// ```rust
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct ChangeEvent {
+///     pub position: u64,
+///     pub kind: ChangeKind,
+///     pub key: Vec<u8>,
+///     pub old: Option<FieldData>,
+///     pub new: Option<FieldData>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChangeEvent {
+    /// The sequence of the change in the CDC log of the database
+    pub position: u64,
+    /// Whether the field was inserted, modified or removed
+    pub kind: ChangeKind,
+    /// The key of the field
+    pub key: Vec<u8>,
+    /// The value before the change
+    pub old: Option<FieldData>,
+    /// The value after the change
+    pub new: Option<FieldData>,
+}
+
+/// ### Subscribes to the changes of the fields of a document
+/// Only fields whose keys start with `prefix` are watched, an empty prefix watches the whole document.
+/// The connection is dedicated to the subscription until it is closed by the client
+/// ```rust
+/// #[derive(Debug, Serialize, Clone, Default)]
+/// pub struct SubscribeQuery {
+///     db: String,
+///     document: String,
+///     prefix: Vec<u8>,
+///     position: Option<u64>,
+/// }
+/// ```
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct SubscribeQuery {
+    db: String,
+    document: String,
+    prefix: Vec<u8>,
+    position: Option<u64>,
+}
+
+impl SubscribeQuery {
+    /// ### Initialize a new empty subscription
+    /// #### Usage
+    /// ```rust
+    /// use crate::SubscribeQuery;
+    ///
+    /// SubscribeQuery::new()
+    /// ```
+    pub fn new() -> Self {
+        Self {
+            db: Default::default(),
+            document: Default::default(),
+            prefix: Default::default(),
+            position: Default::default(),
+        }
+    }
+    /// ### Add a database name
+    /// #### Usage
+    /// ```rust
+    /// use crate::SubscribeQuery;
+    ///
+    /// let mut foo = SubscribeQuery::new();
+    /// foo.db("db_name");
+    /// ```
+    pub fn db(&mut self, name: &str) -> &mut Self {
+        self.db = name.into();
+
+        self
+    }
+    /// ### Add a document name
+    /// #### Usage
+    /// ```rust
+    /// use crate::SubscribeQuery;
+    ///
+    /// let mut foo = SubscribeQuery::new();
+    /// foo
+    ///   .db("db_name")
+    ///   .document("document_name");
+    /// ```
+    pub fn document(&mut self, name: &str) -> &mut Self {
+        self.document = name.into();
+
+        self
+    }
+    /// ### Only watch the fields whose keys start with `prefix`
+    /// #### Usage
+    /// ```rust
+    /// use crate::SubscribeQuery;
+    ///
+    /// let mut foo = SubscribeQuery::new();
+    /// foo
+    ///   .db("db_name")
+    ///   .document("document_name")
+    ///   .prefix(b"user:");
+    /// ```
+    pub fn prefix(&mut self, prefix: &[u8]) -> &mut Self {
+        self.prefix = prefix.to_vec();
+
+        self
+    }
+    /// ### Resume from the `position` of the last `ChangeEvent` received
+    /// Every change to the watched fields made after it, removals included, is sent before any new change.
+    /// The server answers with an error when its CDC log no longer keeps those changes
+    /// #### Usage
+    /// ```rust
+    /// use crate::SubscribeQuery;
+    ///
+    /// let mut foo = SubscribeQuery::new();
+    /// foo
+    ///   .db("db_name")
+    ///   .document("document_name")
+    ///   .position(last_event.position);
+    /// ```
+    pub fn position(&mut self, position: u64) -> &mut Self {
+        self.position = Some(position);
+
+        self
+    }
+    /// ### Subscribes to the changes of the document
+    /// #### Usage
+    /// ```rust
+    /// use crate::SubscribeQuery;
+    ///
+    /// let mut foo = SubscribeQuery::new();
+    /// foo
+    ///   .db("db_name")
+    ///   .document("document_name")
+    ///   .prefix(b"user:")
+    ///   .subscribe()
+    /// ```
+    pub fn subscribe(&self) -> Result<Vec<u8>> {
+        let mut packet = from_op(&TuringOp::Subscribe).to_vec();
+
+        let data = bincode::serialize::<Self>(self)?;
+        packet.extend_from_slice(&data);
+
+        Ok(packet)
+    }
+}
@@ -0,0 +1,747 @@
+use crate::commands::{from_op, to_op, TuringOp};
+use crate::{DbQuery, ReplicationQuery};
+use anyhow::{anyhow, bail, Result};
+use custom_codes::DbOps;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    io::{Read, Write},
+    net::TcpStream,
+};
+
+/// The number of points every node has on the hash ring by default
+const DEFAULT_VNODES: u32 = 64;
+const RESPONSE_BUFFER_CAPACITY: usize = 64 * 1024;
+
+/// ### The metadata of a sharded cluster
+/// Every database lives on a single node, found by hashing its name onto a ring
+/// where each node has `vnodes` points. Adding or removing a node only moves
+/// the databases between the changed points, which `rebalance()` lists.
+/// The `epoch` grows with every change so clients can tell which map is newer
+/// ```rust
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct ClusterMap {
+///     epoch: u64,
+///     vnodes: u32,
+///     nodes: BTreeMap<String, String>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClusterMap {
+    epoch: u64,
+    vnodes: u32,
+    nodes: BTreeMap<String, String>,
+}
+
+impl Default for ClusterMap {
+    fn default() -> Self {
+        Self {
+            epoch: 0,
+            vnodes: DEFAULT_VNODES,
+            nodes: BTreeMap::new(),
+        }
+    }
+}
+
+/// A database that has to move to another node after the members of a cluster changed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShardMove {
+    /// The name of the database
+    pub db: String,
+    /// The node holding the database
+    pub from: String,
+    /// The node owning the database in the new map
+    pub to: String,
+}
+
+impl ClusterMap {
+    /// ### Initialize an empty cluster map
+    /// #### Usage
+    /// ```rust
+    /// use crate::ClusterMap;
+    ///
+    /// ClusterMap::new()
+    /// ```
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// ### Set the number of points every node has on the hash ring
+    /// More points spread the databases more evenly. Every client of a cluster has to use the same number
+    /// #### Usage
+    /// ```rust
+    /// use crate::ClusterMap;
+    ///
+    /// let mut foo = ClusterMap::new();
+    /// foo.vnodes(128);
+    /// ```
+    pub fn vnodes(&mut self, vnodes: u32) -> &mut Self {
+        self.vnodes = vnodes.max(1);
+        self.epoch += 1;
+
+        self
+    }
+    /// ### Add a node, or change its address, by the name it keeps on the ring
+    /// #### Usage
+    /// ```rust
+    /// use crate::ClusterMap;
+    ///
+    /// let mut foo = ClusterMap::new();
+    /// foo
+    ///   .add_node("node-1", "127.0.0.1:4343")
+    ///   .add_node("node-2", "127.0.0.1:4344");
+    /// ```
+    pub fn add_node(&mut self, name: &str, address: &str) -> &mut Self {
+        self.nodes.insert(name.into(), address.into());
+        self.epoch += 1;
+
+        self
+    }
+    /// ### Remove a node
+    /// #### Usage
+    /// ```rust
+    /// use crate::ClusterMap;
+    ///
+    /// let mut foo = ClusterMap::new();
+    /// foo.remove_node("node-2");
+    /// ```
+    pub fn remove_node(&mut self, name: &str) -> &mut Self {
+        if self.nodes.remove(name).is_some() {
+            self.epoch += 1;
+        }
+
+        self
+    }
+    /// The version of the map, which grows with every change
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+    /// The name and address of every node
+    pub fn nodes(&self) -> &BTreeMap<String, String> {
+        &self.nodes
+    }
+    /// The address of a node
+    pub fn address(&self, node: &str) -> Option<&str> {
+        self.nodes.get(node).map(|address| address.as_str())
+    }
+    /// The name of the node owning a database
+    pub fn owner(&self, db: &str) -> Option<String> {
+        HashRing::new(self).owner(db).map(|node| node.to_owned())
+    }
+    /// ### List the databases that change owner from this map to `next`
+    /// `placement` holds the node every database is on
+    /// #### Usage
+    /// ```rust
+    /// use crate::ClusterMap;
+    ///
+    /// let mut next = current.clone();
+    /// next.add_node("node-3", "127.0.0.1:4345");
+    ///
+    /// let moves = current.rebalance(&next, &placement);
+    /// ```
+    pub fn rebalance(
+        &self,
+        next: &ClusterMap,
+        placement: &BTreeMap<String, String>,
+    ) -> Vec<ShardMove> {
+        let ring = HashRing::new(next);
+
+        placement
+            .iter()
+            .filter_map(|(db, from)| match ring.owner(db) {
+                Some(to) if to != from => Some(ShardMove {
+                    db: db.clone(),
+                    from: from.clone(),
+                    to: to.to_owned(),
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+    /// ### Serialize the map to store or share it
+    /// #### Usage
+    /// ```rust
+    /// use crate::ClusterMap;
+    ///
+    /// let bytes = foo.to_bytes()?;
+    /// let foo = ClusterMap::from_bytes(&bytes)?;
+    /// ```
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        Ok(bincode::serialize::<Self>(self)?)
+    }
+    /// ### Deserialize a map created by `to_bytes()`
+    pub fn from_bytes(value: &[u8]) -> Result<Self> {
+        Ok(bincode::deserialize::<Self>(value)?)
+    }
+}
+
+/// The points of every node of a `ClusterMap` on the ring of 64-bit hashes
+#[derive(Debug)]
+struct HashRing {
+    points: BTreeMap<u64, String>,
+}
+
+impl HashRing {
+    fn new(map: &ClusterMap) -> Self {
+        let mut points = BTreeMap::new();
+
+        for node in map.nodes.keys() {
+            for vnode in 0..map.vnodes {
+                let point = seahash::hash(format!("{}#{}", node, vnode).as_bytes());
+                points.insert(point, node.clone());
+            }
+        }
+
+        Self { points }
+    }
+    /// The node of the first point at or after the hash of the key, wrapping around the ring
+    fn owner(&self, key: &str) -> Option<&str> {
+        let hash = seahash::hash(key.as_bytes());
+
+        self.points
+            .range(hash..)
+            .next()
+            .or_else(|| self.points.iter().next())
+            .map(|(_, node)| node.as_str())
+    }
+}
+
+/// ### Adds a database copied from another node of a sharded cluster
+/// `snapshot` holds the `DbSnapshot` returned by a `ReplicationQuery::snapshot()`.
+/// The nodes have to use the same master key for encrypted databases
+/// ```rust
+/// #[derive(Debug, Serialize, Clone, Default)]
+/// pub struct ShardQuery {
+///     db: String,
+///     snapshot: Vec<u8>,
+/// }
+/// ```
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct ShardQuery {
+    db: String,
+    snapshot: Vec<u8>,
+}
+
+impl ShardQuery {
+    /// ### Initialize a new empty query
+    /// #### Usage
+    /// ```rust
+    /// use crate::ShardQuery;
+    ///
+    /// ShardQuery::new()
+    /// ```
+    pub fn new() -> Self {
+        Self {
+            db: Default::default(),
+            snapshot: Default::default(),
+        }
+    }
+    /// ### Add the name of the database
+    /// #### Usage
+    /// ```rust
+    /// use crate::ShardQuery;
+    ///
+    /// let mut foo = ShardQuery::new();
+    /// foo.db("db_name");
+    /// ```
+    pub fn db(&mut self, name: &str) -> &mut Self {
+        self.db = name.into();
+
+        self
+    }
+    /// ### Add the copy of the database
+    /// #### Usage
+    /// ```rust
+    /// use crate::ShardQuery;
+    ///
+    /// let mut foo = ShardQuery::new();
+    /// foo
+    ///   .db("db_name")
+    ///   .snapshot(snapshot);
+    /// ```
+    pub fn snapshot(&mut self, snapshot: Vec<u8>) -> &mut Self {
+        self.snapshot = snapshot;
+
+        self
+    }
+    /// ### Add the database to the node the query is sent to
+    /// #### Usage
+    /// ```rust
+    /// use crate::ShardQuery;
+    ///
+    /// let mut foo = ShardQuery::new();
+    /// foo
+    ///   .db("db_name")
+    ///   .snapshot(snapshot)
+    ///   .import()
+    /// ```
+    pub fn import(&self) -> Result<Vec<u8>> {
+        let mut packet = from_op(&TuringOp::ShardImport).to_vec();
+
+        let data = bincode::serialize::<Self>(self)?;
+        packet.extend_from_slice(&data);
+
+        Ok(packet)
+    }
+}
+
+/// ### Lists the databases or documents of every node of a cluster
+/// The server receiving the query asks the other nodes of its `--cluster` map
+/// and responds with a `DbOps::FieldContents` holding a `ClusterListing` serialized with bincode
+/// ```rust
+/// #[derive(Debug, Serialize, Clone, Default)]
+/// pub struct GatherQuery {
+///     db: String,
+/// }
+/// ```
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct GatherQuery {
+    db: String,
+}
+
+impl GatherQuery {
+    /// ### Initialize a new empty query
+    /// #### Usage
+    /// ```rust
+    /// use crate::GatherQuery;
+    ///
+    /// GatherQuery::new()
+    /// ```
+    pub fn new() -> Self {
+        Self {
+            db: Default::default(),
+        }
+    }
+    /// ### Add the name of the database
+    /// #### Usage
+    /// ```rust
+    /// use crate::GatherQuery;
+    ///
+    /// let mut foo = GatherQuery::new();
+    /// foo.db("db_name");
+    /// ```
+    pub fn db(&mut self, name: &str) -> &mut Self {
+        self.db = name.into();
+
+        self
+    }
+    /// ### List the databases of every node
+    /// #### Usage
+    /// ```rust
+    /// use crate::GatherQuery;
+    ///
+    /// GatherQuery::new().db_list()
+    /// ```
+    pub fn db_list(&self) -> Vec<u8> {
+        from_op(&TuringOp::ClusterDbList).to_vec()
+    }
+    /// ### List the documents of a database on every node
+    /// #### Usage
+    /// ```rust
+    /// use crate::GatherQuery;
+    ///
+    /// let mut foo = GatherQuery::new();
+    /// foo
+    ///   .db("db_name")
+    ///   .document_list()
+    /// ```
+    pub fn document_list(&self) -> Result<Vec<u8>> {
+        let mut packet = from_op(&TuringOp::ClusterDocumentList).to_vec();
+
+        let data = bincode::serialize::<Self>(self)?;
+        packet.extend_from_slice(&data);
+
+        Ok(packet)
+    }
+}
+
+/// ### The merged response of a `GatherQuery`
+/// `items` are sorted like `db_list_sorted()` and `document_list_sorted()` with the duplicates removed.
+/// `missing` holds the error of every node that did not respond, so `items` may be incomplete
+/// ```rust
+/// #[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+/// pub struct ClusterListing {
+///     pub items: Vec<String>,
+///     pub missing: BTreeMap<String, String>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct ClusterListing {
+    /// The sorted names of the databases or documents
+    pub items: Vec<String>,
+    /// The nodes that did not respond, with the error of each
+    pub missing: BTreeMap<String, String>,
+}
+
+impl ClusterListing {
+    /// Whether every node responded
+    pub fn is_complete(&self) -> bool {
+        self.missing.is_empty()
+    }
+}
+
+/// ### Sends every query to the node owning its database
+/// Queries that are not about a single database, like `DbList`, are sent to every node with `broadcast()`.
+/// A connection is kept open to every node and opened again after an error
+/// ```rust
+/// #[derive(Debug)]
+/// pub struct ClusterClient {
+///     map: ClusterMap,
+///     ring: HashRing,
+///     connections: BTreeMap<String, TcpStream>,
+/// }
+/// ```
+/// #### Usage
+/// ```rust
+/// use crate::{ClusterClient, ClusterMap, DbQuery};
+///
+/// let mut map = ClusterMap::new();
+/// map
+///   .add_node("node-1", "127.0.0.1:4343")
+///   .add_node("node-2", "127.0.0.1:4344");
+///
+/// let mut client = ClusterClient::new(map);
+/// client.send(&DbQuery::new().db("db_name").create())?;
+/// ```
+#[derive(Debug)]
+pub struct ClusterClient {
+    map: ClusterMap,
+    ring: HashRing,
+    connections: BTreeMap<String, TcpStream>,
+}
+
+impl ClusterClient {
+    /// ### Route queries using a cluster map
+    pub fn new(map: ClusterMap) -> Self {
+        Self {
+            ring: HashRing::new(&map),
+            map,
+            connections: BTreeMap::new(),
+        }
+    }
+    /// The cluster map used to route queries
+    pub fn map(&self) -> &ClusterMap {
+        &self.map
+    }
+    /// ### The node owning the database of a query
+    /// Returns `None` for a query sent to every node
+    pub fn route(&self, packet: &[u8]) -> Result<Option<String>> {
+        let db = match ClusterClient::db_name(packet)? {
+            None => return Ok(None),
+            Some(db) => db,
+        };
+
+        match self.ring.owner(&db) {
+            None => bail!("[TuringDB::<Cluster>::(ERROR)-CLUSTER_HAS_NO_NODES]"),
+            Some(node) => Ok(Some(node.to_owned())),
+        }
+    }
+    /// ### Send a query to the node owning its database
+    /// #### Usage
+    /// ```rust
+    /// use crate::{ClusterClient, FieldQuery};
+    ///
+    /// let mut foo = FieldQuery::new();
+    /// foo
+    ///   .db("db_name")
+    ///   .document("document_name")
+    ///   .field("field_name");
+    ///
+    /// client.send(&foo.get()?)?;
+    /// ```
+    pub fn send(&mut self, packet: &[u8]) -> Result<DbOps> {
+        if packet.first().map(|op| to_op(&[*op])) == Some(TuringOp::Subscribe) {
+            bail!("[TuringDB::<Cluster>::(ERROR)-SUBSCRIBE_NEEDS_A_DEDICATED_CONNECTION]");
+        }
+
+        if packet.first().map(|op| to_op(&[*op])) == Some(TuringOp::BulkInsert) {
+            bail!("[TuringDB::<Cluster>::(ERROR)-BULK_INSERT_NEEDS_A_DEDICATED_CONNECTION]");
+        }
+
+        match self.route(packet)? {
+            Some(node) => self.request(&node, packet),
+            // Any node gathers the databases of the whole cluster
+            None if packet.first().map(|op| to_op(&[*op])) == Some(TuringOp::ClusterDbList) => {
+                match self.map.nodes.keys().next().cloned() {
+                    None => bail!("[TuringDB::<Cluster>::(ERROR)-CLUSTER_HAS_NO_NODES]"),
+                    Some(node) => self.request(&node, packet),
+                }
+            }
+            None => bail!("[TuringDB::<Cluster>::(ERROR)-QUERY_SPANS_THE_CLUSTER]"),
+        }
+    }
+    /// ### Send a query to every node, returning the response of each node by its name
+    /// #### Usage
+    /// ```rust
+    /// use crate::{ClusterClient, DbQuery};
+    ///
+    /// for (node, response) in client.broadcast(DbQuery::new().list()) {
+    ///     println!("{}: {:?}", node, response);
+    /// }
+    /// ```
+    pub fn broadcast(&mut self, packet: &[u8]) -> BTreeMap<String, Result<DbOps>> {
+        let nodes = self.map.nodes.keys().cloned().collect::<Vec<String>>();
+
+        nodes
+            .into_iter()
+            .map(|node| {
+                let response = self.request(&node, packet);
+
+                (node, response)
+            })
+            .collect()
+    }
+    /// ### Move the databases to their owners in `next` and start routing with it
+    /// Every database found on a node that it does not belong to in `next` is copied to its new owner
+    /// and then dropped from the old one. Writes to a database that is moving may be lost,
+    /// so they have to be held back until the rebalance returns
+    /// #### Usage
+    /// ```rust
+    /// use crate::ClusterClient;
+    ///
+    /// let mut next = client.map().clone();
+    /// next.add_node("node-3", "127.0.0.1:4345");
+    ///
+    /// let moves = client.rebalance(next)?;
+    /// ```
+    pub fn rebalance(&mut self, next: ClusterMap) -> Result<Vec<ShardMove>> {
+        let mut placement = BTreeMap::new();
+
+        for (node, response) in self.broadcast(DbQuery::new().list()) {
+            match response? {
+                DbOps::DbList(dbs) => {
+                    for db in dbs {
+                        placement.insert(db, node.clone());
+                    }
+                }
+                DbOps::RepoEmpty | DbOps::DbEmpty => (),
+                op_result => bail!("[TuringDB::<Cluster>::(ERROR)-{:?}]", op_result),
+            }
+        }
+
+        let moves = self.map.rebalance(&next, &placement);
+
+        // The new nodes are only known to `next`
+        for (node, address) in next.nodes.iter() {
+            if self.map.address(node) != Some(address) {
+                self.connections.remove(node);
+            }
+        }
+        self.map = next;
+        self.ring = HashRing::new(&self.map);
+
+        for shard in moves.iter() {
+            let snapshot = match self.request(
+                &shard.from,
+                &ReplicationQuery::new().db(&shard.db).snapshot()?,
+            )? {
+                DbOps::FieldContents(snapshot) => snapshot,
+                op_result => bail!("[TuringDB::<Cluster>::(ERROR)-{:?}]", op_result),
+            };
+
+            match self.request(
+                &shard.to,
+                &ShardQuery::new()
+                    .db(&shard.db)
+                    .snapshot(snapshot)
+                    .import()?,
+            )? {
+                DbOps::DbCreated => (),
+                op_result => bail!("[TuringDB::<Cluster>::(ERROR)-{:?}]", op_result),
+            }
+
+            match self.request(&shard.from, &DbQuery::new().db(&shard.db).drop())? {
+                DbOps::DbDropped => (),
+                op_result => bail!("[TuringDB::<Cluster>::(ERROR)-{:?}]", op_result),
+            }
+        }
+
+        Ok(moves)
+    }
+    /// The name of the database a query is about, `None` for a query sent to every node
+    fn db_name(packet: &[u8]) -> Result<Option<String>> {
+        let (op, value) = match packet.split_first() {
+            None => bail!("[TuringDB::<Cluster>::(ERROR)-EMPTY_PACKET]"),
+            Some((op, value)) => (to_op(&[*op]), value),
+        };
+
+        match op {
+            TuringOp::RepoCreate
+            | TuringOp::RepoDrop
+            | TuringOp::DbList
+            | TuringOp::AuditVerify
+            | TuringOp::ReplicationPoll
+            | TuringOp::ReplicationStatus
+            | TuringOp::ClusterDbList
+            | TuringOp::NotSupported => Ok(None),
+            // The name of the database is all the data of these queries
+            TuringOp::DbCreate | TuringOp::DbDrop => {
+                Ok(Some(std::str::from_utf8(value)?.to_owned()))
+            }
+            // Every other query is serialized with bincode starting with the name of the database
+            _ => Ok(Some(bincode::deserialize::<String>(value)?)),
+        }
+    }
+
+    fn request(&mut self, node: &str, packet: &[u8]) -> Result<DbOps> {
+        let response = self.exchange(node, packet);
+
+        // Connect again on the next query instead of reading a stale response
+        if response.is_err() {
+            self.connections.remove(node);
+        }
+
+        response
+    }
+
+    fn exchange(&mut self, node: &str, packet: &[u8]) -> Result<DbOps> {
+        if !self.connections.contains_key(node) {
+            let address = match self.map.address(node) {
+                None => bail!("[TuringDB::<Cluster>::(ERROR)-UNKNOWN_NODE_{}]", node),
+                Some(address) => address,
+            };
+
+            let stream = TcpStream::connect(address)?;
+            self.connections.insert(node.to_owned(), stream);
+        }
+
+        let stream = match self.connections.get_mut(node) {
+            None => return Err(anyhow!("[TuringDB::<Cluster>::(ERROR)-NOT_CONNECTED]")),
+            Some(stream) => stream,
+        };
+
+        stream.write_all(packet)?;
+        stream.flush()?;
+
+        let mut buffer = [0; RESPONSE_BUFFER_CAPACITY];
+        let mut container_buffer: Vec<u8> = Vec::new();
+
+        loop {
+            let bytes_read = stream.read(&mut buffer)?;
+
+            if bytes_read == 0 {
+                bail!(
+                    "[TuringDB::<Cluster>::(ERROR)-CONNECTION_CLOSED_BY_{}]",
+                    node
+                );
+            }
+
+            container_buffer.extend_from_slice(&buffer[..bytes_read]);
+
+            // The response is complete once it deserializes
+            if let Ok(op_result) = bincode::deserialize::<DbOps>(&container_buffer) {
+                return Ok(op_result);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    fn cluster(nodes: &[&str]) -> ClusterMap {
+        let mut map = ClusterMap::new();
+        for (port, node) in nodes.iter().enumerate() {
+            map.add_node(node, &format!("127.0.0.1:{}", 4343 + port));
+        }
+
+        map
+    }
+    /// The node every one of 500 databases is on under `map`
+    fn placement(map: &ClusterMap) -> BTreeMap<String, String> {
+        (0..500)
+            .map(|db| {
+                let db = format!("db-{}", db);
+                let owner = map.owner(&db).unwrap();
+
+                (db, owner)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn the_placement_only_depends_on_the_nodes() {
+        let map = cluster(&["node-1", "node-2", "node-3"]);
+        let reordered = cluster(&["node-3", "node-1", "node-2"]);
+        let restored = ClusterMap::from_bytes(&map.to_bytes().unwrap()).unwrap();
+
+        assert_eq!(placement(&map), placement(&reordered));
+        assert_eq!(placement(&map), placement(&restored));
+        assert!(map.rebalance(&reordered, &placement(&map)).is_empty());
+
+        let owners = placement(&map).into_values().collect::<BTreeSet<String>>();
+        assert_eq!(owners.len(), 3);
+    }
+
+    #[test]
+    fn adding_a_node_only_moves_databases_to_it() {
+        let current = cluster(&["node-1", "node-2", "node-3"]);
+        let mut next = current.clone();
+        next.add_node("node-4", "127.0.0.1:4346");
+
+        let before = placement(&current);
+        let after = placement(&next);
+        let moves = current.rebalance(&next, &before);
+
+        assert!(!moves.is_empty());
+        assert!(moves.len() < before.len() / 2);
+        for shard_move in &moves {
+            assert_eq!(shard_move.to, "node-4");
+            assert_eq!(shard_move.from, before[&shard_move.db]);
+        }
+
+        let moved = moves
+            .iter()
+            .map(|shard_move| shard_move.db.as_str())
+            .collect::<Vec<&str>>();
+        for (db, owner) in &after {
+            assert_eq!(moved.contains(&db.as_str()), owner == "node-4");
+        }
+    }
+
+    #[test]
+    fn removing_a_node_only_moves_its_databases() {
+        let current = cluster(&["node-1", "node-2", "node-3"]);
+        let mut next = current.clone();
+        next.remove_node("node-2");
+
+        let before = placement(&current);
+        let after = placement(&next);
+        let moves = current.rebalance(&next, &before);
+
+        let held = before.values().filter(|owner| *owner == "node-2").count();
+        assert_eq!(moves.len(), held);
+        for shard_move in &moves {
+            assert_eq!(shard_move.from, "node-2");
+            assert_eq!(shard_move.to, after[&shard_move.db]);
+        }
+
+        for (db, owner) in &before {
+            if owner != "node-2" {
+                assert_eq!(&after[db], owner);
+            }
+        }
+    }
+
+    #[test]
+    fn adding_back_a_removed_node_moves_its_databases_back() {
+        let current = cluster(&["node-1", "node-2", "node-3"]);
+        let mut without = current.clone();
+        without.remove_node("node-3");
+
+        let moved_out = current.rebalance(&without, &placement(&current));
+        let moved_back = without.rebalance(&current, &placement(&without));
+
+        let reversed = moved_out
+            .into_iter()
+            .map(|shard_move| ShardMove {
+                db: shard_move.db,
+                from: shard_move.to,
+                to: shard_move.from,
+            })
+            .collect::<Vec<ShardMove>>();
+        assert_eq!(moved_back, reversed);
+        assert!(without.epoch() > current.epoch());
+    }
+}
@@ -29,6 +29,40 @@ pub enum TuringOp {
     FieldModify,
     /// List all fields in a document
     FieldList,
+    /// Insert a JSON value into a field
+    JsonSet,
+    /// Read a JSON value or the value at a path inside it
+    JsonGet,
+    /// Replace the value at a path inside a JSON field
+    JsonModify,
+    /// Remove the value at a path inside a JSON field
+    JsonRemove,
+    /// Verify that no entry of the audit log was removed, reordered or modified
+    AuditVerify,
+    /// Receive the changes to the fields of a document as they happen
+    Subscribe,
+    /// Copy a database to bootstrap a replica
+    ReplicationSnapshot,
+    /// Read the changes of every database from the positions of a replica
+    ReplicationPoll,
+    /// Report how far the databases of a replica are behind its leader
+    ReplicationStatus,
+    /// Add a database copied from another node of a sharded cluster
+    ShardImport,
+    /// List the databases of every node of a cluster
+    ClusterDbList,
+    /// List the documents of a database on every node of a cluster
+    ClusterDocumentList,
+    /// Stream many fields into a database
+    BulkInsert,
+    /// Apply many field operations in one request
+    Batch,
+    /// Declare a secondary index of a document
+    IndexCreate,
+    /// Drop a secondary index of a document
+    IndexDrop,
+    /// Find the fields of a document by the values of an index
+    IndexFind,
     /// The command is not supported
     NotSupported,
 }
@@ -48,6 +82,23 @@ pub fn from_op<'op>(value: &TuringOp) -> &'op [u8] {
         TuringOp::FieldRemove => &[0x0a],
         TuringOp::FieldModify => &[0x0b],
         TuringOp::FieldList => &[0x0c],
+        TuringOp::JsonSet => &[0x0d],
+        TuringOp::JsonGet => &[0x0e],
+        TuringOp::JsonModify => &[0x0f],
+        TuringOp::JsonRemove => &[0x10],
+        TuringOp::AuditVerify => &[0x11],
+        TuringOp::Subscribe => &[0x12],
+        TuringOp::ReplicationSnapshot => &[0x13],
+        TuringOp::ReplicationPoll => &[0x14],
+        TuringOp::ReplicationStatus => &[0x15],
+        TuringOp::ShardImport => &[0x16],
+        TuringOp::ClusterDbList => &[0x17],
+        TuringOp::ClusterDocumentList => &[0x18],
+        TuringOp::BulkInsert => &[0x19],
+        TuringOp::Batch => &[0x1a],
+        TuringOp::IndexCreate => &[0x1b],
+        TuringOp::IndexDrop => &[0x1c],
+        TuringOp::IndexFind => &[0x1d],
         TuringOp::NotSupported => &[0xf1],
     }
 }
@@ -67,6 +118,23 @@ pub fn to_op(value: &[u8]) -> TuringOp {
         [0x0a] => TuringOp::FieldRemove,
         [0x0b] => TuringOp::FieldModify,
         [0x0c] => TuringOp::FieldList,
+        [0x0d] => TuringOp::JsonSet,
+        [0x0e] => TuringOp::JsonGet,
+        [0x0f] => TuringOp::JsonModify,
+        [0x10] => TuringOp::JsonRemove,
+        [0x11] => TuringOp::AuditVerify,
+        [0x12] => TuringOp::Subscribe,
+        [0x13] => TuringOp::ReplicationSnapshot,
+        [0x14] => TuringOp::ReplicationPoll,
+        [0x15] => TuringOp::ReplicationStatus,
+        [0x16] => TuringOp::ShardImport,
+        [0x17] => TuringOp::ClusterDbList,
+        [0x18] => TuringOp::ClusterDocumentList,
+        [0x19] => TuringOp::BulkInsert,
+        [0x1a] => TuringOp::Batch,
+        [0x1b] => TuringOp::IndexCreate,
+        [0x1c] => TuringOp::IndexDrop,
+        [0x1d] => TuringOp::IndexFind,
         [0xf1] => TuringOp::NotSupported,
         _ => TuringOp::NotSupported,
     }
@@ -107,4 +175,16 @@ impl FieldData {
 
         self
     }
+    /// Get the data held by the field
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+    /// The time the field was first inserted
+    pub fn created(&self) -> TAI64N {
+        self.created
+    }
+    /// The time the field was last modified
+    pub fn modified(&self) -> TAI64N {
+        self.modified
+    }
 }
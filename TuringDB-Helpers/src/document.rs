@@ -71,8 +71,6 @@ impl DocumentQuery {
     /// ```
     pub fn create(&self) -> Result<Vec<u8>> {
         let mut packet = from_op(&TuringOp::DocumentCreate).to_vec();
-        packet.extend_from_slice(self.db.as_bytes());
-
         let data = bincode::serialize::<Self>(self)?;
         packet.extend_from_slice(&data);
 
@@ -90,8 +88,6 @@ impl DocumentQuery {
     /// ```
     pub fn list(&self) -> Result<Vec<u8>> {
         let mut packet = from_op(&TuringOp::DocumentList).to_vec();
-        packet.extend_from_slice(self.db.as_bytes());
-
         let data = bincode::serialize::<Self>(self)?;
         packet.extend_from_slice(&data);
 
@@ -110,8 +106,6 @@ impl DocumentQuery {
     /// ```
     pub fn drop(&self) -> Result<Vec<u8>> {
         let mut packet = from_op(&TuringOp::DocumentDrop).to_vec();
-        packet.extend_from_slice(self.db.as_bytes());
-
         let data = bincode::serialize::<Self>(self)?;
         packet.extend_from_slice(&data);
 
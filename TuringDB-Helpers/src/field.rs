@@ -116,8 +116,6 @@ where
     /// ```
     pub async fn set(&self) -> Result<Vec<u8>> {
         let mut packet = from_op(&TuringOp::FieldInsert).to_vec();
-        packet.extend_from_slice(self.db.as_bytes());
-
         let data = bincode::serialize::<Self>(self)?;
         packet.extend_from_slice(&data);
 
@@ -137,8 +135,6 @@ where
     /// ```
     pub async fn get(&self) -> Result<Vec<u8>> {
         let mut packet = from_op(&TuringOp::FieldGet).to_vec();
-        packet.extend_from_slice(self.db.as_bytes());
-
         let data = bincode::serialize::<Self>(self)?;
         packet.extend_from_slice(&data);
 
@@ -157,8 +153,6 @@ where
     /// ```
     pub fn list(&self) -> Result<Vec<u8>> {
         let mut packet = from_op(&TuringOp::FieldList).to_vec();
-        packet.extend_from_slice(self.db.as_bytes());
-
         let data = bincode::serialize::<Self>(self)?;
         packet.extend_from_slice(&data);
 
@@ -178,8 +172,6 @@ where
     /// ```
     pub fn remove(&self) -> Result<Vec<u8>> {
         let mut packet = from_op(&TuringOp::FieldRemove).to_vec();
-        packet.extend_from_slice(self.db.as_bytes());
-
         let data = bincode::serialize::<Self>(self)?;
         packet.extend_from_slice(&data);
 
@@ -200,8 +192,6 @@ where
     /// ```
     pub fn modify(&self) -> Result<Vec<u8>> {
         let mut packet = from_op(&TuringOp::FieldModify).to_vec();
-        packet.extend_from_slice(self.db.as_bytes());
-
         let data = bincode::serialize::<Self>(self)?;
         packet.extend_from_slice(&data);
 
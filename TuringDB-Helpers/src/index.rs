@@ -0,0 +1,276 @@
+use crate::commands::{from_op, TuringOp};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tai64::TAI64N;
+
+/// ### A value held by a secondary index
+/// The server decodes the value of a field by its data type, reading every integer type
+/// from its little-endian bytes as an `Integer`, `F32` and `F64` as a `Float`, `STRING` as a `Text`,
+/// `TAI64` and `TAI64N` as a `Time` and keeping any other data type as `Bytes`.
+/// Values of different variants are never equal
+/// ```rust
+/// #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// pub enum IndexValue {
+///     Boolean(bool),
+///     Integer(i128),
+///     Float(f64),
+///     Text(String),
+///     Time(TAI64N),
+///     Bytes(Vec<u8>),
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum IndexValue {
+    /// A `Boolean` value or a JSON boolean
+    Boolean(bool),
+    /// A value of any integer type or a JSON integer
+    Integer(i128),
+    /// An `F32` or `F64` value or a JSON number with a fraction
+    Float(f64),
+    /// A `STRING` value or a JSON string
+    Text(String),
+    /// A `TAI64` or `TAI64N` value
+    Time(TAI64N),
+    /// The value of any other data type
+    Bytes(Vec<u8>),
+}
+
+/// ### The fields looked up in an index
+/// Those whose indexed value is `Value` or lies in a `Range` including both bounds.
+/// A missing bound leaves the range open up to the values of the variant of the other bound
+/// ```rust
+/// #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// pub enum IndexLookup {
+///     Value(IndexValue),
+///     Range { from: Option<IndexValue>, to: Option<IndexValue> },
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum IndexLookup {
+    /// The fields holding the value
+    Value(IndexValue),
+    /// The fields holding a value between the bounds
+    Range {
+        /// The lowest value found
+        from: Option<IndexValue>,
+        /// The highest value found
+        to: Option<IndexValue>,
+    },
+}
+
+/// ### Handles all queries related to the secondary indexes of a document
+/// ```rust
+/// #[derive(Debug, Serialize, Clone, Default)]
+/// pub struct IndexQuery {
+///     db: String,
+///     document: String,
+///     index: String,
+///     path: Option<String>,
+///     unique: bool,
+///     lookup: Option<IndexLookup>,
+/// }
+/// ```
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct IndexQuery {
+    db: String,
+    document: String,
+    index: String,
+    path: Option<String>,
+    unique: bool,
+    lookup: Option<IndexLookup>,
+}
+
+impl IndexQuery {
+    /// ### Initialize a new empty index query
+    /// #### Usage
+    /// ```rust
+    /// use crate::IndexQuery;
+    ///
+    /// IndexQuery::new()
+    /// ```
+    pub fn new() -> Self {
+        Self {
+            db: Default::default(),
+            document: Default::default(),
+            index: Default::default(),
+            path: Default::default(),
+            unique: Default::default(),
+            lookup: Default::default(),
+        }
+    }
+    /// ### Add a database name
+    /// #### Usage
+    /// ```rust
+    /// use crate::IndexQuery;
+    ///
+    /// let mut foo = IndexQuery::new();
+    /// foo.db("db_name");
+    /// ```
+    pub fn db(&mut self, name: &str) -> &mut Self {
+        self.db = name.into();
+
+        self
+    }
+    /// ### Add a document name
+    /// #### Usage
+    /// ```rust
+    /// use crate::IndexQuery;
+    ///
+    /// let mut foo = IndexQuery::new();
+    /// foo
+    ///   .db("db_name")
+    ///   .document("document_name");
+    /// ```
+    pub fn document(&mut self, name: &str) -> &mut Self {
+        self.document = name.into();
+
+        self
+    }
+    /// ### Add an index name
+    /// #### Usage
+    /// ```rust
+    /// use crate::IndexQuery;
+    ///
+    /// let mut foo = IndexQuery::new();
+    /// foo
+    ///   .db("db_name")
+    ///   .document("document_name")
+    ///   .index("index_name");
+    /// ```
+    pub fn index(&mut self, name: &str) -> &mut Self {
+        self.index = name.into();
+
+        self
+    }
+    /// ### Index the value at a path inside the JSON value of every field
+    /// The path is made up of object keys separated by `.` and array indices like `user.emails[0]`.
+    /// Without a path the index holds the whole value of every field
+    /// #### Usage
+    /// ```rust
+    /// use crate::IndexQuery;
+    ///
+    /// let mut foo = IndexQuery::new();
+    /// foo
+    ///   .db("db_name")
+    ///   .document("document_name")
+    ///   .index("by_email")
+    ///   .path("email");
+    /// ```
+    pub fn path(&mut self, path: &str) -> &mut Self {
+        self.path = Some(path.into());
+
+        self
+    }
+    /// ### Make the index refuse any write leaving two fields of the document with the same value
+    /// Such a write fails with a `UniqueViolation` naming the key of the field already holding the value
+    /// #### Usage
+    /// ```rust
+    /// use crate::IndexQuery;
+    ///
+    /// let mut foo = IndexQuery::new();
+    /// foo
+    ///   .db("db_name")
+    ///   .document("document_name")
+    ///   .index("by_email")
+    ///   .path("email")
+    ///   .unique();
+    /// ```
+    pub fn unique(&mut self) -> &mut Self {
+        self.unique = true;
+
+        self
+    }
+    /// ### Look up the fields holding a value
+    /// #### Usage
+    /// ```rust
+    /// use crate::{IndexQuery, IndexValue};
+    ///
+    /// let mut foo = IndexQuery::new();
+    /// foo
+    ///   .db("db_name")
+    ///   .document("document_name")
+    ///   .index("by_email")
+    ///   .value(IndexValue::Text("turing@example.com".into()));
+    /// ```
+    pub fn value(&mut self, value: IndexValue) -> &mut Self {
+        self.lookup = Some(IndexLookup::Value(value));
+
+        self
+    }
+    /// ### Look up the fields holding a value between two values, both included
+    /// #### Usage
+    /// ```rust
+    /// use crate::{IndexQuery, IndexValue};
+    ///
+    /// let mut foo = IndexQuery::new();
+    /// foo
+    ///   .db("db_name")
+    ///   .document("document_name")
+    ///   .index("by_age")
+    ///   .range(Some(IndexValue::Integer(18)), None);
+    /// ```
+    pub fn range(&mut self, from: Option<IndexValue>, to: Option<IndexValue>) -> &mut Self {
+        self.lookup = Some(IndexLookup::Range { from, to });
+
+        self
+    }
+    /// ### Declare the index and add the fields the document already holds to it
+    /// #### Usage
+    /// ```rust
+    /// use crate::IndexQuery;
+    ///
+    /// let mut foo = IndexQuery::new();
+    /// foo
+    ///   .db("db_name")
+    ///   .document("document_name")
+    ///   .index("by_email")
+    ///   .path("email")
+    ///   .create()
+    /// ```
+    pub fn create(&self) -> Result<Vec<u8>> {
+        self.to_packet(&TuringOp::IndexCreate)
+    }
+    /// ### Drop the index
+    /// #### Usage
+    /// ```rust
+    /// use crate::IndexQuery;
+    ///
+    /// let mut foo = IndexQuery::new();
+    /// foo
+    ///   .db("db_name")
+    ///   .document("document_name")
+    ///   .index("by_email")
+    ///   .drop()
+    /// ```
+    pub fn drop(&self) -> Result<Vec<u8>> {
+        self.to_packet(&TuringOp::IndexDrop)
+    }
+    /// ### Find the fields of the lookup
+    /// The server answers with a `DbOps::FieldContents` holding the key and the contents of every field found,
+    /// a `Vec<(Vec<u8>, Vec<u8>)>` serialized with bincode in the order of the indexed values.
+    /// Without a lookup every field of the index is found
+    /// #### Usage
+    /// ```rust
+    /// use crate::{IndexQuery, IndexValue};
+    ///
+    /// let mut foo = IndexQuery::new();
+    /// foo
+    ///   .db("db_name")
+    ///   .document("document_name")
+    ///   .index("by_age")
+    ///   .range(Some(IndexValue::Integer(18)), Some(IndexValue::Integer(65)))
+    ///   .find()
+    /// ```
+    pub fn find(&self) -> Result<Vec<u8>> {
+        self.to_packet(&TuringOp::IndexFind)
+    }
+
+    fn to_packet(&self, op: &TuringOp) -> Result<Vec<u8>> {
+        let mut packet = from_op(op).to_vec();
+
+        let data = bincode::serialize::<Self>(self)?;
+        packet.extend_from_slice(&data);
+
+        Ok(packet)
+    }
+}
@@ -0,0 +1,197 @@
+use crate::commands::{from_op, TuringOp};
+use anyhow::Result;
+use serde::Serialize;
+
+/// ### Handles all queries related to JSON values stored in fields
+/// The `path` points inside the JSON value using object keys separated by `.`
+/// and array indices like `user.addresses[0].city`. An empty path points to the whole value
+/// ```rust
+/// #[derive(Debug, Serialize, Clone, Default)]
+/// pub struct JsonQuery {
+///     db: String,
+///     document: String,
+///     field: String,
+///     path: String,
+///     payload: Option<String>,
+/// }
+/// ```
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct JsonQuery {
+    db: String,
+    document: String,
+    field: String,
+    path: String,
+    payload: Option<String>,
+}
+
+impl JsonQuery {
+    /// ### Initialize a new empty JSON query
+    /// #### Usage
+    /// ```rust
+    /// use crate::JsonQuery;
+    ///
+    /// JsonQuery::new()
+    /// ```
+    pub fn new() -> Self {
+        Self {
+            db: Default::default(),
+            document: Default::default(),
+            field: Default::default(),
+            path: Default::default(),
+            payload: Default::default(),
+        }
+    }
+    /// ### Add a database name
+    /// #### Usage
+    /// ```rust
+    /// use crate::JsonQuery;
+    ///
+    /// let mut foo = JsonQuery::new();
+    /// foo.db("db_name");
+    /// ```
+    pub fn db(&mut self, name: &str) -> &mut Self {
+        self.db = name.into();
+
+        self
+    }
+    /// ### Add a document name
+    /// #### Usage
+    /// ```rust
+    /// use crate::JsonQuery;
+    ///
+    /// let mut foo = JsonQuery::new();
+    /// foo
+    ///   .db("db_name")
+    ///   .document("document_name");
+    /// ```
+    pub fn document(&mut self, name: &str) -> &mut Self {
+        self.document = name.into();
+
+        self
+    }
+    /// ### Add a field name
+    /// #### Usage
+    /// ```rust
+    /// use crate::JsonQuery;
+    ///
+    /// let mut foo = JsonQuery::new();
+    /// foo
+    ///   .db("db_name")
+    ///   .document("document_name")
+    ///   .field("field_name");
+    /// ```
+    pub fn field(&mut self, name: &str) -> &mut Self {
+        self.field = name.into();
+
+        self
+    }
+    /// ### Add a path inside the JSON value
+    /// #### Usage
+    /// ```rust
+    /// use crate::JsonQuery;
+    ///
+    /// let mut foo = JsonQuery::new();
+    /// foo
+    ///   .db("db_name")
+    ///   .document("document_name")
+    ///   .field("field_name")
+    ///   .path("user.address.city");
+    /// ```
+    pub fn path(&mut self, path: &str) -> &mut Self {
+        self.path = path.into();
+
+        self
+    }
+    /// ### Add a JSON payload
+    /// The payload must be valid JSON text, it is parsed by the server
+    /// #### Usage
+    /// ```rust
+    /// use crate::JsonQuery;
+    ///
+    /// let mut foo = JsonQuery::new();
+    /// foo
+    ///   .db("db_name")
+    ///   .document("document_name")
+    ///   .field("field_name")
+    ///   .payload(r#"{"city": "Nairobi"}"#);
+    /// ```
+    pub fn payload(&mut self, json: &str) -> &mut Self {
+        self.payload = Some(json.into());
+
+        self
+    }
+    /// ### Inserts a JSON value into a field, failing if the field already exists
+    /// #### Usage
+    /// ```rust
+    /// use crate::JsonQuery;
+    ///
+    /// let mut foo = JsonQuery::new();
+    /// foo
+    ///   .db("db_name")
+    ///   .document("document_name")
+    ///   .field("field_name")
+    ///   .payload(r#"{"user": {"name": "Turing"}}"#)
+    ///   .set()
+    /// ```
+    pub fn set(&self) -> Result<Vec<u8>> {
+        self.to_packet(&TuringOp::JsonSet)
+    }
+    /// ### Gets the JSON value at `path` inside a field
+    /// #### Usage
+    /// ```rust
+    /// use crate::JsonQuery;
+    ///
+    /// let mut foo = JsonQuery::new();
+    /// foo
+    ///   .db("db_name")
+    ///   .document("document_name")
+    ///   .field("field_name")
+    ///   .path("user.name")
+    ///   .get()
+    /// ```
+    pub fn get(&self) -> Result<Vec<u8>> {
+        self.to_packet(&TuringOp::JsonGet)
+    }
+    /// ### Replaces the value at `path` inside a JSON field with the payload
+    /// #### Usage
+    /// ```rust
+    /// use crate::JsonQuery;
+    ///
+    /// let mut foo = JsonQuery::new();
+    /// foo
+    ///   .db("db_name")
+    ///   .document("document_name")
+    ///   .field("field_name")
+    ///   .path("user.phones[0]")
+    ///   .payload(r#""+254700000000""#)
+    ///   .modify()
+    /// ```
+    pub fn modify(&self) -> Result<Vec<u8>> {
+        self.to_packet(&TuringOp::JsonModify)
+    }
+    /// ### Removes the value at `path` inside a JSON field
+    /// #### Usage
+    /// ```rust
+    /// use crate::JsonQuery;
+    ///
+    /// let mut foo = JsonQuery::new();
+    /// foo
+    ///   .db("db_name")
+    ///   .document("document_name")
+    ///   .field("field_name")
+    ///   .path("user.phones[0]")
+    ///   .remove()
+    /// ```
+    pub fn remove(&self) -> Result<Vec<u8>> {
+        self.to_packet(&TuringOp::JsonRemove)
+    }
+
+    fn to_packet(&self, op: &TuringOp) -> Result<Vec<u8>> {
+        let mut packet = from_op(op).to_vec();
+
+        let data = bincode::serialize::<Self>(self)?;
+        packet.extend_from_slice(&data);
+
+        Ok(packet)
+    }
+}
@@ -28,6 +28,27 @@ pub use document::*;
 mod field;
 /// Handles field queries
 pub use field::*;
+mod json;
+/// Handles JSON field queries
+pub use json::*;
+mod changefeed;
+/// Handles changefeed subscriptions
+pub use changefeed::*;
+mod replication;
+/// Handles replication between servers
+pub use replication::*;
+mod bulk;
+/// Handles bulk inserts
+pub use bulk::*;
+mod batch;
+/// Handles batches of field operations
+pub use batch::*;
+mod index;
+/// Handles secondary index queries
+pub use index::*;
+mod cluster;
+/// Handles sharding databases across the nodes of a cluster
+pub use cluster::*;
 mod commands;
 /// Handles commands queries
 pub use commands::*;
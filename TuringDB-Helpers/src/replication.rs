@@ -0,0 +1,172 @@
+use crate::commands::{from_op, TuringOp};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tai64::TAI64N;
+
+/// How far a database of a replica is behind its leader.
+/// `position` is the sequence of the next change the replica applies
+/// and `leader_next` the sequence of the next change on the leader when it was last contacted
+/// ```rust
+/// #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct ReplicaLag {
+///     pub position: u64,
+///     pub leader_next: u64,
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplicaLag {
+    /// The sequence of the next change the replica applies
+    pub position: u64,
+    /// The sequence of the next change on the leader
+    pub leader_next: u64,
+}
+
+impl ReplicaLag {
+    /// The number of changes the replica has not applied yet
+    pub fn changes_behind(&self) -> u64 {
+        self.leader_next.saturating_sub(self.position)
+    }
+}
+
+/// ### The state of replication on a server
+/// Arrives as a `DbOps::FieldContents` holding the status serialized with bincode.
+/// `replica` is `false` and `databases` is empty on a server that is not a replica
+/// ```rust
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct ReplicationStatus {
+///     pub replica: bool,
+///     pub last_contact: Option<TAI64N>,
+///     pub databases: BTreeMap<String, ReplicaLag>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplicationStatus {
+    /// Whether the server is a read-only replica
+    pub replica: bool,
+    /// The last time the replica received changes from its leader
+    pub last_contact: Option<TAI64N>,
+    /// How far every database of the replica is behind the leader
+    pub databases: BTreeMap<String, ReplicaLag>,
+}
+
+/// ### Handles the queries a replica sends to its leader
+/// ```rust
+/// #[derive(Debug, Serialize, Clone, Default)]
+/// pub struct ReplicationQuery {
+///     db: String,
+///     positions: BTreeMap<String, u64>,
+///     max: usize,
+/// }
+/// ```
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct ReplicationQuery {
+    db: String,
+    positions: BTreeMap<String, u64>,
+    max: usize,
+}
+
+impl ReplicationQuery {
+    /// ### Initialize a new empty query
+    /// #### Usage
+    /// ```rust
+    /// use crate::ReplicationQuery;
+    ///
+    /// ReplicationQuery::new()
+    /// ```
+    pub fn new() -> Self {
+        Self {
+            db: Default::default(),
+            positions: Default::default(),
+            max: Default::default(),
+        }
+    }
+    /// ### Add the name of the database to copy
+    /// #### Usage
+    /// ```rust
+    /// use crate::ReplicationQuery;
+    ///
+    /// let mut foo = ReplicationQuery::new();
+    /// foo.db("db_name");
+    /// ```
+    pub fn db(&mut self, name: &str) -> &mut Self {
+        self.db = name.into();
+
+        self
+    }
+    /// ### Add the position of every database of the replica
+    /// Databases without a position are bootstrapped from a snapshot
+    /// #### Usage
+    /// ```rust
+    /// use crate::ReplicationQuery;
+    ///
+    /// let mut foo = ReplicationQuery::new();
+    /// foo.positions(positions);
+    /// ```
+    pub fn positions(&mut self, positions: BTreeMap<String, u64>) -> &mut Self {
+        self.positions = positions;
+
+        self
+    }
+    /// ### Limit the number of changes read from every database
+    /// #### Usage
+    /// ```rust
+    /// use crate::ReplicationQuery;
+    ///
+    /// let mut foo = ReplicationQuery::new();
+    /// foo
+    ///   .positions(positions)
+    ///   .max(1000);
+    /// ```
+    pub fn max(&mut self, max: usize) -> &mut Self {
+        self.max = max;
+
+        self
+    }
+    /// ### Copy a database to bootstrap a replica
+    /// #### Usage
+    /// ```rust
+    /// use crate::ReplicationQuery;
+    ///
+    /// let mut foo = ReplicationQuery::new();
+    /// foo
+    ///   .db("db_name")
+    ///   .snapshot()
+    /// ```
+    pub fn snapshot(&self) -> Result<Vec<u8>> {
+        self.to_packet(&TuringOp::ReplicationSnapshot)
+    }
+    /// ### Read the changes of every database from the positions of the replica
+    /// #### Usage
+    /// ```rust
+    /// use crate::ReplicationQuery;
+    ///
+    /// let mut foo = ReplicationQuery::new();
+    /// foo
+    ///   .positions(positions)
+    ///   .max(1000)
+    ///   .poll()
+    /// ```
+    pub fn poll(&self) -> Result<Vec<u8>> {
+        self.to_packet(&TuringOp::ReplicationPoll)
+    }
+    /// ### Report how far the databases of a replica are behind its leader
+    /// #### Usage
+    /// ```rust
+    /// use crate::ReplicationQuery;
+    ///
+    /// ReplicationQuery::new().status()
+    /// ```
+    pub fn status(&self) -> Result<Vec<u8>> {
+        self.to_packet(&TuringOp::ReplicationStatus)
+    }
+
+    fn to_packet(&self, op: &TuringOp) -> Result<Vec<u8>> {
+        let mut packet = from_op(op).to_vec();
+
+        let data = bincode::serialize::<Self>(self)?;
+        packet.extend_from_slice(&data);
+
+        Ok(packet)
+    }
+}
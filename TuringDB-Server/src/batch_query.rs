@@ -0,0 +1,69 @@
+use crate::errors::{format_engine_error, format_error};
+use async_dup::Arc;
+use camino::Utf8Path;
+use custom_codes::DbOps;
+use serde::{Deserialize, Serialize};
+use turingdb::{BatchOperation, BatchResult, OpsOutcome, TuringEngine};
+use turingdb_helpers::TuringOp;
+
+/// Handles batches of field operations
+/// ```rust
+/// #[derive(Debug, Serialize, Deserialize)]
+/// pub(crate) struct BatchQuery {
+///     db: String,
+///     atomic: bool,
+///     operations: Vec<BatchOperation>,
+/// }
+/// ```
+///
+/// #### Usage
+/// ```rust
+/// let mut engine = TuringEngine::new().await?;
+/// engine.repo_init().await?;
+/// let storage = Arc::new(engine);
+///
+/// // The bytes of a `turingdb_helpers::BatchQuery` following its `TuringOp` header
+/// let outcome = BatchQuery::run(Arc::clone(&storage), &packet[1..]).await;
+/// ```
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct BatchQuery {
+    db: String,
+    atomic: bool,
+    operations: Vec<BatchOperation>,
+}
+
+impl BatchQuery {
+    /// ### Apply the operations of a batch in order
+    ///
+    /// This function also takes an array of bytes `&[u8]` as a parameter;
+    /// This array of bytes must be able to deserialize into a `crate::BatchQuery` struct  using bincode
+    ///
+    /// Responds with a `DbOps::FieldContents` holding the `Vec<BatchResult>` of the operations serialized with bincode.
+    /// An atomic batch writes nothing if one of its operations fails and responds with the error of that operation
+    pub async fn run(storage: Arc<TuringEngine>, value: &[u8]) -> DbOps {
+        if value.is_empty() {
+            return DbOps::EncounteredErrors(
+                "[TuringDB::<Batch>::(ERROR)-GOOD_HEADER_NO_DATA]".to_owned(),
+            );
+        }
+
+        let query = match bincode::deserialize::<BatchQuery>(value) {
+            Ok(query) => query,
+            Err(e) => return format_error(&TuringOp::Batch, &anyhow::Error::new(e)),
+        };
+
+        match storage
+            .batch(Utf8Path::new(&query.db), &query.operations, query.atomic)
+            .await
+        {
+            Ok(OpsOutcome::BatchApplied(results)) => {
+                match bincode::serialize::<Vec<BatchResult>>(&results) {
+                    Ok(results) => DbOps::FieldContents(results),
+                    Err(e) => format_error(&TuringOp::Batch, &anyhow::Error::new(e)),
+                }
+            }
+            Ok(_) => DbOps::NotExecuted,
+            Err(e) => format_engine_error(&TuringOp::Batch, &e),
+        }
+    }
+}
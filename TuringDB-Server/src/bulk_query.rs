@@ -0,0 +1,164 @@
+use crate::{
+    errors::{format_engine_error, format_error},
+    handle_response, BUFFER_CAPACITY, BUFFER_DATA_CAPACITY,
+};
+use anyhow::{bail, Result};
+use async_dup::Arc;
+use async_net::TcpStream;
+use camino::Utf8Path;
+use custom_codes::DbOps;
+use futures_lite::AsyncReadExt;
+use serde::{Deserialize, Serialize};
+use turingdb::{BulkOptions, BulkProgress, BulkRecord, OpsOutcome, TuringEngine};
+use turingdb_helpers::TuringOp;
+
+/// Handles bulk inserts
+/// ```rust
+/// #[derive(Debug, Serialize, Deserialize)]
+/// pub(crate) struct BulkInsertQuery {
+///     db: String,
+///     check_existing: bool,
+///     batch_size: Option<usize>,
+/// }
+/// ```
+///
+/// #### Usage
+/// ```rust
+/// let mut engine = TuringEngine::new().await?;
+/// engine.repo_init().await?;
+/// let storage = Arc::new(engine);
+///
+/// // The bytes of a `turingdb_helpers::BulkInsertQuery` following its `TuringOp` header,
+/// // the chunks of records are then read from the `stream`
+/// BulkInsertQuery::load(&mut stream, Arc::clone(&storage), &packet[1..]).await?;
+/// ```
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct BulkInsertQuery {
+    db: String,
+    check_existing: bool,
+    batch_size: Option<usize>,
+}
+
+impl BulkInsertQuery {
+    /// ### Write the chunks of records streamed by the client until it sends an empty chunk
+    ///
+    /// This function also takes an array of bytes `&[u8]` as a parameter;
+    /// This array of bytes must be able to deserialize into a `crate::BulkInsertQuery` struct  using bincode
+    ///
+    /// Every chunk is a `Vec<BulkRecord>` serialized with bincode and is answered with a `DbOps::FieldContents`
+    /// holding the `BulkProgress` of the whole load serialized with bincode.
+    /// The database is only flushed once the load ends, either with an empty chunk or the client disconnecting
+    pub async fn load(
+        stream: &mut TcpStream,
+        storage: Arc<TuringEngine>,
+        value: &[u8],
+    ) -> Result<()> {
+        if value.is_empty() {
+            return handle_response(
+                stream,
+                DbOps::EncounteredErrors(
+                    "[TuringDB::<BulkInsert>::(ERROR)-GOOD_HEADER_NO_DATA]".to_owned(),
+                ),
+            )
+            .await;
+        }
+
+        let query = match bincode::deserialize::<BulkInsertQuery>(value) {
+            Ok(query) => query,
+            Err(e) => {
+                return handle_response(
+                    stream,
+                    format_error(&TuringOp::BulkInsert, &anyhow::Error::new(e)),
+                )
+                .await
+            }
+        };
+
+        let db = Utf8Path::new(&query.db);
+        let mut options = BulkOptions::new().check_existing(query.check_existing);
+        if let Some(batch_size) = query.batch_size {
+            options = options.batch_size(batch_size);
+        }
+
+        let mut progress = BulkProgress::default();
+        handle_response(stream, BulkInsertQuery::progress(&progress)?).await?;
+
+        loop {
+            let records = match BulkInsertQuery::read_chunk(stream).await {
+                Ok(Some(records)) if !records.is_empty() => records,
+                // The load ends with an empty chunk, a chunk that is too large or the client disconnecting
+                chunk => {
+                    let flushed = storage.db_flush(db).await;
+
+                    return match (chunk, flushed) {
+                        (Ok(None), _) => Ok(()),
+                        // The rest of an invalid chunk cannot be told apart from the next query
+                        // so the connection is closed
+                        (Err(e), _) => {
+                            let error = format!("[TuringDB::<BulkInsert>::(ERROR)-{}]", e);
+                            handle_response(stream, DbOps::EncounteredErrors(error)).await?;
+
+                            Err(e)
+                        }
+                        (Ok(Some(_)), Ok(_)) => {
+                            handle_response(stream, BulkInsertQuery::progress(&progress)?).await
+                        }
+                        (Ok(Some(_)), Err(e)) => {
+                            handle_response(stream, format_engine_error(&TuringOp::BulkInsert, &e))
+                                .await
+                        }
+                    };
+                }
+            };
+
+            match storage.bulk_insert(db, &records, &options).await {
+                Ok(OpsOutcome::BulkInserted(chunk)) => progress.extend(chunk),
+                Ok(_) => (),
+                Err(e) => {
+                    // Keep what was written so far before giving up on the load
+                    let _ = storage.db_flush(db).await;
+
+                    return handle_response(stream, format_engine_error(&TuringOp::BulkInsert, &e))
+                        .await;
+                }
+            }
+
+            handle_response(stream, BulkInsertQuery::progress(&progress)?).await?;
+        }
+    }
+
+    fn progress(progress: &BulkProgress) -> Result<DbOps> {
+        Ok(DbOps::FieldContents(bincode::serialize::<BulkProgress>(
+            progress,
+        )?))
+    }
+
+    /// Read the next chunk of records, `None` if the client closed the connection
+    async fn read_chunk(stream: &mut TcpStream) -> Result<Option<Vec<BulkRecord>>> {
+        let mut buffer = [0; BUFFER_CAPACITY];
+        let mut container_buffer: Vec<u8> = Vec::new();
+
+        loop {
+            let bytes_read = stream.read(&mut buffer).await?;
+
+            if bytes_read == 0 {
+                return Ok(None);
+            }
+
+            container_buffer.extend_from_slice(&buffer[..bytes_read]);
+
+            if container_buffer.len() > BUFFER_DATA_CAPACITY {
+                bail!("BUFFER_CAPACITY_EXCEEDED_16MB");
+            }
+
+            // A read shorter than the buffer is likely the end of the chunk
+            if bytes_read < BUFFER_CAPACITY {
+                match bincode::deserialize::<Vec<BulkRecord>>(&container_buffer) {
+                    Ok(records) => return Ok(Some(records)),
+                    // The rest of the chunk has not arrived yet
+                    Err(_) => continue,
+                }
+            }
+        }
+    }
+}
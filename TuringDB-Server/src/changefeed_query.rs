@@ -0,0 +1,121 @@
+use crate::{
+    errors::{format_engine_error, format_error},
+    handle_response,
+};
+use anyhow::Result;
+use async_dup::Arc;
+use async_net::TcpStream;
+use camino::Utf8Path;
+use custom_codes::DbOps;
+use futures_lite::{AsyncReadExt, FutureExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use turingdb::{ChangeEvent, TuringEngine};
+use turingdb_helpers::TuringOp;
+
+/// Handles changefeed subscriptions
+/// ```rust
+/// #[derive(Debug, Serialize, Deserialize)]
+/// pub(crate) struct SubscribeQuery {
+///     db: String,
+///     document: String,
+///     prefix: Vec<u8>,
+///     position: Option<u64>,
+/// }
+/// ```
+///
+/// #### Usage
+/// ```rust
+/// let mut engine = TuringEngine::new().await?;
+/// engine.repo_init().await?;
+/// let storage = Arc::new(engine);
+///
+/// // The bytes of a `turingdb_helpers::SubscribeQuery` following its `TuringOp` header
+/// SubscribeQuery::subscribe(&mut stream, Arc::clone(&storage), &packet[1..]).await?;
+/// ```
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct SubscribeQuery {
+    db: String,
+    document: String,
+    prefix: Vec<u8>,
+    position: Option<u64>,
+}
+
+impl SubscribeQuery {
+    /// ### Push the changes to the fields of a document to the client as they happen
+    ///
+    /// This function also takes an array of bytes `&[u8]` as a parameter;
+    /// This array of bytes must be able to deserialize into a `crate::SubscribeQuery` struct  using bincode
+    ///
+    /// Every change is sent as a `DbOps::FieldContents` holding the `ChangeEvent` serialized with bincode.
+    /// The subscription runs until the client sends any data or closes the connection
+    pub async fn subscribe(
+        stream: &mut TcpStream,
+        storage: Arc<TuringEngine>,
+        value: &[u8],
+    ) -> Result<()> {
+        if value.is_empty() {
+            return handle_response(
+                stream,
+                DbOps::EncounteredErrors(
+                    "[TuringDB::<Subscribe>::(ERROR)-GOOD_HEADER_NO_DATA]".to_owned(),
+                ),
+            )
+            .await;
+        }
+
+        let query = match bincode::deserialize::<SubscribeQuery>(value) {
+            Ok(query) => query,
+            Err(e) => {
+                return handle_response(
+                    stream,
+                    format_error(&TuringOp::Subscribe, &anyhow::Error::new(e)),
+                )
+                .await
+            }
+        };
+
+        let db = Utf8Path::new(&query.db);
+        let document = Utf8Path::new(&query.document);
+
+        let feed = match query.position {
+            Some(position) => {
+                storage
+                    .watch_since(db, document, &query.prefix, position)
+                    .await
+            }
+            None => storage.watch(db, document, &query.prefix),
+        };
+
+        let mut feed = match feed {
+            Ok(feed) => feed,
+            Err(e) => {
+                return handle_response(stream, format_engine_error(&TuringOp::Subscribe, &e)).await
+            }
+        };
+
+        loop {
+            let mut unsubscribe = [0u8; 1];
+            let mut reader = stream.clone();
+
+            let next = async { Some(feed.next().await) }
+                .or(async {
+                    // Any data sent by the client or the client disconnecting ends the subscription
+                    let _ = reader.read(&mut unsubscribe).await;
+                    None
+                })
+                .await;
+
+            let event = match next {
+                None | Some(None) => return Ok(()),
+                Some(Some(Ok(event))) => event,
+                Some(Some(Err(e))) => {
+                    return handle_response(stream, format_engine_error(&TuringOp::Subscribe, &e))
+                        .await
+                }
+            };
+
+            let event = bincode::serialize::<ChangeEvent>(&event)?;
+            handle_response(stream, DbOps::FieldContents(event)).await?;
+        }
+    }
+}
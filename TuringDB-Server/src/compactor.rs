@@ -0,0 +1,21 @@
+use async_dup::Arc;
+use smol::Timer;
+use std::time::Duration;
+use turingdb::TuringEngine;
+
+/// Drop the previous versions of fields that the retention of their document no longer keeps,
+/// once every `interval` for as long as the server runs
+pub(crate) async fn compact_history(storage: Arc<TuringEngine>, interval: Duration) {
+    loop {
+        Timer::new(interval).await;
+
+        if let Err(e) = storage.history_compact().await {
+            eprintln!("[TuringDB::<HistoryCompaction>::(ERROR)-{:?}]", e); //FIXME log!()
+        }
+    }
+}
+
+/// Remove the fields that outlived their time-to-live once every `interval` for as long as the server runs
+pub(crate) async fn sweep_expired(storage: Arc<TuringEngine>, interval: Duration) {
+    storage.expiry_sweeper(interval).await
+}
@@ -0,0 +1,339 @@
+use crate::{errors::format_error, process_op};
+use anyhow::{anyhow, bail, Result};
+use async_dup::Arc;
+use async_net::TcpStream;
+use camino::Utf8Path;
+use custom_codes::DbOps;
+use futures_lite::{future, AsyncReadExt, AsyncWriteExt};
+use smol::{Task, Timer};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::Duration,
+};
+use turingdb::TuringEngine;
+use turingdb_helpers::{to_op, ClusterListing, ClusterMap, DbQuery, DocumentQuery, TuringOp};
+
+/// How long the coordinator waits for a node before reporting it as missing
+const NODE_TIMEOUT: Duration = Duration::from_secs(5);
+const RESPONSE_BUFFER_CAPACITY: usize = 64 * 1024;
+
+/// ### Runs a listing on every node of a cluster and merges the results
+/// The node listening on the address of the server is queried directly and every other node
+/// over a new connection. A node that fails or does not respond within `timeout`, which is `NODE_TIMEOUT`,
+/// is reported in `ClusterListing::missing` instead of failing the whole query
+/// ```rust
+/// #[derive(Debug)]
+/// pub(crate) struct Coordinator {
+///     local: String,
+///     nodes: BTreeMap<String, String>,
+///     timeout: Duration,
+/// }
+/// ```
+#[derive(Debug)]
+pub(crate) struct Coordinator {
+    local: String,
+    nodes: BTreeMap<String, String>,
+    timeout: Duration,
+}
+
+impl Coordinator {
+    /// ### Coordinate the nodes of a cluster map for the server listening on `listen`
+    /// Without a map the server is the only node of its cluster
+    pub fn new(listen: &str, map: Option<ClusterMap>) -> Self {
+        let mut nodes = match map {
+            None => BTreeMap::new(),
+            Some(map) => map.nodes().clone(),
+        };
+
+        let local = match nodes.iter().find(|(_, address)| address.as_str() == listen) {
+            Some((node, _)) => node.clone(),
+            None => {
+                nodes.insert(listen.to_owned(), listen.to_owned());
+                listen.to_owned()
+            }
+        };
+
+        Self {
+            local,
+            nodes,
+            timeout: NODE_TIMEOUT,
+        }
+    }
+    /// ### Read a cluster map from a file
+    /// Every line holds the name and the address of a node separated by whitespace.
+    /// Empty lines and lines starting with `#` are skipped
+    /// ```text
+    /// # name   address
+    /// node-1   127.0.0.1:4343
+    /// node-2   127.0.0.1:4344
+    /// ```
+    pub fn load(path: &Utf8Path) -> Result<ClusterMap> {
+        let contents = std::fs::read_to_string(path)?;
+        let mut map = ClusterMap::new();
+
+        for (line_number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut parts = line.split_whitespace();
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(node), Some(address), None) => {
+                    map.add_node(node, address);
+                }
+                _ => bail!("INVALID_CLUSTER_MAP_LINE_{}", line_number + 1),
+            }
+        }
+
+        Ok(map)
+    }
+    /// ### Run a `ClusterDbList` or `ClusterDocumentList` query on every node
+    ///
+    /// This function also takes an array of bytes `&[u8]` as a parameter;
+    /// For a `ClusterDocumentList` this array of bytes must be able to deserialize into
+    /// the name of a database using bincode
+    ///
+    /// The result is sent as a `DbOps::FieldContents` holding a `ClusterListing` serialized with bincode
+    ///
+    /// #### Usage
+    /// ```rust
+    /// let coordinator = Coordinator::new("127.0.0.1:4343", Some(map));
+    /// let listing = coordinator
+    ///     .gather(&TuringOp::ClusterDbList, Arc::clone(&storage), &[])
+    ///     .await;
+    /// ```
+    pub async fn gather(&self, op: &TuringOp, storage: Arc<TuringEngine>, value: &[u8]) -> DbOps {
+        let packet = match op {
+            TuringOp::ClusterDbList => DbQuery::new().list().to_vec(),
+            TuringOp::ClusterDocumentList => {
+                let db = match bincode::deserialize::<String>(value) {
+                    Ok(db) => db,
+                    Err(e) => return format_error(op, &anyhow::Error::new(e)),
+                };
+
+                match DocumentQuery::new().db(&db).list() {
+                    Ok(packet) => packet,
+                    Err(e) => return format_error(op, &e),
+                }
+            }
+            _ => return DbOps::NotExecuted,
+        };
+
+        let tasks = self
+            .nodes
+            .iter()
+            .filter(|(node, _)| **node != self.local)
+            .map(|(node, address)| {
+                let address = address.clone();
+                let packet = packet.clone();
+                let timeout = self.timeout;
+                let response = Task::spawn(async move {
+                    future::race(request(&address, &packet), async {
+                        Timer::new(timeout).await;
+                        Err(anyhow!("TIMED_OUT"))
+                    })
+                    .await
+                });
+
+                (node.clone(), response)
+            })
+            .collect::<Vec<_>>();
+
+        let mut responses = BTreeMap::new();
+        let local_op = to_op(&[packet[0]]);
+        let local_response = process_op(&local_op, storage, &packet[1..]).await;
+        responses.insert(self.local.clone(), Ok(local_response));
+
+        for (node, response) in tasks {
+            responses.insert(node, response.await);
+        }
+
+        Coordinator::merge(op, responses)
+    }
+
+    fn merge(op: &TuringOp, responses: BTreeMap<String, Result<DbOps>>) -> DbOps {
+        let nodes = responses.len();
+        let mut found = false;
+        let mut items = BTreeSet::new();
+        let mut missing = BTreeMap::new();
+
+        for (node, response) in responses {
+            match response {
+                Ok(DbOps::DbList(list)) | Ok(DbOps::DocumentList(list)) => {
+                    found = true;
+                    items.extend(list);
+                }
+                Ok(DbOps::RepoEmpty) | Ok(DbOps::DbEmpty) | Ok(DbOps::DocumentEmpty) => {
+                    found = true;
+                }
+                // The database is on another node
+                Ok(DbOps::DbNotFound) => (),
+                Ok(op_result) => {
+                    missing.insert(node, format!("{:?}", op_result));
+                }
+                Err(e) => {
+                    missing.insert(node, format!("{:#}", e));
+                }
+            }
+        }
+
+        if missing.len() == nodes {
+            return DbOps::EncounteredErrors(format!(
+                "[TuringDB::<{:?}>::(ERROR)-NO_NODE_RESPONDED-{:?}]",
+                op, missing
+            ));
+        }
+
+        if !found && missing.is_empty() && op == &TuringOp::ClusterDocumentList {
+            return DbOps::DbNotFound;
+        }
+
+        // A `BTreeSet` keeps the order of `db_list_sorted()` and `document_list_sorted()`
+        let listing = ClusterListing {
+            items: items.into_iter().collect(),
+            missing,
+        };
+
+        match bincode::serialize::<ClusterListing>(&listing) {
+            Ok(contents) => DbOps::FieldContents(contents),
+            Err(e) => format_error(op, &anyhow::Error::new(e)),
+        }
+    }
+}
+
+/// Send a query to a node over a new connection and return its response
+async fn request(address: &str, packet: &[u8]) -> Result<DbOps> {
+    let mut stream = TcpStream::connect(address).await?;
+    stream.write_all(packet).await?;
+    stream.flush().await?;
+
+    let mut buffer = [0; RESPONSE_BUFFER_CAPACITY];
+    let mut container_buffer: Vec<u8> = Vec::new();
+
+    loop {
+        let bytes_read = stream.read(&mut buffer).await?;
+
+        if bytes_read == 0 {
+            bail!("The node closed the connection");
+        }
+
+        container_buffer.extend_from_slice(&buffer[..bytes_read]);
+
+        // The response is complete once it deserializes
+        if let Ok(op_result) = bincode::deserialize::<DbOps>(&container_buffer) {
+            return Ok(op_result);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_net::TcpListener;
+    use camino::Utf8PathBuf;
+    use turingdb::TuringDBOps;
+
+    fn listing(result: DbOps) -> ClusterListing {
+        match result {
+            DbOps::FieldContents(contents) => bincode::deserialize(&contents).unwrap(),
+            result => panic!("Unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn the_listings_of_every_node_are_merged_in_order() {
+        let mut responses = BTreeMap::new();
+        responses.insert(
+            "node-1".to_owned(),
+            Ok(DbOps::DbList(vec!["zeta".to_owned(), "alpha".to_owned()])),
+        );
+        responses.insert(
+            "node-2".to_owned(),
+            Ok(DbOps::DbList(vec!["beta".to_owned(), "alpha".to_owned()])),
+        );
+        responses.insert("node-3".to_owned(), Ok(DbOps::RepoEmpty));
+
+        let listing = listing(Coordinator::merge(&TuringOp::ClusterDbList, responses));
+
+        assert_eq!(listing.items, vec!["alpha", "beta", "zeta"]);
+        assert!(listing.is_complete());
+    }
+
+    #[test]
+    fn a_database_no_node_holds_is_not_found() {
+        let mut responses = BTreeMap::new();
+        responses.insert("node-1".to_owned(), Ok(DbOps::DbNotFound));
+        responses.insert("node-2".to_owned(), Ok(DbOps::DbNotFound));
+
+        assert_eq!(
+            Coordinator::merge(&TuringOp::ClusterDocumentList, responses),
+            DbOps::DbNotFound
+        );
+    }
+
+    #[test]
+    fn a_listing_fails_when_no_node_responds() {
+        let mut responses = BTreeMap::new();
+        responses.insert("node-1".to_owned(), Err(anyhow!("TIMED_OUT")));
+        responses.insert("node-2".to_owned(), Ok(DbOps::EncounteredErrors("".into())));
+
+        match Coordinator::merge(&TuringOp::ClusterDbList, responses) {
+            DbOps::EncounteredErrors(error) => assert!(error.contains("NO_NODE_RESPONDED")),
+            result => panic!("Unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn the_nodes_that_do_not_respond_are_reported_missing() {
+        smol::run(async {
+            let repo_dir = std::env::temp_dir().join(format!(
+                "turingdb-server-coordinator-{}",
+                std::process::id()
+            ));
+            let repo_dir = Utf8PathBuf::from_path_buf(repo_dir).unwrap();
+            let _ = std::fs::remove_dir_all(&repo_dir);
+
+            let mut engine = TuringEngine::builder()
+                .repo_dir(repo_dir.clone())
+                .build()
+                .await
+                .unwrap();
+            engine.repo_create().await.unwrap();
+            engine.repo_init().await.unwrap();
+            engine
+                .db_create(TuringDBOps::default().set_db_name("local"))
+                .await
+                .unwrap();
+
+            // Accepts connections without ever answering them
+            let silent = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            // Refuses connections once it is dropped
+            let closed = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let closed_address = closed.local_addr().unwrap().to_string();
+            drop(closed);
+
+            let mut map = ClusterMap::new();
+            map.add_node("node-1", "127.0.0.1:4343")
+                .add_node("node-2", &silent.local_addr().unwrap().to_string())
+                .add_node("node-3", &closed_address);
+
+            let mut coordinator = Coordinator::new("127.0.0.1:4343", Some(map));
+            coordinator.timeout = Duration::from_millis(200);
+
+            let result = coordinator
+                .gather(&TuringOp::ClusterDbList, Arc::new(engine), &[])
+                .await;
+            let listing = listing(result);
+
+            assert_eq!(listing.items, vec!["local"]);
+            assert_eq!(
+                listing.missing.keys().collect::<Vec<&String>>(),
+                vec!["node-2", "node-3"]
+            );
+            assert_eq!(listing.missing["node-2"], "TIMED_OUT");
+
+            let _ = std::fs::remove_dir_all(&repo_dir);
+        })
+    }
+}
@@ -1,53 +1,40 @@
-use crate::errors::format_error;
+use crate::errors::{format_engine_error, format_error};
 use async_dup::Arc;
-use custom_codes::{DbOps, DownCastErrors};
-use std::path::Path;
-use turingdb::TuringEngine;
+use custom_codes::DbOps;
+use turingdb::{OpsOutcome, TuringDBOps, TuringDbError, TuringEngine};
 use turingdb_helpers::TuringOp;
 /// Handles database queries
 /// ```rust
 /// pub(crate) struct DbQuery;
 /// ```
+///
+/// #### Usage
+/// ```rust
+/// let mut engine = TuringEngine::new().await?;
+/// engine.repo_init().await?;
+/// let storage = Arc::new(engine);
+///
+/// let outcome = DbQuery::create(Arc::clone(&storage), b"db_name").await;
+/// let databases = DbQuery::list(Arc::clone(&storage)).await;
+/// ```
 pub(crate) struct DbQuery;
 
 impl DbQuery {
     /// ### Gets a list of all databases in a repo
-    /// #### Usage
-    /// ```rust
-    /// use crate::DatabaseQuery;
-    /// use turingdb::TuringEngine;
-    ///
-    /// let foo = TuringEngine::new();
-    /// foo.repo_init().await;
-    /// // Start an async runtime
-    ///     |- let foo = Arc::new(&foo); // This `Arc` must be from a module supporting async
-    ///     |-  // spawn a task
-    ///             |- let foo = Arc::clone(&foo);
-    ///             |- DatabaseQuery::list(&foo);
-    /// ```
     pub async fn list(storage: Arc<TuringEngine>) -> DbOps {
-        storage.db_list().await
+        match storage.db_list_sorted() {
+            OpsOutcome::DbList(list) => {
+                DbOps::DbList(list.into_iter().map(|db| db.into_string()).collect())
+            }
+            _ => DbOps::RepoEmpty,
+        }
     }
     /// ### Create a database in a repo
     ///
     /// This function also takes an array of bytes `&[u8]` as a parameter;
     /// This array of bytes must be able to deserialize into a database name `&str` using `std::str::from_utf8(value)`
-    ///
-    /// #### Usage
-    /// ```rust
-    /// use crate::DatabaseQuery;
-    /// use turingdb::TuringEngine;
-    ///
-    /// let foo = TuringEngine::new();
-    /// foo.repo_init().await;
-    /// // Start an async runtime
-    ///     |- let foo = Arc::new(&foo); // This `Arc` must be from a module supporting async
-    ///     |-  // spawn a task
-    ///             |- let foo = Arc::clone(&foo);
-    ///             |- DatabaseQuery::create(&foo, &[data_to_deserialize]).await;
-    /// ```
     pub async fn create(storage: Arc<TuringEngine>, value: &[u8]) -> DbOps {
-        if value.is_empty() == true {
+        if value.is_empty() {
             return DbOps::EncounteredErrors(
                 "[TuringDB::<DbCreate>::(ERROR)-MISSING_DB_NAME]".to_owned(),
             );
@@ -58,36 +45,22 @@ impl DbQuery {
             Err(e) => return format_error(&TuringOp::DbCreate, &anyhow::Error::new(e)),
         };
 
-        match storage.db_create(&Path::new(db_name)).await {
-            Ok(op_result) => op_result,
-            Err(e) => match custom_codes::try_downcast(&e) {
-                DownCastErrors::AlreadyExists => DbOps::DbAlreadyExists,
-                DownCastErrors::NotFound => DbOps::RepoNotFound,
-                DownCastErrors::PermissionDenied => DbOps::PermissionDenied,
-                _ => format_error(&TuringOp::DbCreate, &e),
-            },
+        let ops = TuringDBOps::default().set_db_name(db_name);
+
+        match storage.db_create(ops).await {
+            Ok(_) => DbOps::DbCreated,
+            Err(TuringDbError::AlreadyExists) => DbOps::DbAlreadyExists,
+            Err(TuringDbError::NotFound) => DbOps::RepoNotFound,
+            Err(TuringDbError::PermissionDenied) => DbOps::PermissionDenied,
+            Err(e) => format_engine_error(&TuringOp::DbCreate, &e),
         }
     }
     /// ### Drop a database in a repo
     ///
     /// This function also takes an array of bytes `&[u8]` as a parameter;
     /// This array of bytes must be able to deserialize into a database name `&str` using `std::str::from_utf8(value)`
-    ///
-    /// #### Usage
-    /// ```rust
-    /// use crate::DatabaseQuery;
-    /// use turingdb::TuringEngine;
-    ///
-    /// let foo = TuringEngine::new();
-    /// foo.repo_init().await;
-    /// // Start an async runtime
-    ///     |- let foo = Arc::new(&foo); // This `Arc` must be from a module supporting async
-    ///     |-  // spawn a task
-    ///             |- let foo = Arc::clone(&foo);
-    ///             |- DatabaseQuery::create(&foo, &[data_to_deserialize]).await;
-    /// ```
     pub async fn drop(storage: Arc<TuringEngine>, value: &[u8]) -> DbOps {
-        if value.is_empty() == true {
+        if value.is_empty() {
             return DbOps::EncounteredErrors(
                 "[TuringDB::<DbDrop>::(ERROR)-MISSING_DB_NAME]".to_owned(),
             );
@@ -98,13 +71,13 @@ impl DbQuery {
             Err(e) => return format_error(&TuringOp::DbDrop, &anyhow::Error::new(e)),
         };
 
-        match storage.db_drop(&Path::new(db_name)).await {
-            Ok(op_result) => op_result,
-            Err(e) => match custom_codes::try_downcast(&e) {
-                DownCastErrors::NotFound => DbOps::RepoNotFound,
-                DownCastErrors::PermissionDenied => DbOps::PermissionDenied,
-                _ => format_error(&TuringOp::DbCreate, &e),
-            },
+        let ops = TuringDBOps::default().set_db_name(db_name);
+
+        match storage.db_drop(ops).await {
+            Ok(_) => DbOps::DbDropped,
+            Err(TuringDbError::NotFound) => DbOps::DbNotFound,
+            Err(TuringDbError::PermissionDenied) => DbOps::PermissionDenied,
+            Err(e) => format_engine_error(&TuringOp::DbDrop, &e),
         }
     }
 }
@@ -1,12 +1,11 @@
-use crate::errors::format_error;
+use crate::errors::{format_engine_error, format_error};
 use async_dup::Arc;
-use custom_codes::{DbOps, DownCastErrors};
+use custom_codes::DbOps;
 use serde::{Deserialize, Serialize};
-use std::path::Path;
-use turingdb::TuringEngine;
+use turingdb::{OpsOutcome, TuringDBDocumentOps, TuringDBOps, TuringDbError, TuringEngine};
 use turingdb_helpers::TuringOp;
 
-/// Handles database queries
+/// Handles document queries
 /// ```rust
 /// #[derive(Debug, Serialize, Deserialize)]
 /// pub(crate) struct DocumentQuery {
@@ -14,6 +13,16 @@ use turingdb_helpers::TuringOp;
 ///     document: Option<String>,
 /// }
 /// ```
+///
+/// #### Usage
+/// ```rust
+/// let mut engine = TuringEngine::new().await?;
+/// engine.repo_init().await?;
+/// let storage = Arc::new(engine);
+///
+/// // The bytes of a `turingdb_helpers::DocumentQuery` following its `TuringOp` header
+/// let outcome = DocumentQuery::create(Arc::clone(&storage), &packet[1..]).await;
+/// ```
 #[derive(Debug, Serialize, Deserialize)]
 pub(crate) struct DocumentQuery {
     db: String,
@@ -25,22 +34,8 @@ impl DocumentQuery {
     ///
     /// This function also takes an array of bytes `&[u8]` as a parameter;
     /// This array of bytes must be able to deserialize into a `crate::DocumentQuery` struct  using bincode
-    ///
-    /// #### Usage
-    /// ```rust
-    /// use crate::DocumentQuery;
-    /// use turingdb::TuringEngine;
-    ///
-    /// let foo = TuringEngine::new();
-    /// foo.repo_init().await;
-    /// // Start an async runtime
-    ///     |- let foo = Arc::new(&foo); // This `Arc` must be from a module supporting async
-    ///     |-  // spawn a task
-    ///             |- let foo = Arc::clone(&foo);
-    ///             |- DocumentQuery::create(&foo, &[data_to_deserialize]).await;
-    /// ```
     pub async fn create(storage: Arc<TuringEngine>, value: &[u8]) -> DbOps {
-        if value.is_empty() == true {
+        if value.is_empty() {
             return DbOps::EncounteredErrors(
                 "[TuringDB::<DocumentCreate>::(ERROR)-GOOD_HEADER_NO_DATA]".to_owned(),
             );
@@ -60,39 +55,24 @@ impl DocumentQuery {
             }
         };
 
-        match storage
-            .doc_create(&Path::new(&deser_document.db), Path::new(&doc_check))
-            .await
-        {
-            Ok(op_result) => op_result,
-            Err(e) => match custom_codes::try_downcast(&e) {
-                DownCastErrors::AlreadyExists => DbOps::DocumentAlreadyExists,
-                DownCastErrors::NotFound => DbOps::RepoNotFound,
-                DownCastErrors::PermissionDenied => DbOps::PermissionDenied,
-                _ => format_error(&TuringOp::DocumentCreate, &e),
-            },
+        let ops = TuringDBDocumentOps::default()
+            .set_db_name(&deser_document.db)
+            .set_document_name(&doc_check);
+
+        match storage.document_create(&ops).await {
+            Ok(_) => DbOps::DocumentCreated,
+            Err(TuringDbError::AlreadyExists) => DbOps::DocumentAlreadyExists,
+            Err(TuringDbError::DbNotFound) => DbOps::DbNotFound,
+            Err(TuringDbError::PermissionDenied) => DbOps::PermissionDenied,
+            Err(e) => format_engine_error(&TuringOp::DocumentCreate, &e),
         }
     }
     /// ### List all documents in a database
     ///
     /// This function also takes an array of bytes `&[u8]` as a parameter;
     /// This array of bytes must be able to deserialize into a `crate::DocumentQuery` struct  using bincode
-    ///
-    /// #### Usage
-    /// ```rust
-    /// use crate::DocumentQuery;
-    /// use turingdb::TuringEngine;
-    ///
-    /// let foo = TuringEngine::new();
-    /// foo.repo_init().await;
-    /// // Start an async runtime
-    ///     |- let foo = Arc::new(&foo); // This `Arc` must be from a module supporting async
-    ///     |-  // spawn a task
-    ///             |- let foo = Arc::clone(&foo);
-    ///             |- DocumentQuery::list(&foo, &[data_to_deserialize]).await;
-    /// ```
     pub async fn list(storage: Arc<TuringEngine>, value: &[u8]) -> DbOps {
-        if value.is_empty() == true {
+        if value.is_empty() {
             return DbOps::EncounteredErrors(
                 "[TuringDB::<DbList>::(ERROR)-GOOD_HEADER_NO_DATA]".to_owned(),
             );
@@ -103,37 +83,31 @@ impl DocumentQuery {
             Err(e) => return format_error(&TuringOp::DocumentList, &anyhow::Error::new(e)),
         };
 
-        match deser_document.document {
-            Some(_) => {
-                return DbOps::EncounteredErrors(
-                    "[TuringDB::<DocumentList>::(ERROR)-QUERY_ARGS_EXCEEDED]".to_owned(),
-                )
-            }
-            None => (),
-        };
+        if deser_document.document.is_some() {
+            return DbOps::EncounteredErrors(
+                "[TuringDB::<DocumentList>::(ERROR)-QUERY_ARGS_EXCEEDED]".to_owned(),
+            );
+        }
+
+        let ops = TuringDBOps::default().set_db_name(&deser_document.db);
 
-        storage.doc_list(&Path::new(&deser_document.db)).await
+        match storage.document_list_sorted(&ops) {
+            Ok(OpsOutcome::DocumentList(list)) => DbOps::DocumentList(
+                list.into_iter()
+                    .map(|document| document.into_string())
+                    .collect(),
+            ),
+            Ok(_) => DbOps::DbEmpty,
+            Err(TuringDbError::DbNotFound) => DbOps::DbNotFound,
+            Err(e) => format_engine_error(&TuringOp::DocumentList, &e),
+        }
     }
     /// ### Drops a document in a database
     ///
     /// This function also takes an array of bytes `&[u8]` as a parameter;
     /// This array of bytes must be able to deserialize into a `crate::DocumentQuery` struct  using bincode
-    ///
-    /// #### Usage
-    /// ```rust
-    /// use crate::DocumentQuery;
-    /// use turingdb::TuringEngine;
-    ///
-    /// let foo = TuringEngine::new();
-    /// foo.repo_init().await;
-    /// // Start an async runtime
-    ///     |- let foo = Arc::new(&foo); // This `Arc` must be from a module supporting async
-    ///     |-  // spawn a task
-    ///             |- let foo = Arc::clone(&foo);
-    ///             |- DocumentQuery::drop(&foo, &[data_to_deserialize]).await;
-    /// ```
     pub async fn drop(storage: Arc<TuringEngine>, value: &[u8]) -> DbOps {
-        if value.is_empty() == true {
+        if value.is_empty() {
             return DbOps::EncounteredErrors(
                 "[TuringDB::<DbDrop>::(ERROR)-GOOD_HEADER_NO_DATA]".to_owned(),
             );
@@ -153,16 +127,16 @@ impl DocumentQuery {
             }
         };
 
-        match storage
-            .doc_drop(Path::new(&deser_document.db), Path::new(&doc_check))
-            .await
-        {
-            Ok(op_result) => op_result,
-            Err(e) => match custom_codes::try_downcast(&e) {
-                DownCastErrors::NotFound => DbOps::RepoNotFound,
-                DownCastErrors::PermissionDenied => DbOps::PermissionDenied,
-                _ => format_error(&TuringOp::DocumentDrop, &e),
-            },
+        let ops = TuringDBDocumentOps::default()
+            .set_db_name(&deser_document.db)
+            .set_document_name(&doc_check);
+
+        match storage.document_drop(&ops).await {
+            Ok(_) => DbOps::DocumentDropped,
+            Err(TuringDbError::DbNotFound) => DbOps::DbNotFound,
+            Err(TuringDbError::NotFound) => DbOps::DocumentNotFound,
+            Err(TuringDbError::PermissionDenied) => DbOps::PermissionDenied,
+            Err(e) => format_engine_error(&TuringOp::DocumentDrop, &e),
         }
     }
 }
@@ -1,4 +1,5 @@
 use custom_codes::DbOps;
+use turingdb::TuringDbError;
 use turingdb_helpers::TuringOp;
 
 /// Handles converting an error to a common error syntax `[TuringDB::<TuringOp>::(ERROR)-{error}]`
@@ -10,3 +11,8 @@ pub(crate) fn format_error(op: &TuringOp, error: &anyhow::Error) -> DbOps {
     );
     DbOps::EncounteredErrors(unhandled_error)
 }
+
+/// Handles converting an error returned by the `TuringEngine` to the common error syntax `[TuringDB::<TuringOp>::(ERROR)-{error}]`
+pub(crate) fn format_engine_error(op: &TuringOp, error: &TuringDbError) -> DbOps {
+    DbOps::EncounteredErrors(format!("[TuringDB::<{:?}>::(ERROR)-{:?}]", op, error))
+}
@@ -1,12 +1,11 @@
-use crate::errors::format_error;
+use crate::errors::{format_engine_error, format_error};
 use async_dup::Arc;
-use custom_codes::{DbOps, DownCastErrors};
+use custom_codes::DbOps;
 use serde::{Deserialize, Serialize};
-use std::path::Path;
-use turingdb::TuringEngine;
+use turingdb::{OpsOutcome, TuringDBDocumentOps, TuringDBFieldOps, TuringDbError, TuringEngine};
 use turingdb_helpers::TuringOp;
 
-/// Handles database queries
+/// Handles field queries
 /// ```rust
 /// #[derive(Debug, Serialize, Deserialize)]
 /// pub(crate) struct FieldQuery {
@@ -16,6 +15,16 @@ use turingdb_helpers::TuringOp;
 ///     payload: Option<Vec<u8>>,
 /// }
 /// ```
+///
+/// #### Usage
+/// ```rust
+/// let mut engine = TuringEngine::new().await?;
+/// engine.repo_init().await?;
+/// let storage = Arc::new(engine);
+///
+/// // The bytes of a `turingdb_helpers::FieldQuery` following its `TuringOp` header
+/// let outcome = FieldQuery::get(Arc::clone(&storage), &packet[1..]).await;
+/// ```
 #[derive(Debug, Serialize, Deserialize)]
 pub(crate) struct FieldQuery {
     db: String,
@@ -29,301 +38,144 @@ impl FieldQuery {
     ///
     /// This function also takes an array of bytes `&[u8]` as a parameter;
     /// This array of bytes must be able to deserialize into a `crate::FieldQuery` struct  using bincode
-    ///
-    /// #### Usage
-    /// ```rust
-    /// use crate::FieldQuery;
-    /// use turingdb::TuringEngine;
-    ///
-    /// let foo = TuringEngine::new();
-    /// foo.repo_init().await;
-    /// // Start an async runtime
-    ///     |- let foo = Arc::new(&foo); // This `Arc` must be from a module supporting async
-    ///     |-  // spawn a task
-    ///             |- let foo = Arc::clone(&foo);
-    ///             |- FieldQuery::list(&foo, &[data_to_deserialize]).await;
-    /// ```
     pub async fn list(storage: Arc<TuringEngine>, value: &[u8]) -> DbOps {
-        if value.is_empty() == true {
-            return DbOps::EncounteredErrors(
-                "[TuringDB::<FieldList>::(ERROR)-GOOD_HEADER_NO_DATA]".to_owned(),
-            );
-        }
-
-        let deser_document = match bincode::deserialize::<FieldQuery>(value) {
-            Ok(value) => value,
-            Err(e) => return format_error(&TuringOp::FieldList, &anyhow::Error::new(e)),
+        let ops = match FieldQuery::to_ops(&TuringOp::FieldList, value, false) {
+            Ok(ops) => ops,
+            Err(error) => return error,
         };
-
-        match deser_document.payload {
-            Some(_) => {
-                return DbOps::EncounteredErrors(
-                    "[TuringDB::<FieldList>::(ERROR)-QUERY_ARGS_EXCEEDED]".to_owned(),
-                )
-            }
-            None => (),
-        };
-
-        storage
-            .field_list(
-                &Path::new(&deser_document.db),
-                &Path::new(&deser_document.document),
-            )
-            .await
+        let ops = TuringDBDocumentOps::default()
+            .set_db_name(ops.get_db_name().as_str())
+            .set_document_name(ops.get_document_name().as_str());
+
+        match storage.field_list(&ops) {
+            Ok(OpsOutcome::FieldList(list)) => DbOps::FieldList(list),
+            Ok(_) => DbOps::DocumentEmpty,
+            Err(error) => FieldQuery::engine_error(&TuringOp::FieldList, &error),
+        }
     }
     /// ### Insert key/value in a document, failing if the key already exists
     ///
     /// This function also takes an array of bytes `&[u8]` as a parameter;
     /// This array of bytes must be able to deserialize into a `crate::FieldQuery` struct  using bincode
-    ///
-    /// #### Usage
-    /// ```rust
-    /// use crate::FieldQuery;
-    /// use turingdb::TuringEngine;
-    ///
-    /// let foo = TuringEngine::new();
-    /// foo.repo_init().await;
-    /// // Start an async runtime
-    ///     |- let foo = Arc::new(&foo); // This `Arc` must be from a module supporting async
-    ///     |-  // spawn a task
-    ///             |- let foo = Arc::clone(&foo);
-    ///             |- FieldQuery::insert(&foo, &[data_to_deserialize]).await;
-    /// ```
     pub async fn insert(storage: Arc<TuringEngine>, value: &[u8]) -> DbOps {
-        if value.is_empty() == true {
-            return DbOps::EncounteredErrors(
-                "[TuringDB::<FieldInsert>::(ERROR)-GOOD_HEADER_NO_DATA]".to_owned(),
-            );
-        }
-
-        let deser_document = match bincode::deserialize::<FieldQuery>(value) {
-            Ok(value) => value,
-            Err(e) => return format_error(&TuringOp::FieldInsert, &anyhow::Error::new(e)),
+        let ops = match FieldQuery::to_ops(&TuringOp::FieldInsert, value, true) {
+            Ok(ops) => ops,
+            Err(error) => return error,
         };
 
-        let data_check = match deser_document.payload {
-            Some(document) => document,
-            None => {
-                return DbOps::EncounteredErrors(
-                    "[TuringDB::<FieldInsert>::(ERROR)-FIELD_PAYLOAD_NOT_PROVIDED]".to_owned(),
-                )
-            }
-        };
-
-        match storage
-            .field_insert(
-                &Path::new(&deser_document.db),
-                &Path::new(&deser_document.document),
-                &deser_document.field.as_bytes(),
-                &data_check,
-            )
-            .await
-        {
-            Ok(op_result) => {
-                match storage
-                    .flush(
-                        Path::new(&deser_document.db),
-                        Path::new(&deser_document.document),
-                    )
+        match storage.field_set(&ops).await {
+            Ok(_) => {
+                FieldQuery::flush(&storage, &TuringOp::FieldInsert, &ops, DbOps::FieldInserted)
                     .await
-                {
-                    Ok(_) => op_result,
-                    Err(e) => DbOps::EncounteredErrors(e.to_string()),
-                }
             }
-            Err(e) => match custom_codes::try_downcast(&e) {
-                DownCastErrors::NotFound => DbOps::RepoNotFound,
-                DownCastErrors::PermissionDenied => DbOps::PermissionDenied,
-                _ => format_error(&TuringOp::FieldInsert, &e),
-            },
+            Err(error) => FieldQuery::engine_error(&TuringOp::FieldInsert, &error),
         }
     }
     /// ### get a field value in a document using its `key`
     ///
     /// This function also takes an array of bytes `&[u8]` as a parameter;
     /// This array of bytes must be able to deserialize into a `crate::FieldQuery` struct  using bincode
-    ///
-    /// #### Usage
-    /// ```rust
-    /// use crate::FieldQuery;
-    /// use turingdb::TuringEngine;
-    ///
-    /// let foo = TuringEngine::new();
-    /// foo.repo_init().await;
-    /// // Start an async runtime
-    ///     |- let foo = Arc::new(&foo); // This `Arc` must be from a module supporting async
-    ///     |-  // spawn a task
-    ///             |- let foo = Arc::clone(&foo);
-    ///             |- FieldQuery::get(&foo, &[data_to_deserialize]).await;
-    /// ```
     pub async fn get(storage: Arc<TuringEngine>, value: &[u8]) -> DbOps {
-        if value.is_empty() == true {
-            return DbOps::EncounteredErrors(
-                "[TuringDB::<FieldGet>::(ERROR)-GOOD_HEADER_NO_DATA]".to_owned(),
-            );
-        }
-
-        let deser_document = match bincode::deserialize::<FieldQuery>(value) {
-            Ok(value) => value,
-            Err(e) => return format_error(&TuringOp::FieldGet, &anyhow::Error::new(e)),
-        };
-
-        match deser_document.payload {
-            Some(_) => {
-                return DbOps::EncounteredErrors(
-                    "[TuringDB::<FieldGet>::(ERROR)-QUERY_ARGS_EXCEEDED]".to_owned(),
-                )
-            }
-            None => (),
+        let ops = match FieldQuery::to_ops(&TuringOp::FieldGet, value, false) {
+            Ok(ops) => ops,
+            Err(error) => return error,
         };
 
-        match storage
-            .field_get(
-                &Path::new(&deser_document.db),
-                &Path::new(&deser_document.document),
-                &deser_document.field.as_bytes(),
-            )
-            .await
-        {
-            Ok(op_result) => op_result,
-            Err(e) => match custom_codes::try_downcast(&e) {
-                DownCastErrors::NotFound => DbOps::RepoNotFound,
-                DownCastErrors::PermissionDenied => DbOps::PermissionDenied,
-                _ => format_error(&TuringOp::FieldGet, &e),
-            },
+        match storage.field_get(&ops).await {
+            Ok(OpsOutcome::FieldContents(contents)) => DbOps::FieldContents(contents),
+            Ok(_) => DbOps::NotExecuted,
+            Err(error) => FieldQuery::engine_error(&TuringOp::FieldGet, &error),
         }
     }
     /// ### Remove a field in a document based on its `key`
     ///
     /// This function also takes an array of bytes `&[u8]` as a parameter;
     /// This array of bytes must be able to deserialize into a `crate::FieldQuery` struct  using bincode
-    ///
-    /// #### Usage
-    /// ```rust
-    /// use crate::FieldQuery;
-    /// use turingdb::TuringEngine;
-    ///
-    /// let foo = TuringEngine::new();
-    /// foo.repo_init().await;
-    /// // Start an async runtime
-    ///     |- let foo = Arc::new(&foo); // This `Arc` must be from a module supporting async
-    ///     |-  // spawn a task
-    ///             |- let foo = Arc::clone(&foo);
-    ///             |- FieldQuery::remove(&foo, &[data_to_deserialize]).await;
-    /// ```
     pub async fn remove(storage: Arc<TuringEngine>, value: &[u8]) -> DbOps {
-        if value.is_empty() == true {
-            return DbOps::EncounteredErrors(
-                "[TuringDB::<FieldRemove>::(ERROR)-GOOD_HEADER_NO_DATA]".to_owned(),
-            );
-        }
-
-        let deser_document = match bincode::deserialize::<FieldQuery>(value) {
-            Ok(value) => value,
-            Err(e) => return format_error(&TuringOp::FieldRemove, &anyhow::Error::new(e)),
-        };
-
-        match deser_document.payload {
-            Some(_) => {
-                return DbOps::EncounteredErrors(
-                    "[TuringDB::<FieldRemove>::(ERROR)-QUERY_ARGS_EXCEEDED]".to_owned(),
-                )
-            }
-            None => (),
+        let ops = match FieldQuery::to_ops(&TuringOp::FieldRemove, value, false) {
+            Ok(ops) => ops,
+            Err(error) => return error,
         };
 
-        match storage
-            .field_remove(
-                &Path::new(&deser_document.db),
-                &Path::new(&deser_document.document),
-                &deser_document.field.as_bytes(),
-            )
-            .await
-        {
-            Ok(op_result) => {
-                match storage
-                    .flush(
-                        &Path::new(&deser_document.db),
-                        &Path::new(&deser_document.document),
-                    )
-                    .await
-                {
-                    Ok(_) => op_result,
-                    Err(e) => DbOps::EncounteredErrors(e.to_string()),
-                }
+        match storage.field_remove(&ops).await {
+            Ok(_) => {
+                FieldQuery::flush(&storage, &TuringOp::FieldRemove, &ops, DbOps::FieldDropped).await
             }
-            Err(e) => match custom_codes::try_downcast(&e) {
-                DownCastErrors::NotFound => DbOps::RepoNotFound,
-                DownCastErrors::PermissionDenied => DbOps::PermissionDenied,
-                _ => format_error(&TuringOp::FieldRemove, &e),
-            },
+            Err(error) => FieldQuery::engine_error(&TuringOp::FieldRemove, &error),
         }
     }
     /// ### Update the `value` contents all a `key` in a field
     ///
     /// This function also takes an array of bytes `&[u8]` as a parameter;
     /// This array of bytes must be able to deserialize into a `crate::FieldQuery` struct  using bincode
-    ///
-    /// #### Usage
-    /// ```rust
-    /// use crate::FieldQuery;
-    /// use turingdb::TuringEngine;
-    ///
-    /// let foo = TuringEngine::new();
-    /// foo.repo_init().await;
-    /// // Start an async runtime
-    ///     |- let foo = Arc::new(&foo); // This `Arc` must be from a module supporting async
-    ///     |-  // spawn a task
-    ///             |- let foo = Arc::clone(&foo);
-    ///             |- FieldQuery::modify(&foo, &[data_to_deserialize]).await;
-    /// ```
     pub async fn modify(storage: Arc<TuringEngine>, value: &[u8]) -> DbOps {
-        if value.is_empty() == true {
-            return DbOps::EncounteredErrors(
-                "[TuringDB::<FieldModify>::(ERROR)-GOOD_HEADER_NO_DATA]".to_owned(),
-            );
+        let ops = match FieldQuery::to_ops(&TuringOp::FieldModify, value, true) {
+            Ok(ops) => ops,
+            Err(error) => return error,
+        };
+
+        match storage.field_modify(&ops).await {
+            Ok(_) => {
+                FieldQuery::flush(&storage, &TuringOp::FieldModify, &ops, DbOps::FieldModified)
+                    .await
+            }
+            Err(error) => FieldQuery::engine_error(&TuringOp::FieldModify, &error),
+        }
+    }
+
+    fn to_ops(op: &TuringOp, value: &[u8], needs_payload: bool) -> Result<TuringDBFieldOps, DbOps> {
+        if value.is_empty() {
+            return Err(DbOps::EncounteredErrors(format!(
+                "[TuringDB::<{:?}>::(ERROR)-GOOD_HEADER_NO_DATA]",
+                op
+            )));
         }
 
-        let deser_document = match bincode::deserialize::<FieldQuery>(value) {
+        let deser_field = match bincode::deserialize::<FieldQuery>(value) {
             Ok(value) => value,
-            Err(e) => return format_error(&TuringOp::FieldModify, &anyhow::Error::new(e)),
+            Err(e) => return Err(format_error(op, &anyhow::Error::new(e))),
         };
 
-        let data_check = match deser_document.payload {
-            Some(document) => document,
-            None => {
-                return DbOps::EncounteredErrors(
-                    "[TuringDB::<FieldModify>::(ERROR)-FIELD_PAYLOAD_NOT_PROVIDED]".to_owned(),
-                )
-            }
-        };
+        let ops = TuringDBFieldOps::default()
+            .db(&deser_field.db)
+            .document(&deser_field.document)
+            .key(deser_field.field.as_bytes());
+
+        match (deser_field.payload, needs_payload) {
+            (Some(payload), true) => Ok(ops.value(&payload)),
+            (None, false) => Ok(ops),
+            (None, true) => Err(DbOps::EncounteredErrors(format!(
+                "[TuringDB::<{:?}>::(ERROR)-FIELD_PAYLOAD_NOT_PROVIDED]",
+                op
+            ))),
+            (Some(_), false) => Err(DbOps::EncounteredErrors(format!(
+                "[TuringDB::<{:?}>::(ERROR)-QUERY_ARGS_EXCEEDED]",
+                op
+            ))),
+        }
+    }
 
-        match storage
-            .field_modify(
-                &Path::new(&deser_document.db),
-                &Path::new(&deser_document.document),
-                &deser_document.field.as_bytes(),
-                &data_check,
-            )
-            .await
-        {
-            Ok(op_result) => {
-                match storage
-                    .flush(
-                        Path::new(&deser_document.db),
-                        Path::new(&deser_document.document),
-                    )
-                    .await
-                {
-                    Ok(_) => op_result,
-                    Err(e) => DbOps::EncounteredErrors(e.to_string()),
-                }
-            }
-            Err(e) => match custom_codes::try_downcast(&e) {
-                DownCastErrors::NotFound => DbOps::RepoNotFound,
-                DownCastErrors::PermissionDenied => DbOps::PermissionDenied,
-                _ => format_error(&TuringOp::FieldModify, &e),
-            },
+    /// Flush the database of a field once it was written, responding with `done`
+    async fn flush(
+        storage: &Arc<TuringEngine>,
+        op: &TuringOp,
+        ops: &TuringDBFieldOps,
+        done: DbOps,
+    ) -> DbOps {
+        match storage.db_flush(&ops.get_db_name()).await {
+            Ok(_) => done,
+            Err(error) => format_engine_error(op, &error),
+        }
+    }
+
+    fn engine_error(op: &TuringOp, error: &TuringDbError) -> DbOps {
+        match error {
+            TuringDbError::DbNotFound => DbOps::DbNotFound,
+            TuringDbError::DocumentNotFound => DbOps::DocumentNotFound,
+            TuringDbError::FieldNotFound => DbOps::FieldNotFound,
+            TuringDbError::KeyAlreadyExists => DbOps::FieldAlreadyExists,
+            TuringDbError::PermissionDenied => DbOps::PermissionDenied,
+            _ => format_engine_error(op, error),
         }
     }
 }
@@ -0,0 +1,145 @@
+use crate::errors::{format_engine_error, format_error};
+use async_dup::Arc;
+use camino::Utf8Path;
+use custom_codes::DbOps;
+use serde::{Deserialize, Serialize};
+use turingdb::{IndexDefinition, IndexLookup, OpsOutcome, TuringDBDocumentOps, TuringEngine};
+use turingdb_helpers::TuringOp;
+
+/// Handles secondary index queries
+/// ```rust
+/// #[derive(Debug, Serialize, Deserialize)]
+/// pub(crate) struct IndexQuery {
+///     db: String,
+///     document: String,
+///     index: String,
+///     path: Option<String>,
+///     unique: bool,
+///     lookup: Option<IndexLookup>,
+/// }
+/// ```
+///
+/// #### Usage
+/// ```rust
+/// let mut engine = TuringEngine::new().await?;
+/// engine.repo_init().await?;
+/// let storage = Arc::new(engine);
+///
+/// // The bytes of a `turingdb_helpers::IndexQuery` following its `TuringOp` header
+/// let outcome = IndexQuery::find(Arc::clone(&storage), &packet[1..]).await;
+/// ```
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct IndexQuery {
+    db: String,
+    document: String,
+    index: String,
+    path: Option<String>,
+    unique: bool,
+    lookup: Option<IndexLookup>,
+}
+
+impl IndexQuery {
+    /// ### Declare an index of a document and add the fields it already holds to it
+    ///
+    /// This function also takes an array of bytes `&[u8]` as a parameter;
+    /// This array of bytes must be able to deserialize into a `crate::IndexQuery` struct  using bincode
+    pub async fn create(storage: Arc<TuringEngine>, value: &[u8]) -> DbOps {
+        let query = match IndexQuery::from_bytes(&TuringOp::IndexCreate, value) {
+            Ok(query) => query,
+            Err(error) => return error,
+        };
+
+        let mut definition = IndexDefinition::new(&query.index);
+        if let Some(path) = &query.path {
+            definition = definition.path(path);
+        }
+        if query.unique {
+            definition = definition.unique();
+        }
+
+        match storage.index_create(&query.to_ops(), definition).await {
+            Ok(_) => DbOps::Created,
+            Err(e) => format_engine_error(&TuringOp::IndexCreate, &e),
+        }
+    }
+    /// ### Drop an index of a document
+    ///
+    /// This function also takes an array of bytes `&[u8]` as a parameter;
+    /// This array of bytes must be able to deserialize into a `crate::IndexQuery` struct  using bincode
+    pub async fn drop(storage: Arc<TuringEngine>, value: &[u8]) -> DbOps {
+        let query = match IndexQuery::from_bytes(&TuringOp::IndexDrop, value) {
+            Ok(query) => query,
+            Err(error) => return error,
+        };
+
+        match storage.index_drop(&query.to_ops(), &query.index).await {
+            Ok(_) => DbOps::Deleted,
+            Err(e) => format_engine_error(&TuringOp::IndexDrop, &e),
+        }
+    }
+    /// ### Find the fields of a document by the values of an index
+    ///
+    /// This function also takes an array of bytes `&[u8]` as a parameter;
+    /// This array of bytes must be able to deserialize into a `crate::IndexQuery` struct  using bincode
+    ///
+    /// Responds with a `DbOps::FieldContents` holding the key and the contents of every field found
+    /// as a `Vec<(Vec<u8>, Vec<u8>)>` serialized with bincode. Without a lookup every field of the index is found
+    pub async fn find(storage: Arc<TuringEngine>, value: &[u8]) -> DbOps {
+        let query = match IndexQuery::from_bytes(&TuringOp::IndexFind, value) {
+            Ok(query) => query,
+            Err(error) => return error,
+        };
+
+        let lookup = match query.lookup {
+            Some(lookup) => lookup,
+            None => IndexLookup::Range {
+                from: None,
+                to: None,
+            },
+        };
+
+        match storage
+            .find_by_index(
+                Utf8Path::new(&query.db),
+                Utf8Path::new(&query.document),
+                &query.index,
+                &lookup,
+            )
+            .await
+        {
+            Ok(OpsOutcome::FieldsFound(fields)) => {
+                let fields = fields
+                    .into_iter()
+                    .map(|(key, field_data)| (key.to_vec(), field_data.data().to_vec()))
+                    .collect::<Vec<(Vec<u8>, Vec<u8>)>>();
+
+                match bincode::serialize::<Vec<(Vec<u8>, Vec<u8>)>>(&fields) {
+                    Ok(fields) => DbOps::FieldContents(fields),
+                    Err(e) => format_error(&TuringOp::IndexFind, &anyhow::Error::new(e)),
+                }
+            }
+            Ok(_) => DbOps::NotExecuted,
+            Err(e) => format_engine_error(&TuringOp::IndexFind, &e),
+        }
+    }
+
+    fn from_bytes(op: &TuringOp, value: &[u8]) -> Result<IndexQuery, DbOps> {
+        if value.is_empty() {
+            return Err(DbOps::EncounteredErrors(format!(
+                "[TuringDB::<{:?}>::(ERROR)-GOOD_HEADER_NO_DATA]",
+                op
+            )));
+        }
+
+        match bincode::deserialize::<IndexQuery>(value) {
+            Ok(query) => Ok(query),
+            Err(e) => Err(format_error(op, &anyhow::Error::new(e))),
+        }
+    }
+
+    fn to_ops(&self) -> TuringDBDocumentOps {
+        TuringDBDocumentOps::default()
+            .set_db_name(&self.db)
+            .set_document_name(&self.document)
+    }
+}
@@ -0,0 +1,136 @@
+use crate::errors::{format_engine_error, format_error};
+use async_dup::Arc;
+use custom_codes::DbOps;
+use serde::{Deserialize, Serialize};
+use turingdb::{OpsOutcome, TuringDBJsonOps, TuringEngine};
+use turingdb_helpers::TuringOp;
+
+/// Handles JSON field queries
+/// ```rust
+/// #[derive(Debug, Serialize, Deserialize)]
+/// pub(crate) struct JsonQuery {
+///     db: String,
+///     document: String,
+///     field: String,
+///     path: String,
+///     payload: Option<String>,
+/// }
+/// ```
+///
+/// #### Usage
+/// ```rust
+/// let mut engine = TuringEngine::new().await?;
+/// engine.repo_init().await?;
+/// let storage = Arc::new(engine);
+///
+/// // The bytes of a `turingdb_helpers::JsonQuery` following its `TuringOp` header
+/// let outcome = JsonQuery::get(Arc::clone(&storage), &packet[1..]).await;
+/// ```
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct JsonQuery {
+    db: String,
+    document: String,
+    field: String,
+    path: String,
+    payload: Option<String>,
+}
+
+impl JsonQuery {
+    /// ### Insert a JSON value into a field, failing if the field already exists
+    ///
+    /// This function also takes an array of bytes `&[u8]` as a parameter;
+    /// This array of bytes must be able to deserialize into a `crate::JsonQuery` struct  using bincode
+    pub async fn set(storage: Arc<TuringEngine>, value: &[u8]) -> DbOps {
+        let ops = match JsonQuery::to_ops(&TuringOp::JsonSet, value, true) {
+            Ok(ops) => ops,
+            Err(error) => return error,
+        };
+
+        match storage.json_set(&ops).await {
+            Ok(_) => DbOps::FieldInserted,
+            Err(e) => format_engine_error(&TuringOp::JsonSet, &e),
+        }
+    }
+    /// ### Get the JSON value at a path inside a field
+    ///
+    /// This function also takes an array of bytes `&[u8]` as a parameter;
+    /// This array of bytes must be able to deserialize into a `crate::JsonQuery` struct  using bincode
+    pub async fn get(storage: Arc<TuringEngine>, value: &[u8]) -> DbOps {
+        let ops = match JsonQuery::to_ops(&TuringOp::JsonGet, value, false) {
+            Ok(ops) => ops,
+            Err(error) => return error,
+        };
+
+        match storage.json_get(&ops).await {
+            Ok(OpsOutcome::JsonContents(json)) => DbOps::FieldContents(json.into_bytes()),
+            Ok(_) => DbOps::NotExecuted,
+            Err(e) => format_engine_error(&TuringOp::JsonGet, &e),
+        }
+    }
+    /// ### Replace the value at a path inside a JSON field
+    ///
+    /// This function also takes an array of bytes `&[u8]` as a parameter;
+    /// This array of bytes must be able to deserialize into a `crate::JsonQuery` struct  using bincode
+    pub async fn modify(storage: Arc<TuringEngine>, value: &[u8]) -> DbOps {
+        let ops = match JsonQuery::to_ops(&TuringOp::JsonModify, value, true) {
+            Ok(ops) => ops,
+            Err(error) => return error,
+        };
+
+        match storage.json_modify(&ops).await {
+            Ok(_) => DbOps::FieldModified,
+            Err(e) => format_engine_error(&TuringOp::JsonModify, &e),
+        }
+    }
+    /// ### Remove the value at a path inside a JSON field
+    ///
+    /// This function also takes an array of bytes `&[u8]` as a parameter;
+    /// This array of bytes must be able to deserialize into a `crate::JsonQuery` struct  using bincode
+    pub async fn remove(storage: Arc<TuringEngine>, value: &[u8]) -> DbOps {
+        let ops = match JsonQuery::to_ops(&TuringOp::JsonRemove, value, false) {
+            Ok(ops) => ops,
+            Err(error) => return error,
+        };
+
+        match storage.json_remove(&ops).await {
+            Ok(_) => DbOps::FieldModified,
+            Err(e) => format_engine_error(&TuringOp::JsonRemove, &e),
+        }
+    }
+
+    fn to_ops(op: &TuringOp, value: &[u8], needs_payload: bool) -> Result<TuringDBJsonOps, DbOps> {
+        if value.is_empty() {
+            return Err(DbOps::EncounteredErrors(format!(
+                "[TuringDB::<{:?}>::(ERROR)-GOOD_HEADER_NO_DATA]",
+                op
+            )));
+        }
+
+        let deser_json = match bincode::deserialize::<JsonQuery>(value) {
+            Ok(value) => value,
+            Err(e) => return Err(format_error(op, &anyhow::Error::new(e))),
+        };
+
+        let ops = TuringDBJsonOps::default()
+            .db(&deser_json.db)
+            .document(&deser_json.document)
+            .key(deser_json.field.as_bytes())
+            .path(&deser_json.path);
+
+        match (deser_json.payload, needs_payload) {
+            (Some(payload), true) => match serde_json::from_str(&payload) {
+                Ok(json) => Ok(ops.value(json)),
+                Err(e) => Err(format_error(op, &anyhow::Error::new(e))),
+            },
+            (None, false) => Ok(ops),
+            (None, true) => Err(DbOps::EncounteredErrors(format!(
+                "[TuringDB::<{:?}>::(ERROR)-FIELD_PAYLOAD_NOT_PROVIDED]",
+                op
+            ))),
+            (Some(_), false) => Err(DbOps::EncounteredErrors(format!(
+                "[TuringDB::<{:?}>::(ERROR)-QUERY_ARGS_EXCEEDED]",
+                op
+            ))),
+        }
+    }
+}
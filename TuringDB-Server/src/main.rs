@@ -13,26 +13,62 @@
 //! 2. async-locks for increased acid guarantees
 //! 3. Insert operations will fail if a key already exists, use `modify()` method on a key to change its value
 //! 4. in-memory locks to ensure that document locks are not dropped until the application is halted
+//! 5. changefeeds without polling, inspired by RethinkDB, pushed to clients that send a `Subscribe` query
+//! 6. asynchronous leader-follower replication where a read-only replica bootstraps from a snapshot
+//!    of the leader and then tails its changes. A write is acknowledged once the leader applied it,
+//!    before any follower did, so the writes a failed leader did not send yet are lost
+//! 7. sharding databases across servers by consistent hashing, where `ClusterClient` from `turingdb-helpers`
+//!    routes every query to the server owning its database and moves databases when servers are added
+//! 8. scatter-gather listings where the server receiving a `ClusterDbList` or `ClusterDocumentList` query
+//!    asks every node of its cluster and merges their sorted results, reporting the nodes that did not respond
+//! 9. bulk inserts streamed in chunks over a dedicated connection with a `BulkInsert` query, written in batches
+//!    and flushed once at the end, reporting the progress and the rejected records after every chunk
+//! 10. batches of get, set, modify and remove operations across the documents of a database sent with a `Batch` query
+//!     and answered with the result of every operation, optionally written only if all of them succeed
+//! 11. fields with a time-to-live that are hidden once they expire and removed in the background,
+//!     pushing an `Expire` change to the clients subscribed to their document
+//! 12. secondary indexes on the decoded values of the fields of a document, or on a path inside their JSON values,
+//!     declared with an `IndexCreate` query and looked up by value or range with an `IndexFind` query,
+//!     optionally unique so that no two fields of the document can hold the same value
 //!
 //! Some features that are under development include
 //!
-//! 1. Replication
-//! 2. Multi-cluster queries
-//! 3. Changefeeds without polling, inspired by RethinkDB
-//! 4. Running the server as a daemon
-//! 5. Loggin
+//! 1. Multi-cluster queries
+//! 2. Running the server as a daemon
+//! 3. Loggin
 //!
 //! To install the server, run `cargo install turingdb-server`
 //!
-//! To run the server, run `turingdb-server` from a terminal
+//! To run the server, run `turingdb-server` from a terminal.
+//! The server accepts the options
+//!
+//! - `--listen <ADDRESS>` the address to listen on, `127.0.0.1:4343` by default
+//! - `--repo <DIRECTORY>` the directory of the repo, `TuringDB-Repo` in the home directory by default
+//! - `--follow <LEADER ADDRESS>` run as a read-only replica of the server listening on the leader address
+//! - `--cluster <FILE>` the nodes of the cluster with a name and an address on every line, like `node-1 127.0.0.1:4343`
+//! - `--history <SECONDS>` keep the previous versions of every field and drop the versions
+//!   the retention of their document no longer keeps every `SECONDS`
+//! - `--expiry <SECONDS>` remove the fields that outlived their time-to-live every `SECONDS`, every second by default
+//!
+//! so a leader and a replica can run on the same host with
+//! `turingdb-server --repo /tmp/leader` and
+//! `turingdb-server --listen 127.0.0.1:4344 --repo /tmp/replica --follow 127.0.0.1:4343`
+//!
+//! A database is moved between repos, while no server has them open, with
+//! `turingdb-server export <DB> --format <ndjson|cbor> --repo <DIRECTORY> > <FILE>` and
+//! `turingdb-server import <DB> --format <ndjson|cbor> --repo <DIRECTORY> < <FILE>`
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use async_dup::Arc;
 use async_net::{TcpListener, TcpStream};
 use custom_codes::DbOps;
+use camino::Utf8PathBuf;
 use futures_lite::*;
 use smol::Task;
-use std::net::{Shutdown, SocketAddr};
+use std::{
+    net::{Shutdown, SocketAddr},
+    time::Duration,
+};
 use turingdb::TuringEngine;
 use turingdb_helpers::{to_op, TuringOp};
 
@@ -48,10 +84,42 @@ use document_query::*;
 mod field_query;
 use field_query::*;
 
+mod json_query;
+use json_query::*;
+
+mod changefeed_query;
+use changefeed_query::*;
+
+mod replication_query;
+use replication_query::*;
+
+mod shard_query;
+use shard_query::*;
+
+mod bulk_query;
+use bulk_query::*;
+
+mod batch_query;
+use batch_query::*;
+
+mod index_query;
+use index_query::*;
+
+mod coordinator;
+use coordinator::*;
+
+mod compactor;
+use compactor::*;
+
+mod transfer;
+
 mod errors;
 
 const BUFFER_CAPACITY: usize = 64 * 1024; //16Kb
 const BUFFER_DATA_CAPACITY: usize = 1024 * 1024 * 16; // Db cannot hold data more than 16MB in size
+const DEFAULT_LISTEN_ADDRESS: &str = "127.0.0.1:4343";
+/// Remove the fields that outlived their time-to-live every second
+const DEFAULT_EXPIRY_INTERVAL: Duration = Duration::from_secs(1);
 
 // FIXME Create a heartbeat of 100ms to check for when a repository is deliberately manipulated in the
 // file system by the OS. Or acquire a lock to prevent modification by another process
@@ -59,12 +127,52 @@ const BUFFER_DATA_CAPACITY: usize = 1024 * 1024 * 16; // Db cannot hold data mor
 //FIXME 2. ENABLE RECORDING OF UNDERGOING OPERATIONS
 //FIXME 5. LOGGING OF ERRORS
 fn main() -> anyhow::Result<()> {
-    // Initialize here to prevent issues with borrowing
-    let storage = Arc::new(TuringEngine::new());
+    // `export` and `import` run against the repo once instead of starting the server
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    if let Some(command) = args.first() {
+        if command == "export" || command == "import" {
+            if let Err(e) = transfer::run(command, &args[1..]) {
+                eprintln!("[TuringDB::<{}>::(ERROR)-{}]", command.to_uppercase(), e);
+                std::process::exit(1);
+            }
+
+            return Ok(());
+        }
+    }
+
+    let options = match ServerOptions::from_args() {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("[TuringDB::<INIT>::(ERROR)-{}]", e);
+            std::process::exit(1);
+        }
+    };
 
     smol::run(async {
-        let storage = Arc::clone(&storage);
-        match storage.repo_init().await {
+        let mut builder = TuringEngine::builder()
+            .replica(options.follow.is_some())
+            .history(options.history.is_some());
+        if let Some(repo_dir) = options.repo {
+            builder = builder.repo_dir(repo_dir);
+        }
+
+        let mut engine = match builder.build().await {
+            Ok(engine) => engine,
+            Err(e) => {
+                eprintln!("[TuringDB::<INIT>::(ERROR)-{:?}]", e); //FIXME log!()
+                std::process::exit(1);
+            }
+        };
+
+        // A replica creates its repo before copying the databases of its leader into it
+        if options.follow.is_some() && !engine.get_repo_dir().await.exists() {
+            if let Err(e) = engine.repo_create().await {
+                eprintln!("[TuringDB::<INIT>::(ERROR)-{:?}]", e); //FIXME log!()
+                std::process::exit(1);
+            }
+        }
+
+        match engine.repo_init().await {
             Ok(_) => (),
             Err(e) => {
                 eprintln!("[TuringDB::<INIT>::(ERROR)-{:?}]", e); //FIXME log!()
@@ -72,15 +180,40 @@ fn main() -> anyhow::Result<()> {
             }
         };
 
-        let listener = TcpListener::bind("127.0.0.1:4343").await?;
+        let storage = Arc::new(engine);
+
+        let cluster = match options.cluster {
+            None => None,
+            Some(path) => match Coordinator::load(&path) {
+                Ok(map) => Some(map),
+                Err(e) => {
+                    eprintln!("[TuringDB::<INIT>::(ERROR)-{:?}]", e); //FIXME log!()
+                    std::process::exit(1);
+                }
+            },
+        };
+        let coordinator = Arc::new(Coordinator::new(&options.listen, cluster));
+
+        // A replica removes expired fields when its leader does
+        match options.follow {
+            Some(leader) => Task::spawn(follow(leader, Arc::clone(&storage))).detach(),
+            None => Task::spawn(sweep_expired(Arc::clone(&storage), options.expiry)).detach(),
+        }
+
+        if let Some(interval) = options.history {
+            Task::spawn(compact_history(Arc::clone(&storage), interval)).detach();
+        }
+
+        let listener = TcpListener::bind(&options.listen).await?;
         println!("Listening on {}", listener.local_addr()?);
 
         while let Some(stream) = listener.incoming().next().await {
             let stream = stream?;
             let storage = Arc::clone(&storage);
+            let coordinator = Arc::clone(&coordinator);
 
             Task::spawn(async move {
-                match handle_client(stream, storage).await {
+                match handle_client(stream, storage, coordinator).await {
                     Ok(addr) => {
                         println!("x[TERMINATED] device[{}:{}]", addr.ip(), addr.port())
                         //FIXME log!()
@@ -90,14 +223,18 @@ fn main() -> anyhow::Result<()> {
                     }
                 }
             })
-            .await;
+            .detach();
         }
 
         Ok(())
     })
 }
 
-async fn handle_client(mut stream: TcpStream, storage: Arc<TuringEngine>) -> Result<SocketAddr> {
+async fn handle_client(
+    mut stream: TcpStream,
+    storage: Arc<TuringEngine>,
+    coordinator: Arc<Coordinator>,
+) -> Result<SocketAddr> {
     println!("↓[CONNECTED] device[{}]", stream.peer_addr()?);
 
     let mut buffer = [0; BUFFER_CAPACITY];
@@ -131,8 +268,38 @@ async fn handle_client(mut stream: TcpStream, storage: Arc<TuringEngine>) -> Res
             // Ensure that the data is appended before being deserialized by bincode
             container_buffer.append(&mut buffer[..bytes_read].to_owned());
             let op = to_op(&[container_buffer[0]]);
-            let op_result = process_op(&op, storage.clone(), &container_buffer[1..]).await;
+
+            // A bulk insert reads its chunks from the stream before the connection takes other queries
+            if op == TuringOp::BulkInsert {
+                BulkInsertQuery::load(&mut stream, storage.clone(), &container_buffer[1..]).await?;
+
+                container_buffer.clear();
+                continue;
+            }
+
+            // A subscription keeps pushing changes over the stream so it ends the connection
+            if op == TuringOp::Subscribe {
+                SubscribeQuery::subscribe(&mut stream, storage.clone(), &container_buffer[1..])
+                    .await?;
+
+                let peer = stream.peer_addr()?;
+                stream.shutdown(Shutdown::Both)?;
+                return Ok(peer);
+            }
+
+            let op_result = match op {
+                TuringOp::ClusterDbList | TuringOp::ClusterDocumentList => {
+                    coordinator
+                        .gather(&op, storage.clone(), &container_buffer[1..])
+                        .await
+                }
+                _ => process_op(&op, storage.clone(), &container_buffer[1..]).await,
+            };
             handle_response(&mut stream, op_result).await?;
+
+            // Start the next query with an empty buffer so a connection can send more than one query
+            container_buffer.clear();
+            continue;
         }
         // Append data to buffer
         container_buffer.append(&mut buffer[..bytes_read].to_owned());
@@ -154,18 +321,96 @@ async fn process_op(op: &TuringOp, storage: Arc<TuringEngine>, value: &[u8]) ->
         &TuringOp::FieldRemove => FieldQuery::remove(storage, value).await,
         &TuringOp::FieldModify => FieldQuery::modify(storage, value).await,
         &TuringOp::FieldList => FieldQuery::list(storage, value).await,
+        &TuringOp::JsonSet => JsonQuery::set(storage, value).await,
+        &TuringOp::JsonGet => JsonQuery::get(storage, value).await,
+        &TuringOp::JsonModify => JsonQuery::modify(storage, value).await,
+        &TuringOp::JsonRemove => JsonQuery::remove(storage, value).await,
+        &TuringOp::AuditVerify => RepoQuery::audit_verify(storage).await,
+        // Subscriptions and bulk inserts are handled by `handle_client` since they send more than one response
+        &TuringOp::Subscribe | &TuringOp::BulkInsert => DbOps::NotExecuted,
+        &TuringOp::ReplicationSnapshot => ReplicationQuery::snapshot(storage, value).await,
+        &TuringOp::ReplicationPoll => ReplicationQuery::poll(storage, value).await,
+        &TuringOp::ReplicationStatus => ReplicationQuery::status(storage).await,
+        &TuringOp::ShardImport => ShardQuery::import(storage, value).await,
+        // Listings of the whole cluster are handled by the `Coordinator` of `handle_client`
+        &TuringOp::ClusterDbList | &TuringOp::ClusterDocumentList => DbOps::NotExecuted,
+        &TuringOp::Batch => BatchQuery::run(storage, value).await,
+        &TuringOp::IndexCreate => IndexQuery::create(storage, value).await,
+        &TuringOp::IndexDrop => IndexQuery::drop(storage, value).await,
+        &TuringOp::IndexFind => IndexQuery::find(storage, value).await,
         &TuringOp::NotSupported => DbOps::NotExecuted,
     }
 }
 
 async fn handle_response(stream: &mut TcpStream, ops: DbOps) -> Result<()> {
     let ops_to_bytes = bincode::serialize::<DbOps>(&ops)?;
-    stream.write(&ops_to_bytes).await?;
+    stream.write_all(&ops_to_bytes).await?;
     stream.flush().await?;
 
     Ok(())
 }
 
+/// The command line options of the server
+/// ```rust
+/// struct ServerOptions {
+///     listen: String,
+///     repo: Option<Utf8PathBuf>,
+///     follow: Option<String>,
+///     cluster: Option<Utf8PathBuf>,
+///     history: Option<Duration>,
+///     expiry: Duration,
+/// }
+/// ```
+struct ServerOptions {
+    listen: String,
+    repo: Option<Utf8PathBuf>,
+    follow: Option<String>,
+    cluster: Option<Utf8PathBuf>,
+    history: Option<Duration>,
+    expiry: Duration,
+}
+
+impl ServerOptions {
+    fn from_args() -> Result<Self> {
+        let mut options = ServerOptions {
+            listen: DEFAULT_LISTEN_ADDRESS.to_owned(),
+            repo: None,
+            follow: None,
+            cluster: None,
+            history: None,
+            expiry: DEFAULT_EXPIRY_INTERVAL,
+        };
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let value = match args.next() {
+                Some(value) => value,
+                None => bail!("MISSING_VALUE_FOR_{}", arg),
+            };
+
+            match arg.as_str() {
+                "--listen" => options.listen = value,
+                "--repo" => options.repo = Some(Utf8PathBuf::from(value)),
+                "--follow" => options.follow = Some(value),
+                "--cluster" => options.cluster = Some(Utf8PathBuf::from(value)),
+                "--history" => match value.parse::<u64>() {
+                    Ok(seconds) if seconds > 0 => {
+                        options.history = Some(Duration::from_secs(seconds))
+                    }
+                    _ => bail!("INVALID_HISTORY_INTERVAL_{}", value),
+                },
+                "--expiry" => match value.parse::<u64>() {
+                    Ok(seconds) if seconds > 0 => options.expiry = Duration::from_secs(seconds),
+                    _ => bail!("INVALID_EXPIRY_INTERVAL_{}", value),
+                },
+                _ => bail!("UNKNOWN_OPTION_{}", arg),
+            }
+        }
+
+        Ok(options)
+    }
+}
+
 /*let (signal_sender, signal_receiver) = signal_msg::new();
 signal_sender.prepare_signals();
 
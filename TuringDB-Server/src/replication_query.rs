@@ -0,0 +1,231 @@
+use crate::errors::{format_engine_error, format_error};
+use anyhow::{anyhow, bail, Result};
+use async_dup::Arc;
+use async_net::TcpStream;
+use camino::Utf8Path;
+use custom_codes::DbOps;
+use futures_lite::{AsyncReadExt, AsyncWriteExt};
+use serde::{Deserialize, Serialize};
+use smol::Timer;
+use std::{collections::BTreeMap, time::Duration};
+use turingdb::{DbSnapshot, ReplicationBatch, ReplicationStatus, TuringEngine};
+use turingdb_helpers::TuringOp;
+
+/// The number of changes read from every database of the leader in a single poll
+const REPLICATION_BATCH: usize = 1000;
+/// How long a replica waits before polling a leader that had no new changes
+const REPLICATION_IDLE: Duration = Duration::from_millis(100);
+/// How long a replica waits before connecting to the leader again after an error
+const REPLICATION_RECONNECT: Duration = Duration::from_secs(1);
+const RESPONSE_BUFFER_CAPACITY: usize = 64 * 1024;
+
+/// Handles the queries a replica sends to its leader
+/// ```rust
+/// #[derive(Debug, Serialize, Deserialize)]
+/// pub(crate) struct ReplicationQuery {
+///     db: String,
+///     positions: BTreeMap<String, u64>,
+///     max: usize,
+/// }
+/// ```
+///
+/// #### Usage
+/// ```rust
+/// let mut engine = TuringEngine::new().await?;
+/// engine.repo_init().await?;
+/// let storage = Arc::new(engine);
+///
+/// // The bytes of a `turingdb_helpers::ReplicationQuery` following its `TuringOp` header
+/// let outcome = ReplicationQuery::poll(Arc::clone(&storage), &packet[1..]).await;
+/// ```
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct ReplicationQuery {
+    db: String,
+    positions: BTreeMap<String, u64>,
+    max: usize,
+}
+
+impl ReplicationQuery {
+    /// ### Copy a database to bootstrap a replica
+    ///
+    /// This function also takes an array of bytes `&[u8]` as a parameter;
+    /// This array of bytes must be able to deserialize into a `crate::ReplicationQuery` struct  using bincode
+    ///
+    /// The snapshot is sent as a `DbOps::FieldContents` holding the `DbSnapshot` serialized with bincode
+    pub async fn snapshot(storage: Arc<TuringEngine>, value: &[u8]) -> DbOps {
+        let query = match ReplicationQuery::deserialize(&TuringOp::ReplicationSnapshot, value) {
+            Ok(query) => query,
+            Err(op_result) => return op_result,
+        };
+
+        match storage.replication_snapshot(Utf8Path::new(&query.db)).await {
+            Ok(snapshot) => ReplicationQuery::to_contents(
+                &TuringOp::ReplicationSnapshot,
+                bincode::serialize::<DbSnapshot>(&snapshot),
+            ),
+            Err(e) => format_engine_error(&TuringOp::ReplicationSnapshot, &e),
+        }
+    }
+    /// ### Read the changes of every database from the positions of a replica
+    ///
+    /// This function also takes an array of bytes `&[u8]` as a parameter;
+    /// This array of bytes must be able to deserialize into a `crate::ReplicationQuery` struct  using bincode
+    ///
+    /// The changes are sent as a `DbOps::FieldContents` holding the `ReplicationBatch` serialized with bincode
+    pub async fn poll(storage: Arc<TuringEngine>, value: &[u8]) -> DbOps {
+        let query = match ReplicationQuery::deserialize(&TuringOp::ReplicationPoll, value) {
+            Ok(query) => query,
+            Err(op_result) => return op_result,
+        };
+
+        match storage
+            .replication_changes(&query.positions, query.max)
+            .await
+        {
+            Ok(batch) => ReplicationQuery::to_contents(
+                &TuringOp::ReplicationPoll,
+                bincode::serialize::<ReplicationBatch>(&batch),
+            ),
+            Err(e) => format_engine_error(&TuringOp::ReplicationPoll, &e),
+        }
+    }
+    /// ### Report how far the databases of a replica are behind its leader
+    ///
+    /// The status is sent as a `DbOps::FieldContents` holding the `ReplicationStatus` serialized with bincode
+    pub async fn status(storage: Arc<TuringEngine>) -> DbOps {
+        let status = storage.replication_status().await;
+
+        ReplicationQuery::to_contents(
+            &TuringOp::ReplicationStatus,
+            bincode::serialize::<ReplicationStatus>(&status),
+        )
+    }
+
+    fn deserialize(op: &TuringOp, value: &[u8]) -> Result<Self, DbOps> {
+        if value.is_empty() {
+            return Err(DbOps::EncounteredErrors(format!(
+                "[TuringDB::<{:?}>::(ERROR)-GOOD_HEADER_NO_DATA]",
+                op
+            )));
+        }
+
+        match bincode::deserialize::<ReplicationQuery>(value) {
+            Ok(query) => Ok(query),
+            Err(e) => Err(format_error(op, &anyhow::Error::new(e))),
+        }
+    }
+
+    fn to_contents(op: &TuringOp, contents: bincode::Result<Vec<u8>>) -> DbOps {
+        match contents {
+            Ok(contents) => DbOps::FieldContents(contents),
+            Err(e) => format_error(op, &anyhow::Error::new(e)),
+        }
+    }
+}
+
+/// ### Replicate the databases of a leader into a read-only replica
+/// The replica polls the leader for the changes after its positions, bootstraps every database
+/// it has no usable position for from a snapshot and connects again after any error.
+/// Leader and replica have to use the same master key for encrypted databases
+///
+/// #### Usage
+/// ```rust
+/// let mut engine = TuringEngine::builder().replica(true).build().await?;
+/// engine.repo_init().await?;
+/// let storage = Arc::new(engine);
+///
+/// Task::spawn(follow("127.0.0.1:4343".to_owned(), Arc::clone(&storage))).detach();
+/// ```
+pub(crate) async fn follow(leader: String, storage: Arc<TuringEngine>) {
+    loop {
+        match TcpStream::connect(&leader).await {
+            Err(e) => eprintln!("[TuringDB::<Replication>::(ERROR)-{:?}]", e), //FIXME log!()
+            Ok(mut stream) => {
+                println!("→[FOLLOWING] leader[{}]", leader);
+
+                loop {
+                    match replicate(&mut stream, storage.clone()).await {
+                        Ok(true) => (),
+                        Ok(false) => {
+                            Timer::new(REPLICATION_IDLE).await;
+                        }
+                        Err(e) => {
+                            eprintln!("[TuringDB::<Replication>::(ERROR)-{:?}]", e); //FIXME log!()
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+
+        Timer::new(REPLICATION_RECONNECT).await;
+    }
+}
+
+/// Apply a single batch of changes from the leader.
+/// Returns `false` if the leader had no new changes
+async fn replicate(stream: &mut TcpStream, storage: Arc<TuringEngine>) -> Result<bool> {
+    let positions = match storage.replication_positions().await {
+        Ok(positions) => positions,
+        Err(e) => bail!("{:?}", e),
+    };
+
+    let packet = turingdb_helpers::ReplicationQuery::new()
+        .positions(positions)
+        .max(REPLICATION_BATCH)
+        .poll()?;
+    let batch = bincode::deserialize::<ReplicationBatch>(&request(stream, &packet).await?)?;
+
+    let changed = batch
+        .databases
+        .values()
+        .any(|changes| !changes.records.is_empty());
+
+    let bootstrap = match storage.replication_apply(batch).await {
+        Ok(bootstrap) => bootstrap,
+        Err(e) => bail!("{:?}", e),
+    };
+
+    for db in bootstrap.iter() {
+        let packet = turingdb_helpers::ReplicationQuery::new()
+            .db(db)
+            .snapshot()?;
+        let snapshot = bincode::deserialize::<DbSnapshot>(&request(stream, &packet).await?)?;
+
+        match storage.replication_bootstrap(snapshot).await {
+            Ok(_) => println!("↓[BOOTSTRAPPED] database[{}]", db),
+            Err(e) => bail!("{:?}", e),
+        }
+    }
+
+    Ok(changed || !bootstrap.is_empty())
+}
+
+/// Send a query to the leader and return the contents of its response
+async fn request(stream: &mut TcpStream, packet: &[u8]) -> Result<Vec<u8>> {
+    stream.write_all(packet).await?;
+    stream.flush().await?;
+
+    let mut buffer = [0; RESPONSE_BUFFER_CAPACITY];
+    let mut container_buffer: Vec<u8> = Vec::new();
+
+    loop {
+        let bytes_read = stream.read(&mut buffer).await?;
+
+        if bytes_read == 0 {
+            bail!("The leader closed the connection");
+        }
+
+        container_buffer.extend_from_slice(&buffer[..bytes_read]);
+
+        // A read shorter than the buffer is likely the end of the response
+        if bytes_read < RESPONSE_BUFFER_CAPACITY {
+            match bincode::deserialize::<DbOps>(&container_buffer) {
+                Ok(DbOps::FieldContents(contents)) => return Ok(contents),
+                Ok(op_result) => return Err(anyhow!("{:?}", op_result)),
+                // The rest of the response has not arrived yet
+                Err(_) => continue,
+            }
+        }
+    }
+}
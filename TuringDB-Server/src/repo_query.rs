@@ -1,62 +1,49 @@
-use crate::errors::format_error;
+use crate::errors::format_engine_error;
 use async_dup::Arc;
-use custom_codes::{DbOps, DownCastErrors};
-use turingdb::TuringEngine;
+use custom_codes::DbOps;
+use turingdb::{TuringDbError, TuringEngine};
 use turingdb_helpers::TuringOp;
 
 /// Handles repository queries
 /// ```rust
 /// pub(crate) struct RepoQuery;
 /// ```
+///
+/// #### Usage
+/// ```rust
+/// let mut engine = TuringEngine::new().await?;
+/// engine.repo_init().await?;
+/// let storage = Arc::new(engine);
+///
+/// let outcome = RepoQuery::audit_verify(Arc::clone(&storage)).await;
+/// ```
 pub(crate) struct RepoQuery;
 
 impl RepoQuery {
     /// ### Create a new repository
-    /// #### Usage
-    /// ```rust
-    /// use crate::RepoQuery;
-    /// use turingdb::TuringEngine;
-    ///
-    /// let foo = TuringEngine::new();
-    /// foo.repo_init().await;
-    /// // Start an async runtime
-    ///     |- let foo = Arc::new(&foo); // This `Arc` must be from a module supporting async
-    ///     |-  // spawn a task
-    ///             |- let foo = Arc::clone(&foo);
-    ///             |- RepoQuery::create(&foo).await;
-    /// ```
     pub async fn create(storage: Arc<TuringEngine>) -> DbOps {
         match storage.repo_create().await {
-            Ok(op_result) => op_result,
-            Err(e) => match custom_codes::try_downcast(&e) {
-                DownCastErrors::AlreadyExists => DbOps::RepoAlreadyExists,
-                DownCastErrors::PermissionDenied => DbOps::PermissionDenied,
-                _ => format_error(&TuringOp::RepoCreate, &e),
-            },
+            Ok(_) => DbOps::RepoCreated,
+            Err(TuringDbError::AlreadyExists) => DbOps::RepoAlreadyExists,
+            Err(TuringDbError::PermissionDenied) => DbOps::PermissionDenied,
+            Err(e) => format_engine_error(&TuringOp::RepoCreate, &e),
         }
     }
     /// ### Drop an existing repository
-    /// #### Usage
-    /// ```rust
-    /// use crate::RepoQuery;
-    /// use turingdb::TuringEngine;
-    ///
-    /// let foo = TuringEngine::new();
-    /// foo.repo_init().await;
-    /// // Start an async runtime
-    ///     |- let foo = Arc::new(&foo); // This `Arc` must be from a module supporting async
-    ///     |-  // spawn a task
-    ///             |- let foo = Arc::clone(&foo);
-    ///             |- RepoQuery::drop(&foo).await;
-    /// ```
     pub async fn drop(storage: Arc<TuringEngine>) -> DbOps {
         match storage.repo_drop().await {
-            Ok(op_result) => op_result,
-            Err(e) => match custom_codes::try_downcast(&e) {
-                DownCastErrors::NotFound => DbOps::RepoNotFound,
-                DownCastErrors::PermissionDenied => DbOps::PermissionDenied,
-                _ => format_error(&TuringOp::RepoDrop, &e),
-            },
+            Ok(_) => DbOps::RepoDropped,
+            Err(TuringDbError::NotFound) => DbOps::RepoNotFound,
+            Err(TuringDbError::PermissionDenied) => DbOps::PermissionDenied,
+            Err(e) => format_engine_error(&TuringOp::RepoDrop, &e),
+        }
+    }
+    /// ### Verify the audit log of the repository
+    pub async fn audit_verify(storage: Arc<TuringEngine>) -> DbOps {
+        match storage.audit_verify().await {
+            Ok(_) => DbOps::DbIntegrityConsistent,
+            Err(TuringDbError::AuditViolation(_)) => DbOps::DbIntegrityCorrupted,
+            Err(e) => format_engine_error(&TuringOp::AuditVerify, &e),
         }
     }
 }
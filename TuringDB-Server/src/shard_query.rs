@@ -0,0 +1,66 @@
+use crate::errors::{format_engine_error, format_error};
+use async_dup::Arc;
+use custom_codes::DbOps;
+use serde::{Deserialize, Serialize};
+use turingdb::{DbSnapshot, TuringEngine};
+use turingdb_helpers::TuringOp;
+
+/// Handles the queries that move databases between the nodes of a sharded cluster
+/// ```rust
+/// #[derive(Debug, Serialize, Deserialize)]
+/// pub(crate) struct ShardQuery {
+///     db: String,
+///     snapshot: Vec<u8>,
+/// }
+/// ```
+///
+/// #### Usage
+/// ```rust
+/// let mut engine = TuringEngine::new().await?;
+/// engine.repo_init().await?;
+/// let storage = Arc::new(engine);
+///
+/// // The bytes of a `turingdb_helpers::ShardQuery` following its `TuringOp` header
+/// let outcome = ShardQuery::import(Arc::clone(&storage), &packet[1..]).await;
+/// ```
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct ShardQuery {
+    db: String,
+    snapshot: Vec<u8>,
+}
+
+impl ShardQuery {
+    /// ### Add a database copied from another node
+    ///
+    /// This function also takes an array of bytes `&[u8]` as a parameter;
+    /// This array of bytes must be able to deserialize into a `crate::ShardQuery` struct  using bincode
+    /// and its `snapshot` into a `DbSnapshot` returned by a `ReplicationSnapshot` query
+    pub async fn import(storage: Arc<TuringEngine>, value: &[u8]) -> DbOps {
+        if value.is_empty() {
+            return DbOps::EncounteredErrors(
+                "[TuringDB::<ShardImport>::(ERROR)-GOOD_HEADER_NO_DATA]".to_owned(),
+            );
+        }
+
+        let query = match bincode::deserialize::<ShardQuery>(value) {
+            Ok(query) => query,
+            Err(e) => return format_error(&TuringOp::ShardImport, &anyhow::Error::new(e)),
+        };
+
+        let snapshot = match bincode::deserialize::<DbSnapshot>(&query.snapshot) {
+            Ok(snapshot) => snapshot,
+            Err(e) => return format_error(&TuringOp::ShardImport, &anyhow::Error::new(e)),
+        };
+
+        if snapshot.name != query.db {
+            return DbOps::EncounteredErrors(
+                "[TuringDB::<ShardImport>::(ERROR)-SNAPSHOT_OF_ANOTHER_DATABASE]".to_owned(),
+            );
+        }
+
+        match storage.db_import(&snapshot).await {
+            Ok(_) => DbOps::DbCreated,
+            Err(e) => format_engine_error(&TuringOp::ShardImport, &e),
+        }
+    }
+}
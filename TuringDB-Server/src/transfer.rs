@@ -0,0 +1,103 @@
+use anyhow::{bail, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use futures_lite::AsyncWriteExt;
+use smol::Unblock;
+use turingdb::{ExportFormat, TuringEngine};
+
+/// ### Export or import a database from the command line
+/// `turingdb-server export <DB>` writes the database to stdout and
+/// `turingdb-server import <DB>` adds the records read from stdin to the database.
+/// Both accept the options
+///
+/// - `--format <ndjson|cbor>` the format of the records, `ndjson` by default
+/// - `--repo <DIRECTORY>` the directory of the repo, `TuringDB-Repo` in the home directory by default
+///
+/// The repo must not be opened by a running server and `import` creates it if it does not exist
+/// ```text
+/// turingdb-server export users --repo /tmp/staging > users.ndjson
+/// turingdb-server import users --repo /tmp/production < users.ndjson
+/// ```
+pub(crate) fn run(command: &str, args: &[String]) -> Result<()> {
+    let db = match args.first() {
+        Some(db) if !db.starts_with("--") => Utf8PathBuf::from(db),
+        _ => bail!("MISSING_DATABASE_NAME"),
+    };
+
+    let mut format = ExportFormat::Ndjson;
+    let mut repo = None;
+
+    let mut options = args[1..].iter();
+    while let Some(option) = options.next() {
+        let value = match options.next() {
+            Some(value) => value,
+            None => bail!("MISSING_VALUE_FOR_{}", option),
+        };
+
+        match option.as_str() {
+            "--format" => {
+                format = match value.as_str() {
+                    "ndjson" => ExportFormat::Ndjson,
+                    "cbor" => ExportFormat::Cbor,
+                    _ => bail!("UNKNOWN_FORMAT_{}", value),
+                }
+            }
+            "--repo" => repo = Some(Utf8PathBuf::from(value)),
+            _ => bail!("UNKNOWN_OPTION_{}", option),
+        }
+    }
+
+    smol::run(async {
+        let mut builder = TuringEngine::builder();
+        if let Some(repo_dir) = repo {
+            builder = builder.repo_dir(repo_dir);
+        }
+
+        let mut engine = match builder.build().await {
+            Ok(engine) => engine,
+            Err(e) => bail!("{:?}", e),
+        };
+
+        // An import into a new environment creates its repo
+        if command == "import" && !engine.get_repo_dir().await.exists() {
+            if let Err(e) = engine.repo_create().await {
+                bail!("{:?}", e);
+            }
+        }
+
+        if let Err(e) = engine.repo_init().await {
+            bail!("{:?}", e);
+        }
+
+        match command {
+            "export" => export(&engine, &db, format).await,
+            _ => import(&mut engine, &db, format).await,
+        }
+    })
+}
+
+async fn export(engine: &TuringEngine, db: &Utf8Path, format: ExportFormat) -> Result<()> {
+    let mut stdout = Unblock::new(std::io::stdout());
+
+    match engine.export(db, format, &mut stdout).await {
+        Ok(outcome) => {
+            stdout.flush().await?;
+            eprintln!("{:?}", outcome);
+
+            Ok(())
+        }
+        Err(e) => bail!("{:?}", e),
+    }
+}
+
+async fn import(engine: &mut TuringEngine, db: &Utf8Path, format: ExportFormat) -> Result<()> {
+    let stdin = Unblock::new(std::io::stdin());
+
+    match engine.import(db, format, stdin).await {
+        Ok(outcome) => {
+            eprintln!("{:?}", outcome);
+
+            Ok(())
+        }
+        Err(e) => bail!("{:?}", e),
+    }
+}
@@ -0,0 +1,206 @@
+//! A leader and a `--follow` replica started as two processes on the loopback interface
+
+use camino::Utf8PathBuf;
+use custom_codes::DbOps;
+use std::{
+    net::{TcpListener, TcpStream},
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+use turingdb::{TDBCell, TuringEngine};
+use turingdb_helpers::{ClusterClient, ClusterMap, DbQuery, DocumentQuery, FieldQuery};
+
+const DB: &str = "db";
+const DOCUMENT: &str = "document";
+/// How long a server has to start or a replica to catch up
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A directory of the system temporary directory removed once the test is done
+struct TestDir {
+    path: Utf8PathBuf,
+}
+
+impl TestDir {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("turingdb-{}-{}", name, std::process::id()));
+        let path = Utf8PathBuf::from_path_buf(path).unwrap();
+
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+
+        Self { path }
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+/// A `turingdb-server` process killed once the test is done, with a client connected to it
+struct Server {
+    process: Child,
+    client: ClusterClient,
+}
+
+impl Server {
+    fn start(address: &str, args: &[&str]) -> Self {
+        let process = Command::new(env!("CARGO_BIN_EXE_turingdb-server"))
+            .arg("--listen")
+            .arg(address)
+            .args(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+
+        let mut map = ClusterMap::new();
+        map.add_node("server", address);
+
+        // The server listens once its repo is loaded
+        let started = Instant::now();
+        while TcpStream::connect(address).is_err() {
+            assert!(started.elapsed() < TIMEOUT, "The server did not start");
+            thread::sleep(Duration::from_millis(50));
+        }
+
+        Self {
+            process,
+            client: ClusterClient::new(map),
+        }
+    }
+
+    fn send(&mut self, packet: &[u8]) -> DbOps {
+        self.client.send(packet).unwrap()
+    }
+
+    /// The value of a field of `DOCUMENT`, `None` if the server does not hold it
+    fn value(&mut self, field: &str) -> Option<Vec<u8>> {
+        let packet = smol::block_on(async {
+            let mut query = FieldQuery::<Vec<u8>>::new().await;
+            query.db(DB).await;
+            query.document(DOCUMENT).await;
+            query.field(field).await;
+
+            query.get().await.unwrap()
+        });
+
+        match self.client.send(&packet) {
+            Ok(DbOps::FieldContents(contents)) => {
+                Some(TDBCell::from_bytes(&contents).unwrap().get_data().to_vec())
+            }
+            _ => None,
+        }
+    }
+
+    /// Wait for the field of `DOCUMENT` to hold `value`
+    fn wait_for(&mut self, field: &str, value: Option<&[u8]>) {
+        let started = Instant::now();
+
+        while self.value(field).as_deref() != value {
+            assert!(
+                started.elapsed() < TIMEOUT,
+                "The field {} did not replicate",
+                field
+            );
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+/// A loopback address no other process listens on
+fn free_address() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+
+    listener.local_addr().unwrap().to_string()
+}
+
+fn field_packet(field: &str, value: &[u8], modify: bool) -> Vec<u8> {
+    smol::block_on(async {
+        let mut query = FieldQuery::new().await;
+        query.db(DB).await;
+        query.document(DOCUMENT).await;
+        query.field(field).await;
+        query.payload(value.to_vec()).await;
+
+        match modify {
+            true => query.modify().unwrap(),
+            false => query.set().await.unwrap(),
+        }
+    })
+}
+
+#[test]
+fn a_follower_copies_the_writes_of_its_leader() {
+    let dir = TestDir::new("server-replication");
+    let (leader_repo, follower_repo) = (dir.path.join("leader"), dir.path.join("follower"));
+
+    // The leader serves an existing repo while a follower creates its own
+    smol::run(async {
+        let engine = TuringEngine::builder()
+            .repo_dir(leader_repo.clone())
+            .build()
+            .await
+            .unwrap();
+        engine.repo_create().await.unwrap();
+    });
+
+    let leader_address = free_address();
+    let mut leader = Server::start(&leader_address, &["--repo", leader_repo.as_str()]);
+
+    assert_eq!(
+        leader.send(&DbQuery::new().db(DB).create()),
+        DbOps::DbCreated
+    );
+    let mut document = DocumentQuery::new();
+    document.db(DB);
+    document.document(DOCUMENT);
+    assert_eq!(
+        leader.send(&document.create().unwrap()),
+        DbOps::DocumentCreated
+    );
+    assert_eq!(
+        leader.send(&field_packet("alice", b"admin", false)),
+        DbOps::FieldInserted
+    );
+
+    // The follower bootstraps from a snapshot of the leader
+    let mut follower = Server::start(
+        &free_address(),
+        &[
+            "--repo",
+            follower_repo.as_str(),
+            "--follow",
+            &leader_address,
+        ],
+    );
+    follower.wait_for("alice", Some(b"admin"));
+
+    // and then tails its changes
+    assert_eq!(
+        leader.send(&field_packet("alice", b"guest", true)),
+        DbOps::FieldModified
+    );
+    assert_eq!(
+        leader.send(&field_packet("bob", b"admin", false)),
+        DbOps::FieldInserted
+    );
+    follower.wait_for("alice", Some(b"guest"));
+    follower.wait_for("bob", Some(b"admin"));
+
+    // A follower is read-only
+    match follower.send(&field_packet("carol", b"admin", false)) {
+        DbOps::EncounteredErrors(error) => assert!(error.contains("ReadOnlyReplica")),
+        op_result => panic!("Unexpected response {:?}", op_result),
+    }
+    assert_eq!(leader.value("carol"), None);
+}
@@ -0,0 +1,462 @@
+use crate::{CipherErrors, CipherOps, XChaCha8Blake3Siv};
+use aes_gcm::Aes256Gcm;
+use chacha20poly1305::{
+    aead::{Aead, NewAead, Payload},
+    Key, XChaCha12Poly1305, XChaCha20Poly1305, XChaCha8Poly1305,
+};
+use secrecy::{ExposeSecret, Secret};
+
+/// Identifies the cipher that sealed a ciphertext.
+/// The identifier is stored as the first byte of every ciphertext and reuses the `DataType` tags
+/// so that data sealed before the default cipher changes can still be opened
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[allow(non_camel_case_types)]
+pub enum CipherKind {
+    XChaCha8Poly1305 = 0x29,
+    XChaCha12Poly1305 = 0x30,
+    XChaCha20Poly1305 = 0x31,
+    XChaCha8Blake3SIV = 0x33,
+    AES256_GCM = 0x34,
+}
+
+impl CipherKind {
+    /// Get the `CipherKind` from the identifier byte stored in front of a ciphertext
+    pub fn from_byte(value: u8) -> Option<CipherKind> {
+        match value {
+            0x29 => Some(CipherKind::XChaCha8Poly1305),
+            0x30 => Some(CipherKind::XChaCha12Poly1305),
+            0x31 => Some(CipherKind::XChaCha20Poly1305),
+            0x33 => Some(CipherKind::XChaCha8Blake3SIV),
+            0x34 => Some(CipherKind::AES256_GCM),
+            _ => None,
+        }
+    }
+    /// The size of the nonce in bytes
+    pub fn nonce_len(&self) -> usize {
+        match self {
+            CipherKind::AES256_GCM => 12,
+            _ => 24,
+        }
+    }
+}
+
+/// A `Cipher` only holds the key. A fresh random nonce is generated for every message and
+/// stored with the ciphertext, which is laid out as `cipher identifier || nonce || ciphertext`.
+/// The 24 byte nonces of the XChaCha ciphers are safe to pick at random for any number of messages,
+/// the 12 byte nonces of AES-256-GCM should not be used for more than 2^32 messages under one key
+#[derive(Debug, Clone)]
+#[allow(non_camel_case_types)]
+pub enum Cipher {
+    XChaCha8Poly1305 { key: Secret<[u8; 32]> },
+    XChaCha12Poly1305 { key: Secret<[u8; 32]> },
+    XChaCha20Poly1305 { key: Secret<[u8; 32]> },
+    XChaCha8Blake3SIV { key: Secret<[u8; 32]> },
+    AES256_GCM { key: Secret<[u8; 32]> },
+    InvalidCipher,
+}
+
+impl secrecy::DebugSecret for Cipher {}
+
+/// Ciphers are compared through the BLAKE3 hashes of their identifier and key
+/// since `blake3::Hash` equality is constant-time
+impl PartialEq for Cipher {
+    fn eq(&self, other: &Self) -> bool {
+        match (self.key_bytes(), other.key_bytes()) {
+            (Ok(key_bytes), Ok(other_key_bytes)) => {
+                blake3::hash(key_bytes.expose_secret())
+                    == blake3::hash(other_key_bytes.expose_secret())
+            }
+            (Err(_), Err(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Cipher {}
+
+impl Cipher {
+    /// Create a `Cipher` of the given kind from a key
+    pub fn new(kind: CipherKind, key: Secret<[u8; 32]>) -> Cipher {
+        match kind {
+            CipherKind::XChaCha8Poly1305 => Self::XChaCha8Poly1305 { key },
+            CipherKind::XChaCha12Poly1305 => Self::XChaCha12Poly1305 { key },
+            CipherKind::XChaCha20Poly1305 => Self::XChaCha20Poly1305 { key },
+            CipherKind::XChaCha8Blake3SIV => Self::XChaCha8Blake3SIV { key },
+            CipherKind::AES256_GCM => Self::AES256_GCM { key },
+        }
+    }
+    /// The kind of cipher used to seal new data
+    pub fn kind(&self) -> Option<CipherKind> {
+        match self {
+            Self::XChaCha8Poly1305 { .. } => Some(CipherKind::XChaCha8Poly1305),
+            Self::XChaCha12Poly1305 { .. } => Some(CipherKind::XChaCha12Poly1305),
+            Self::XChaCha20Poly1305 { .. } => Some(CipherKind::XChaCha20Poly1305),
+            Self::XChaCha8Blake3SIV { .. } => Some(CipherKind::XChaCha8Blake3SIV),
+            Self::AES256_GCM { .. } => Some(CipherKind::AES256_GCM),
+            Self::InvalidCipher => None,
+        }
+    }
+
+    /// The cipher identifier followed by the key, used to seal the key with another `Cipher`
+    pub(crate) fn key_bytes(&self) -> Result<Secret<Vec<u8>>, CipherErrors> {
+        match (self.kind(), self.key()) {
+            (Some(kind), Some(key)) => {
+                let mut key_bytes = Vec::with_capacity(33);
+                key_bytes.push(kind as u8);
+                key_bytes.extend_from_slice(key);
+
+                Ok(Secret::new(key_bytes))
+            }
+            _ => Err(CipherErrors::InvalidCipher),
+        }
+    }
+
+    fn key(&self) -> Option<&[u8; 32]> {
+        match self {
+            Self::XChaCha8Poly1305 { key }
+            | Self::XChaCha12Poly1305 { key }
+            | Self::XChaCha20Poly1305 { key }
+            | Self::XChaCha8Blake3SIV { key }
+            | Self::AES256_GCM { key } => Some(key.expose_secret()),
+            Self::InvalidCipher => None,
+        }
+    }
+
+    fn seal(
+        kind: CipherKind,
+        key: &[u8; 32],
+        nonce: &[u8],
+        payload: Payload,
+    ) -> Result<Vec<u8>, CipherErrors> {
+        let key = Key::from_slice(key);
+
+        let sealed = match kind {
+            CipherKind::XChaCha8Poly1305 => {
+                XChaCha8Poly1305::new(key).encrypt(nonce.into(), payload)
+            }
+            CipherKind::XChaCha12Poly1305 => {
+                XChaCha12Poly1305::new(key).encrypt(nonce.into(), payload)
+            }
+            CipherKind::XChaCha20Poly1305 => {
+                XChaCha20Poly1305::new(key).encrypt(nonce.into(), payload)
+            }
+            CipherKind::AES256_GCM => Aes256Gcm::new(key).encrypt(nonce.into(), payload),
+            CipherKind::XChaCha8Blake3SIV => {
+                return XChaCha8Blake3Siv::new(key.as_ref()).encrypt(nonce, payload)
+            }
+        };
+
+        match sealed {
+            Ok(ciphertext) => Ok(ciphertext),
+            Err(_) => Err(CipherErrors::EncryptionError),
+        }
+    }
+
+    fn open(
+        kind: CipherKind,
+        key: &[u8; 32],
+        nonce: &[u8],
+        payload: Payload,
+    ) -> Result<Secret<Vec<u8>>, CipherErrors> {
+        let key = Key::from_slice(key);
+
+        let opened = match kind {
+            CipherKind::XChaCha8Poly1305 => {
+                XChaCha8Poly1305::new(key).decrypt(nonce.into(), payload)
+            }
+            CipherKind::XChaCha12Poly1305 => {
+                XChaCha12Poly1305::new(key).decrypt(nonce.into(), payload)
+            }
+            CipherKind::XChaCha20Poly1305 => {
+                XChaCha20Poly1305::new(key).decrypt(nonce.into(), payload)
+            }
+            CipherKind::AES256_GCM => Aes256Gcm::new(key).decrypt(nonce.into(), payload),
+            CipherKind::XChaCha8Blake3SIV => {
+                return XChaCha8Blake3Siv::new(key.as_ref()).decrypt(nonce, payload)
+            }
+        };
+
+        match opened {
+            Ok(plaintext) => Ok(Secret::new(plaintext)),
+            Err(_) => Err(CipherErrors::DecryptionError),
+        }
+    }
+}
+
+impl CipherOps for Cipher {
+    fn encrypt(
+        &self,
+        plaintext: Secret<Vec<u8>>,
+        associated_data: &[u8],
+    ) -> core::result::Result<Vec<u8>, CipherErrors> {
+        let (kind, key) = match (self.kind(), self.key()) {
+            (Some(kind), Some(key)) => (kind, key),
+            _ => return Err(CipherErrors::InvalidCipher),
+        };
+
+        let mut nonce = vec![0u8; kind.nonce_len()];
+        if getrandom::getrandom(&mut nonce).is_err() {
+            return Err(CipherErrors::RandomnessUnavailable);
+        }
+
+        let payload = Payload {
+            msg: plaintext.expose_secret(),
+            aad: associated_data,
+        };
+        let ciphertext = Cipher::seal(kind, key, &nonce, payload)?;
+
+        let mut sealed = Vec::with_capacity(1 + nonce.len() + ciphertext.len());
+        sealed.push(kind as u8);
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+
+        Ok(sealed)
+    }
+    /// The first byte of the ciphertext selects the cipher used to open it so data sealed
+    /// by another kind of cipher can be opened as long as the key is the same
+    fn decrypt(
+        &self,
+        ciphertext: &[u8],
+        associated_data: &[u8],
+    ) -> core::result::Result<Secret<Vec<u8>>, CipherErrors> {
+        let (kind, ciphertext) = match ciphertext.split_first() {
+            None => return Err(CipherErrors::DecryptionError),
+            Some((identifier, ciphertext)) => match CipherKind::from_byte(*identifier) {
+                None => return Err(CipherErrors::InvalidCipher),
+                Some(kind) => (kind, ciphertext),
+            },
+        };
+
+        if ciphertext.len() < kind.nonce_len() {
+            return Err(CipherErrors::DecryptionError);
+        }
+
+        let (nonce, ciphertext) = ciphertext.split_at(kind.nonce_len());
+
+        let key = match self.key() {
+            None => return Err(CipherErrors::InvalidCipher),
+            Some(key) => key,
+        };
+
+        let payload = Payload {
+            msg: ciphertext,
+            aad: associated_data,
+        };
+
+        Cipher::open(kind, key, nonce, payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The inputs of the XChaCha20-Poly1305 test vector in section A.3.1 of draft-irtf-cfrg-xchacha-03
+    const PLAINTEXT: &[u8] = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+    const AAD: &str = "50515253c0c1c2c3c4c5c6c7";
+    const KEY: &str = "808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f";
+    const NONCE: &str = "404142434445464748494a4b4c4d4e4f5051525354555657";
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(&hex[index..index + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn key(hex: &str) -> [u8; 32] {
+        let mut key = [0u8; 32];
+        key.copy_from_slice(&from_hex(hex));
+
+        key
+    }
+
+    fn known_answer(
+        kind: CipherKind,
+        key: &[u8; 32],
+        nonce: &[u8],
+        plaintext: &[u8],
+        aad: &[u8],
+        expected: &str,
+    ) {
+        let sealed = Cipher::seal(
+            kind,
+            key,
+            nonce,
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .unwrap();
+        assert_eq!(sealed, from_hex(expected));
+
+        let opened = Cipher::open(kind, key, nonce, Payload { msg: &sealed, aad }).unwrap();
+        assert_eq!(opened.expose_secret().as_slice(), plaintext);
+    }
+
+    #[test]
+    fn xchacha20poly1305_draft_vector() {
+        // Ciphertext followed by the tag from section A.3.1 of draft-irtf-cfrg-xchacha-03
+        let expected = concat!(
+            "bd6d179d3e83d43b9576579493c0e939572a1700252bfaccbed2902c21396cbb",
+            "731c7f1b0b4aa6440bf3a82f4eda7e39ae64c6708c54c216cb96b72e1213b452",
+            "2f8c9ba40db5d945b11b69b982c1bb9e3f3fac2bc369488f76b2383565d3fff9",
+            "21f9664c97637da9768812f615c68b13b52ec0875924c1c7987947deafd8780a",
+            "cf49",
+        );
+
+        known_answer(
+            CipherKind::XChaCha20Poly1305,
+            &key(KEY),
+            &from_hex(NONCE),
+            PLAINTEXT,
+            &from_hex(AAD),
+            expected,
+        );
+    }
+
+    // There are no published vectors for the reduced-round variants. These apply the construction
+    // of draft-irtf-cfrg-xchacha-03 with HChaCha and ChaCha reduced to 12 and 8 rounds and the
+    // AEAD of RFC 8439 to the inputs of section A.3.1, computed by a reference implementation
+    // that reproduces the 20 round vector above
+    #[test]
+    fn xchacha12poly1305_reduced_round_vector() {
+        let expected = concat!(
+            "a72fecf89d872db14763f044e9cbeeb28a9e4dea987d6227c2221d19fa387418",
+            "cc6e1155c50e6224196a395fb4a18f70e6835982d420b7c147c27629ab0e52ea",
+            "5d8bf877e3841b55903530453e059c033c5a9a3e72ef5277a0970575057a874f",
+            "0ed2a5c9dfec3572679e5848e535642e1a2e7e3887c50317d0e2f9c0c7c1d71e",
+            "1b25",
+        );
+
+        known_answer(
+            CipherKind::XChaCha12Poly1305,
+            &key(KEY),
+            &from_hex(NONCE),
+            PLAINTEXT,
+            &from_hex(AAD),
+            expected,
+        );
+    }
+
+    #[test]
+    fn xchacha8poly1305_reduced_round_vector() {
+        let expected = concat!(
+            "e8d3cdd892148d476a97be2e6564240b7e416f42d86a535c61efef00a2c301e9",
+            "a56c97fa6002d1c2aaf3f59bc6901a3ec623d6dfe6e5b19c28bfdae5ee44b2fd",
+            "4cf4d721c2e3ce1c455740535385b85276bf2179378133c91d3ce6144eec4d9b",
+            "46d0939dabbca5b281fa14f3b03b753579708a998abd74866cefcc7782890918",
+            "c7df",
+        );
+
+        known_answer(
+            CipherKind::XChaCha8Poly1305,
+            &key(KEY),
+            &from_hex(NONCE),
+            PLAINTEXT,
+            &from_hex(AAD),
+            expected,
+        );
+    }
+
+    #[test]
+    fn aes256gcm_nist_vector() {
+        // Test case 16 of "The Galois/Counter Mode of Operation (GCM)" by McGrew and Viega
+        let key = key("feffe9928665731c6d6a8f9467308308feffe9928665731c6d6a8f9467308308");
+        let nonce = from_hex("cafebabefacedbaddecaf888");
+        let plaintext = from_hex(concat!(
+            "d9313225f88406e5a55909c5aff5269a86a7a9531534f7da2e4c303d8a318a72",
+            "1c3c0c95956809532fcf0e2449a6b525b16aedf5aa0de657ba637b39",
+        ));
+        let aad = from_hex("feedfacedeadbeeffeedfacedeadbeefabaddad2");
+        let expected = concat!(
+            "522dc1f099567d07f47f37a32a84427d643a8cdcbfe5c0c97598a2bd2555d1aa",
+            "8cb08e48590dbb3da7b08b1056828838c5f61e6393ba7a0abcc9f66276fc6ece",
+            "0f4e1768cddf8853bb2d551b",
+        );
+
+        known_answer(
+            CipherKind::AES256_GCM,
+            &key,
+            &nonce,
+            &plaintext,
+            &aad,
+            expected,
+        );
+    }
+
+    #[test]
+    fn sealed_data_opens_with_any_cipher_holding_the_key() {
+        for kind in [
+            CipherKind::XChaCha8Poly1305,
+            CipherKind::XChaCha12Poly1305,
+            CipherKind::XChaCha20Poly1305,
+            CipherKind::XChaCha8Blake3SIV,
+            CipherKind::AES256_GCM,
+        ] {
+            let cipher = Cipher::new(kind, Secret::new(key(KEY)));
+            let sealed = cipher
+                .encrypt(Secret::new(PLAINTEXT.to_vec()), b"field")
+                .unwrap();
+
+            let tag_len = match kind {
+                CipherKind::XChaCha8Blake3SIV => 32,
+                _ => 16,
+            };
+            assert_eq!(sealed[0], kind as u8);
+            assert_eq!(
+                sealed.len(),
+                1 + kind.nonce_len() + PLAINTEXT.len() + tag_len
+            );
+
+            let other = Cipher::new(CipherKind::XChaCha20Poly1305, Secret::new(key(KEY)));
+            let opened = other.decrypt(&sealed, b"field").unwrap();
+            assert_eq!(opened.expose_secret().as_slice(), PLAINTEXT);
+
+            assert!(matches!(
+                cipher.decrypt(&sealed, b"other field"),
+                Err(CipherErrors::DecryptionError)
+            ));
+        }
+    }
+
+    #[test]
+    fn tampered_or_unknown_ciphertext_is_rejected() {
+        let cipher = Cipher::new(CipherKind::XChaCha20Poly1305, Secret::new(key(KEY)));
+        let sealed = cipher
+            .encrypt(Secret::new(PLAINTEXT.to_vec()), &[])
+            .unwrap();
+
+        for index in [1, 25, sealed.len() - 1] {
+            let mut tampered = sealed.clone();
+            tampered[index] ^= 0x01;
+
+            assert!(matches!(
+                cipher.decrypt(&tampered, &[]),
+                Err(CipherErrors::DecryptionError)
+            ));
+        }
+
+        let mut unknown = sealed.clone();
+        unknown[0] = 0x28;
+        assert!(matches!(
+            cipher.decrypt(&unknown, &[]),
+            Err(CipherErrors::InvalidCipher)
+        ));
+        assert!(matches!(
+            cipher.decrypt(&sealed[..10], &[]),
+            Err(CipherErrors::DecryptionError)
+        ));
+        assert!(matches!(
+            cipher.decrypt(&[], &[]),
+            Err(CipherErrors::DecryptionError)
+        ));
+
+        let wrong_key = Cipher::new(CipherKind::XChaCha20Poly1305, Secret::new([7u8; 32]));
+        assert!(matches!(
+            wrong_key.decrypt(&sealed, &[]),
+            Err(CipherErrors::DecryptionError)
+        ));
+    }
+}
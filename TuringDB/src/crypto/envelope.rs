@@ -0,0 +1,150 @@
+use crate::{Cipher, CipherErrors, CipherKind, CipherOps};
+use secrecy::{ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
+
+const WRAPPED_KEY_CONTEXT: &[u8] = b"TuringDB data key";
+
+/// The data keys of a database sealed with the master key.
+/// `previous` only exists while the data of the database is being re-encrypted
+/// with the `current` data key after a key rotation
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct WrappedDataKeys {
+///     current: Vec<u8>,
+///     previous: Option<Vec<u8>>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WrappedDataKeys {
+    current: Vec<u8>,
+    previous: Option<Vec<u8>>,
+}
+
+/// The unwrapped data keys of a database
+#[derive(Debug, Clone)]
+pub struct DataKeys {
+    pub current: Cipher,
+    pub previous: Option<Cipher>,
+}
+
+impl DataKeys {
+    /// Generate a new random data key of the same kind as the master key
+    pub fn generate(master_key: &Cipher) -> Result<DataKeys, CipherErrors> {
+        Ok(Self {
+            current: DataKeys::generate_key(master_key)?,
+            previous: None,
+        })
+    }
+    /// Replace the current data key with a new random one, keeping the old one
+    /// as `previous` so existing data can still be read until it is re-encrypted
+    pub fn rotate(&self, master_key: &Cipher) -> Result<DataKeys, CipherErrors> {
+        Ok(Self {
+            current: DataKeys::generate_key(master_key)?,
+            previous: Some(self.current.clone()),
+        })
+    }
+    /// Seal the data keys with the master key, binding them to the database name
+    pub fn wrap(
+        &self,
+        master_key: &Cipher,
+        db_name: &str,
+    ) -> Result<WrappedDataKeys, CipherErrors> {
+        let associated_data = DataKeys::associated_data(db_name);
+
+        let previous = match &self.previous {
+            None => None,
+            Some(previous) => Some(master_key.encrypt(previous.key_bytes()?, &associated_data)?),
+        };
+
+        Ok(WrappedDataKeys {
+            current: master_key.encrypt(self.current.key_bytes()?, &associated_data)?,
+            previous,
+        })
+    }
+
+    fn generate_key(master_key: &Cipher) -> Result<Cipher, CipherErrors> {
+        let kind = match master_key.kind() {
+            None => return Err(CipherErrors::InvalidCipher),
+            Some(kind) => kind,
+        };
+
+        let mut key = [0u8; 32];
+        if getrandom::getrandom(&mut key).is_err() {
+            return Err(CipherErrors::RandomnessUnavailable);
+        }
+
+        let cipher = Cipher::new(kind, Secret::new(key));
+        key.zeroize();
+
+        Ok(cipher)
+    }
+
+    fn associated_data(db_name: &str) -> Vec<u8> {
+        let mut associated_data = WRAPPED_KEY_CONTEXT.to_vec();
+        associated_data.extend_from_slice(db_name.as_bytes());
+
+        associated_data
+    }
+}
+
+impl WrappedDataKeys {
+    /// Open the data keys with the master key
+    pub fn unwrap(&self, master_key: &Cipher, db_name: &str) -> Result<DataKeys, CipherErrors> {
+        let associated_data = DataKeys::associated_data(db_name);
+
+        let previous = match &self.previous {
+            None => None,
+            Some(previous) => Some(WrappedDataKeys::unwrap_key(
+                master_key,
+                previous,
+                &associated_data,
+            )?),
+        };
+
+        Ok(DataKeys {
+            current: WrappedDataKeys::unwrap_key(master_key, &self.current, &associated_data)?,
+            previous,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, CipherErrors> {
+        match bincode::serialize::<WrappedDataKeys>(self) {
+            Ok(bytes) => Ok(bytes),
+            Err(_) => Err(CipherErrors::InvalidCipher),
+        }
+    }
+
+    pub fn from_bytes(value: &[u8]) -> Result<WrappedDataKeys, CipherErrors> {
+        match bincode::deserialize::<WrappedDataKeys>(value) {
+            Ok(wrapped) => Ok(wrapped),
+            Err(_) => Err(CipherErrors::InvalidCipher),
+        }
+    }
+
+    fn unwrap_key(
+        master_key: &Cipher,
+        wrapped: &[u8],
+        associated_data: &[u8],
+    ) -> Result<Cipher, CipherErrors> {
+        let key_bytes = master_key.decrypt(wrapped, associated_data)?;
+        let key_bytes = key_bytes.expose_secret();
+
+        if key_bytes.len() != 33 {
+            return Err(CipherErrors::DecryptionError);
+        }
+
+        let kind = match CipherKind::from_byte(key_bytes[0]) {
+            None => return Err(CipherErrors::InvalidCipher),
+            Some(kind) => kind,
+        };
+
+        let mut key = [0u8; 32];
+        key.copy_from_slice(&key_bytes[1..]);
+
+        let cipher = Cipher::new(kind, Secret::new(key));
+        key.zeroize();
+
+        Ok(cipher)
+    }
+}
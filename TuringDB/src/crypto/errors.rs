@@ -1,4 +1,5 @@
 /// These types don't convey much information to avoid potential side-channel leakage (e.g. padding oracle).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum CipherErrors {
     /// The cipher used has not been implemented yet or the cipher is not supported
     InvalidCipher,
@@ -6,4 +7,8 @@ pub enum CipherErrors {
     EncryptionError,
     /// The data could not be decrypted
     DecryptionError,
+    /// The operating system could not provide random bytes for a nonce
+    RandomnessUnavailable,
+    /// A key could not be derived from the passphrase
+    KeyDerivationError,
 }
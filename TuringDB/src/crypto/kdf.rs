@@ -0,0 +1,87 @@
+use crate::{Cipher, CipherErrors, CipherKind};
+use argon2::{Algorithm, Argon2, Params, Version};
+use secrecy::{ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
+
+/// Memory cost in KiB, iterations and lanes recommended for Argon2id
+const ARGON2_MEMORY_KIB: u32 = 19 * 1024;
+const ARGON2_ITERATIONS: u32 = 2;
+const ARGON2_PARALLELISM: u32 = 1;
+
+/// The salt and Argon2id parameters used to derive a master key from a passphrase.
+/// These are not secret and are stored in the repo so the same key is derived every time
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct KeyDerivation {
+///     salt: [u8; 16],
+///     memory_kib: u32,
+///     iterations: u32,
+///     parallelism: u32,
+///     kind: u8,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyDerivation {
+    salt: [u8; 16],
+    memory_kib: u32,
+    iterations: u32,
+    parallelism: u32,
+    kind: u8,
+}
+
+impl KeyDerivation {
+    /// Generate a random salt and use the recommended Argon2id parameters
+    pub fn generate(kind: CipherKind) -> Result<KeyDerivation, CipherErrors> {
+        let mut salt = [0u8; 16];
+        if getrandom::getrandom(&mut salt).is_err() {
+            return Err(CipherErrors::RandomnessUnavailable);
+        }
+
+        Ok(Self {
+            salt,
+            memory_kib: ARGON2_MEMORY_KIB,
+            iterations: ARGON2_ITERATIONS,
+            parallelism: ARGON2_PARALLELISM,
+            kind: kind as u8,
+        })
+    }
+    /// Derive the master key `Cipher` from the passphrase
+    pub fn derive(&self, passphrase: &Secret<String>) -> Result<Cipher, CipherErrors> {
+        let kind = match CipherKind::from_byte(self.kind) {
+            None => return Err(CipherErrors::InvalidCipher),
+            Some(kind) => kind,
+        };
+
+        let params = match Params::new(self.memory_kib, self.iterations, self.parallelism, Some(32))
+        {
+            Ok(params) => params,
+            Err(_) => return Err(CipherErrors::KeyDerivationError),
+        };
+
+        let mut key = [0u8; 32];
+        let derived = match Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.expose_secret().as_bytes(), &self.salt, &mut key)
+        {
+            Ok(_) => Ok(Cipher::new(kind, Secret::new(key))),
+            Err(_) => Err(CipherErrors::KeyDerivationError),
+        };
+        key.zeroize();
+
+        derived
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, CipherErrors> {
+        match bincode::serialize::<KeyDerivation>(self) {
+            Ok(bytes) => Ok(bytes),
+            Err(_) => Err(CipherErrors::KeyDerivationError),
+        }
+    }
+
+    pub fn from_bytes(value: &[u8]) -> Result<KeyDerivation, CipherErrors> {
+        match bincode::deserialize::<KeyDerivation>(value) {
+            Ok(key_derivation) => Ok(key_derivation),
+            Err(_) => Err(CipherErrors::KeyDerivationError),
+        }
+    }
+}
@@ -1,5 +1,11 @@
-mod aead_chacha8;
-pub use aead_chacha8::*;
+mod aead;
+pub use aead::*;
+mod siv;
+pub(crate) use siv::*;
+mod kdf;
+pub use kdf::*;
+mod envelope;
+pub use envelope::*;
 mod traits;
 pub use traits::*;
 mod errors;
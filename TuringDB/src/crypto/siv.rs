@@ -0,0 +1,201 @@
+use crate::CipherErrors;
+use chacha20::{
+    cipher::{NewCipher, StreamCipher},
+    Key, XChaCha8, XNonce,
+};
+use chacha20poly1305::aead::Payload;
+use secrecy::Secret;
+use zeroize::Zeroize;
+
+const ENCRYPTION_CONTEXT: &str = "TuringDB 2021-04-20 XChaCha8Blake3SIV encryption key";
+const AUTHENTICATION_CONTEXT: &str = "TuringDB 2021-04-20 XChaCha8Blake3SIV authentication key";
+const TAG_LEN: usize = 32;
+
+/// A deterministic authenticated cipher (SIV) made from the XChaCha8 stream cipher and keyed BLAKE3.
+///
+/// The 32 byte tag is the keyed BLAKE3 hash of the nonce, associated data and plaintext.
+/// The first 24 bytes of the tag are then used as the XChaCha8 nonce, so reusing a nonce
+/// only reveals whether two plaintexts are equal. Separate encryption and authentication keys are derived from the key
+/// using BLAKE3 in key derivation mode.
+///
+/// The output is laid out as `tag || ciphertext`. The derived keys are wiped when it is dropped
+pub(crate) struct XChaCha8Blake3Siv {
+    encryption_key: [u8; 32],
+    authentication_key: [u8; 32],
+}
+
+impl XChaCha8Blake3Siv {
+    pub(crate) fn new(key: &[u8]) -> Self {
+        Self {
+            encryption_key: blake3::derive_key(ENCRYPTION_CONTEXT, key),
+            authentication_key: blake3::derive_key(AUTHENTICATION_CONTEXT, key),
+        }
+    }
+
+    pub(crate) fn encrypt(&self, nonce: &[u8], payload: Payload) -> Result<Vec<u8>, CipherErrors> {
+        let tag = self.tag(nonce, payload.aad, payload.msg);
+
+        let mut sealed = Vec::with_capacity(TAG_LEN + payload.msg.len());
+        sealed.extend_from_slice(tag.as_bytes());
+        sealed.extend_from_slice(payload.msg);
+
+        self.apply_keystream(tag.as_bytes(), &mut sealed[TAG_LEN..]);
+
+        Ok(sealed)
+    }
+
+    pub(crate) fn decrypt(
+        &self,
+        nonce: &[u8],
+        payload: Payload,
+    ) -> Result<Secret<Vec<u8>>, CipherErrors> {
+        if payload.msg.len() < TAG_LEN {
+            return Err(CipherErrors::DecryptionError);
+        }
+
+        let (tag, ciphertext) = payload.msg.split_at(TAG_LEN);
+
+        let mut plaintext = ciphertext.to_vec();
+        self.apply_keystream(tag, &mut plaintext);
+
+        let mut expected_tag = [0u8; TAG_LEN];
+        expected_tag.copy_from_slice(tag);
+
+        // `blake3::Hash` equality is constant-time
+        if self.tag(nonce, payload.aad, &plaintext) == blake3::Hash::from(expected_tag) {
+            Ok(Secret::new(plaintext))
+        } else {
+            plaintext.zeroize();
+
+            Err(CipherErrors::DecryptionError)
+        }
+    }
+
+    fn tag(&self, nonce: &[u8], associated_data: &[u8], plaintext: &[u8]) -> blake3::Hash {
+        let mut hasher = blake3::Hasher::new_keyed(&self.authentication_key);
+        hasher.update(&(nonce.len() as u64).to_le_bytes());
+        hasher.update(nonce);
+        hasher.update(&(associated_data.len() as u64).to_le_bytes());
+        hasher.update(associated_data);
+        hasher.update(plaintext);
+
+        hasher.finalize()
+    }
+
+    fn apply_keystream(&self, tag: &[u8], buffer: &mut [u8]) {
+        let key = Key::from_slice(&self.encryption_key);
+        let nonce = XNonce::from_slice(&tag[..24]);
+
+        XChaCha8::new(key, nonce).apply_keystream(buffer);
+    }
+}
+
+impl Drop for XChaCha8Blake3Siv {
+    fn drop(&mut self) {
+        self.encryption_key.zeroize();
+        self.authentication_key.zeroize();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use secrecy::ExposeSecret;
+
+    const KEY: [u8; 32] = [0x42; 32];
+    const NONCE: [u8; 24] = [0x24; 24];
+    const PLAINTEXT: &[u8] = b"a field sealed by the SIV cipher";
+    const AAD: &[u8] = b"db/document/key";
+
+    fn seal(key: &[u8], nonce: &[u8], plaintext: &[u8], aad: &[u8]) -> Vec<u8> {
+        XChaCha8Blake3Siv::new(key)
+            .encrypt(
+                nonce,
+                Payload {
+                    msg: plaintext,
+                    aad,
+                },
+            )
+            .unwrap()
+    }
+
+    fn open(
+        key: &[u8],
+        nonce: &[u8],
+        sealed: &[u8],
+        aad: &[u8],
+    ) -> Result<Secret<Vec<u8>>, CipherErrors> {
+        XChaCha8Blake3Siv::new(key).decrypt(nonce, Payload { msg: sealed, aad })
+    }
+
+    #[test]
+    fn round_trip() {
+        for plaintext in [&[][..], b"x", PLAINTEXT, &[0xff; 1000][..]] {
+            let sealed = seal(&KEY, &NONCE, plaintext, AAD);
+            assert_eq!(sealed.len(), TAG_LEN + plaintext.len());
+
+            let opened = open(&KEY, &NONCE, &sealed, AAD).unwrap();
+            assert_eq!(opened.expose_secret().as_slice(), plaintext);
+        }
+    }
+
+    #[test]
+    fn encryption_is_deterministic_for_a_nonce() {
+        assert_eq!(
+            seal(&KEY, &NONCE, PLAINTEXT, AAD),
+            seal(&KEY, &NONCE, PLAINTEXT, AAD)
+        );
+        assert_ne!(
+            seal(&KEY, &NONCE, PLAINTEXT, AAD),
+            seal(&KEY, &[0x25; 24], PLAINTEXT, AAD)
+        );
+
+        let sealed = seal(&KEY, &NONCE, PLAINTEXT, AAD);
+        assert_ne!(&sealed[TAG_LEN..], PLAINTEXT);
+    }
+
+    #[test]
+    fn tampering_is_detected() {
+        let sealed = seal(&KEY, &NONCE, PLAINTEXT, AAD);
+
+        for index in [0, TAG_LEN - 1, TAG_LEN, sealed.len() - 1] {
+            let mut tampered = sealed.clone();
+            tampered[index] ^= 0x01;
+
+            assert!(matches!(
+                open(&KEY, &NONCE, &tampered, AAD),
+                Err(CipherErrors::DecryptionError)
+            ));
+        }
+
+        assert!(matches!(
+            open(&KEY, &NONCE, &sealed[..sealed.len() - 1], AAD),
+            Err(CipherErrors::DecryptionError)
+        ));
+        assert!(matches!(
+            open(&KEY, &NONCE, &sealed[..TAG_LEN - 1], AAD),
+            Err(CipherErrors::DecryptionError)
+        ));
+        assert!(matches!(
+            open(&KEY, &NONCE, &sealed, b"db/document/other"),
+            Err(CipherErrors::DecryptionError)
+        ));
+        assert!(matches!(
+            open(&KEY, &[0x25; 24], &sealed, AAD),
+            Err(CipherErrors::DecryptionError)
+        ));
+    }
+
+    #[test]
+    fn wrong_key_is_rejected() {
+        let sealed = seal(&KEY, &NONCE, PLAINTEXT, AAD);
+
+        let mut wrong_key = KEY;
+        wrong_key[31] ^= 0x01;
+
+        assert!(matches!(
+            open(&wrong_key, &NONCE, &sealed, AAD),
+            Err(CipherErrors::DecryptionError)
+        ));
+    }
+}
@@ -2,8 +2,18 @@ use crate::CipherErrors;
 use secrecy::Secret;
 
 pub trait CipherOps {
-    /// FIXME add support for `Payload` and removing `as_ref()` as a result
-    fn encrypt(&self, plaintext: Secret<Vec<u8>>) -> core::result::Result<Vec<u8>, CipherErrors>;
+    /// Seal the plaintext with a freshly generated nonce.
+    /// The `associated_data` is authenticated but not encrypted and must be supplied again to `decrypt()`
+    fn encrypt(
+        &self,
+        plaintext: Secret<Vec<u8>>,
+        associated_data: &[u8],
+    ) -> core::result::Result<Vec<u8>, CipherErrors>;
 
-    fn decrypt(&self, ciphertext: &[u8]) -> core::result::Result<Vec<u8>, CipherErrors>;
+    /// Open the ciphertext. The plaintext is returned as a `Secret` so it is wiped from memory once dropped
+    fn decrypt(
+        &self,
+        ciphertext: &[u8],
+        associated_data: &[u8],
+    ) -> core::result::Result<Secret<Vec<u8>>, CipherErrors>;
 }
@@ -1,11 +1,51 @@
-use async_lock::Mutex;
 use camino::{Utf8Path, Utf8PathBuf};
-use sled::IVec;
-use std::io::ErrorKind;
+use serde::{Deserialize, Serialize};
+use sled::{transaction::TransactionError, IVec};
+use std::{io::ErrorKind, time::Duration};
+use zeroize::{Zeroize, Zeroizing};
 
-use crate::TuringDB;
+use crate::{BatchResult, BulkProgress, CipherErrors, FieldData};
 
 const REPO_NAME: &str = "TuringDB-Repo";
+/// Marker file in a database directory showing that all its documents are encrypted
+pub(crate) const DB_ENCRYPTED_MARKER: &str = "ENCRYPTED";
+/// File in a database directory holding its data keys sealed with the master key
+pub(crate) const DATA_KEYS_FILE: &str = "DATA_KEYS";
+/// File in the repo directory holding the salt and parameters used to derive the master key
+pub(crate) const KEY_DERIVATION_FILE: &str = "KEY_DERIVATION";
+/// File in the repo directory holding the key of the integrity manifests
+pub(crate) const INTEGRITY_KEY_FILE: &str = "INTEGRITY_KEY";
+/// File in the repo directory listing its databases and documents
+pub(crate) const REPO_MANIFEST_FILE: &str = "MANIFEST";
+/// The append-only log of mutations in the repo directory
+pub(crate) const OPS_LOG_FILE: &str = "ops.log";
+/// The hash-chained audit log in the repo directory
+pub(crate) const AUDIT_LOG_FILE: &str = "audit.log";
+/// File in the repo directory holding the number of audit entries and the hash of the last one
+pub(crate) const AUDIT_HEAD_FILE: &str = "AUDIT_HEAD";
+/// Prefix of the segments of the CDC log in a database directory, followed by the sequence of their first record
+pub(crate) const CDC_LOG_FILE: &str = "cdc";
+/// File in a database directory holding the offsets committed by the consumer groups of its CDC log
+pub(crate) const CDC_OFFSETS_FILE: &str = "CDC_OFFSETS";
+/// File in the repo directory of a replica holding the position of every replicated database
+pub(crate) const REPLICA_POSITIONS_FILE: &str = "REPLICA_POSITIONS";
+/// The Raft log of a node in the repo directory holding its term, vote and entries
+pub(crate) const RAFT_LOG_FILE: &str = "raft.log";
+/// File in the repo directory of a Raft node holding its latest snapshot
+pub(crate) const RAFT_SNAPSHOT_FILE: &str = "RAFT_SNAPSHOT";
+/// File in a backup directory listing its databases and the checksums of their files
+pub(crate) const BACKUP_MANIFEST_FILE: &str = "BACKUP_MANIFEST";
+/// Marker file in the repo directory showing that the staged key files of a master key change
+/// are to replace the key files in use
+pub(crate) const MASTER_KEY_CHANGE_FILE: &str = "MASTER_KEY_CHANGE";
+/// Extension of a key file staged by a master key change
+pub(crate) const STAGED_EXTENSION: &str = "next";
+/// The actor recorded in the ops.log when an operation does not name one
+pub const DEFAULT_ACTOR: &str = "local";
+/// The actor recorded in the ops.log for the changes a replica applies from its leader
+pub const REPLICATION_ACTOR: &str = "replication";
+/// Extension of the marker file in a database directory showing that a document is encrypted
+pub(crate) const DOCUMENT_ENCRYPTED_MARKER: &str = "encrypted";
 
 pub type TuringResult<T> = Result<T, TuringDbError>;
 pub type Document = sled::Db;
@@ -42,6 +82,105 @@ pub enum TuringDbError {
     SystemViolation(String),
     Bug(String),
     DocumentCorrupted { at: Option<sled::DiskPtr>, bt: () },
+    FieldNotFound,
+    FieldDataCorrupted,
+    FieldNotJson,
+    JsonInvalid(String),
+    JsonPathInvalid(String),
+    JsonPathNotFound(String),
+    EncryptionKeyMissing,
+    KeyRotationInProgress,
+    Cipher(CipherErrors),
+    IntegrityKeyCorrupted,
+    /// The repo has integrity manifests but its `INTEGRITY_KEY` is gone,
+    /// see `TuringEngineBuilder::rebuild_manifests()`
+    IntegrityKeyMissing,
+    MasterKeyChangeCorrupted,
+    IntegrityViolation(Vec<IntegrityViolation>),
+    OpsLogCorrupted { file: String, offset: u64 },
+    AuditViolation(AuditViolation),
+    CdcLogCorrupted { file: String, offset: u64 },
+    CdcOffsetOutOfRange { requested: u64, first: u64, next: u64 },
+    ReadOnlyReplica,
+    NotReplica,
+    ReplicaPositionsCorrupted,
+    RaftNotLeader { leader: Option<u64> },
+    RaftMembershipChangePending,
+    RaftProposalDropped,
+    RaftLogCorrupted { file: String, offset: u64 },
+    BackupCorrupted { file: String },
+    BackupChainBroken { backup: String },
+    HistoryDisabled,
+    ImportInvalid { record: u64, error: String },
+    BatchFailed { operation: u64, error: String },
+    IndexNotFound,
+    IndexAlreadyExists,
+    IndexOnEncryptedDocument,
+    UniqueViolation { index: String, key: FieldKey },
+}
+
+/// The first problem found while verifying the audit log
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AuditViolation {
+    /// The entry at this position does not match its hash
+    EntryModified { position: u64 },
+    /// The entry at this position has another entry's sequence number
+    EntryOutOfOrder { position: u64, sequence: u64 },
+    /// The entry does not carry the hash of the entry before it
+    ChainBroken { sequence: u64 },
+    /// Entries were removed from the end of the log
+    Truncated { expected: u64, found: u64 },
+    /// The log ends with part of an entry
+    IncompleteEntry { position: u64 },
+    /// The log has entries but its head is missing
+    HeadMissing,
+    /// The head does not match its hash
+    HeadModified,
+}
+
+impl From<AuditViolation> for TuringDbError {
+    fn from(violation: AuditViolation) -> Self {
+        TuringDbError::AuditViolation(violation)
+    }
+}
+
+/// A change to the repo that was not made through TuringDB
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum IntegrityViolation {
+    /// The repo has databases but its `MANIFEST` file is missing
+    ManifestMissing,
+    /// The `MANIFEST` file does not match its hash
+    ManifestModified,
+    DatabaseAdded(Utf8PathBuf),
+    DatabaseRemoved(Utf8PathBuf),
+    DocumentAdded {
+        db: Utf8PathBuf,
+        document: Utf8PathBuf,
+    },
+    DocumentRemoved {
+        db: Utf8PathBuf,
+        document: Utf8PathBuf,
+    },
+    /// The stored field hashes of the document do not match its Merkle root
+    DocumentModified {
+        db: Utf8PathBuf,
+        document: Utf8PathBuf,
+    },
+    FieldAdded {
+        db: Utf8PathBuf,
+        document: Utf8PathBuf,
+        field: FieldKey,
+    },
+    FieldModified {
+        db: Utf8PathBuf,
+        document: Utf8PathBuf,
+        field: FieldKey,
+    },
+    FieldRemoved {
+        db: Utf8PathBuf,
+        document: Utf8PathBuf,
+        field: FieldKey,
+    },
 }
 
 impl From<std::io::Error> for TuringDbError {
@@ -87,12 +226,30 @@ impl From<sled::Error> for TuringDbError {
     }
 }
 
+impl From<TransactionError<TuringDbError>> for TuringDbError {
+    fn from(error: TransactionError<TuringDbError>) -> Self {
+        match error {
+            TransactionError::Abort(error) => error,
+            TransactionError::Storage(error) => error.into(),
+        }
+    }
+}
+
+impl From<CipherErrors> for TuringDbError {
+    fn from(error: CipherErrors) -> Self {
+        TuringDbError::Cipher(error)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum OpsOutcome {
     /// A temporary value for testing
     OpsOutcomePlaceholder,
     RepoCreated,
     RepoInitialized,
+    RepoDropped,
+    RepoVerified,
+    AuditVerified(u64),
     RepoEmpty,
     DbCreated,
     DbDropped,
@@ -101,7 +258,35 @@ pub enum OpsOutcome {
     DocumentList(Vec<Utf8PathBuf>),
     DocumentCreated,
     DocumentDropped,
+    DocumentEmpty,
+    FieldList(Vec<FieldKey>),
     FieldInserted,
+    FieldModified,
+    FieldRemoved,
+    FieldContents(Vec<u8>),
+    JsonContents(String),
+    DataKeyRotationStarted,
+    MasterKeyRotated,
+    CdcOffsetCommitted,
+    ReplicaBootstrapped,
+    ReplicaApplied,
+    ReplicaDropped,
+    RepoRestored,
+    DbImported,
+    RaftMembershipChanged,
+    RepoRewound(u64),
+    FieldHistory(Vec<FieldData>),
+    HistoryRetentionSet,
+    HistoryCompacted(u64),
+    DbExported(u64),
+    FieldsImported(u64),
+    BulkInserted(BulkProgress),
+    DbFlushed,
+    BatchApplied(Vec<BatchResult>),
+    FieldsExpired(u64),
+    IndexCreated,
+    IndexDropped,
+    FieldsFound(Vec<(FieldKey, FieldData)>),
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -124,36 +309,99 @@ impl RepoPath {
             }
         }
     }
+    /// Replace a file through a synced temporary file and a rename,
+    /// so a crash leaves either the previous or the new contents
+    pub(crate) fn replace_file(path: &Utf8Path, contents: &[u8]) -> TuringResult<()> {
+        use std::io::Write;
+
+        let temporary = Utf8PathBuf::from(format!("{}.tmp", path));
+        let mut file = std::fs::File::create(&temporary)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+
+        std::fs::rename(&temporary, path)?;
+        RepoPath::sync_parent(path)
+    }
+    /// `replace_file()` on the blocking thread pool
+    pub(crate) async fn replace(path: &Utf8Path, contents: Vec<u8>) -> TuringResult<()> {
+        let path = path.to_path_buf();
+
+        blocking::unblock(move || RepoPath::replace_file(&path, &contents)).await
+    }
+    /// Make a rename or a removal in the directory of `path` durable
+    pub(crate) fn sync_parent(path: &Utf8Path) -> TuringResult<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::File::open(parent)?.sync_all()?;
+        }
+
+        Ok(())
+    }
+    /// The path a master key change stages the new contents of a key file at
+    pub(crate) fn staged(path: &Utf8Path) -> Utf8PathBuf {
+        Utf8PathBuf::from(format!("{}.{}", path, STAGED_EXTENSION))
+    }
 }
 
 pub type DBName = Utf8PathBuf;
 pub type RepoName = Utf8PathBuf;
 pub type DocumentName = Utf8PathBuf;
-pub type FieldKey = DataType;
-pub type FieldValue = DataType;
+pub type FieldKey = Vec<u8>;
+pub type FieldValue = Vec<u8>;
 
-pub struct TuringDBOps(DBName);
+pub struct TuringDBOps {
+    db_name: DBName,
+    encrypted: bool,
+    actor: String,
+}
 
 impl Default for TuringDBOps {
     fn default() -> Self {
-        Self(Utf8PathBuf::default())
+        Self {
+            db_name: DBName::default(),
+            encrypted: false,
+            actor: DEFAULT_ACTOR.to_owned(),
+        }
     }
 }
 
 impl TuringDBOps {
     pub fn set_db_name(mut self, db_name: &str) -> Self {
-        self.0 = Utf8Path::new(&db_name).to_path_buf();
+        self.db_name = Utf8Path::new(&db_name).to_path_buf();
+
+        self
+    }
+    /// Seal the values of every field in every document of the database with the engine's `Cipher`
+    pub fn set_encrypted(mut self, encrypted: bool) -> Self {
+        self.encrypted = encrypted;
+
+        self
+    }
+
+    /// Name the client or user performing the operation in the ops.log
+    pub fn set_actor(mut self, actor: &str) -> Self {
+        self.actor = actor.to_owned();
 
         self
     }
 
     pub fn get_db_name(&self) -> Utf8PathBuf {
-        self.0.to_owned()
+        self.db_name.to_owned()
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.encrypted
+    }
+
+    /// The client or user performing the operation, recorded in the ops.log
+    pub fn get_actor(&self) -> &str {
+        &self.actor
     }
 }
 pub struct TuringDBDocumentOps {
     db_name: DBName,
     document_name: DocumentName,
+    encrypted: bool,
+    actor: String,
 }
 
 impl Default for TuringDBDocumentOps {
@@ -161,6 +409,8 @@ impl Default for TuringDBDocumentOps {
         Self {
             db_name: DBName::default(),
             document_name: DocumentName::default(),
+            encrypted: false,
+            actor: DEFAULT_ACTOR.to_owned(),
         }
     }
 }
@@ -177,6 +427,19 @@ impl TuringDBDocumentOps {
 
         self
     }
+    /// Seal the values of every field in the document with the engine's `Cipher`
+    pub fn set_encrypted(mut self, encrypted: bool) -> Self {
+        self.encrypted = encrypted;
+
+        self
+    }
+
+    /// Name the client or user performing the operation in the ops.log
+    pub fn set_actor(mut self, actor: &str) -> Self {
+        self.actor = actor.to_owned();
+
+        self
+    }
 
     pub fn get_db_name(&self) -> Utf8PathBuf {
         self.db_name.to_owned()
@@ -185,6 +448,15 @@ impl TuringDBDocumentOps {
     pub fn get_document_name(&self) -> Utf8PathBuf {
         self.document_name.to_owned()
     }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.encrypted
+    }
+
+    /// The client or user performing the operation, recorded in the ops.log
+    pub fn get_actor(&self) -> &str {
+        &self.actor
+    }
 }
 
 pub struct TuringDBFieldOps {
@@ -192,6 +464,23 @@ pub struct TuringDBFieldOps {
     document_name: DocumentName,
     field_name: FieldKey,
     field_value: FieldValue,
+    data_type: DataType,
+    ttl: Option<Duration>,
+    actor: String,
+}
+
+impl Default for TuringDBFieldOps {
+    fn default() -> Self {
+        Self {
+            db_name: DBName::default(),
+            document_name: DocumentName::default(),
+            field_name: FieldKey::default(),
+            field_value: FieldValue::default(),
+            data_type: DataType::BINARY,
+            ttl: None,
+            actor: DEFAULT_ACTOR.to_owned(),
+        }
+    }
 }
 
 impl TuringDBFieldOps {
@@ -207,6 +496,38 @@ impl TuringDBFieldOps {
         self
     }
 
+    pub fn key(mut self, field_name: &[u8]) -> Self {
+        self.field_name = field_name.to_owned();
+
+        self
+    }
+
+    pub fn value(mut self, field_value: &[u8]) -> Self {
+        self.field_value = field_value.to_owned();
+
+        self
+    }
+
+    pub fn data_type(mut self, data_type: DataType) -> Self {
+        self.data_type = data_type;
+
+        self
+    }
+
+    /// Remove the field once `ttl` has passed, used by `field_set()` and `field_modify()`
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+
+        self
+    }
+
+    /// Name the client or user performing the operation in the ops.log
+    pub fn actor(mut self, actor: &str) -> Self {
+        self.actor = actor.to_owned();
+
+        self
+    }
+
     pub fn get_db_name(&self) -> Utf8PathBuf {
         self.db_name.to_owned()
     }
@@ -216,11 +537,113 @@ impl TuringDBFieldOps {
     }
 
     pub fn get_key(&self) -> FieldKey {
-        self.field_name
+        self.field_name.to_owned()
     }
 
     pub fn get_value(&self) -> FieldValue {
-        self.field_value
+        self.field_value.to_owned()
+    }
+
+    pub fn get_data_type(&self) -> DataType {
+        self.data_type
+    }
+
+    pub fn get_ttl(&self) -> Option<Duration> {
+        self.ttl
+    }
+
+    /// The client or user performing the operation, recorded in the ops.log
+    pub fn get_actor(&self) -> &str {
+        &self.actor
+    }
+}
+
+/// Operations on a JSON value stored in a field.
+/// An empty `path` refers to the whole JSON value, otherwise the path is made up of
+/// object keys separated by `.` and array indices like `user.addresses[0].city`
+pub struct TuringDBJsonOps {
+    db_name: DBName,
+    document_name: DocumentName,
+    field_name: FieldKey,
+    path: String,
+    value: Option<serde_json::Value>,
+    actor: String,
+}
+
+impl Default for TuringDBJsonOps {
+    fn default() -> Self {
+        Self {
+            db_name: DBName::default(),
+            document_name: DocumentName::default(),
+            field_name: FieldKey::default(),
+            path: String::default(),
+            value: None,
+            actor: DEFAULT_ACTOR.to_owned(),
+        }
+    }
+}
+
+impl TuringDBJsonOps {
+    pub fn db(mut self, db_name: &str) -> Self {
+        self.db_name = Utf8Path::new(&db_name).to_path_buf();
+
+        self
+    }
+
+    pub fn document(mut self, document_name: &str) -> Self {
+        self.document_name = Utf8Path::new(&document_name).to_path_buf();
+
+        self
+    }
+
+    pub fn key(mut self, field_name: &[u8]) -> Self {
+        self.field_name = field_name.to_owned();
+
+        self
+    }
+
+    pub fn path(mut self, path: &str) -> Self {
+        self.path = path.to_owned();
+
+        self
+    }
+
+    pub fn value(mut self, value: serde_json::Value) -> Self {
+        self.value = Some(value);
+
+        self
+    }
+
+    /// Name the client or user performing the operation in the ops.log
+    pub fn actor(mut self, actor: &str) -> Self {
+        self.actor = actor.to_owned();
+
+        self
+    }
+
+    pub fn get_db_name(&self) -> Utf8PathBuf {
+        self.db_name.to_owned()
+    }
+
+    pub fn get_document_name(&self) -> Utf8PathBuf {
+        self.document_name.to_owned()
+    }
+
+    pub fn get_key(&self) -> FieldKey {
+        self.field_name.to_owned()
+    }
+
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn get_value(&self) -> Option<&serde_json::Value> {
+        self.value.as_ref()
+    }
+
+    /// The client or user performing the operation, recorded in the ops.log
+    pub fn get_actor(&self) -> &str {
+        &self.actor
     }
 }
 
@@ -246,7 +669,7 @@ enum SerDerType {
 }
 */
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum DataType {
     Boolean = 0x00,
     U8 = 0x01,
@@ -283,17 +706,89 @@ pub enum DataType {
     CHACHAPOLY1305 = 0x32,
     XCHACHABLAKE3SIV = 0x33,
     AES256GCM = 0x34,
+    JSON = 0x35,
 }
 
-const TRUE: u8 = 1;
-const FALSE: u8 = 1;
+impl DataType {
+    /// Convert the tag byte stored in front of a field's data back into a `DataType`
+    pub fn from_byte(value: u8) -> Option<DataType> {
+        let data_type = match value {
+            0x00 => DataType::Boolean,
+            0x01 => DataType::U8,
+            0x02 => DataType::I8,
+            0x03 => DataType::U16,
+            0x04 => DataType::I16,
+            0x05 => DataType::U32,
+            0x06 => DataType::I32,
+            0x07 => DataType::U64,
+            0x08 => DataType::I64,
+            0x09 => DataType::U128,
+            0x10 => DataType::I128,
+            0x11 => DataType::F32,
+            0x12 => DataType::F64,
+            0x13 => DataType::STRING,
+            0x14 => DataType::ARRAY,
+            0x15 => DataType::UTC,
+            0x16 => DataType::TAI64,
+            0x17 => DataType::TAI64N,
+            0x18 => DataType::TAI64NA,
+            0x19 => DataType::RANGE,
+            0x20 => DataType::TIMESPEC,
+            0x21 => DataType::OPTION,
+            0x22 => DataType::BLAKE3,
+            0x23 => DataType::BLAKE3HMAC,
+            0x24 => DataType::SHA3,
+            0x25 => DataType::SHA3HMAC,
+            0x26 => DataType::BORSCH,
+            0x27 => DataType::GEO,
+            0x28 => DataType::BINARY,
+            0x29 => DataType::CHACHA8,
+            0x30 => DataType::CHACHA12,
+            0x31 => DataType::CHACHA20,
+            0x32 => DataType::CHACHAPOLY1305,
+            0x33 => DataType::XCHACHABLAKE3SIV,
+            0x34 => DataType::AES256GCM,
+            0x35 => DataType::JSON,
+            _ => return None,
+        };
+
+        Some(data_type)
+    }
+}
 
+/// A value tagged with its `DataType`. The data is wiped from memory when the cell is dropped
 pub struct TDBCell {
     data_type: DataType,
     data: Vec<u8>,
 }
 
 impl TDBCell {
+    pub fn new(data_type: DataType, data: &[u8]) -> Self {
+        Self {
+            data_type,
+            data: data.to_owned(),
+        }
+    }
+
+    /// Split bytes created by `to_ivec()` back into their `DataType` tag and data
+    pub fn from_bytes(value: &[u8]) -> TuringResult<Self> {
+        match value.split_first() {
+            None => Err(TuringDbError::FieldDataCorrupted),
+            Some((tag, data)) => match DataType::from_byte(*tag) {
+                None => Err(TuringDbError::FieldDataCorrupted),
+                Some(data_type) => Ok(Self::new(data_type, data)),
+            },
+        }
+    }
+
+    pub fn get_data_type(&self) -> DataType {
+        self.data_type
+    }
+
+    pub fn get_data(&self) -> &[u8] {
+        &self.data
+    }
+
     pub fn data_type(&mut self, value: DataType) -> &mut Self {
         self.data_type = value;
 
@@ -301,6 +796,7 @@ impl TDBCell {
     }
 
     pub fn data(&mut self, value: &[u8]) -> &mut Self {
+        self.data.zeroize();
         self.data = value.to_owned();
 
         self
@@ -313,4 +809,18 @@ impl TDBCell {
 
         IVec::from(data)
     }
+    /// Same as `to_ivec()` but the bytes are wiped from memory once dropped
+    pub fn to_bytes(&self) -> Zeroizing<Vec<u8>> {
+        let mut data = Zeroizing::new(Vec::with_capacity(1 + self.data.len()));
+        data.push(self.data_type as u8);
+        data.extend_from_slice(&self.data);
+
+        data
+    }
+}
+
+impl Drop for TDBCell {
+    fn drop(&mut self) {
+        self.data.zeroize();
+    }
 }
@@ -0,0 +1,69 @@
+//#![forbid(unsafe_code)]
+//#![deny(missing_docs)]
+
+//! TuringDB is a key-value database written using async code and backed by `sled.rs`  embedded key/value store.
+//! This is just a library version, the server can be found by searching crates-io for `turingdb-server`
+//! or checking under the Github repository https://github.com/charleschege/TuringDB/TuringDB-Server/
+//!
+//!
+//! This codebase uses `sled` as the underlying key/value store and builds upon that
+//! to provide other functionality like
+//!
+//! 1. in-memory keys,
+//! 2. async-locks for increased acid guarantees
+//! 3. Insert operations will fail if a key already exists, use `modify()` method on a key to change its value
+//! 4. in-memory locks to ensure that document locks are not dropped until the application is halted
+//! 5. JSON values in fields that can be read and partially updated using paths like `user.address.city`
+//! 6. optional encryption at rest of field values per database or per document, with per-database data keys
+//!    sealed by a master key that can be derived from a passphrase and rotated online
+//! 7. tamper-evident integrity manifests, keyed BLAKE3 Merkle trees over every document checked by `repo_verify()`
+//! 8. an append-only, checksummed `ops.log` of every mutation that is rotated by size and can be tailed or replayed
+//! 9. a hash-chained audit log signed with keyed BLAKE3 whose truncation, reordering or modification is detected by `audit_verify()`
+//! 10. changefeeds without polling, inspired by RethinkDB, that stream the insert, modify and remove events
+//!     of the fields of a document from `watch()` and resume from a CDC sequence with `watch_since()`
+//! 11. a durable change-data-capture log per database with sequence numbers, retention settings
+//!     and consumer groups that commit their offsets to resume after a restart
+//! 12. asynchronous leader-follower replication where a read-only replica bootstraps from
+//!     `replication_snapshot()` and then applies the changes read by `replication_changes()`
+//! 13. Raft consensus replication with `RaftEngine`, where a mutation is only applied once it is committed
+//!     to a majority of the cluster, with leader election, membership changes, snapshots of the engine
+//!     and `RaftCluster` to run a cluster in-process on a simulated clock and network.
+//!     An application proposes its mutations and carries the messages between the nodes itself:
+//!     `turingdb-server` does not run Raft, so its writes are only copied to followers by the replication above
+//! 14. online backups with `snapshot()`, a point-in-time copy of every database with a manifest of checksums,
+//!     incremental backups of the changes since the previous backup read from the CDC logs with `snapshot_incremental()`
+//!     and `restore_chain()` that checks a chain of backups before rebuilding the repo as it was at any point in time
+//! 15. an optional history of the previous versions of every field, read as of any time with `field_get_at()`,
+//!     and `repo_rewind()` that puts every field back to the value it held at a time to recover from a bad deploy.
+//!     `field_history()` lists the previous versions of a field and `history_compact()` drops the versions
+//!     that the retention of their document, a number of versions or a maximum age, no longer keeps
+//! 16. `export()` and `import()` of a database as NDJSON or CBOR, keeping the documents, field keys, timestamps
+//!     and `DataType` tags of the fields so data can be moved between environments
+//! 17. `bulk_insert()` for a fast initial ingestion, writing fields in sled transactions with a single sync
+//!     of the CDC log per batch, optionally without checking for existing keys, and reporting the rejected records.
+//!     The documents are flushed once at the end of the load with `db_flush()`
+//! 18. `batch()` of get, set, modify and remove operations across the documents of a database, returning
+//!     the result of every operation, where an atomic batch stages every operation before writing any of them
+//!     and writes nothing if one fails. Fields are also changed one at a time with `field_modify()` and `field_remove()`
+//! 19. a time-to-live given to `field_set()` and `field_modify()` with `TuringDBFieldOps::ttl()`. An expired field
+//!     is hidden from reads right away and removed by `expiry_sweep()`, which `expiry_sweeper()` runs in the background,
+//!     reporting a `ChangeKind::Expire` change to the change feeds and the CDC log
+//! 20. secondary indexes declared per document with `index_create()` on the decoded values of the fields
+//!     or on a JSON path inside them, updated in the same sled transaction as every write to the document
+//!     and queried for a value or a range of values with `find_by_index()`
+//! 21. unique indexes that refuse any insert or modification leaving two fields of a document with the same value,
+//!     like the email of a user, checked inside the same transaction as the write with a `UniqueViolation` error
+//!
+//! Some features that are under development include
+//!
+//! 1. Multi-cluster queries
+//!
+//!
+//! This module contains all the modules for the database engine that you can use to build a database server
+//! or embed in your own app
+mod t_engine;
+pub use t_engine::*;
+mod global;
+pub use global::*;
+mod crypto;
+pub use crypto::*;
@@ -0,0 +1,618 @@
+use crate::{
+    AuditViolation, IntegrityKey, LoggedOperation, LoggedOutcome, OpsOutcome, TuringDbError,
+    TuringResult, AUDIT_HEAD_FILE, AUDIT_LOG_FILE,
+};
+use async_fs::{File, OpenOptions};
+use async_lock::Mutex;
+use camino::{Utf8Path, Utf8PathBuf};
+use futures_lite::io::AsyncWriteExt;
+use secrecy::{ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+use std::{convert::TryInto, io::ErrorKind, sync::RwLock};
+use tai64::TAI64N;
+
+const AUDIT_CONTEXT: &str = "TuringDB 2021-05-01 audit log signing key";
+/// The `previous` hash of the first entry
+const GENESIS_HASH: [u8; 32] = [0u8; 32];
+const HASH_LEN: usize = 32;
+const LEN_PREFIX: usize = 4;
+
+const ENTRY_DOMAIN: u8 = 0x00;
+const HEAD_DOMAIN: u8 = 0x01;
+
+/// A single audit record. Each entry carries the hash of the entry before it
+/// so removing, reordering or editing an entry breaks the chain
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct AuditEntry {
+///     pub sequence: u64,
+///     pub timestamp: TAI64N,
+///     pub actor: String,
+///     pub operation: LoggedOperation,
+///     pub outcome: LoggedOutcome,
+///     pub previous: [u8; 32],
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub sequence: u64,
+    pub timestamp: TAI64N,
+    pub actor: String,
+    pub operation: LoggedOperation,
+    pub outcome: LoggedOutcome,
+    pub previous: [u8; 32],
+}
+
+/// The number of entries in the audit log and the hash of the last one,
+/// kept outside the log so that cutting entries off its end is detected
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct AuditHead {
+    entries: u64,
+    last: [u8; 32],
+    tag: [u8; 32],
+}
+
+#[derive(Debug)]
+struct AuditWriter {
+    file: Option<File>,
+    entries: u64,
+    last: [u8; 32],
+}
+
+/// The complete entries found at the start of the log
+#[derive(Debug)]
+struct AuditChain {
+    entries: u64,
+    /// The hash of the last entry
+    last: [u8; 32],
+    /// The hash of the entry before the last one
+    before_last: [u8; 32],
+    /// The offset right after the last complete entry
+    end: usize,
+}
+
+/// An append-only, hash-chained audit log signed with keyed BLAKE3.
+/// Every record is laid out as `length || entry || BLAKE3 keyed hash of the entry`
+/// and the signing key is derived from the integrity key of the repo.
+///
+/// Restoring an older copy of both the log and its head cannot be detected from the repo alone,
+/// so `head()` can be exported to an external system to anchor the log.
+///
+/// A log that does not match its head, or an entry that could not be written,
+/// is kept as the `failure()` of the log and nothing is appended to it afterwards
+/// ```
+/// #[derive(Debug)]
+/// pub struct AuditLog {
+///     dir: Utf8PathBuf,
+///     key: Secret<[u8; 32]>,
+///     writer: Mutex<AuditWriter>,
+///     failure: RwLock<Option<TuringDbError>>,
+/// }
+/// ```
+#[derive(Debug)]
+pub struct AuditLog {
+    dir: Utf8PathBuf,
+    key: Secret<[u8; 32]>,
+    writer: Mutex<AuditWriter>,
+    failure: RwLock<Option<TuringDbError>>,
+}
+
+impl AuditLog {
+    pub(crate) fn new(dir: &Utf8Path, integrity_key: &IntegrityKey) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            key: integrity_key.derive(AUDIT_CONTEXT),
+            writer: Mutex::new(AuditWriter {
+                file: None,
+                entries: 0,
+                last: GENESIS_HASH,
+            }),
+            failure: RwLock::new(None),
+        }
+    }
+    /// Record a mutation together with its outcome, chained to the previous entry.
+    /// A mutation that cannot be recorded is kept as the `failure()` of the log
+    pub(crate) async fn record(
+        &self,
+        actor: &str,
+        operation: LoggedOperation,
+        outcome: &TuringResult<OpsOutcome>,
+    ) {
+        // The failure is kept as `failure()` and refuses every later mutation of the repo
+        let _ = self.append(actor, operation, outcome.into()).await;
+    }
+    /// Check the existing log against its head before anything is appended to it
+    pub(crate) async fn resume(&self) {
+        let mut writer = self.writer.lock().await;
+        let _ = self.load(&mut writer).await;
+    }
+    /// Why the log stopped recording mutations, `None` while it records them
+    pub fn failure(&self) -> Option<TuringDbError> {
+        match self.failure.read() {
+            Ok(failure) => failure.clone(),
+            Err(_) => Some(TuringDbError::Bug(
+                "The audit log failure lock is poisoned".into(),
+            )),
+        }
+    }
+    /// The number of entries and the hash of the last entry
+    pub async fn head(&self) -> TuringResult<(u64, [u8; 32])> {
+        let mut writer = self.writer.lock().await;
+        self.load(&mut writer).await?;
+
+        Ok((writer.entries, writer.last))
+    }
+    /// Check every entry against its hash, its position and the hash of the entry
+    /// before it, then check the last entry against the head of the log.
+    /// Returns the number of entries
+    pub async fn verify(&self) -> TuringResult<u64> {
+        let mut writer = self.writer.lock().await;
+        self.load(&mut writer).await?;
+
+        let contents = self.read_log().await?;
+        let chain = self.chain(&contents)?;
+
+        match self.read_head().await? {
+            None if chain.entries == 0 => Ok(0),
+            None => Err(AuditViolation::HeadMissing.into()),
+            Some(head) => {
+                self.check_head(&head)?;
+
+                if head.entries != chain.entries || !self.matches(&head.last, &chain.last) {
+                    return Err(AuditViolation::Truncated {
+                        expected: head.entries,
+                        found: chain.entries,
+                    }
+                    .into());
+                }
+
+                Ok(chain.entries)
+            }
+        }
+    }
+
+    async fn append(
+        &self,
+        actor: &str,
+        operation: LoggedOperation,
+        outcome: LoggedOutcome,
+    ) -> TuringResult<()> {
+        let mut writer = self.writer.lock().await;
+        self.load(&mut writer).await?;
+
+        let entry = AuditEntry {
+            sequence: writer.entries,
+            timestamp: TAI64N::now(),
+            actor: actor.to_owned(),
+            operation,
+            outcome,
+            previous: writer.last,
+        };
+
+        let entry = match bincode::serialize::<AuditEntry>(&entry) {
+            Ok(entry) => entry,
+            Err(_) => {
+                return self.fail(TuringDbError::Bug(
+                    "Unable to serialize an audit entry".into(),
+                ))
+            }
+        };
+        let hash = self.entry_hash(&entry);
+
+        let mut record = Vec::with_capacity(LEN_PREFIX + entry.len() + HASH_LEN);
+        record.extend_from_slice(&(entry.len() as u32).to_le_bytes());
+        record.extend_from_slice(&entry);
+        record.extend_from_slice(&hash);
+
+        if let Some(file) = &mut writer.file {
+            let written = match file.write_all(&record).await {
+                Ok(_) => file.sync_data().await,
+                Err(error) => Err(error),
+            };
+
+            if let Err(error) = written {
+                return self.fail(error.into());
+            }
+        }
+
+        writer.entries += 1;
+        writer.last = hash;
+
+        match self.write_head(writer.entries, &hash).await {
+            Ok(_) => Ok(()),
+            Err(error) => self.fail(error),
+        }
+    }
+    /// Open the log once, refusing to go on if it failed before
+    async fn load(&self, writer: &mut AuditWriter) -> TuringResult<()> {
+        if let Some(failure) = self.failure() {
+            return Err(failure);
+        }
+
+        match self.open(writer).await {
+            Ok(_) => Ok(()),
+            Err(error) => self.fail(error),
+        }
+    }
+    /// Keep the first failure of the log
+    fn fail(&self, error: TuringDbError) -> TuringResult<()> {
+        if let Ok(mut failure) = self.failure.write() {
+            if failure.is_none() {
+                *failure = Some(error.clone());
+            }
+        }
+
+        Err(error)
+    }
+    /// Continue the chain from the last entry in the log once the log matches its head.
+    /// The log is never cut, an entry left incomplete by a crash is reported instead
+    async fn open(&self, writer: &mut AuditWriter) -> TuringResult<()> {
+        if writer.file.is_some() {
+            return Ok(());
+        }
+
+        let contents = self.read_log().await?;
+        let chain = self.chain(&contents)?;
+
+        let head = match self.read_head().await? {
+            Some(head) => {
+                self.check_head(&head)?;
+
+                head
+            }
+            None => AuditHead {
+                entries: 0,
+                last: GENESIS_HASH,
+                tag: GENESIS_HASH,
+            },
+        };
+
+        if head.entries == chain.entries && self.matches(&head.last, &chain.last) {
+            // The log matches its head
+        } else if head.entries + 1 == chain.entries && self.matches(&head.last, &chain.before_last)
+        {
+            // A crash after the last entry was synced and before the head was replaced
+            self.write_head(chain.entries, &chain.last).await?;
+        } else if head.entries == 0 {
+            return Err(AuditViolation::HeadMissing.into());
+        } else {
+            return Err(AuditViolation::Truncated {
+                expected: head.entries,
+                found: chain.entries,
+            }
+            .into());
+        }
+
+        writer.file = Some(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.dir.join(AUDIT_LOG_FILE))
+                .await?,
+        );
+        writer.entries = chain.entries;
+        writer.last = chain.last;
+
+        Ok(())
+    }
+
+    async fn read_log(&self) -> TuringResult<Vec<u8>> {
+        match async_fs::read(self.dir.join(AUDIT_LOG_FILE)).await {
+            Ok(contents) => Ok(contents),
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(error) => Err(error.into()),
+        }
+    }
+    /// Check every entry of the log against its hash, its position and the hash of the entry before it
+    fn chain(&self, contents: &[u8]) -> Result<AuditChain, AuditViolation> {
+        let mut chain = AuditChain {
+            entries: 0,
+            last: GENESIS_HASH,
+            before_last: GENESIS_HASH,
+            end: 0,
+        };
+
+        while let Some((entry, hash, next)) = self.decode(contents, chain.end) {
+            let expected = self.entry_hash(&contents[chain.end + LEN_PREFIX..next - HASH_LEN]);
+
+            let entry = match entry {
+                Some(entry) if self.matches(&hash, &expected) => entry,
+                _ => {
+                    return Err(AuditViolation::EntryModified {
+                        position: chain.entries,
+                    })
+                }
+            };
+
+            if entry.sequence != chain.entries {
+                return Err(AuditViolation::EntryOutOfOrder {
+                    position: chain.entries,
+                    sequence: entry.sequence,
+                });
+            }
+
+            if !self.matches(&entry.previous, &chain.last) {
+                return Err(AuditViolation::ChainBroken {
+                    sequence: entry.sequence,
+                });
+            }
+
+            chain.before_last = chain.last;
+            chain.last = hash;
+            chain.entries += 1;
+            chain.end = next;
+        }
+
+        if chain.end < contents.len() {
+            return Err(AuditViolation::IncompleteEntry {
+                position: chain.entries,
+            });
+        }
+
+        Ok(chain)
+    }
+
+    fn check_head(&self, head: &AuditHead) -> Result<(), AuditViolation> {
+        match self.matches(&head.tag, &self.head_tag(head.entries, &head.last)) {
+            true => Ok(()),
+            false => Err(AuditViolation::HeadModified),
+        }
+    }
+    /// Decode the record at `offset`, returning the entry if it can be deserialized,
+    /// its stored hash and the offset of the next record.
+    /// Returns `None` at the end of the log or at an incomplete record
+    fn decode(
+        &self,
+        contents: &[u8],
+        offset: usize,
+    ) -> Option<(Option<AuditEntry>, [u8; HASH_LEN], usize)> {
+        let len_bytes: [u8; LEN_PREFIX] =
+            contents.get(offset..offset + LEN_PREFIX)?.try_into().ok()?;
+        let len = u32::from_le_bytes(len_bytes) as usize;
+
+        let start = offset + LEN_PREFIX;
+        let entry = contents.get(start..start + len)?;
+        let hash: [u8; HASH_LEN] = contents
+            .get(start + len..start + len + HASH_LEN)?
+            .try_into()
+            .ok()?;
+
+        Some((
+            bincode::deserialize::<AuditEntry>(entry).ok(),
+            hash,
+            start + len + HASH_LEN,
+        ))
+    }
+
+    async fn read_head(&self) -> TuringResult<Option<AuditHead>> {
+        match async_fs::read(self.dir.join(AUDIT_HEAD_FILE)).await {
+            Ok(value) => match bincode::deserialize::<AuditHead>(&value) {
+                Ok(head) => Ok(Some(head)),
+                Err(_) => Err(AuditViolation::HeadModified.into()),
+            },
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error.into()),
+        }
+    }
+    /// Replace the head through a synced file and a rename so a crash never leaves a partly written head
+    async fn write_head(&self, entries: u64, last: &[u8; 32]) -> TuringResult<()> {
+        let head = AuditHead {
+            entries,
+            last: *last,
+            tag: self.head_tag(entries, last),
+        };
+
+        let bytes = match bincode::serialize::<AuditHead>(&head) {
+            Ok(bytes) => bytes,
+            Err(_) => {
+                return Err(TuringDbError::Bug(
+                    "Unable to serialize the audit head".into(),
+                ))
+            }
+        };
+
+        let temporary = self.dir.join(format!("{}.tmp", AUDIT_HEAD_FILE));
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&temporary)
+            .await?;
+        file.write_all(&bytes).await?;
+        file.sync_all().await?;
+        async_fs::rename(&temporary, self.dir.join(AUDIT_HEAD_FILE)).await?;
+
+        Ok(())
+    }
+
+    fn entry_hash(&self, entry: &[u8]) -> [u8; 32] {
+        let mut hasher = blake3::Hasher::new_keyed(self.key.expose_secret());
+        hasher.update(&[ENTRY_DOMAIN]);
+        hasher.update(entry);
+
+        *hasher.finalize().as_bytes()
+    }
+
+    fn head_tag(&self, entries: u64, last: &[u8; 32]) -> [u8; 32] {
+        let mut hasher = blake3::Hasher::new_keyed(self.key.expose_secret());
+        hasher.update(&[HEAD_DOMAIN]);
+        hasher.update(&entries.to_le_bytes());
+        hasher.update(last);
+
+        *hasher.finalize().as_bytes()
+    }
+    /// `blake3::Hash` equality is constant-time
+    fn matches(&self, stored: &[u8; 32], expected: &[u8; 32]) -> bool {
+        blake3::Hash::from(*stored) == blake3::Hash::from(*expected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{t_engine::testing::*, TuringEngine};
+    use futures_lite::future::block_on;
+
+    /// The records of the audit log, each still holding its length and its hash
+    fn records(repo: &Utf8Path) -> Vec<Vec<u8>> {
+        let contents = std::fs::read(repo.join(AUDIT_LOG_FILE)).unwrap();
+        let mut records = Vec::new();
+        let mut offset = 0usize;
+
+        while offset < contents.len() {
+            let len_bytes: [u8; LEN_PREFIX] =
+                contents[offset..offset + LEN_PREFIX].try_into().unwrap();
+            let next = offset + LEN_PREFIX + u32::from_le_bytes(len_bytes) as usize + HASH_LEN;
+
+            records.push(contents[offset..next].to_vec());
+            offset = next;
+        }
+
+        records
+    }
+
+    fn write_records(repo: &Utf8Path, records: &[Vec<u8>]) {
+        std::fs::write(repo.join(AUDIT_LOG_FILE), records.concat()).unwrap();
+    }
+    /// Load the repo again as a restarted server does
+    async fn restart(repo: &Utf8Path) -> TuringEngine {
+        let mut engine = TuringEngine::builder()
+            .repo_dir(repo.to_path_buf())
+            .build()
+            .await
+            .unwrap();
+        engine.repo_init().await.unwrap();
+
+        engine
+    }
+
+    #[test]
+    fn a_modified_entry_is_found() {
+        block_on(async {
+            let dir = TestDir::new("audit-modified");
+            let repo = dir.path().join("repo");
+            let engine = test_engine(&repo, false).await;
+            field_set(&engine, "alice", "admin").await.unwrap();
+
+            let mut log = records(&repo);
+            let last = log[1].len() - HASH_LEN - 1;
+            log[1][last] ^= 0xff;
+            write_records(&repo, &log);
+
+            assert_eq!(
+                engine.audit_verify().await,
+                Err(AuditViolation::EntryModified { position: 1 }.into())
+            );
+        })
+    }
+
+    #[test]
+    fn reordered_entries_are_found() {
+        block_on(async {
+            let dir = TestDir::new("audit-reordered");
+            let repo = dir.path().join("repo");
+            let engine = test_engine(&repo, false).await;
+            field_set(&engine, "alice", "admin").await.unwrap();
+
+            let mut log = records(&repo);
+            log.swap(1, 2);
+            write_records(&repo, &log);
+
+            assert_eq!(
+                engine.audit_verify().await,
+                Err(AuditViolation::EntryOutOfOrder {
+                    position: 1,
+                    sequence: 2
+                }
+                .into())
+            );
+        })
+    }
+
+    #[test]
+    fn a_truncated_log_is_found_and_no_longer_written_after_a_restart() {
+        block_on(async {
+            let dir = TestDir::new("audit-truncated");
+            let repo = dir.path().join("repo");
+            let engine = test_engine(&repo, false).await;
+            field_set(&engine, "alice", "admin").await.unwrap();
+
+            let mut log = records(&repo);
+            let entries = log.len() as u64;
+            log.pop();
+            write_records(&repo, &log);
+
+            let truncated: TuringDbError = AuditViolation::Truncated {
+                expected: entries,
+                found: entries - 1,
+            }
+            .into();
+            assert_eq!(engine.audit_verify().await, Err(truncated.clone()));
+            drop(engine);
+
+            // The restarted repo serves reads but refuses the writes it could not audit
+            let engine = restart(&repo).await;
+            assert_eq!(engine.audit_log().failure(), Some(truncated.clone()));
+            assert_eq!(
+                field_set(&engine, "bob", "guest").await,
+                Err(truncated.clone())
+            );
+            assert_eq!(field_value(&engine, "alice").await, Some(b"admin".to_vec()));
+            assert_eq!(field_value(&engine, "bob").await, None);
+
+            assert_eq!(records(&repo), log);
+            assert_eq!(engine.audit_verify().await, Err(truncated));
+        })
+    }
+
+    #[test]
+    fn an_incomplete_entry_is_reported_instead_of_cut_off() {
+        block_on(async {
+            let dir = TestDir::new("audit-incomplete");
+            let repo = dir.path().join("repo");
+            let engine = test_engine(&repo, false).await;
+            drop(engine);
+
+            let entries = records(&repo).len() as u64;
+            let mut contents = std::fs::read(repo.join(AUDIT_LOG_FILE)).unwrap();
+            contents.extend_from_slice(&[8, 0, 0, 0, 1, 2]);
+            std::fs::write(repo.join(AUDIT_LOG_FILE), &contents).unwrap();
+
+            let engine = restart(&repo).await;
+            assert_eq!(
+                engine.audit_log().failure(),
+                Some(AuditViolation::IncompleteEntry { position: entries }.into())
+            );
+            assert!(field_set(&engine, "alice", "admin").await.is_err());
+            assert_eq!(std::fs::read(repo.join(AUDIT_LOG_FILE)).unwrap(), contents);
+        })
+    }
+
+    #[test]
+    fn an_entry_written_before_a_crash_replaced_the_head_is_kept() {
+        block_on(async {
+            let dir = TestDir::new("audit-crash");
+            let repo = dir.path().join("repo");
+            let engine = test_engine(&repo, false).await;
+
+            let head = std::fs::read(repo.join(AUDIT_HEAD_FILE)).unwrap();
+            field_set(&engine, "alice", "admin").await.unwrap();
+            drop(engine);
+            std::fs::write(repo.join(AUDIT_HEAD_FILE), head).unwrap();
+
+            let entries = records(&repo).len() as u64;
+            let engine = restart(&repo).await;
+            assert_eq!(engine.audit_log().failure(), None);
+            assert_eq!(
+                engine.audit_verify().await,
+                Ok(OpsOutcome::AuditVerified(entries))
+            );
+
+            field_set(&engine, "bob", "guest").await.unwrap();
+            assert_eq!(
+                engine.audit_verify().await,
+                Ok(OpsOutcome::AuditVerified(entries + 1))
+            );
+        })
+    }
+}
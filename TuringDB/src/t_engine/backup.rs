@@ -0,0 +1,379 @@
+use crate::{DbChanges, DbSnapshot, TuringDbError, TuringResult, BACKUP_MANIFEST_FILE};
+use camino::Utf8Path;
+use futures_lite::io::AsyncWriteExt;
+use serde::{Deserialize, Serialize};
+use std::io::ErrorKind;
+use tai64::TAI64N;
+
+/// The version of the backup format written by `TuringEngine::snapshot()`
+const BACKUP_VERSION: u8 = 2;
+
+/// A database copied into a backup.
+/// The file holds a `DbSnapshot` of the database when `from` is `None`,
+/// otherwise the `DbChanges` read from its CDC log from the sequence `from` to `next`
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct BackupEntry {
+///     pub db: String,
+///     pub file: String,
+///     pub size: u64,
+///     pub checksum: [u8; 32],
+///     pub from: Option<u64>,
+///     pub next: u64,
+///     pub data_keys: Option<Vec<u8>>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupEntry {
+    /// The name of the database
+    pub db: String,
+    /// The file in the backup directory holding the database or its changes
+    pub file: String,
+    /// The size of the file in bytes
+    pub size: u64,
+    /// The BLAKE3 hash of the file
+    pub checksum: [u8; 32],
+    /// The sequence of the first change in the file, `None` for a copy of the whole database
+    pub from: Option<u64>,
+    /// The sequence of the first change to the database that is not part of the backup
+    pub next: u64,
+    /// The data keys of the database sealed with the master key, so an incremental backup
+    /// copies the whole database again after they were rotated
+    pub data_keys: Option<Vec<u8>>,
+}
+
+/// Lists the databases of a backup with the checksum of every file.
+/// It is written last, through a rename, so a backup without a manifest is incomplete.
+/// An incremental backup holds the checksum of the manifest of the backup it continues in `parent`
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct BackupManifest {
+///     pub version: u8,
+///     pub created: TAI64N,
+///     pub parent: Option<[u8; 32]>,
+///     pub databases: Vec<BackupEntry>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupManifest {
+    /// The version of the backup format
+    pub version: u8,
+    /// When the databases were copied
+    pub created: TAI64N,
+    /// The checksum of the manifest of the previous backup, `None` for a full backup
+    pub parent: Option<[u8; 32]>,
+    /// The databases sorted by name
+    pub databases: Vec<BackupEntry>,
+}
+
+/// The contents of a file of a backup
+#[derive(Debug)]
+pub(crate) enum BackupContents {
+    Snapshot(DbSnapshot),
+    Changes {
+        db: String,
+        from: u64,
+        changes: DbChanges,
+    },
+}
+
+impl BackupManifest {
+    /// Write the databases into `dest`, which must not exist yet
+    pub(crate) async fn write(
+        dest: &Utf8Path,
+        parent: Option<[u8; 32]>,
+        contents: &[BackupContents],
+    ) -> TuringResult<Self> {
+        if let Some(parent) = dest.parent() {
+            async_fs::create_dir_all(parent).await?;
+        }
+        async_fs::create_dir(dest).await?;
+
+        let mut databases = Vec::with_capacity(contents.len());
+
+        for (index, db_contents) in contents.iter().enumerate() {
+            let file = format!("db-{:06}", index);
+
+            let (bytes, db, from, next, data_keys) = match db_contents {
+                BackupContents::Snapshot(snapshot) => (
+                    BackupManifest::serialize(snapshot)?,
+                    &snapshot.name,
+                    None,
+                    snapshot.next,
+                    &snapshot.data_keys,
+                ),
+                BackupContents::Changes { db, from, changes } => (
+                    BackupManifest::serialize(changes)?,
+                    db,
+                    Some(*from),
+                    changes.next,
+                    &changes.data_keys,
+                ),
+            };
+
+            BackupManifest::write_synced(&dest.join(&file), &bytes).await?;
+
+            databases.push(BackupEntry {
+                db: db.clone(),
+                file,
+                size: bytes.len() as u64,
+                checksum: *blake3::hash(&bytes).as_bytes(),
+                from,
+                next,
+                data_keys: data_keys.clone(),
+            });
+        }
+
+        let manifest = BackupManifest {
+            version: BACKUP_VERSION,
+            created: TAI64N::now(),
+            parent,
+            databases,
+        };
+
+        // The manifest ends with the hash of its contents
+        let mut bytes = BackupManifest::serialize(&manifest)?;
+        let checksum = blake3::hash(&bytes);
+        bytes.extend_from_slice(checksum.as_bytes());
+
+        let temporary = dest.join(format!("{}.tmp", BACKUP_MANIFEST_FILE));
+        BackupManifest::write_synced(&temporary, &bytes).await?;
+        async_fs::rename(&temporary, dest.join(BACKUP_MANIFEST_FILE)).await?;
+
+        Ok(manifest)
+    }
+    /// Read the manifest of the backup in `src` and check it against its checksum
+    pub(crate) async fn read(src: &Utf8Path) -> TuringResult<Self> {
+        let corrupted = || TuringDbError::BackupCorrupted {
+            file: BACKUP_MANIFEST_FILE.to_owned(),
+        };
+
+        let contents = match async_fs::read(src.join(BACKUP_MANIFEST_FILE)).await {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Err(corrupted()),
+            Err(error) => return Err(error.into()),
+        };
+
+        if contents.len() < 32 {
+            return Err(corrupted());
+        }
+
+        let (manifest, checksum) = contents.split_at(contents.len() - 32);
+        if blake3::hash(manifest).as_bytes()[..] != *checksum {
+            return Err(corrupted());
+        }
+
+        match bincode::deserialize::<BackupManifest>(manifest) {
+            Ok(manifest) if manifest.version == BACKUP_VERSION => Ok(manifest),
+            _ => Err(corrupted()),
+        }
+    }
+    /// The checksum of the manifest, which the next incremental backup holds as its `parent`
+    pub fn checksum(&self) -> TuringResult<[u8; 32]> {
+        Ok(*blake3::hash(&BackupManifest::serialize(self)?).as_bytes())
+    }
+    /// The entry of a database
+    pub fn database(&self, db: &str) -> Option<&BackupEntry> {
+        self.databases.iter().find(|entry| entry.db == db)
+    }
+    /// Read every database of the backup in `src`, checking the size and checksum of its file
+    pub(crate) async fn load(&self, src: &Utf8Path) -> TuringResult<Vec<BackupContents>> {
+        let mut contents = Vec::with_capacity(self.databases.len());
+
+        for entry in self.databases.iter() {
+            let corrupted = || TuringDbError::BackupCorrupted {
+                file: entry.file.clone(),
+            };
+
+            // A file name from the manifest must stay inside the backup
+            if entry.file.contains(&['/', '\\'][..]) || entry.file.starts_with('.') {
+                return Err(corrupted());
+            }
+
+            let bytes = match async_fs::read(src.join(&entry.file)).await {
+                Ok(bytes) => bytes,
+                Err(error) if error.kind() == ErrorKind::NotFound => return Err(corrupted()),
+                Err(error) => return Err(error.into()),
+            };
+
+            if bytes.len() as u64 != entry.size
+                || blake3::hash(&bytes) != blake3::Hash::from(entry.checksum)
+            {
+                return Err(corrupted());
+            }
+
+            match entry.from {
+                None => match bincode::deserialize::<DbSnapshot>(&bytes) {
+                    Ok(snapshot) if snapshot.name == entry.db => {
+                        contents.push(BackupContents::Snapshot(snapshot))
+                    }
+                    _ => return Err(corrupted()),
+                },
+                Some(from) => match bincode::deserialize::<DbChanges>(&bytes) {
+                    Ok(changes) => contents.push(BackupContents::Changes {
+                        db: entry.db.clone(),
+                        from,
+                        changes,
+                    }),
+                    Err(_) => return Err(corrupted()),
+                },
+            }
+        }
+
+        Ok(contents)
+    }
+
+    fn serialize<T: Serialize>(value: &T) -> TuringResult<Vec<u8>> {
+        match bincode::serialize::<T>(value) {
+            Ok(contents) => Ok(contents),
+            Err(_) => Err(TuringDbError::Bug("Unable to serialize a backup".into())),
+        }
+    }
+
+    async fn write_synced(path: &Utf8Path, contents: &[u8]) -> TuringResult<()> {
+        let mut file = async_fs::File::create(path).await?;
+        file.write_all(contents).await?;
+        file.sync_all().await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        t_engine::testing::*, IndexDefinition, IndexLookup, IndexValue, OpsOutcome, TuringEngine,
+    };
+    use futures_lite::future::block_on;
+
+    /// An engine holding the field `stale` whose databases are replaced by a restore
+    async fn restore_engine(dir: &TestDir) -> TuringEngine {
+        let engine = test_engine(&dir.path().join("restored"), false).await;
+        field_set(&engine, "stale", "stale").await.unwrap();
+
+        engine
+    }
+
+    #[test]
+    fn a_backup_is_restored_into_another_repo() {
+        block_on(async {
+            let dir = TestDir::new("backup");
+            let engine = test_engine(&dir.path().join("repo"), false).await;
+            field_set(&engine, "alice", "admin").await.unwrap();
+            field_set(&engine, "bob", "guest").await.unwrap();
+            engine
+                .index_create(&document_ops(), IndexDefinition::new("by_role"))
+                .await
+                .unwrap();
+
+            let backup = dir.path().join("backups").join("full");
+            let manifest = engine.snapshot(&backup).await.unwrap();
+            assert_eq!(manifest.version, BACKUP_VERSION);
+            assert_eq!(manifest.parent, None);
+            assert_eq!(engine.snapshot_verify(&backup).await.unwrap(), manifest);
+
+            // Changes made after the backup are not part of it
+            field_set(&engine, "carol", "admin").await.unwrap();
+
+            let restored = restore_engine(&dir).await;
+            assert_eq!(
+                restored.restore(&backup).await.unwrap(),
+                OpsOutcome::RepoRestored
+            );
+
+            assert_eq!(
+                field_value(&restored, "alice").await,
+                Some(b"admin".to_vec())
+            );
+            assert_eq!(field_value(&restored, "bob").await, Some(b"guest".to_vec()));
+            assert_eq!(field_value(&restored, "carol").await, None);
+            assert_eq!(field_value(&restored, "stale").await, None);
+
+            // The entries of the indexes are built again from the restored fields
+            let admins = IndexLookup::Value(IndexValue::Bytes(b"admin".to_vec()));
+            match restored
+                .find_by_index(
+                    Utf8Path::new(DB),
+                    Utf8Path::new(DOCUMENT),
+                    "by_role",
+                    &admins,
+                )
+                .await
+                .unwrap()
+            {
+                OpsOutcome::FieldsFound(fields) => {
+                    assert_eq!(fields.len(), 1);
+                    assert_eq!(fields[0].0, b"alice".to_vec());
+                }
+                outcome => panic!("Unexpected outcome {:?}", outcome),
+            }
+        })
+    }
+
+    #[test]
+    fn incremental_backups_are_restored_in_the_order_they_were_made() {
+        block_on(async {
+            let dir = TestDir::new("backup-incremental");
+            let engine = test_engine(&dir.path().join("repo"), false).await;
+            let backups = dir.path().join("backups");
+            let (full, first, second) =
+                (backups.join("full"), backups.join("1"), backups.join("2"));
+            let (full, first, second) = (full.as_path(), first.as_path(), second.as_path());
+
+            field_set(&engine, "balance", "10").await.unwrap();
+            let full_manifest = engine.snapshot(full).await.unwrap();
+
+            engine
+                .field_modify(&field_ops("balance", "5"))
+                .await
+                .unwrap();
+            field_set(&engine, "invite", "pending").await.unwrap();
+            let first_manifest = engine.snapshot_incremental(full, first).await.unwrap();
+            assert_eq!(
+                first_manifest.parent,
+                Some(full_manifest.checksum().unwrap())
+            );
+
+            engine.field_remove(&field_ops("invite", "")).await.unwrap();
+            let second_manifest = engine.snapshot_incremental(first, second).await.unwrap();
+            assert_eq!(
+                second_manifest.parent,
+                Some(first_manifest.checksum().unwrap())
+            );
+
+            let restored = restore_engine(&dir).await;
+            restored.restore_chain(&[full, first], None).await.unwrap();
+            assert_eq!(field_value(&restored, "balance").await, Some(b"5".to_vec()));
+            assert_eq!(
+                field_value(&restored, "invite").await,
+                Some(b"pending".to_vec())
+            );
+            assert_eq!(field_value(&restored, "stale").await, None);
+
+            restored
+                .restore_chain(&[full, first, second], None)
+                .await
+                .unwrap();
+            assert_eq!(field_value(&restored, "balance").await, Some(b"5".to_vec()));
+            assert_eq!(field_value(&restored, "invite").await, None);
+
+            // A backup whose parent is not the backup before it breaks the chain
+            let broken: [(&[&Utf8Path], &Utf8Path); 3] = [
+                (&[full, second], second),
+                (&[first], first),
+                (&[full, second, first], second),
+            ];
+            for (chain, backup) in broken.iter() {
+                match restored.restore_chain(chain, None).await {
+                    Err(TuringDbError::BackupChainBroken { backup: broken }) => {
+                        assert_eq!(broken, backup.as_str())
+                    }
+                    outcome => panic!("Unexpected outcome {:?}", outcome),
+                }
+            }
+            assert_eq!(field_value(&restored, "balance").await, Some(b"5".to_vec()));
+            assert_eq!(field_value(&restored, "invite").await, None);
+        })
+    }
+}
@@ -0,0 +1,500 @@
+use crate::{
+    DataType, Expiry, FieldData, FieldWrite, TDBCell, TuringDB, TuringDbError, TuringResult,
+};
+use camino::Utf8Path;
+use serde::{Deserialize, Serialize};
+use sled::IVec;
+use std::collections::{btree_map::Entry, BTreeMap};
+use tai64::TAI64N;
+
+/// A field operation of `TuringEngine::batch()`.
+/// `data_type` is the tag byte of the `DataType` of the value, like `0x28` for `DataType::BINARY`
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub enum BatchOperation {
+///     Get { document: String, key: Vec<u8> },
+///     Set { document: String, key: Vec<u8>, data_type: u8, value: Vec<u8> },
+///     Modify { document: String, key: Vec<u8>, data_type: u8, value: Vec<u8> },
+///     Remove { document: String, key: Vec<u8> },
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BatchOperation {
+    /// Read the contents of a field
+    Get { document: String, key: Vec<u8> },
+    /// Insert a field, failing if it already exists
+    Set {
+        document: String,
+        key: Vec<u8>,
+        data_type: u8,
+        value: Vec<u8>,
+    },
+    /// Replace the value of a field that exists
+    Modify {
+        document: String,
+        key: Vec<u8>,
+        data_type: u8,
+        value: Vec<u8>,
+    },
+    /// Remove a field that exists
+    Remove { document: String, key: Vec<u8> },
+}
+
+/// The result of an operation of `TuringEngine::batch()`, in the order of the operations
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum BatchResult {
+    /// The contents of the field read by a `Get`
+    Contents(Vec<u8>),
+    /// The field of a `Set` was inserted
+    Inserted,
+    /// The field of a `Modify` was changed
+    Modified,
+    /// The field of a `Remove` was removed
+    Removed,
+    /// The operation failed with the error
+    Failed(String),
+}
+
+impl BatchOperation {
+    /// The document of the field the operation applies to
+    pub fn document(&self) -> &str {
+        match self {
+            BatchOperation::Get { document, .. }
+            | BatchOperation::Set { document, .. }
+            | BatchOperation::Modify { document, .. }
+            | BatchOperation::Remove { document, .. } => document,
+        }
+    }
+    /// The key of the field the operation applies to
+    pub fn key(&self) -> &[u8] {
+        match self {
+            BatchOperation::Get { key, .. }
+            | BatchOperation::Set { key, .. }
+            | BatchOperation::Modify { key, .. }
+            | BatchOperation::Remove { key, .. } => key,
+        }
+    }
+    /// Check whether the operation changes a field
+    pub fn is_write(&self) -> bool {
+        !matches!(self, BatchOperation::Get { .. })
+    }
+
+    fn cell(data_type: u8, value: &[u8]) -> TuringResult<TDBCell> {
+        match DataType::from_byte(data_type) {
+            None => Err(TuringDbError::InvalidInput),
+            Some(data_type) => Ok(TDBCell::new(data_type, value)),
+        }
+    }
+}
+
+/// A field changed by an atomic batch before the changes are written.
+/// `original` and `current` hold the bytes stored in sled
+/// and `expiry_cleared` marks a field set or removed by the batch, which loses its time-to-live
+struct StagedField {
+    original: Option<IVec>,
+    current: Option<Vec<u8>>,
+    replaced: TAI64N,
+    expiry_cleared: bool,
+}
+
+impl TuringDB {
+    /// Apply the operations one after the other, carrying on after an operation fails
+    pub(crate) async fn batch(&self, operations: &[BatchOperation]) -> Vec<BatchResult> {
+        let mut results = Vec::with_capacity(operations.len());
+
+        for operation in operations {
+            let result = match self.batch_operation(operation).await {
+                Ok(result) => result,
+                Err(error) => BatchResult::Failed(format!("{:?}", error)),
+            };
+
+            results.push(result);
+        }
+
+        results
+    }
+    /// Check every operation against the fields as the earlier operations left them
+    /// and write the changes only if all of them succeed.
+    /// The database is held throughout so no other change is applied in between.
+    /// Every document is written in a single transaction that only goes ahead while its fields
+    /// still hold the values the operations were checked against. A transaction of sled cannot span
+    /// the documents, so all of them are first checked in transactions that are rolled back
+    /// and written only once every one of them passes
+    pub(crate) async fn batch_atomic(
+        &self,
+        operations: &[BatchOperation],
+    ) -> TuringResult<Vec<BatchResult>> {
+        for operation in operations {
+            self.expire_if_due(Utf8Path::new(operation.document()), operation.key())
+                .await?;
+        }
+
+        let _writing = self.write_lock_all().await;
+        let mut capture = self.cdc_capture().await?;
+
+        let mut staged: BTreeMap<(&str, &[u8]), StagedField> = BTreeMap::new();
+        let mut results = Vec::with_capacity(operations.len());
+
+        for (index, operation) in operations.iter().enumerate() {
+            let result = match self.batch_stage(operation, &mut staged) {
+                Ok(result) => result,
+                Err(error) => {
+                    return Err(TuringDbError::BatchFailed {
+                        operation: index as u64,
+                        error: format!("{:?}", error),
+                    })
+                }
+            };
+
+            results.push(result);
+        }
+
+        let mut documents: BTreeMap<&str, Vec<(&[u8], StagedField)>> = BTreeMap::new();
+
+        for ((document_name, key), field) in staged {
+            if field.original.as_deref() != field.current.as_deref() {
+                documents
+                    .entry(document_name)
+                    .or_default()
+                    .push((key, field));
+            }
+        }
+
+        let mut writes = Vec::with_capacity(documents.len());
+
+        for (document_name, fields) in documents.iter() {
+            let document_name = Utf8Path::new(document_name);
+            let document = match self.document(document_name) {
+                None => return Err(TuringDbError::DocumentNotFound),
+                Some(document) => document,
+            };
+
+            let mut document_writes = Vec::with_capacity(fields.len());
+            for (key, field) in fields.iter() {
+                let expiry = match field.expiry_cleared {
+                    true => Expiry::Clear,
+                    false => Expiry::Keep,
+                };
+
+                document_writes.push(
+                    FieldWrite::new(key, field.current.as_deref())
+                        .expect(field.original.as_deref())
+                        .expiry(expiry)
+                        .replaced(field.replaced),
+                );
+            }
+
+            if !self.field_check(document_name, &document, &document_writes)? {
+                return Err(batch_changed(operations, document_name));
+            }
+
+            writes.push((document_name, document, document_writes));
+        }
+
+        // The changes of the documents already written are captured even if a later document fails
+        let mut written = Ok(());
+        for (document_name, document, document_writes) in writes {
+            let applied = self.field_apply(
+                document_name,
+                &document,
+                &document_writes,
+                true,
+                capture.as_mut(),
+            );
+
+            written = match applied {
+                Ok(true) => continue,
+                Ok(false) => Err(batch_changed(operations, document_name)),
+                Err(error) => Err(error),
+            };
+            break;
+        }
+
+        self.cdc_write(capture).await;
+        written?;
+
+        Ok(results)
+    }
+
+    async fn batch_operation(&self, operation: &BatchOperation) -> TuringResult<BatchResult> {
+        let document_name = Utf8Path::new(operation.document());
+
+        match operation {
+            BatchOperation::Get { key, .. } => {
+                let field_data = self.field_get(document_name, key).await?;
+
+                Ok(BatchResult::Contents(field_data.data().to_vec()))
+            }
+            BatchOperation::Set {
+                key,
+                data_type,
+                value,
+                ..
+            } => {
+                let cell = BatchOperation::cell(*data_type, value)?;
+                self.field_set(
+                    document_name,
+                    IVec::from(key.as_slice()),
+                    &cell.to_bytes(),
+                    None,
+                )
+                .await?;
+
+                Ok(BatchResult::Inserted)
+            }
+            BatchOperation::Modify {
+                key,
+                data_type,
+                value,
+                ..
+            } => {
+                let cell = BatchOperation::cell(*data_type, value)?;
+                self.field_modify(document_name, key, &cell.to_bytes(), None)
+                    .await?;
+
+                Ok(BatchResult::Modified)
+            }
+            BatchOperation::Remove { key, .. } => {
+                self.field_remove(document_name, key).await?;
+
+                Ok(BatchResult::Removed)
+            }
+        }
+    }
+    /// Apply an operation of an atomic batch to the staged fields
+    fn batch_stage<'o>(
+        &self,
+        operation: &'o BatchOperation,
+        staged: &mut BTreeMap<(&'o str, &'o [u8]), StagedField>,
+    ) -> TuringResult<BatchResult> {
+        let document_name = Utf8Path::new(operation.document());
+        let key = operation.key();
+
+        let document = match self.document(document_name) {
+            None => return Err(TuringDbError::DocumentNotFound),
+            Some(document) => document,
+        };
+
+        let field = match staged.entry((operation.document(), key)) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let original = document.get(key)?;
+
+                entry.insert(StagedField {
+                    current: original.as_ref().map(|value| value.to_vec()),
+                    original,
+                    replaced: TAI64N::now(),
+                    expiry_cleared: false,
+                })
+            }
+        };
+
+        match operation {
+            BatchOperation::Get { .. } => match &field.current {
+                None => Err(TuringDbError::FieldNotFound),
+                Some(current) => Ok(BatchResult::Contents(
+                    self.unseal(document_name, key, current)?.data().to_vec(),
+                )),
+            },
+            BatchOperation::Set {
+                data_type, value, ..
+            } => {
+                if field.current.is_some() {
+                    return Err(TuringDbError::KeyAlreadyExists);
+                }
+
+                let cell = BatchOperation::cell(*data_type, value)?;
+                let field_data = FieldData::new(&cell.to_bytes());
+
+                field.current = Some(self.seal(document_name, key, &field_data)?);
+                field.replaced = field_data.modified();
+                field.expiry_cleared = true;
+
+                Ok(BatchResult::Inserted)
+            }
+            BatchOperation::Modify {
+                data_type, value, ..
+            } => {
+                let mut field_data = match &field.current {
+                    None => return Err(TuringDbError::FieldNotFound),
+                    Some(current) => self.unseal(document_name, key, current)?,
+                };
+
+                let cell = BatchOperation::cell(*data_type, value)?;
+                field_data.update(&cell.to_bytes());
+
+                field.current = Some(self.seal(document_name, key, &field_data)?);
+                field.replaced = field_data.modified();
+
+                Ok(BatchResult::Modified)
+            }
+            BatchOperation::Remove { .. } => match field.current.take() {
+                None => Err(TuringDbError::FieldNotFound),
+                Some(_) => {
+                    field.replaced = TAI64N::now();
+                    field.expiry_cleared = true;
+
+                    Ok(BatchResult::Removed)
+                }
+            },
+        }
+    }
+}
+
+/// The error of an atomic batch whose fields in a document changed after they were checked,
+/// reported on the first operation on the document
+fn batch_changed(operations: &[BatchOperation], document_name: &Utf8Path) -> TuringDbError {
+    let operation = operations
+        .iter()
+        .position(|operation| operation.document() == document_name.as_str())
+        .unwrap_or_default();
+
+    TuringDbError::BatchFailed {
+        operation: operation as u64,
+        error: "The fields changed while the batch was applied".into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{t_engine::testing::*, OpsOutcome, TuringEngine};
+    use futures_lite::future::block_on;
+
+    const OTHER: &str = "other";
+
+    fn set(document: &str, key: &str, value: &str) -> BatchOperation {
+        BatchOperation::Set {
+            document: document.into(),
+            key: key.as_bytes().to_vec(),
+            data_type: DataType::BINARY as u8,
+            value: value.as_bytes().to_vec(),
+        }
+    }
+
+    fn modify(document: &str, key: &str, value: &str) -> BatchOperation {
+        BatchOperation::Modify {
+            document: document.into(),
+            key: key.as_bytes().to_vec(),
+            data_type: DataType::BINARY as u8,
+            value: value.as_bytes().to_vec(),
+        }
+    }
+
+    fn remove(document: &str, key: &str) -> BatchOperation {
+        BatchOperation::Remove {
+            document: document.into(),
+            key: key.as_bytes().to_vec(),
+        }
+    }
+
+    /// A repo holding the field `balance` in `DOCUMENT` and the field `invite` in `OTHER`
+    async fn batch_engine(dir: &TestDir) -> TuringEngine {
+        let engine = test_engine(&dir.path().join("repo"), false).await;
+        engine
+            .document_create(&document_ops().set_document_name(OTHER))
+            .await
+            .unwrap();
+        field_set(&engine, "balance", "10").await.unwrap();
+        engine
+            .field_set(&field_ops("invite", "pending").document(OTHER))
+            .await
+            .unwrap();
+
+        engine
+    }
+
+    #[test]
+    fn an_atomic_batch_writes_every_document() {
+        block_on(async {
+            let dir = TestDir::new("batch-atomic");
+            let engine = batch_engine(&dir).await;
+
+            let operations = vec![
+                set(DOCUMENT, "alice", "admin"),
+                modify(DOCUMENT, "balance", "5"),
+                remove(OTHER, "invite"),
+                set(OTHER, "member", "alice"),
+            ];
+
+            assert_eq!(
+                engine
+                    .batch(Utf8Path::new(DB), &operations, true)
+                    .await
+                    .unwrap(),
+                OpsOutcome::BatchApplied(vec![
+                    BatchResult::Inserted,
+                    BatchResult::Modified,
+                    BatchResult::Removed,
+                    BatchResult::Inserted,
+                ])
+            );
+
+            assert_eq!(field_value(&engine, "alice").await, Some(b"admin".to_vec()));
+            assert_eq!(field_value(&engine, "balance").await, Some(b"5".to_vec()));
+            assert_eq!(document_value(&engine, OTHER, "invite").await, None);
+            assert_eq!(
+                document_value(&engine, OTHER, "member").await,
+                Some(b"alice".to_vec())
+            );
+        })
+    }
+
+    #[test]
+    fn a_failed_atomic_batch_leaves_every_document_untouched() {
+        block_on(async {
+            let dir = TestDir::new("batch-atomic-failed");
+            let engine = batch_engine(&dir).await;
+
+            let operations = vec![
+                set(DOCUMENT, "alice", "admin"),
+                modify(DOCUMENT, "balance", "5"),
+                remove(OTHER, "invite"),
+                // Inserted by the first operation
+                set(DOCUMENT, "alice", "again"),
+                set(OTHER, "member", "alice"),
+            ];
+
+            match engine.batch(Utf8Path::new(DB), &operations, true).await {
+                Err(TuringDbError::BatchFailed { operation, error }) => {
+                    assert_eq!(operation, 3);
+                    assert_eq!(error, "KeyAlreadyExists");
+                }
+                outcome => panic!("Unexpected outcome {:?}", outcome),
+            }
+
+            assert_eq!(field_value(&engine, "alice").await, None);
+            assert_eq!(field_value(&engine, "balance").await, Some(b"10".to_vec()));
+            assert_eq!(
+                document_value(&engine, OTHER, "invite").await,
+                Some(b"pending".to_vec())
+            );
+            assert_eq!(document_value(&engine, OTHER, "member").await, None);
+        })
+    }
+
+    #[test]
+    fn a_batch_that_is_not_atomic_carries_on_after_a_failure() {
+        block_on(async {
+            let dir = TestDir::new("batch");
+            let engine = batch_engine(&dir).await;
+
+            let operations = vec![
+                modify(DOCUMENT, "missing", "5"),
+                modify(DOCUMENT, "balance", "5"),
+            ];
+
+            assert_eq!(
+                engine
+                    .batch(Utf8Path::new(DB), &operations, false)
+                    .await
+                    .unwrap(),
+                OpsOutcome::BatchApplied(vec![
+                    BatchResult::Failed("FieldNotFound".into()),
+                    BatchResult::Modified,
+                ])
+            );
+            assert_eq!(field_value(&engine, "balance").await, Some(b"5".to_vec()));
+        })
+    }
+}
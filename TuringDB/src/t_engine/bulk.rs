@@ -0,0 +1,428 @@
+use crate::{
+    DataType, Expiry, FieldData, FieldWrite, TDBCell, TuringDB, TuringDbError, TuringResult,
+};
+use camino::Utf8Path;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+
+/// The number of records written together by default
+const DEFAULT_BULK_BATCH_SIZE: usize = 10_000;
+
+/// A field written by `TuringEngine::bulk_insert()`.
+/// `data_type` is the tag byte of the `DataType` of the value, like `0x28` for `DataType::BINARY`
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct BulkRecord {
+///     pub document: String,
+///     pub key: Vec<u8>,
+///     pub data_type: u8,
+///     pub value: Vec<u8>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BulkRecord {
+    /// The document the field is written to
+    pub document: String,
+    /// The key of the field
+    pub key: Vec<u8>,
+    /// The tag byte of the `DataType` of the value
+    pub data_type: u8,
+    /// The value of the field
+    pub value: Vec<u8>,
+}
+
+/// How `TuringEngine::bulk_insert()` writes its records.
+/// Records are written `batch_size` at a time, the records of a document in each batch
+/// with a single sled transaction and a single sync of the CDC log.
+/// With `check_existing` a record whose key already exists, or appears earlier in the same call,
+/// is rejected like `field_set()` would. Without it the record overwrites the field,
+/// its previous value being kept in the history and the CDC log like a modification
+/// ```
+/// #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct BulkOptions {
+///     batch_size: usize,
+///     check_existing: bool,
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BulkOptions {
+    batch_size: usize,
+    check_existing: bool,
+}
+
+impl Default for BulkOptions {
+    fn default() -> Self {
+        Self {
+            batch_size: DEFAULT_BULK_BATCH_SIZE,
+            check_existing: true,
+        }
+    }
+}
+
+impl BulkOptions {
+    /// Write 10,000 records per batch and reject the keys that already exist
+    pub fn new() -> Self {
+        BulkOptions::default()
+    }
+    /// The number of records written together, at least one
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+
+        self
+    }
+    /// The number of records written together
+    pub fn get_batch_size(&self) -> usize {
+        self.batch_size
+    }
+    /// Reject the records whose keys already exist instead of overwriting them
+    pub fn check_existing(mut self, check_existing: bool) -> Self {
+        self.check_existing = check_existing;
+
+        self
+    }
+}
+
+/// A record that `TuringEngine::bulk_insert()` did not write.
+/// `index` is the position of the record in the records passed to `bulk_insert()`
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+/// pub struct BulkRejection {
+///     pub index: u64,
+///     pub document: String,
+///     pub key: Vec<u8>,
+///     pub error: String,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct BulkRejection {
+    /// The position of the record
+    pub index: u64,
+    /// The document of the record
+    pub document: String,
+    /// The key of the record
+    pub key: Vec<u8>,
+    /// Why the record was rejected
+    pub error: String,
+}
+
+/// How many records of a bulk load were received and written, with the records that were rejected
+/// ```
+/// #[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+/// pub struct BulkProgress {
+///     pub received: u64,
+///     pub inserted: u64,
+///     pub rejected: Vec<BulkRejection>,
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct BulkProgress {
+    /// The number of records received
+    pub received: u64,
+    /// The number of records written
+    pub inserted: u64,
+    /// The records that were not written
+    pub rejected: Vec<BulkRejection>,
+}
+
+impl BulkProgress {
+    /// Add the progress of the next call to `bulk_insert()` of a load made up of many calls,
+    /// so the index of every rejection is its position in the whole load
+    pub fn extend(&mut self, next: BulkProgress) {
+        let offset = self.received;
+
+        self.rejected
+            .extend(next.rejected.into_iter().map(|mut rejection| {
+                rejection.index += offset;
+
+                rejection
+            }));
+        self.received += next.received;
+        self.inserted += next.inserted;
+    }
+}
+
+impl TuringDB {
+    /// Write the records `batch_size` at a time, rejecting the records that cannot be written
+    pub(crate) async fn bulk_insert(
+        &self,
+        records: &[BulkRecord],
+        options: &BulkOptions,
+    ) -> TuringResult<BulkProgress> {
+        let mut progress = BulkProgress {
+            received: records.len() as u64,
+            ..BulkProgress::default()
+        };
+
+        for (batch_index, batch) in records.chunks(options.batch_size).enumerate() {
+            let first = (batch_index * options.batch_size) as u64;
+
+            self.bulk_write(first, batch, options, &mut progress)
+                .await?;
+        }
+
+        // Records are written a document at a time so rejections are put back in the order of the records
+        progress.rejected.sort();
+
+        Ok(progress)
+    }
+    /// Flush every document of the database to disk
+    pub(crate) async fn flush(&self) -> TuringResult<()> {
+        for (_, document) in self.documents() {
+            document.flush_async().await?;
+        }
+
+        Ok(())
+    }
+    /// Write a batch of records, where `first` is the index of the first record of the batch
+    async fn bulk_write(
+        &self,
+        first: u64,
+        records: &[BulkRecord],
+        options: &BulkOptions,
+        progress: &mut BulkProgress,
+    ) -> TuringResult<()> {
+        let mut documents: BTreeMap<&str, Vec<(u64, &BulkRecord)>> = BTreeMap::new();
+
+        for (index, record) in records.iter().enumerate() {
+            documents
+                .entry(record.document.as_str())
+                .or_default()
+                .push((first + index as u64, record));
+        }
+
+        for (document_name, fields) in documents {
+            let document_name = Utf8Path::new(document_name);
+
+            let document = match self.document(document_name) {
+                Some(document) => document,
+                None => {
+                    for (index, record) in fields {
+                        progress
+                            .rejected
+                            .push(record.rejection(index, TuringDbError::DocumentNotFound));
+                    }
+                    continue;
+                }
+            };
+
+            if options.check_existing {
+                for (_, record) in fields.iter() {
+                    self.expire_if_due(document_name, &record.key).await?;
+                }
+            }
+
+            // Hold the database so no other change is applied between the checks and the batch
+            let _writing = self.write_lock_all().await;
+            let mut capture = self.cdc_capture().await?;
+
+            let mut staged = Vec::with_capacity(fields.len());
+            // The fields written by the earlier records of the batch
+            let mut held: HashSet<&[u8]> = HashSet::with_capacity(fields.len());
+
+            for (index, record) in fields {
+                let data_type = match DataType::from_byte(record.data_type) {
+                    Some(data_type) => data_type,
+                    None => {
+                        progress
+                            .rejected
+                            .push(record.rejection(index, TuringDbError::InvalidInput));
+                        continue;
+                    }
+                };
+
+                let key = record.key.as_slice();
+
+                if options.check_existing && (held.contains(key) || document.contains_key(key)?) {
+                    progress
+                        .rejected
+                        .push(record.rejection(index, TuringDbError::KeyAlreadyExists));
+                    continue;
+                }
+
+                let cell = TDBCell::new(data_type, &record.value);
+                let new = self.seal(document_name, key, &FieldData::new(&cell.to_bytes()))?;
+
+                held.insert(key);
+                staged.push(BulkWrite { index, record, new });
+            }
+
+            if staged.is_empty() {
+                continue;
+            }
+
+            // An overwritten field loses the time-to-live of its previous value
+            let writes: Vec<FieldWrite> = staged
+                .iter()
+                .map(|field| {
+                    FieldWrite::new(&field.record.key, Some(&field.new)).expiry(Expiry::Clear)
+                })
+                .collect();
+
+            let written =
+                match self.field_apply(document_name, &document, &writes, true, capture.as_mut()) {
+                    Ok(_) => staged.len(),
+                    // The records are written one at a time so only those taking the value
+                    // of a unique index from another field are rejected
+                    Err(TuringDbError::UniqueViolation { .. }) => {
+                        let mut written = 0;
+
+                        for field in staged {
+                            let write = FieldWrite::new(&field.record.key, Some(&field.new))
+                                .expiry(Expiry::Clear);
+
+                            match self.field_apply(
+                                document_name,
+                                &document,
+                                &[write],
+                                true,
+                                capture.as_mut(),
+                            ) {
+                                Ok(_) => written += 1,
+                                Err(error @ TuringDbError::UniqueViolation { .. }) => progress
+                                    .rejected
+                                    .push(field.record.rejection(field.index, error)),
+                                Err(error) => {
+                                    // The records already written are still captured
+                                    self.cdc_write(capture).await;
+
+                                    return Err(error);
+                                }
+                            }
+                        }
+
+                        written
+                    }
+                    Err(error) => return Err(error),
+                };
+
+            self.cdc_write(capture).await;
+
+            progress.inserted += written as u64;
+        }
+
+        Ok(())
+    }
+}
+
+/// A record of a batch ready to be written, `new` holding the bytes stored in sled
+struct BulkWrite<'r> {
+    index: u64,
+    record: &'r BulkRecord,
+    new: Vec<u8>,
+}
+
+impl BulkRecord {
+    fn rejection(&self, index: u64, error: TuringDbError) -> BulkRejection {
+        BulkRejection {
+            index,
+            document: self.document.clone(),
+            key: self.key.clone(),
+            error: format!("{:?}", error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{t_engine::testing::*, OpsOutcome};
+    use futures_lite::future::block_on;
+
+    fn record(document: &str, key: &str, data_type: u8, value: &str) -> BulkRecord {
+        BulkRecord {
+            document: document.into(),
+            key: key.as_bytes().to_vec(),
+            data_type,
+            value: value.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn records_that_cannot_be_written_are_rejected_and_the_others_inserted() {
+        block_on(async {
+            let dir = TestDir::new("bulk-rejections");
+            let engine = test_engine(&dir.path().join("repo"), false).await;
+            field_set(&engine, "existing", "before").await.unwrap();
+
+            let binary = DataType::BINARY as u8;
+            let records = vec![
+                record(DOCUMENT, "alice", binary, "a"),
+                record(DOCUMENT, "existing", binary, "after"),
+                record("missing", "bob", binary, "b"),
+                record(DOCUMENT, "alice", binary, "again"),
+                record(DOCUMENT, "carol", 0xff, "c"),
+                record(DOCUMENT, "dave", binary, "d"),
+            ];
+            let options = BulkOptions::new().batch_size(4);
+
+            let progress = match engine
+                .bulk_insert(Utf8Path::new(DB), &records, &options)
+                .await
+                .unwrap()
+            {
+                OpsOutcome::BulkInserted(progress) => progress,
+                outcome => panic!("Unexpected outcome {:?}", outcome),
+            };
+
+            assert_eq!(progress.received, 6);
+            assert_eq!(progress.inserted, 2);
+            let rejected: Vec<(u64, &str)> = progress
+                .rejected
+                .iter()
+                .map(|rejection| (rejection.index, rejection.error.as_str()))
+                .collect();
+            assert_eq!(
+                rejected,
+                vec![
+                    (1, "KeyAlreadyExists"),
+                    (2, "DocumentNotFound"),
+                    (3, "KeyAlreadyExists"),
+                    (4, "InvalidInput"),
+                ]
+            );
+
+            assert_eq!(field_value(&engine, "alice").await, Some(b"a".to_vec()));
+            assert_eq!(
+                field_value(&engine, "existing").await,
+                Some(b"before".to_vec())
+            );
+            assert_eq!(field_value(&engine, "carol").await, None);
+            assert_eq!(field_value(&engine, "dave").await, Some(b"d".to_vec()));
+        })
+    }
+
+    #[test]
+    fn without_check_existing_records_overwrite_the_fields() {
+        block_on(async {
+            let dir = TestDir::new("bulk-overwrite");
+            let engine = test_engine(&dir.path().join("repo"), false).await;
+            field_set(&engine, "existing", "before").await.unwrap();
+
+            let binary = DataType::BINARY as u8;
+            let records = vec![
+                record(DOCUMENT, "existing", binary, "after"),
+                record(DOCUMENT, "alice", binary, "a"),
+                record(DOCUMENT, "alice", binary, "again"),
+            ];
+            let options = BulkOptions::new().check_existing(false);
+
+            match engine
+                .bulk_insert(Utf8Path::new(DB), &records, &options)
+                .await
+                .unwrap()
+            {
+                OpsOutcome::BulkInserted(progress) => {
+                    assert_eq!(progress.inserted, 3);
+                    assert!(progress.rejected.is_empty());
+                }
+                outcome => panic!("Unexpected outcome {:?}", outcome),
+            }
+
+            assert_eq!(
+                field_value(&engine, "existing").await,
+                Some(b"after".to_vec())
+            );
+            assert_eq!(field_value(&engine, "alice").await, Some(b"again".to_vec()));
+        })
+    }
+}
@@ -0,0 +1,850 @@
+use crate::{
+    decode_records, encode_record, ChangeKind, FieldData, IndexDefinition, TuringDB, TuringDbError,
+    TuringResult, CDC_LOG_FILE, CDC_OFFSETS_FILE,
+};
+use async_fs::{File, OpenOptions};
+use async_lock::{Mutex, MutexGuard};
+use camino::{Utf8Path, Utf8PathBuf};
+use futures_lite::{io::AsyncWriteExt, stream::StreamExt};
+use serde::{Deserialize, Serialize};
+use sled::Tree;
+use std::{
+    collections::BTreeMap,
+    convert::TryInto,
+    io::ErrorKind,
+    time::{Duration, SystemTime},
+};
+use tai64::TAI64N;
+
+/// Start a new segment once the current one grows past 4MiB
+const DEFAULT_SEGMENT_SIZE: u64 = 4 * 1024 * 1024;
+/// Drop the oldest segments once the log of a database grows past 256MiB
+const DEFAULT_MAX_BYTES: u64 = 256 * 1024 * 1024;
+/// The tree of a document holding the CDC records of the fields written in the same transaction,
+/// keyed by their big-endian sequence, until they are written to the log
+pub(crate) const CDC_OUTBOX_TREE: &str = "cdc_outbox";
+
+/// How much of the CDC log of every database is kept.
+/// The log is split into segments and retention only ever drops whole segments,
+/// the oldest first and never the segment being written to
+/// ```
+/// #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// pub struct CdcRetention {
+///     segment_size: u64,
+///     max_bytes: Option<u64>,
+///     max_age: Option<Duration>,
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CdcRetention {
+    segment_size: u64,
+    max_bytes: Option<u64>,
+    max_age: Option<Duration>,
+}
+
+impl Default for CdcRetention {
+    fn default() -> Self {
+        Self {
+            segment_size: DEFAULT_SEGMENT_SIZE,
+            max_bytes: Some(DEFAULT_MAX_BYTES),
+            max_age: None,
+        }
+    }
+}
+
+impl CdcRetention {
+    /// Keep up to 256MiB of changes per database in segments of 4MiB
+    pub fn new() -> Self {
+        CdcRetention::default()
+    }
+    /// Start a new segment once the current one grows past `segment_size` bytes
+    pub fn segment_size(mut self, segment_size: u64) -> Self {
+        self.segment_size = segment_size;
+
+        self
+    }
+    /// Drop the oldest segments while the log is larger than `max_bytes`, `None` keeps every segment
+    pub fn max_bytes(mut self, max_bytes: Option<u64>) -> Self {
+        self.max_bytes = max_bytes;
+
+        self
+    }
+    /// Drop the segments whose last change is older than `max_age`
+    pub fn max_age(mut self, max_age: Option<Duration>) -> Self {
+        self.max_age = max_age;
+
+        self
+    }
+}
+
+/// A change captured in the CDC log of a database
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CdcChange {
+    DocumentCreate,
+    DocumentDrop,
+    Field {
+        kind: ChangeKind,
+        key: Vec<u8>,
+        old: Option<FieldData>,
+        new: Option<FieldData>,
+    },
+    /// An index declared on the document, whose entries are built from the fields it holds
+    IndexCreate(IndexDefinition),
+    /// An index dropped from the document
+    IndexDrop {
+        name: String,
+    },
+}
+
+/// A single record of the CDC log of a database.
+/// `sequence` numbers start at zero and increase by one with every change to the database.
+/// `sealed` is set when the field values of an encrypted document are still sealed,
+/// which is the case for records written before the data key they were sealed with was retired.
+/// The `DocumentCreate` record of an encrypted document is always `sealed`
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct CdcRecord {
+///     pub sequence: u64,
+///     pub timestamp: TAI64N,
+///     pub document: String,
+///     pub sealed: bool,
+///     pub change: CdcChange,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CdcRecord {
+    pub sequence: u64,
+    pub timestamp: TAI64N,
+    pub document: String,
+    pub sealed: bool,
+    pub change: CdcChange,
+}
+
+#[derive(Debug)]
+pub(crate) struct CdcWriter {
+    file: Option<File>,
+    /// The sequence of the first record of the segment being written to
+    segment: u64,
+    size: u64,
+    next: u64,
+    /// The records of applied changes that are not written to the log yet,
+    /// with the outbox tree of the document holding them until they are
+    pending: Vec<(CdcRecord, Option<Tree>)>,
+}
+
+/// A durable log of every change to the documents of a database.
+/// The log is made up of the `cdc.<first sequence>` segments in the database directory
+/// and the offsets committed by consumer groups are kept in its `CDC_OFFSETS` file.
+/// The record of a field change is stored in the `CDC_OUTBOX_TREE` of its document by the transaction
+/// writing the field, so a change that could not be written to the log is written on the next
+/// write or read of the log, or once the repo is loaded again after a crash
+/// ```
+/// #[derive(Debug)]
+/// pub struct CdcLog {
+///     dir: Utf8PathBuf,
+///     retention: CdcRetention,
+///     writer: Mutex<CdcWriter>,
+///     offsets: Mutex<()>,
+/// }
+/// ```
+#[derive(Debug)]
+pub struct CdcLog {
+    dir: Utf8PathBuf,
+    retention: CdcRetention,
+    writer: Mutex<CdcWriter>,
+    offsets: Mutex<()>,
+}
+
+/// Holds the CDC log of a database while a change is applied so that the order
+/// of the sequence numbers is the order the changes were applied in
+pub(crate) struct CdcCapture<'c> {
+    log: &'c CdcLog,
+    writer: MutexGuard<'c, CdcWriter>,
+}
+
+impl CdcLog {
+    /// Open the CDC log in a database directory. The first segment is created on the first write
+    pub(crate) fn new(dir: &Utf8Path, retention: CdcRetention) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            retention,
+            writer: Mutex::new(CdcWriter {
+                file: None,
+                segment: 0,
+                size: 0,
+                next: 0,
+                pending: Vec::new(),
+            }),
+            offsets: Mutex::new(()),
+        }
+    }
+    /// Lock the log before applying a change
+    pub(crate) async fn capture(&self) -> TuringResult<CdcCapture<'_>> {
+        let mut writer = self.writer.lock().await;
+        self.open(&mut writer).await?;
+
+        Ok(CdcCapture { log: self, writer })
+    }
+    /// Write the records of the changes that were applied but not written to the log yet
+    pub async fn flush(&self) -> TuringResult<()> {
+        let mut writer = self.writer.lock().await;
+        self.open(&mut writer).await?;
+
+        self.write_pending(&mut writer).await
+    }
+    /// The first sequence still kept and the sequence the next change will get
+    pub async fn bounds(&self) -> TuringResult<(u64, u64)> {
+        let mut writer = self.writer.lock().await;
+        self.open(&mut writer).await?;
+
+        let first = match self.segments().await?.first() {
+            Some((first, _)) => *first,
+            None => writer.next,
+        };
+
+        Ok((first, writer.next))
+    }
+    /// Up to `max` records starting from the record with the sequence `from`, oldest first
+    pub async fn read(&self, from: u64, max: usize) -> TuringResult<Vec<CdcRecord>> {
+        // Hold the lock so retention cannot drop a segment while it is read
+        let mut writer = self.writer.lock().await;
+        self.open(&mut writer).await?;
+        self.write_pending(&mut writer).await?;
+
+        let segments = self.segments().await?;
+        let first = match segments.first() {
+            Some((first, _)) => *first,
+            None => writer.next,
+        };
+
+        if from < first || from > writer.next {
+            return Err(TuringDbError::CdcOffsetOutOfRange {
+                requested: from,
+                first,
+                next: writer.next,
+            });
+        }
+
+        let start = segments
+            .iter()
+            .rposition(|(segment, _)| *segment <= from)
+            .unwrap_or(0);
+
+        let mut records = Vec::new();
+        for (_, path) in &segments[start..] {
+            if records.len() >= max {
+                break;
+            }
+
+            let contents = async_fs::read(path).await?;
+            let (entries, _) = CdcLog::decode(path, &contents)?;
+
+            records.extend(
+                entries
+                    .into_iter()
+                    .filter(|record| record.sequence >= from)
+                    .take(max - records.len()),
+            );
+        }
+
+        Ok(records)
+    }
+    /// The offset committed by a consumer group, which is the sequence of the next record it reads
+    pub async fn committed(&self, group: &str) -> TuringResult<Option<u64>> {
+        let _offsets = self.offsets.lock().await;
+
+        Ok(self.read_offsets().await?.get(group).copied())
+    }
+    /// Commit the offset of a consumer group once it has processed every record before `offset`
+    pub async fn commit(&self, group: &str, offset: u64) -> TuringResult<()> {
+        let (first, next) = self.bounds().await?;
+
+        if offset < first || offset > next {
+            return Err(TuringDbError::CdcOffsetOutOfRange {
+                requested: offset,
+                first,
+                next,
+            });
+        }
+
+        let _offsets = self.offsets.lock().await;
+
+        let mut offsets = self.read_offsets().await?;
+        offsets.insert(group.to_owned(), offset);
+
+        self.write_offsets(&offsets).await
+    }
+    /// Up to `max` records from the offset committed by a consumer group.
+    /// A group that has not committed an offset starts from the oldest record kept
+    pub async fn poll(&self, group: &str, max: usize) -> TuringResult<Vec<CdcRecord>> {
+        let from = match self.committed(group).await? {
+            Some(offset) => offset,
+            None => self.bounds().await?.0,
+        };
+
+        self.read(from, max).await
+    }
+    /// Continue from the last complete record of the newest segment,
+    /// cutting off a record left incomplete by a crash.
+    /// The pending records already written before the log was opened again are dropped
+    async fn open(&self, writer: &mut CdcWriter) -> TuringResult<()> {
+        if writer.file.is_some() {
+            return Ok(());
+        }
+
+        let mut written = writer.segment;
+
+        if let Some((segment, path)) = self.segments().await?.pop() {
+            let contents = async_fs::read(&path).await?;
+            let (entries, valid_len) = CdcLog::decode(&path, &contents)?;
+
+            if valid_len < contents.len() {
+                let file = OpenOptions::new().write(true).open(&path).await?;
+                file.set_len(valid_len as u64).await?;
+                file.sync_all().await?;
+            }
+
+            writer.segment = segment;
+            writer.size = valid_len as u64;
+            written = match entries.last() {
+                Some(record) => record.sequence + 1,
+                None => segment,
+            };
+        }
+
+        writer.file = Some(self.open_segment(writer.segment).await?);
+
+        for (record, outbox) in &writer.pending {
+            if record.sequence < written {
+                CdcLog::clear_outbox(record, outbox.as_ref())?;
+            }
+        }
+        writer
+            .pending
+            .retain(|(record, _)| record.sequence >= written);
+        writer.next = written.max(writer.next);
+
+        self.enforce_retention().await
+    }
+    /// Write the pending records to the log, then drop them from the outboxes of their documents.
+    /// On a failure the records stay pending and the log is opened again before the next write
+    async fn write_pending(&self, writer: &mut CdcWriter) -> TuringResult<()> {
+        if writer.pending.is_empty() {
+            return Ok(());
+        }
+
+        let records = writer
+            .pending
+            .iter()
+            .map(|(record, _)| record.clone())
+            .collect::<Vec<CdcRecord>>();
+
+        if let Err(error) = self.write(writer, &records).await {
+            writer.file = None;
+
+            return Err(error);
+        }
+
+        for (record, outbox) in writer.pending.drain(..) {
+            CdcLog::clear_outbox(&record, outbox.as_ref())?;
+        }
+
+        Ok(())
+    }
+
+    fn clear_outbox(record: &CdcRecord, outbox: Option<&Tree>) -> TuringResult<()> {
+        if let Some(outbox) = outbox {
+            outbox.remove(record.sequence.to_be_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    /// Write the records to the log and sync them once they are all written
+    async fn write(&self, writer: &mut CdcWriter, records: &[CdcRecord]) -> TuringResult<()> {
+        for record in records {
+            let sequence = record.sequence;
+            let record = encode_record(record, "Unable to serialize a CDC record")?;
+
+            if writer.size > 0 && writer.size + record.len() as u64 > self.retention.segment_size {
+                // The segment being closed keeps every record written to it
+                if let Some(file) = &mut writer.file {
+                    file.sync_data().await?;
+                }
+
+                writer.file = None;
+                writer.segment = sequence;
+                writer.size = 0;
+                writer.file = Some(self.open_segment(writer.segment).await?);
+
+                self.enforce_retention().await?;
+            }
+
+            if let Some(file) = &mut writer.file {
+                file.write_all(&record).await?;
+            }
+            writer.size += record.len() as u64;
+        }
+
+        if let Some(file) = &mut writer.file {
+            file.sync_data().await?;
+        }
+
+        Ok(())
+    }
+
+    async fn open_segment(&self, segment: u64) -> TuringResult<File> {
+        Ok(OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.segment_path(segment))
+            .await?)
+    }
+    /// Drop the oldest segments that are past the retention limits, keeping the newest segment
+    async fn enforce_retention(&self) -> TuringResult<()> {
+        let mut segments = Vec::new();
+        for (_, path) in self.segments().await? {
+            let metadata = async_fs::metadata(&path).await?;
+            segments.push((path, metadata));
+        }
+
+        let mut total = segments
+            .iter()
+            .map(|(_, metadata)| metadata.len())
+            .sum::<u64>();
+        segments.pop();
+
+        for (path, metadata) in segments {
+            let too_large = match self.retention.max_bytes {
+                Some(max_bytes) => total > max_bytes,
+                None => false,
+            };
+            let too_old = match (self.retention.max_age, metadata.modified()) {
+                (Some(max_age), Ok(modified)) => match SystemTime::now().duration_since(modified) {
+                    Ok(age) => age > max_age,
+                    Err(_) => false,
+                },
+                _ => false,
+            };
+
+            if !too_large && !too_old {
+                break;
+            }
+
+            async_fs::remove_file(&path).await?;
+            total -= metadata.len();
+        }
+
+        Ok(())
+    }
+    /// The segments of the log with the sequence of their first record, oldest first
+    async fn segments(&self) -> TuringResult<Vec<(u64, Utf8PathBuf)>> {
+        let mut segments = Vec::new();
+
+        let mut entries = match async_fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(segments),
+            Err(error) => return Err(error.into()),
+        };
+
+        while let Some(entry) = entries.try_next().await? {
+            let name = match entry.file_name().into_string() {
+                Ok(name) => name,
+                Err(_) => continue,
+            };
+
+            let sequence = name
+                .strip_prefix(CDC_LOG_FILE)
+                .and_then(|suffix| suffix.strip_prefix('.'))
+                .and_then(|sequence| sequence.parse::<u64>().ok());
+
+            if let Some(sequence) = sequence {
+                segments.push((sequence, self.dir.join(name)));
+            }
+        }
+
+        segments.sort();
+
+        Ok(segments)
+    }
+    /// Zero-padded so the segments also sort by name
+    fn segment_path(&self, segment: u64) -> Utf8PathBuf {
+        self.dir.join(format!("{}.{:020}", CDC_LOG_FILE, segment))
+    }
+
+    async fn read_offsets(&self) -> TuringResult<BTreeMap<String, u64>> {
+        let path = self.dir.join(CDC_OFFSETS_FILE);
+
+        let contents = match async_fs::read(&path).await {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(BTreeMap::new()),
+            Err(error) => return Err(error.into()),
+        };
+
+        let corrupted = |offset| TuringDbError::CdcLogCorrupted {
+            file: path.to_string(),
+            offset,
+        };
+
+        match decode_records::<BTreeMap<String, u64>, _>(&contents, corrupted)? {
+            (mut offsets, valid_len) if offsets.len() == 1 && valid_len == contents.len() => {
+                Ok(offsets.remove(0))
+            }
+            _ => Err(corrupted(0)),
+        }
+    }
+    /// Replace the offsets through a rename so a crash never leaves them partly written
+    async fn write_offsets(&self, offsets: &BTreeMap<String, u64>) -> TuringResult<()> {
+        let record = encode_record(offsets, "Unable to serialize the CDC offsets")?;
+
+        let temporary = self.dir.join(format!("{}.tmp", CDC_OFFSETS_FILE));
+        let mut file = File::create(&temporary).await?;
+        file.write_all(&record).await?;
+        file.sync_all().await?;
+
+        async_fs::rename(&temporary, self.dir.join(CDC_OFFSETS_FILE)).await?;
+
+        Ok(())
+    }
+
+    /// The record of a field change kept in the `CDC_OUTBOX_TREE` of a document under its sequence
+    pub(crate) fn outbox_record(
+        document_name: &Utf8Path,
+        sequence: &[u8],
+        record: &[u8],
+    ) -> TuringResult<CdcRecord> {
+        match bincode::deserialize::<CdcRecord>(record) {
+            Ok(record) => Ok(record),
+            Err(_) => Err(TuringDbError::CdcLogCorrupted {
+                file: document_name.join(CDC_OUTBOX_TREE).to_string(),
+                offset: match sequence.try_into() {
+                    Ok(sequence) => u64::from_be_bytes(sequence),
+                    Err(_) => 0,
+                },
+            }),
+        }
+    }
+
+    fn decode(path: &Utf8Path, contents: &[u8]) -> TuringResult<(Vec<CdcRecord>, usize)> {
+        decode_records(contents, |offset| TuringDbError::CdcLogCorrupted {
+            file: path.to_string(),
+            offset,
+        })
+    }
+}
+
+impl CdcCapture<'_> {
+    /// The sequence the next change will get
+    pub(crate) fn next(&self) -> u64 {
+        self.writer.next
+    }
+    /// Take the records of field changes committed together with their fields,
+    /// which are kept in `outbox` until they are written to the log
+    pub(crate) fn committed(&mut self, records: Vec<CdcRecord>, outbox: &Tree) {
+        for record in records {
+            self.writer.next = record.sequence + 1;
+            self.writer.pending.push((record, Some(outbox.clone())));
+        }
+    }
+    /// Append a change applied while the log was held, returning its sequence.
+    /// The change is applied whether or not the log can be written, see `CdcCapture::write()`
+    pub(crate) async fn append(
+        mut self,
+        document_name: &Utf8Path,
+        sealed: bool,
+        change: CdcChange,
+    ) -> u64 {
+        let record = CdcRecord {
+            sequence: self.writer.next,
+            timestamp: TAI64N::now(),
+            document: document_name.to_string(),
+            sealed,
+            change,
+        };
+        let sequence = record.sequence;
+
+        self.writer.next += 1;
+        self.writer.pending.push((record, None));
+        self.write().await;
+
+        sequence
+    }
+    /// Write the records of the changes applied while the log was held.
+    /// A record that cannot be written stays pending and is written by the next write or read of the log,
+    /// which then reports the failure
+    pub(crate) async fn write(mut self) {
+        let _ = self.log.write_pending(&mut self.writer).await;
+    }
+    /// Write the records left in the outboxes of the documents by a crash after their fields were written
+    pub(crate) async fn recover(mut self, mut records: Vec<(CdcRecord, Tree)>) -> TuringResult<()> {
+        records.sort_by_key(|(record, _)| record.sequence);
+
+        for (record, outbox) in records {
+            if record.sequence < self.writer.next {
+                CdcLog::clear_outbox(&record, Some(&outbox))?;
+            } else {
+                self.committed(vec![record], &outbox);
+            }
+        }
+
+        self.log.write_pending(&mut self.writer).await
+    }
+}
+
+impl TuringDB {
+    /// Hold the CDC log of the database, if it has one, while a change is applied
+    pub(crate) async fn cdc_capture(&self) -> TuringResult<Option<CdcCapture<'_>>> {
+        match &self.cdc {
+            None => Ok(None),
+            Some(cdc) => Ok(Some(cdc.capture().await?)),
+        }
+    }
+    /// Write the records of the field changes committed while the log was held
+    pub(crate) async fn cdc_write(&self, capture: Option<CdcCapture<'_>>) {
+        if let Some(capture) = capture {
+            capture.write().await;
+        }
+    }
+    /// The record of the change of a field using the bytes stored in sled,
+    /// so the values of encrypted documents stay sealed in the log
+    pub(crate) fn cdc_field_record(
+        &self,
+        sequence: u64,
+        document_name: &Utf8Path,
+        kind: ChangeKind,
+        key: &[u8],
+        old: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> TuringResult<CdcRecord> {
+        let old = match old {
+            None => None,
+            Some(old) => Some(FieldData::from_bytes(old)?),
+        };
+        let new = match new {
+            None => None,
+            Some(new) => Some(FieldData::from_bytes(new)?),
+        };
+
+        Ok(CdcRecord {
+            sequence,
+            timestamp: TAI64N::now(),
+            document: document_name.to_string(),
+            sealed: self.is_encrypted(document_name),
+            change: CdcChange::Field {
+                kind,
+                key: key.to_vec(),
+                old,
+                new,
+            },
+        })
+    }
+    /// Write the records left in the outboxes of the documents when the repo was last stopped
+    pub(crate) async fn cdc_recover(&self) -> TuringResult<()> {
+        let capture = match self.cdc_capture().await? {
+            None => return Ok(()),
+            Some(capture) => capture,
+        };
+
+        let mut records = Vec::new();
+        for (document_name, document) in self.documents() {
+            if !document
+                .tree_names()
+                .iter()
+                .any(|name| name == CDC_OUTBOX_TREE.as_bytes())
+            {
+                continue;
+            }
+
+            let outbox = document.open_tree(CDC_OUTBOX_TREE)?;
+            for entry in outbox.iter() {
+                let (sequence, record) = entry?;
+
+                records.push((
+                    CdcLog::outbox_record(&document_name, &sequence, &record)?,
+                    outbox.clone(),
+                ));
+            }
+        }
+
+        capture.recover(records).await
+    }
+    /// Append the creation or removal of a document to the CDC log
+    pub(crate) async fn cdc_record_document(
+        &self,
+        document_name: &Utf8Path,
+        change: CdcChange,
+    ) -> TuringResult<()> {
+        if let Some(capture) = self.cdc_capture().await? {
+            capture
+                .append(document_name, self.is_encrypted(document_name), change)
+                .await;
+        }
+
+        Ok(())
+    }
+    /// Open the field values of a record of an encrypted document.
+    /// Values that cannot be opened with the data keys of the database are left sealed
+    pub(crate) fn cdc_open(&self, mut record: CdcRecord) -> CdcRecord {
+        if !record.sealed {
+            return record;
+        }
+
+        let document_name = Utf8PathBuf::from(&record.document);
+
+        if let CdcChange::Field {
+            kind,
+            key,
+            old,
+            new,
+        } = &record.change
+        {
+            let open = |field_data: &Option<FieldData>| -> TuringResult<Option<FieldData>> {
+                match field_data {
+                    None => Ok(None),
+                    Some(field_data) => Ok(Some(self.sealer.unseal(
+                        &document_name,
+                        key,
+                        &field_data.to_bytes()?,
+                    )?)),
+                }
+            };
+
+            if let (Ok(old), Ok(new)) = (open(old), open(new)) {
+                record.change = CdcChange::Field {
+                    kind: *kind,
+                    key: key.clone(),
+                    old,
+                    new,
+                };
+                record.sealed = false;
+            }
+        }
+
+        record
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{t_engine::testing::*, TDBCell, TuringEngine};
+    use futures_lite::future::block_on;
+
+    async fn restart(repo: &Utf8Path) -> TuringEngine {
+        let mut engine = TuringEngine::builder()
+            .repo_dir(repo.to_path_buf())
+            .build()
+            .await
+            .unwrap();
+        engine.repo_init().await.unwrap();
+
+        engine
+    }
+
+    async fn records(engine: &TuringEngine) -> Vec<CdcRecord> {
+        engine.cdc_read(Utf8Path::new(DB), 0, 100).await.unwrap()
+    }
+
+    fn field_change(record: &CdcRecord) -> (ChangeKind, &[u8], Option<Vec<u8>>) {
+        match &record.change {
+            CdcChange::Field { kind, key, new, .. } => (
+                *kind,
+                key.as_slice(),
+                new.as_ref()
+                    .map(|new| TDBCell::from_bytes(new.data()).unwrap().get_data().to_vec()),
+            ),
+            change => panic!("Unexpected change {:?}", change),
+        }
+    }
+
+    #[test]
+    fn the_captured_sequence_follows_the_writes() {
+        block_on(async {
+            let dir = TestDir::new("cdc-sequence");
+            let engine = test_engine(&dir.path().join("repo"), false).await;
+
+            field_set(&engine, "alice", "admin").await.unwrap();
+            field_set(&engine, "bob", "user").await.unwrap();
+            engine
+                .field_modify(&field_ops("alice", "user"))
+                .await
+                .unwrap();
+            engine.field_remove(&field_ops("bob", "")).await.unwrap();
+
+            let records = records(&engine).await;
+            let sequences: Vec<u64> = records.iter().map(|record| record.sequence).collect();
+            assert_eq!(sequences, vec![0, 1, 2, 3, 4]);
+            assert_eq!(records[0].change, CdcChange::DocumentCreate);
+
+            let changes: Vec<_> = records[1..].iter().map(field_change).collect();
+            assert_eq!(
+                changes,
+                vec![
+                    (ChangeKind::Insert, &b"alice"[..], Some(b"admin".to_vec())),
+                    (ChangeKind::Insert, &b"bob"[..], Some(b"user".to_vec())),
+                    (ChangeKind::Modify, &b"alice"[..], Some(b"user".to_vec())),
+                    (ChangeKind::Remove, &b"bob"[..], None),
+                ]
+            );
+        })
+    }
+
+    #[test]
+    fn a_change_left_in_the_outbox_by_a_crash_is_written_after_a_restart() {
+        block_on(async {
+            let dir = TestDir::new("cdc-outbox");
+            let repo = dir.path().join("repo");
+            let engine = test_engine(&repo, false).await;
+            field_set(&engine, "alice", "admin").await.unwrap();
+
+            let written = records(&engine).await;
+            drop(engine);
+
+            // A crash after the transaction of a field committed and before its record reached the log,
+            // next to a record that was written to the log before the crash
+            let document = sled::open(repo.join(DB).join(DOCUMENT)).unwrap();
+            let outbox = document.open_tree(CDC_OUTBOX_TREE).unwrap();
+            assert!(outbox.is_empty());
+
+            let mut lost = written[1].clone();
+            lost.sequence = 2;
+            lost.change = match lost.change {
+                CdcChange::Field { key, new, .. } => CdcChange::Field {
+                    kind: ChangeKind::Modify,
+                    key,
+                    old: new.clone(),
+                    new,
+                },
+                change => panic!("Unexpected change {:?}", change),
+            };
+            for record in [&written[1], &lost] {
+                outbox
+                    .insert(
+                        record.sequence.to_be_bytes(),
+                        bincode::serialize(record).unwrap(),
+                    )
+                    .unwrap();
+            }
+            outbox.flush().unwrap();
+            drop(outbox);
+            drop(document);
+
+            let engine = restart(&repo).await;
+
+            let records = records(&engine).await;
+            assert_eq!(records.len(), 3);
+            assert_eq!(records[..2], written[..]);
+            assert_eq!(records[2], lost);
+
+            // The next change follows the recovered record
+            field_set(&engine, "bob", "user").await.unwrap();
+            let next = engine.cdc_read(Utf8Path::new(DB), 3, 10).await.unwrap();
+            assert_eq!(next.len(), 1);
+            assert_eq!(next[0].sequence, 3);
+            drop(engine);
+
+            let document = sled::open(repo.join(DB).join(DOCUMENT)).unwrap();
+            assert!(document.open_tree(CDC_OUTBOX_TREE).unwrap().is_empty());
+        })
+    }
+}
@@ -0,0 +1,406 @@
+use crate::{
+    CdcChange, CdcLog, CdcRecord, Document, FieldData, FieldSealer, TuringDB, TuringResult,
+    CDC_OUTBOX_TREE,
+};
+use camino::{Utf8Path, Utf8PathBuf};
+use futures_lite::Stream;
+use serde::{Deserialize, Serialize};
+use sled::{Event, Subscriber};
+use std::{
+    collections::VecDeque,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// How many records of the CDC log are read at a time when a feed is resumed
+const RESUME_BATCH: usize = 1000;
+
+/// The kind of change applied to a field.
+/// `Expire` is the removal of a field that outlived its time-to-live
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChangeKind {
+    Insert,
+    Modify,
+    Remove,
+    Expire,
+}
+
+/// A change to a field of a watched document.
+/// `old` is the value before the change and `new` the value after it,
+/// so an insert has no `old` value and a remove has no `new` value.
+/// `position` is the sequence of the change in the CDC log of the database,
+/// which can be passed to `TuringEngine::watch_since()` to resume a feed after it
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct ChangeEvent {
+///     pub position: u64,
+///     pub kind: ChangeKind,
+///     pub key: Vec<u8>,
+///     pub old: Option<FieldData>,
+///     pub new: Option<FieldData>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChangeEvent {
+    pub position: u64,
+    pub kind: ChangeKind,
+    pub key: Vec<u8>,
+    pub old: Option<FieldData>,
+    pub new: Option<FieldData>,
+}
+
+/// A stream of the changes to the fields of a document whose keys start with a prefix.
+/// It waits on a sled subscriber to the `CDC_OUTBOX_TREE` of the document so no polling is involved.
+///
+/// Every field write stores its CDC record in the outbox in the transaction writing the field,
+/// so the feed reports the old and new values and the kind of every change, expiries included,
+/// without keeping the watched fields in memory.
+/// `next` is the sequence after the last change reported, so a change read from the CDC log
+/// when the feed is resumed is not reported again once it is seen in the outbox.
+/// Writes that are not captured by the CDC log, like re-encrypting a field with a new data key, are not reported
+/// ```
+/// pub struct ChangeFeed {
+///     document_name: Utf8PathBuf,
+///     key_prefix: Vec<u8>,
+///     sealer: Option<FieldSealer>,
+///     subscriber: Subscriber,
+///     next: u64,
+///     pending: VecDeque<ChangeEvent>,
+/// }
+/// ```
+pub struct ChangeFeed {
+    document_name: Utf8PathBuf,
+    key_prefix: Vec<u8>,
+    sealer: Option<FieldSealer>,
+    subscriber: Subscriber,
+    next: u64,
+    pending: VecDeque<ChangeEvent>,
+}
+
+impl ChangeFeed {
+    /// Start watching the fields of a document whose keys start with `key_prefix`.
+    /// `sealer` opens the field values of an encrypted document
+    pub(crate) fn new(
+        document_name: &Utf8Path,
+        document: &Document,
+        key_prefix: &[u8],
+        sealer: Option<FieldSealer>,
+    ) -> TuringResult<Self> {
+        let subscriber = document.open_tree(CDC_OUTBOX_TREE)?.watch_prefix(vec![]);
+
+        Ok(Self {
+            document_name: document_name.to_path_buf(),
+            key_prefix: key_prefix.to_vec(),
+            sealer,
+            subscriber,
+            next: 0,
+            pending: VecDeque::default(),
+        })
+    }
+
+    fn open(&self, key: &[u8], field_data: Option<FieldData>) -> TuringResult<Option<FieldData>> {
+        match (&self.sealer, field_data) {
+            (Some(sealer), Some(field_data)) => Ok(Some(sealer.unseal(
+                &self.document_name,
+                key,
+                &field_data.to_bytes()?,
+            )?)),
+            (_, field_data) => Ok(field_data),
+        }
+    }
+    /// Turn a CDC record into a change event.
+    /// Returns `None` for a change already reported or one the feed does not watch
+    fn event(&mut self, record: CdcRecord) -> TuringResult<Option<ChangeEvent>> {
+        if record.sequence < self.next {
+            return Ok(None);
+        }
+        self.next = record.sequence + 1;
+
+        if record.document != self.document_name.as_str() {
+            return Ok(None);
+        }
+
+        match record.change {
+            CdcChange::Field {
+                kind,
+                key,
+                old,
+                new,
+            } if key.starts_with(&self.key_prefix) => {
+                let (old, new) = match record.sealed {
+                    true => (self.open(&key, old)?, self.open(&key, new)?),
+                    false => (old, new),
+                };
+
+                Ok(Some(ChangeEvent {
+                    position: record.sequence,
+                    kind,
+                    key,
+                    old,
+                    new,
+                }))
+            }
+            _ => Ok(None),
+        }
+    }
+    /// Turn a sled event of the outbox into a change event.
+    /// The records leave the outbox once they are written to the CDC log, which is not a change
+    fn apply(&mut self, event: Event) -> TuringResult<Option<ChangeEvent>> {
+        match event {
+            Event::Insert { key, value } => {
+                let record = CdcLog::outbox_record(&self.document_name, &key, &value)?;
+
+                self.event(record)
+            }
+            Event::Remove { .. } => Ok(None),
+        }
+    }
+}
+
+impl TuringDB {
+    /// Report every change to the fields watched by `feed` made after the change at `position`
+    /// before the changes that follow, reading them from the CDC log.
+    /// Fails with `CdcOffsetOutOfRange` when the log no longer keeps them
+    pub(crate) async fn changes_since(
+        &self,
+        feed: &mut ChangeFeed,
+        position: u64,
+    ) -> TuringResult<()> {
+        let cdc = match &self.cdc {
+            None => return Ok(()),
+            Some(cdc) => cdc,
+        };
+
+        let mut from = position.saturating_add(1);
+
+        loop {
+            let records = cdc.read(from, RESUME_BATCH).await?;
+            let last = match records.last() {
+                None => return Ok(()),
+                Some(record) => record.sequence,
+            };
+
+            for record in records {
+                if let Some(event) = feed.event(record)? {
+                    feed.pending.push_back(event);
+                }
+            }
+
+            from = last + 1;
+        }
+    }
+}
+
+impl Stream for ChangeFeed {
+    type Item = TuringResult<ChangeEvent>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Poll::Ready(Some(Ok(event)));
+            }
+
+            match Pin::new(&mut self.subscriber).poll(cx) {
+                Poll::Pending => return Poll::Pending,
+                // The document has been closed
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Ready(Some(event)) => match self.apply(event) {
+                    Ok(Some(event)) => return Poll::Ready(Some(Ok(event))),
+                    Ok(None) => continue,
+                    Err(error) => return Poll::Ready(Some(Err(error))),
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{t_engine::testing::*, TDBCell, TuringDbError, TuringEngine};
+    use futures_lite::{future::block_on, StreamExt};
+    use std::time::Duration;
+
+    async fn next_change(
+        feed: &mut ChangeFeed,
+    ) -> (u64, ChangeKind, String, Option<Vec<u8>>, Option<Vec<u8>>) {
+        let value = |field_data: Option<FieldData>| {
+            field_data.map(|field_data| {
+                TDBCell::from_bytes(field_data.data())
+                    .unwrap()
+                    .get_data()
+                    .to_vec()
+            })
+        };
+
+        let event = feed.next().await.unwrap().unwrap();
+
+        (
+            event.position,
+            event.kind,
+            String::from_utf8(event.key).unwrap(),
+            value(event.old),
+            value(event.new),
+        )
+    }
+
+    fn watch(engine: &TuringEngine) -> ChangeFeed {
+        engine
+            .watch(Utf8Path::new(DB), Utf8Path::new(DOCUMENT), b"user:")
+            .unwrap()
+    }
+
+    #[test]
+    fn inserts_modifications_and_removals_are_reported_with_their_values() {
+        block_on(async {
+            let dir = TestDir::new("changefeed-events");
+            let engine = test_engine(&dir.path().join("repo"), false).await;
+            let mut feed = watch(&engine);
+
+            field_set(&engine, "user:alice", "admin").await.unwrap();
+            field_set(&engine, "session", "token").await.unwrap();
+            engine
+                .field_modify(&field_ops("user:alice", "user"))
+                .await
+                .unwrap();
+            engine
+                .field_remove(&field_ops("user:alice", ""))
+                .await
+                .unwrap();
+
+            assert_eq!(
+                next_change(&mut feed).await,
+                (
+                    1,
+                    ChangeKind::Insert,
+                    "user:alice".to_owned(),
+                    None,
+                    Some(b"admin".to_vec())
+                )
+            );
+            assert_eq!(
+                next_change(&mut feed).await,
+                (
+                    3,
+                    ChangeKind::Modify,
+                    "user:alice".to_owned(),
+                    Some(b"admin".to_vec()),
+                    Some(b"user".to_vec())
+                )
+            );
+            assert_eq!(
+                next_change(&mut feed).await,
+                (
+                    4,
+                    ChangeKind::Remove,
+                    "user:alice".to_owned(),
+                    Some(b"user".to_vec()),
+                    None
+                )
+            );
+        })
+    }
+
+    #[test]
+    fn a_resumed_feed_reports_the_changes_after_its_position_once() {
+        block_on(async {
+            let dir = TestDir::new("changefeed-resume");
+            let engine = test_engine(&dir.path().join("repo"), false).await;
+            let mut feed = watch(&engine);
+
+            field_set(&engine, "user:alice", "admin").await.unwrap();
+            let (position, ..) = next_change(&mut feed).await;
+            drop(feed);
+
+            engine
+                .field_modify(&field_ops("user:alice", "user"))
+                .await
+                .unwrap();
+            field_set(&engine, "user:bob", "admin").await.unwrap();
+            engine
+                .field_remove(&field_ops("user:alice", ""))
+                .await
+                .unwrap();
+
+            let mut feed = engine
+                .watch_since(
+                    Utf8Path::new(DB),
+                    Utf8Path::new(DOCUMENT),
+                    b"user:",
+                    position,
+                )
+                .await
+                .unwrap();
+            field_set(&engine, "user:carol", "user").await.unwrap();
+
+            let mut changes = Vec::new();
+            for _ in 0..4 {
+                let (position, kind, key, ..) = next_change(&mut feed).await;
+                changes.push((position, kind, key));
+            }
+
+            assert_eq!(
+                changes,
+                vec![
+                    (2, ChangeKind::Modify, "user:alice".to_owned()),
+                    (3, ChangeKind::Insert, "user:bob".to_owned()),
+                    (4, ChangeKind::Remove, "user:alice".to_owned()),
+                    (5, ChangeKind::Insert, "user:carol".to_owned()),
+                ]
+            );
+
+            engine
+                .field_remove(&field_ops("user:bob", ""))
+                .await
+                .unwrap();
+            assert_eq!(next_change(&mut feed).await.0, 6);
+        })
+    }
+
+    #[test]
+    fn the_removal_of_an_expired_field_is_reported_as_its_expiry() {
+        block_on(async {
+            let dir = TestDir::new("changefeed-expiry");
+            let engine = test_engine(&dir.path().join("repo"), false).await;
+            let mut feed = watch(&engine);
+
+            engine
+                .field_set(&field_ops("user:alice", "admin").ttl(Duration::from_millis(50)))
+                .await
+                .unwrap();
+            std::thread::sleep(Duration::from_millis(100));
+            engine.expiry_sweep().await.unwrap();
+
+            assert_eq!(next_change(&mut feed).await.1, ChangeKind::Insert);
+            assert_eq!(
+                next_change(&mut feed).await,
+                (
+                    2,
+                    ChangeKind::Expire,
+                    "user:alice".to_owned(),
+                    Some(b"admin".to_vec()),
+                    None
+                )
+            );
+        })
+    }
+
+    #[test]
+    fn a_feed_cannot_resume_from_a_position_the_log_does_not_hold() {
+        block_on(async {
+            let dir = TestDir::new("changefeed-out-of-range");
+            let engine = test_engine(&dir.path().join("repo"), false).await;
+
+            let resumed = engine
+                .watch_since(Utf8Path::new(DB), Utf8Path::new(DOCUMENT), b"user:", 10)
+                .await;
+
+            match resumed {
+                Err(TuringDbError::CdcOffsetOutOfRange { requested: 11, .. }) => (),
+                Err(error) => panic!("Unexpected error {:?}", error),
+                Ok(_) => panic!("Resumed from a position the log does not hold"),
+            }
+        })
+    }
+}
@@ -0,0 +1,586 @@
+use crate::{
+    BatchOperation, BulkOptions, BulkRecord, DataType, DbSnapshot, IndexDefinition,
+    MembershipChange, NodeId, OpsOutcome, RaftConfig, RaftEntry, RaftEntryKind, RaftMessage,
+    RaftNode, RaftSnapshot, RaftStatus, RaftStorage, TuringDBDocumentOps, TuringDBFieldOps,
+    TuringDBJsonOps, TuringDBOps, TuringDbError, TuringEngine, TuringResult,
+};
+use camino::Utf8Path;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use tai64::TAI64N;
+
+/// A mutation replicated through Raft. Every node applies the same commands in the same order
+/// once they are committed to a majority of the cluster.
+/// JSON values are carried as text and data types as their tag byte.
+/// A field given a time-to-live carries the time it `expires`, so every node expires it at the same time
+/// however late the command is applied
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RaftCommand {
+    DbCreate {
+        db: String,
+        encrypted: bool,
+    },
+    DbDrop {
+        db: String,
+    },
+    DocumentCreate {
+        db: String,
+        document: String,
+        encrypted: bool,
+    },
+    DocumentDrop {
+        db: String,
+        document: String,
+    },
+    FieldSet {
+        db: String,
+        document: String,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        data_type: u8,
+        expires: Option<TAI64N>,
+    },
+    FieldModify {
+        db: String,
+        document: String,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        data_type: u8,
+        expires: Option<TAI64N>,
+    },
+    FieldRemove {
+        db: String,
+        document: String,
+        key: Vec<u8>,
+    },
+    JsonSet {
+        db: String,
+        document: String,
+        key: Vec<u8>,
+        value: String,
+    },
+    JsonModify {
+        db: String,
+        document: String,
+        key: Vec<u8>,
+        path: String,
+        value: String,
+    },
+    JsonRemove {
+        db: String,
+        document: String,
+        key: Vec<u8>,
+        path: String,
+    },
+    DataKeyRotate {
+        db: String,
+    },
+    Batch {
+        db: String,
+        operations: Vec<BatchOperation>,
+        atomic: bool,
+    },
+    BulkInsert {
+        db: String,
+        records: Vec<BulkRecord>,
+        options: BulkOptions,
+    },
+    IndexCreate {
+        db: String,
+        document: String,
+        index: IndexDefinition,
+    },
+    IndexDrop {
+        db: String,
+        document: String,
+        index: String,
+    },
+}
+
+/// A command together with the actor recorded in the ops.log of every node
+#[derive(Debug, Serialize, Deserialize)]
+struct RaftProposal {
+    actor: String,
+    command: RaftCommand,
+}
+
+impl RaftCommand {
+    async fn apply(self, engine: &mut TuringEngine, actor: &str) -> TuringResult<OpsOutcome> {
+        match self {
+            RaftCommand::DbCreate { db, encrypted } => {
+                let ops = TuringDBOps::default()
+                    .set_db_name(&db)
+                    .set_encrypted(encrypted)
+                    .set_actor(actor);
+
+                engine.db_create(ops).await
+            }
+            RaftCommand::DbDrop { db } => {
+                let ops = TuringDBOps::default().set_db_name(&db).set_actor(actor);
+
+                engine.db_drop(ops).await
+            }
+            RaftCommand::DocumentCreate {
+                db,
+                document,
+                encrypted,
+            } => {
+                let ops = TuringDBDocumentOps::default()
+                    .set_db_name(&db)
+                    .set_document_name(&document)
+                    .set_encrypted(encrypted)
+                    .set_actor(actor);
+
+                engine.document_create(&ops).await
+            }
+            RaftCommand::DocumentDrop { db, document } => {
+                let ops = TuringDBDocumentOps::default()
+                    .set_db_name(&db)
+                    .set_document_name(&document)
+                    .set_actor(actor);
+
+                engine.document_drop(&ops).await
+            }
+            RaftCommand::FieldSet {
+                db,
+                document,
+                key,
+                value,
+                data_type,
+                expires,
+            } => {
+                let ops = RaftCommand::field(&db, &document, &key, &value, data_type, expires)?
+                    .actor(actor);
+
+                engine.field_set(&ops).await
+            }
+            RaftCommand::FieldModify {
+                db,
+                document,
+                key,
+                value,
+                data_type,
+                expires,
+            } => {
+                let ops = RaftCommand::field(&db, &document, &key, &value, data_type, expires)?
+                    .actor(actor);
+
+                engine.field_modify(&ops).await
+            }
+            RaftCommand::FieldRemove { db, document, key } => {
+                let ops = TuringDBFieldOps::default()
+                    .db(&db)
+                    .document(&document)
+                    .key(&key)
+                    .actor(actor);
+
+                engine.field_remove(&ops).await
+            }
+            RaftCommand::JsonSet {
+                db,
+                document,
+                key,
+                value,
+            } => {
+                let ops = TuringDBJsonOps::default()
+                    .db(&db)
+                    .document(&document)
+                    .key(&key)
+                    .value(RaftCommand::json(&value)?)
+                    .actor(actor);
+
+                engine.json_set(&ops).await
+            }
+            RaftCommand::JsonModify {
+                db,
+                document,
+                key,
+                path,
+                value,
+            } => {
+                let ops = TuringDBJsonOps::default()
+                    .db(&db)
+                    .document(&document)
+                    .key(&key)
+                    .path(&path)
+                    .value(RaftCommand::json(&value)?)
+                    .actor(actor);
+
+                engine.json_modify(&ops).await
+            }
+            RaftCommand::JsonRemove {
+                db,
+                document,
+                key,
+                path,
+            } => {
+                let ops = TuringDBJsonOps::default()
+                    .db(&db)
+                    .document(&document)
+                    .key(&key)
+                    .path(&path)
+                    .actor(actor);
+
+                engine.json_remove(&ops).await
+            }
+            RaftCommand::DataKeyRotate { db } => {
+                let ops = TuringDBOps::default().set_db_name(&db).set_actor(actor);
+
+                engine.rotate_data_key(&ops).await
+            }
+            RaftCommand::Batch {
+                db,
+                operations,
+                atomic,
+            } => engine.batch(Utf8Path::new(&db), &operations, atomic).await,
+            RaftCommand::BulkInsert {
+                db,
+                records,
+                options,
+            } => {
+                engine
+                    .bulk_insert(Utf8Path::new(&db), &records, &options)
+                    .await
+            }
+            RaftCommand::IndexCreate {
+                db,
+                document,
+                index,
+            } => {
+                let ops = TuringDBDocumentOps::default()
+                    .set_db_name(&db)
+                    .set_document_name(&document)
+                    .set_actor(actor);
+
+                engine.index_create(&ops, index).await
+            }
+            RaftCommand::IndexDrop {
+                db,
+                document,
+                index,
+            } => {
+                let ops = TuringDBDocumentOps::default()
+                    .set_db_name(&db)
+                    .set_document_name(&document)
+                    .set_actor(actor);
+
+                engine.index_drop(&ops, &index).await
+            }
+        }
+    }
+
+    /// The operation writing a field, whose time-to-live is what is left until it `expires`
+    fn field(
+        db: &str,
+        document: &str,
+        key: &[u8],
+        value: &[u8],
+        data_type: u8,
+        expires: Option<TAI64N>,
+    ) -> TuringResult<TuringDBFieldOps> {
+        let data_type = match DataType::from_byte(data_type) {
+            None => return Err(TuringDbError::InvalidInput),
+            Some(data_type) => data_type,
+        };
+
+        let ops = TuringDBFieldOps::default()
+            .db(db)
+            .document(document)
+            .key(key)
+            .value(value)
+            .data_type(data_type);
+
+        match expires {
+            None => Ok(ops),
+            Some(expires) => {
+                // A field that already expired is applied with no time left
+                let ttl = expires.duration_since(&TAI64N::now()).unwrap_or_default();
+
+                Ok(ops.ttl(ttl))
+            }
+        }
+    }
+
+    fn json(value: &str) -> TuringResult<serde_json::Value> {
+        match serde_json::from_str(value) {
+            Ok(value) => Ok(value),
+            Err(error) => Err(TuringDbError::JsonInvalid(error.to_string())),
+        }
+    }
+}
+
+/// #### A `TuringEngine` replicated with Raft
+/// A mutation proposed to the leader is only applied to the engine of any node once it is
+/// committed to a majority of the cluster, so no acknowledged write is lost when the leader fails.
+/// The engine is replaced with a snapshot of the leader when a node falls too far behind.
+///
+/// Reads go straight to `engine()` while every mutation has to go through `propose()`.
+/// Only the mutations proposed here are committed to a majority: the messages between the nodes
+/// are carried by the application, and `turingdb-server` writes to its engine directly
+/// and copies the changes to its followers asynchronously.
+/// The nodes have to use the same master key for encrypted databases
+/// ```
+/// #[derive(Debug)]
+/// pub struct RaftEngine {
+///     node: RaftNode,
+///     storage: RaftStorage,
+///     engine: TuringEngine,
+///     config: RaftConfig,
+///     proposals: BTreeMap<u64, u64>,
+///     outcomes: BTreeMap<u64, TuringResult<OpsOutcome>>,
+/// }
+/// ```
+/// #### Usage
+/// ```
+/// let mut engine = TuringEngine::builder().repo_dir(repo_dir).build().await?;
+/// engine.repo_create().await?;
+/// engine.repo_init().await?;
+///
+/// let mut node = RaftEngine::open(1, engine, RaftConfig::new(), members).await?;
+/// // On every tick of the clock and for every message received from another node
+/// node.tick();
+/// node.step(message);
+/// for message in node.process().await? {
+///     // send `message` to `message.to`
+/// }
+///
+/// let index = node.propose(DEFAULT_ACTOR, RaftCommand::DbCreate { db, encrypted: false })?;
+/// // once `process()` applied the entry
+/// node.outcome(index);
+/// ```
+#[derive(Debug)]
+pub struct RaftEngine {
+    node: RaftNode,
+    storage: RaftStorage,
+    engine: TuringEngine,
+    config: RaftConfig,
+    proposals: BTreeMap<u64, u64>,
+    outcomes: BTreeMap<u64, TuringResult<OpsOutcome>>,
+}
+
+impl RaftEngine {
+    /// Start node `id` on an initialized engine, resuming from the raft.log in its repo.
+    /// `members` is only used the first time a node of a new cluster starts,
+    /// a node joining an existing cluster starts without members until the leader adds it
+    pub async fn open(
+        id: NodeId,
+        engine: TuringEngine,
+        config: RaftConfig,
+        members: BTreeSet<NodeId>,
+    ) -> TuringResult<Self> {
+        let mut storage = RaftStorage::new(engine.get_repo_dir().await);
+
+        let node = match storage.load().await? {
+            Some(state) => {
+                // A crash after a snapshot of the leader was stored but before it was restored
+                let restore = match &state.snapshot {
+                    Some(snapshot) if snapshot.index > state.applied => Some(snapshot.clone()),
+                    _ => None,
+                };
+
+                if let Some(snapshot) = restore {
+                    RaftEngine::restore(&engine, &snapshot).await?;
+                    storage.applied(snapshot.index).await?;
+                }
+
+                RaftNode::restore(id, config, state)
+            }
+            None => {
+                let node = RaftNode::new(id, config, members.clone());
+                let snapshot = RaftSnapshot {
+                    index: 0,
+                    term: 0,
+                    members,
+                    data: Vec::new(),
+                };
+                storage
+                    .compact(&snapshot, node.hard_state(), &[], 0)
+                    .await?;
+
+                node
+            }
+        };
+
+        Ok(Self {
+            node,
+            storage,
+            engine,
+            config,
+            proposals: BTreeMap::new(),
+            outcomes: BTreeMap::new(),
+        })
+    }
+    /// The engine of the node, only used to read
+    pub fn engine(&self) -> &TuringEngine {
+        &self.engine
+    }
+
+    pub fn status(&self) -> RaftStatus {
+        self.node.status()
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.node.is_leader()
+    }
+    /// Advance the clock of the node by one tick
+    pub fn tick(&mut self) {
+        self.node.tick()
+    }
+    /// Handle a message from another node
+    pub fn step(&mut self, message: RaftMessage) {
+        self.node.step(message)
+    }
+    /// Propose a mutation to the leader, returning the index of its entry.
+    /// Fails with `RaftNotLeader` naming the leader if the node knows it
+    pub fn propose(&mut self, actor: &str, command: RaftCommand) -> TuringResult<u64> {
+        let proposal = RaftProposal {
+            actor: actor.to_owned(),
+            command,
+        };
+
+        let proposal = match bincode::serialize(&proposal) {
+            Ok(proposal) => proposal,
+            Err(_) => {
+                return Err(TuringDbError::Bug(
+                    "Unable to serialize a Raft command".into(),
+                ))
+            }
+        };
+
+        let index = self.node.propose(proposal)?;
+        self.proposals.insert(index, self.node.term());
+
+        Ok(index)
+    }
+    /// Add a node to the cluster or remove one from it, returning the index of the change
+    pub fn propose_membership(&mut self, change: MembershipChange) -> TuringResult<u64> {
+        let index = self.node.propose_membership(change)?;
+        self.proposals.insert(index, self.node.term());
+
+        Ok(index)
+    }
+    /// The outcome of a proposal of this node once its entry has been applied.
+    /// A proposal whose outcome the node cannot know, because a new leader overwrote its entry
+    /// or the node received it within a snapshot, fails with `RaftProposalDropped`
+    pub fn outcome(&mut self, index: u64) -> Option<TuringResult<OpsOutcome>> {
+        self.outcomes.remove(&index)
+    }
+    /// Store the state of the node, apply the committed entries to the engine and
+    /// return the messages to send to the other nodes
+    pub async fn process(&mut self) -> TuringResult<Vec<RaftMessage>> {
+        let ready = self.node.ready();
+
+        if let Some(snapshot) = ready.snapshot {
+            // Nothing is marked applied until the engine is restored, so a node that stops
+            // in between restores the snapshot when it starts again
+            self.storage
+                .compact(
+                    &snapshot,
+                    self.node.hard_state(),
+                    self.node.log_entries(),
+                    0,
+                )
+                .await?;
+            RaftEngine::restore(&self.engine, &snapshot).await?;
+            self.storage.applied(snapshot.index).await?;
+
+            self.drop_proposals(snapshot.index);
+        }
+
+        self.storage.append(ready.hard_state, ready.entries).await?;
+
+        if let Some(last) = ready.committed.last() {
+            let applied = last.index;
+
+            for entry in ready.committed {
+                self.apply(entry).await;
+            }
+
+            self.storage.applied(applied).await?;
+        }
+
+        let applied = self.node.applied();
+        if applied - self.node.snapshot_index() >= self.config.get_snapshot_entries() {
+            self.snapshot(applied).await?;
+        }
+
+        Ok(ready.messages)
+    }
+
+    async fn apply(&mut self, entry: RaftEntry) {
+        let outcome = match entry.kind {
+            RaftEntryKind::Noop => return,
+            RaftEntryKind::Membership(_) => Ok(OpsOutcome::RaftMembershipChanged),
+            RaftEntryKind::Command(proposal) => {
+                match bincode::deserialize::<RaftProposal>(&proposal) {
+                    Ok(proposal) => {
+                        proposal
+                            .command
+                            .apply(&mut self.engine, &proposal.actor)
+                            .await
+                    }
+                    Err(_) => Err(TuringDbError::InvalidData),
+                }
+            }
+        };
+
+        match self.proposals.remove(&entry.index) {
+            Some(term) if term == entry.term => {
+                self.outcomes.insert(entry.index, outcome);
+            }
+            Some(_) => {
+                self.outcomes
+                    .insert(entry.index, Err(TuringDbError::RaftProposalDropped));
+            }
+            None => (),
+        }
+
+        self.drop_proposals(entry.index);
+    }
+    /// The proposals before `index` that were never applied were overwritten by a new leader
+    fn drop_proposals(&mut self, index: u64) {
+        let remaining = self.proposals.split_off(&(index + 1));
+
+        for (dropped, _) in std::mem::replace(&mut self.proposals, remaining) {
+            self.outcomes
+                .insert(dropped, Err(TuringDbError::RaftProposalDropped));
+        }
+    }
+    /// Replace the applied entries with a copy of every database
+    async fn snapshot(&mut self, applied: u64) -> TuringResult<()> {
+        let snapshots = self.engine.repo_snapshot().await?;
+
+        let data = match bincode::serialize::<Vec<DbSnapshot>>(&snapshots) {
+            Ok(data) => data,
+            Err(_) => {
+                return Err(TuringDbError::Bug(
+                    "Unable to serialize a Raft snapshot".into(),
+                ))
+            }
+        };
+
+        let snapshot = self.node.compact(applied, data)?;
+        self.storage
+            .compact(
+                &snapshot,
+                self.node.hard_state(),
+                self.node.log_entries(),
+                applied,
+            )
+            .await
+    }
+
+    async fn restore(engine: &TuringEngine, snapshot: &RaftSnapshot) -> TuringResult<OpsOutcome> {
+        let snapshots = match bincode::deserialize::<Vec<DbSnapshot>>(&snapshot.data) {
+            Ok(snapshots) => snapshots,
+            Err(_) => {
+                return Err(TuringDbError::RaftLogCorrupted {
+                    file: crate::RAFT_SNAPSHOT_FILE.to_owned(),
+                    offset: 0,
+                })
+            }
+        };
+
+        engine.repo_restore(&snapshots).await
+    }
+}
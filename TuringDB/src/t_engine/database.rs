@@ -1,38 +1,129 @@
-use crate::{Document, OpsOutcome, TuringDbError, TuringResult};
+use crate::{
+    expires_after, CdcChange, CdcLog, Document, Expiry, FieldData, FieldKey, FieldSealer,
+    FieldWrite, IntegrityManifest, OpsOutcome, TuringDbError, TuringResult, DB_ENCRYPTED_MARKER,
+    DOCUMENT_ENCRYPTED_MARKER,
+};
 use async_fs::DirBuilder;
-use async_lock::Mutex;
+use async_lock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 use camino::{Utf8Path, Utf8PathBuf};
+use dashmap::{DashMap, DashSet};
 use sled::IVec;
-use std::collections::hash_map::HashMap;
+use std::time::Duration;
+use tai64::TAI64N;
 
 /// #### Contains the list of documents and databases in-memory
+/// `encrypted` marks a database whose documents all have their field values sealed
+/// by the `sealer` while `encrypted_documents` holds the individual documents that are sealed.
+/// `integrity` keeps the Merkle manifest of every document up to date
+/// and `cdc` captures every change to the documents in order.
+/// `history` keeps the previous versions of the fields of every document
+/// and `writes` is shared by the writes of single fields and held alone by the writes spanning many.
+/// The documents are cloned out of `list` so no guard of it is held across an `.await`
 /// ```
 /// #[derive(Debug, Clone)]
 /// struct TuringDB {
-///     list: HashMap<Utf8Utf8PathBuf, Document>,
+///     list: DashMap<Utf8PathBuf, Document>,
+///     encrypted: bool,
+///     encrypted_documents: DashSet<Utf8PathBuf>,
+///     sealer: FieldSealer,
+///     integrity: Option<IntegrityManifest>,
+///     cdc: Option<CdcLog>,
+///     history: bool,
+///     writes: RwLock<()>,
 /// }
 ///```
 #[derive(Debug)]
 pub(crate) struct TuringDB {
-    pub(crate) list: HashMap<Utf8PathBuf, Document>,
+    pub(crate) list: DashMap<Utf8PathBuf, Document>,
+    pub(crate) encrypted: bool,
+    pub(crate) encrypted_documents: DashSet<Utf8PathBuf>,
+    pub(crate) sealer: FieldSealer,
+    pub(crate) integrity: Option<IntegrityManifest>,
+    pub(crate) cdc: Option<CdcLog>,
+    pub(crate) history: bool,
+    pub(crate) writes: RwLock<()>,
 }
 
 impl TuringDB {
     /// Create a new in-memory database
     pub(crate) fn new() -> Self {
         Self {
-            list: { HashMap::default() },
+            list: DashMap::default(),
+            encrypted: false,
+            encrypted_documents: DashSet::default(),
+            sealer: FieldSealer::new(&Utf8PathBuf::default()),
+            integrity: None,
+            cdc: None,
+            history: false,
+            writes: RwLock::default(),
         }
     }
+    /// Set the name of the database
+    pub(crate) fn with_name(mut self, name: &Utf8Path) -> Self {
+        self.sealer = FieldSealer::new(name);
+
+        self
+    }
+    /// Keep the integrity manifest of every document up to date
+    pub(crate) fn with_integrity(mut self, integrity: IntegrityManifest) -> Self {
+        self.integrity = Some(integrity);
+
+        self
+    }
+    /// Capture every change to the documents of the database in a CDC log
+    pub(crate) fn with_cdc(mut self, cdc: CdcLog) -> Self {
+        self.cdc = Some(cdc);
+
+        self
+    }
+    /// Keep the previous versions of the fields of every document
+    pub(crate) fn with_history(mut self, history: bool) -> Self {
+        self.history = history;
+
+        self
+    }
+    /// Mark all the documents in the database as encrypted
+    pub(crate) fn with_encryption(mut self, encrypted: bool) -> Self {
+        self.encrypted = encrypted;
+
+        self
+    }
+    /// Check whether the field values of a document are sealed
+    pub(crate) fn is_encrypted(&self, document_name: &Utf8Path) -> bool {
+        self.encrypted || self.encrypted_documents.contains(document_name)
+    }
+    /// Get a document, cloned so the list is not held while it is used
+    pub(crate) fn document(&self, document_name: &Utf8Path) -> Option<Document> {
+        self.list
+            .get(document_name)
+            .map(|document| document.value().clone())
+    }
+    /// Get every document sorted by name, cloned so the list is not held while they are used
+    pub(crate) fn documents(&self) -> Vec<(Utf8PathBuf, Document)> {
+        let mut documents = self
+            .list
+            .iter()
+            .map(|document| (document.key().clone(), document.value().clone()))
+            .collect::<Vec<(Utf8PathBuf, Document)>>();
+
+        documents.sort_by(|(first, _), (second, _)| first.cmp(second));
+
+        documents
+    }
 
     /// Create a database
     pub(crate) async fn db_create(
-        mut self,
+        self,
         repo_dir: &Utf8Path,
         db_name: &Utf8Path,
+        encrypted: bool,
     ) -> Result<OpsOutcome, TuringDbError> {
         let path = Self::build_path(repo_dir, db_name);
-        DirBuilder::new().recursive(false).create(path).await?;
+        DirBuilder::new().recursive(false).create(&path).await?;
+
+        if encrypted {
+            async_fs::write(path.join(DB_ENCRYPTED_MARKER), &[]).await?;
+        }
 
         let new_document = sled::Config::default()
             .path("temp")
@@ -60,7 +151,7 @@ impl TuringDB {
         let mut list: Vec<Utf8PathBuf> = Vec::new();
 
         db.list.iter().for_each(|document_name| {
-            list.push(document_name.0.into());
+            list.push(document_name.key().into());
         });
 
         if list.is_empty() {
@@ -75,7 +166,7 @@ impl TuringDB {
         let mut list: Vec<Utf8PathBuf> = Vec::new();
 
         db.list.iter().for_each(|document_name| {
-            list.push(document_name.0.into());
+            list.push(document_name.key().into());
         });
 
         list.sort();
@@ -88,14 +179,22 @@ impl TuringDB {
     }
     /// Create a new document
     pub(crate) async fn document_create(
-        &mut self,
+        &self,
         repo_dir: &Utf8Path,
         db_name: &Utf8Path,
         document_name: &Utf8Path,
+        encrypted: bool,
     ) -> TuringResult<OpsOutcome> {
-        match self.list.get(document_name) {
+        // Held alone so two creations of the same document do not both open it
+        let _writing = self.write_lock_all().await;
+
+        match self.document(document_name) {
             Some(_) => Err(TuringDbError::AlreadyExists),
             None => {
+                if encrypted && !self.sealer.has_keys()? {
+                    return Err(TuringDbError::EncryptionKeyMissing);
+                }
+
                 let path = TuringDB::build_document_path(repo_dir, db_name, document_name);
 
                 let document = sled::Config::default()
@@ -103,7 +202,18 @@ impl TuringDB {
                     .path(&path)
                     .open()?;
 
+                if encrypted {
+                    async_fs::write(TuringDB::document_marker_path(&path), &[]).await?;
+                    self.encrypted_documents.insert(document_name.to_path_buf());
+                }
+
+                if let Some(integrity) = &self.integrity {
+                    integrity.rebuild(document_name, &document)?;
+                }
+
                 self.list.insert(document_name.to_path_buf(), document);
+                self.cdc_record_document(document_name, CdcChange::DocumentCreate)
+                    .await?;
 
                 Ok(OpsOutcome::DocumentCreated)
             }
@@ -111,41 +221,232 @@ impl TuringDB {
     }
     /// Drop a document
     pub(crate) async fn document_drop(
-        &mut self,
+        &self,
         repo_dir: &Utf8Path,
         db_name: &Utf8Path,
         document_name: &Utf8Path,
     ) -> TuringResult<OpsOutcome> {
+        // Held alone so no write to the document is applied while it is dropped
+        let _writing = self.write_lock_all().await;
+
         let path = TuringDB::build_document_path(repo_dir, db_name, document_name);
 
-        async_fs::remove_dir_all(path).await?;
+        async_fs::remove_dir_all(&path).await?;
+
+        if self.encrypted_documents.remove(document_name).is_some() {
+            async_fs::remove_file(TuringDB::document_marker_path(&path)).await?;
+        }
 
         self.list.remove(document_name);
+        self.cdc_record_document(document_name, CdcChange::DocumentDrop)
+            .await?;
 
         Ok(OpsOutcome::DocumentDropped)
     }
-    /// Field Insert
+    /// Hold off the writes spanning many fields while a single field is written,
+    /// the writes of single fields go ahead together
+    pub(crate) async fn write_lock(&self) -> RwLockReadGuard<'_, ()> {
+        self.writes.read().await
+    }
+    /// Hold the whole database so no other write is applied until the guard is dropped,
+    /// whether the CDC log is kept or not
+    pub(crate) async fn write_lock_all(&self) -> RwLockWriteGuard<'_, ()> {
+        self.writes.write().await
+    }
+    /// Field Insert, removing the field once `ttl` has passed if it is given
     pub(crate) async fn field_set(
-        &mut self,
-        repo_dir: &Utf8Path,
-        db_name: &Utf8Path,
+        &self,
+        document_name: &Utf8Path,
+        key: IVec,
+        value: &[u8],
+        ttl: Option<Duration>,
+    ) -> TuringResult<OpsOutcome> {
+        let expires = ttl.map(expires_after);
+
+        self.field_insert(document_name, key, &FieldData::new(value), expires)
+            .await
+    }
+    /// Insert a field keeping the timestamps of its `FieldData`, to expire at the time `expires` if it is given
+    pub(crate) async fn field_insert(
+        &self,
         document_name: &Utf8Path,
         key: IVec,
-        value: IVec,
+        field_data: &FieldData,
+        expires: Option<TAI64N>,
+    ) -> TuringResult<OpsOutcome> {
+        match self.document(document_name) {
+            None => Err(TuringDbError::DocumentNotFound),
+            Some(sled_db) => {
+                self.expire_if_due(document_name, &key).await?;
+
+                let field_data = self.seal(document_name, &key, field_data)?;
+                let _writing = self.write_lock().await;
+                let mut capture = self.cdc_capture().await?;
+
+                // The expiry of an existing field must not be replaced
+                if sled_db.contains_key(&key)? {
+                    return Err(TuringDbError::KeyAlreadyExists);
+                }
+
+                let expiry = match expires {
+                    None => Expiry::Clear,
+                    Some(expires) => Expiry::At(expires),
+                };
+                let write = FieldWrite::new(&key, Some(&field_data))
+                    .expect(None)
+                    .expiry(expiry);
+
+                match self.field_swap(document_name, &sled_db, write, capture.as_mut())? {
+                    true => {
+                        self.cdc_write(capture).await;
+
+                        Ok(OpsOutcome::FieldInserted)
+                    }
+                    false => Err(TuringDbError::KeyAlreadyExists),
+                }
+            }
+        }
+    }
+    /// Replace the value of a field, keeping the time it was created.
+    /// A `ttl` replaces the time the field expires, otherwise the field keeps it
+    pub(crate) async fn field_modify(
+        &self,
+        document_name: &Utf8Path,
+        key: &[u8],
+        value: &[u8],
+        ttl: Option<Duration>,
     ) -> TuringResult<OpsOutcome> {
-        match self.list.get(&document_name.to_path_buf()) {
+        let sled_db = match self.document(document_name) {
+            None => return Err(TuringDbError::DocumentNotFound),
+            Some(sled_db) => sled_db,
+        };
+
+        self.expire_if_due(document_name, key).await?;
+        let _writing = self.write_lock().await;
+        let mut capture = self.cdc_capture().await?;
+
+        loop {
+            let current = match sled_db.get(key)? {
+                None => return Err(TuringDbError::FieldNotFound),
+                Some(current) => current,
+            };
+
+            let mut field_data = self.unseal(document_name, key, &current)?;
+            field_data.update(value);
+            let sealed = self.seal(document_name, key, &field_data)?;
+
+            let expiry = match ttl {
+                None => Expiry::Keep,
+                Some(ttl) => Expiry::At(expires_after(ttl)),
+            };
+            let write = FieldWrite::new(key, Some(&sealed))
+                .expect(Some(&current))
+                .expiry(expiry)
+                .replaced(field_data.modified());
+
+            if self.field_swap(document_name, &sled_db, write, capture.as_mut())? {
+                self.cdc_write(capture).await;
+
+                return Ok(OpsOutcome::FieldModified);
+            }
+        }
+    }
+    /// Remove a field
+    pub(crate) async fn field_remove(
+        &self,
+        document_name: &Utf8Path,
+        key: &[u8],
+    ) -> TuringResult<OpsOutcome> {
+        match self.document(document_name) {
             None => Err(TuringDbError::DocumentNotFound),
             Some(sled_db) => {
-                if !sled_db.contains_key(&key)? {
-                    sled_db.insert(key, value)?;
+                self.expire_if_due(document_name, key).await?;
+                let _writing = self.write_lock().await;
+                let mut capture = self.cdc_capture().await?;
 
-                    Ok(OpsOutcome::FieldInserted)
+                loop {
+                    let old = match sled_db.get(key)? {
+                        None => return Err(TuringDbError::FieldNotFound),
+                        Some(old) => old,
+                    };
+
+                    let write = FieldWrite::new(key, None).expect(Some(&old));
+
+                    if self.field_swap(document_name, &sled_db, write, capture.as_mut())? {
+                        self.cdc_write(capture).await;
+
+                        return Ok(OpsOutcome::FieldRemoved);
+                    }
+                }
+            }
+        }
+    }
+    /// Get the `FieldData` stored under a key, hiding the field once it expired
+    pub(crate) async fn field_get(
+        &self,
+        document_name: &Utf8Path,
+        key: &[u8],
+    ) -> TuringResult<FieldData> {
+        match self.document(document_name) {
+            None => Err(TuringDbError::DocumentNotFound),
+            Some(sled_db) => match sled_db.get(key)? {
+                None => Err(TuringDbError::FieldNotFound),
+                Some(_) if self.is_expired(&sled_db, key)? => Err(TuringDbError::FieldNotFound),
+                Some(value) => self.unseal(document_name, key, &value),
+            },
+        }
+    }
+    /// List the keys of the fields of a document, leaving out the fields that expired
+    pub(crate) fn field_list(&self, document_name: &Utf8Path) -> TuringResult<OpsOutcome> {
+        match self.document(document_name) {
+            None => Err(TuringDbError::DocumentNotFound),
+            Some(sled_db) => {
+                let mut list: Vec<FieldKey> = Vec::new();
+
+                for field in sled_db.iter() {
+                    let (key, _) = field?;
+
+                    if !self.is_expired(&sled_db, &key)? {
+                        list.push(key.to_vec());
+                    }
+                }
+
+                if list.is_empty() {
+                    Ok(OpsOutcome::DocumentEmpty)
                 } else {
-                    Err(TuringDbError::KeyAlreadyExists)
+                    Ok(OpsOutcome::FieldList(list))
                 }
             }
         }
     }
+    /// Serialize a `FieldData` into the bytes stored in sled, encrypting its data
+    /// first if the document is encrypted
+    pub(crate) fn seal(
+        &self,
+        document_name: &Utf8Path,
+        key: &[u8],
+        field_data: &FieldData,
+    ) -> TuringResult<Vec<u8>> {
+        if self.is_encrypted(document_name) {
+            self.sealer.seal(document_name, key, field_data)
+        } else {
+            field_data.to_bytes()
+        }
+    }
+    /// Deserialize the bytes stored in sled into a `FieldData`, decrypting its data
+    /// if the document is encrypted
+    pub(crate) fn unseal(
+        &self,
+        document_name: &Utf8Path,
+        key: &[u8],
+        value: &[u8],
+    ) -> TuringResult<FieldData> {
+        if self.is_encrypted(document_name) {
+            self.sealer.unseal(document_name, key, value)
+        } else {
+            FieldData::from_bytes(value)
+        }
+    }
 
     fn build_path(repo_dir: &Utf8Path, db_name: &Utf8Path) -> Utf8PathBuf {
         let mut path: Utf8PathBuf = repo_dir.into();
@@ -165,4 +466,8 @@ impl TuringDB {
 
         path
     }
+
+    pub(crate) fn document_marker_path(document_path: &Utf8Path) -> Utf8PathBuf {
+        Utf8PathBuf::from(format!("{}.{}", document_path, DOCUMENT_ENCRYPTED_MARKER))
+    }
 }
@@ -1,63 +1,310 @@
 use crate::{
-    Document, OpsOutcome, RepoPath, TuringDB, TuringDBDocumentOps, TuringDBFieldOps, TuringDBOps,
-    TuringDbError, TuringResult,
+    AuditLog, BackupContents, BackupManifest, BatchOperation, BulkOptions, BulkProgress,
+    BulkRecord, CdcChange, CdcLog, CdcRecord, CdcRetention, ChangeFeed, Cipher, CipherKind,
+    DbChanges, DbSnapshot, ExportFormat, ExportRecord, FieldData, HistoryRetention,
+    IndexDefinition, IndexLookup, IntegrityKey, IntegrityManifest, IntegrityViolation, JsonPath,
+    KeyDerivation, LoggedOperation, OpsLog, OpsOutcome, ReplicaLag, ReplicaState, ReplicationBatch,
+    ReplicationStatus, RepoManifest, RepoPath, TDBCell, TuringDB, TuringDBDocumentOps,
+    TuringDBFieldOps, TuringDBJsonOps, TuringDBOps, TuringDbError, TuringResult, DATA_KEYS_FILE,
+    DB_ENCRYPTED_MARKER, DEFAULT_ACTOR, DOCUMENT_ENCRYPTED_MARKER, INTEGRITY_KEY_FILE,
+    KEY_DERIVATION_FILE, MASTER_KEY_CHANGE_FILE, REPLICATION_ACTOR, REPO_MANIFEST_FILE,
 };
-use anyhow::Result;
-use async_fs::{self, DirBuilder, ReadDir};
-use async_lock::Mutex;
+use async_fs::{self, DirBuilder};
+use async_io::Timer;
+use async_lock::RwLock;
 use camino::{Utf8Path, Utf8PathBuf};
 use dashmap::DashMap;
-use futures_lite::stream::StreamExt;
-use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, ffi::OsString, io::ErrorKind};
+use futures_lite::{
+    io::{AsyncRead, AsyncWrite},
+    stream::{Stream, StreamExt},
+};
+use secrecy::Secret;
+use sled::IVec;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ffi::OsString,
+    io::ErrorKind,
+    sync::Arc,
+    time::Duration,
+};
 use tai64::TAI64N;
+use zeroize::Zeroizing;
 
 // TODO use custom_codes errors to give actual errors
 // TODO Check whether you can respond with sled::Error
 // TODO move repo files to home user
 
+/// Configures and builds a `TuringEngine`
+/// #### Usage
+/// ```
+/// let engine = TuringEngine::builder()
+///     .passphrase(passphrase)
+///     .build()
+///     .await?;
+/// ```
+#[derive(Default)]
+pub struct TuringEngineBuilder {
+    cipher: Option<Cipher>,
+    passphrase: Option<Secret<String>>,
+    ops_log_rotation: Option<(u64, usize)>,
+    cdc_retention: CdcRetention,
+    repo_dir: Option<Utf8PathBuf>,
+    replica: bool,
+    history: bool,
+    history_retention: HistoryRetention,
+    rebuild_manifests: bool,
+}
+
+impl TuringEngineBuilder {
+    /// The master key `Cipher` that seals the data keys of encrypted databases.
+    /// Without a master key encrypted databases and documents can neither be created nor read
+    pub fn cipher(mut self, cipher: Cipher) -> Self {
+        self.cipher = Some(cipher);
+
+        self
+    }
+    /// Derive the master key from a passphrase using Argon2id.
+    /// The salt and parameters are stored in the repo so the same key is derived on every start
+    pub fn passphrase(mut self, passphrase: Secret<String>) -> Self {
+        self.passphrase = Some(passphrase);
+
+        self
+    }
+    /// Rotate the ops.log once it grows past `max_size` bytes, keeping `max_files` rotated logs
+    pub fn ops_log_rotation(mut self, max_size: u64, max_files: usize) -> Self {
+        self.ops_log_rotation = Some((max_size, max_files));
+
+        self
+    }
+    /// Set how much of the CDC log of every database is kept
+    pub fn cdc_retention(mut self, cdc_retention: CdcRetention) -> Self {
+        self.cdc_retention = cdc_retention;
+
+        self
+    }
+    /// Keep the repo in `repo_dir` instead of the home directory of the user,
+    /// so more than one server can run on the same host
+    pub fn repo_dir(mut self, repo_dir: Utf8PathBuf) -> Self {
+        self.repo_dir = Some(repo_dir);
+
+        self
+    }
+    /// Run the engine as a read-only replica that only applies the changes of its leader.
+    /// The replica has to use the same master key as the leader to read encrypted databases
+    pub fn replica(mut self, replica: bool) -> Self {
+        self.replica = replica;
+
+        self
+    }
+    /// Keep the previous versions of every field when it is overwritten or removed,
+    /// so fields can be read with `field_get_at()` and the repo rewound with `repo_rewind()`.
+    /// Versions are only kept while history is enabled and are not part of snapshots or backups
+    pub fn history(mut self, history: bool) -> Self {
+        self.history = history;
+
+        self
+    }
+    /// Set how many previous versions `history_compact()` keeps for the documents
+    /// that have no retention of their own
+    pub fn history_retention(mut self, history_retention: HistoryRetention) -> Self {
+        self.history_retention = history_retention;
+
+        self
+    }
+    /// Hash the contents of the repo again on `repo_init()` instead of verifying them
+    /// against the integrity manifests. This trusts the current contents of the repo,
+    /// so it is only meant for a repo created before it had integrity manifests
+    /// or whose `INTEGRITY_KEY` was lost, which otherwise fails to build
+    pub fn rebuild_manifests(mut self, rebuild_manifests: bool) -> Self {
+        self.rebuild_manifests = rebuild_manifests;
+
+        self
+    }
+    /// Create the in-memory repo
+    pub async fn build(self) -> TuringResult<TuringEngine> {
+        let path = match self.repo_dir {
+            None => RepoPath::access_dir().await?,
+            Some(repo_dir) => repo_dir,
+        };
+
+        TuringEngine::recover_master_key_change(&path).await?;
+
+        let (master_key, key_derivation) = match self.passphrase {
+            None => (self.cipher, None),
+            Some(passphrase) => {
+                let key_derivation = TuringEngine::load_key_derivation(&path).await?;
+                let master_key = key_derivation.derive(&passphrase)?;
+
+                (Some(master_key), Some(key_derivation))
+            }
+        };
+
+        // A new key would report every document as modified
+        let key_missing = async_fs::metadata(path.join(INTEGRITY_KEY_FILE))
+            .await
+            .is_err();
+        let has_manifest = async_fs::metadata(path.join(REPO_MANIFEST_FILE))
+            .await
+            .is_ok();
+        if key_missing && has_manifest && !self.rebuild_manifests {
+            return Err(TuringDbError::IntegrityKeyMissing);
+        }
+        let integrity_key = IntegrityKey::load(&path, master_key.as_ref()).await?;
+
+        let audit_log = AuditLog::new(&path, &integrity_key);
+        let ops_log = match self.ops_log_rotation {
+            None => OpsLog::new(&path),
+            Some((max_size, max_files)) => OpsLog::new(&path).with_rotation(max_size, max_files),
+        };
+        let replica = if self.replica {
+            Some(ReplicaState::new(&path))
+        } else {
+            None
+        };
+
+        Ok(TuringEngine {
+            dbs: DashMap::new(),
+            repo_dir: path,
+            master_key: RwLock::new(master_key),
+            key_derivation: RwLock::new(key_derivation),
+            integrity_key,
+            rebuild_manifest: self.rebuild_manifests,
+            ops_log,
+            audit_log,
+            cdc_retention: self.cdc_retention,
+            replica,
+            history: self.history,
+            history_retention: self.history_retention,
+            checkpoint: RwLock::new(()),
+        })
+    }
+}
+
 /// This engine handles data all database queries and in-memory keys and sled file locks
 /// #### Structure
 /// ```
 /// #[derive(Debug, Clone)]
 /// pub struct TuringEngine {
-///     dbs: DashMap<Utf8Path, Tdb>, // Repo<DatabaseName, Databases>
+///     dbs: DashMap<Utf8PathBuf, Arc<TuringDB>>, // Repo<DatabaseName, Databases>
+///     repo_dir: Utf8PathBuf,
+///     master_key: RwLock<Option<Cipher>>,
+///     key_derivation: RwLock<Option<KeyDerivation>>,
+///     integrity_key: IntegrityKey,
+///     rebuild_manifest: bool,
+///     ops_log: OpsLog,
+///     audit_log: AuditLog,
+///     cdc_retention: CdcRetention,
+///     replica: Option<ReplicaState>,
+///     history: bool,
+///     history_retention: HistoryRetention,
+///     checkpoint: RwLock<()>,
 /// }
 /// ```
 #[derive(Debug)]
 pub struct TuringEngine {
-    dbs: DashMap<Utf8PathBuf, TuringDB>, // Repo<DatabaseName, Databases>
+    dbs: DashMap<Utf8PathBuf, Arc<TuringDB>>, // Repo<DatabaseName, Databases>
     repo_dir: Utf8PathBuf,
+    /// Replaced by a master key change, which holds `checkpoint` exclusively
+    master_key: RwLock<Option<Cipher>>,
+    key_derivation: RwLock<Option<KeyDerivation>>,
+    integrity_key: IntegrityKey,
+    rebuild_manifest: bool,
+    ops_log: OpsLog,
+    audit_log: AuditLog,
+    cdc_retention: CdcRetention,
+    replica: Option<ReplicaState>,
+    history: bool,
+    history_retention: HistoryRetention,
+    /// Held by the mutations that share the engine and exclusively by `repo_snapshot()`
+    checkpoint: RwLock<()>,
 }
 impl TuringEngine {
-    /// Create a new in-memory repo
+    /// Create a new in-memory repo without encryption
     pub async fn new() -> TuringResult<TuringEngine> {
-        let path = RepoPath::access_dir().await?;
-
-        Ok(Self {
-            dbs: DashMap::new(),
-            repo_dir: path,
-        })
+        TuringEngine::builder().build().await
+    }
+    /// Configure the engine before creating it
+    pub fn builder() -> TuringEngineBuilder {
+        TuringEngineBuilder::default()
     }
 
     pub async fn get_repo_dir(&self) -> &Utf8PathBuf {
         &self.repo_dir
     }
+    /// The log of every mutation applied to the repo, used to tail or replay it
+    pub fn ops_log(&self) -> &OpsLog {
+        &self.ops_log
+    }
+    /// The hash-chained log of every mutation applied to the repo
+    pub fn audit_log(&self) -> &AuditLog {
+        &self.audit_log
+    }
+    /// Check that no audit entry was removed, reordered or modified
+    pub async fn audit_verify(&self) -> TuringResult<OpsOutcome> {
+        Ok(OpsOutcome::AuditVerified(self.audit_log.verify().await?))
+    }
 
     /// Create a repo
     pub async fn repo_create(&self) -> TuringResult<OpsOutcome> {
+        let outcome = self.apply_repo_create().await;
+        self.record(DEFAULT_ACTOR, LoggedOperation::RepoCreate, &outcome)
+            .await;
+
+        outcome
+    }
+
+    async fn apply_repo_create(&self) -> TuringResult<OpsOutcome> {
         DirBuilder::new()
             .recursive(false)
             .create(&self.repo_dir)
             .await?;
 
+        if let Some(key_derivation) = &*self.key_derivation.read().await {
+            self.write_key_derivation(key_derivation).await?;
+        }
+
+        self.integrity_key
+            .write(&self.repo_dir, self.master_key().await.as_ref())
+            .await?;
+        self.write_manifest().await?;
+
         Ok(OpsOutcome::RepoCreated)
     }
+    /// Drop the repository with every database in it. The ops.log and the audit log
+    /// are kept in the repository so they are dropped with it
+    pub async fn repo_drop(&self) -> TuringResult<OpsOutcome> {
+        self.writable()?;
+
+        let _checkpoint = self.checkpoint.write().await;
+
+        self.dbs.clear();
+        async_fs::remove_dir_all(&self.repo_dir).await?;
+
+        Ok(OpsOutcome::RepoDropped)
+    }
     /// Check if the repository is empty
     pub fn is_empty(&self) -> bool {
         self.dbs.is_empty()
     }
+    /// Get a database, cloned so the repo is not held across an `.await` while it is used
+    fn db(&self, db_name: &Utf8Path) -> Option<Arc<TuringDB>> {
+        self.dbs.get(db_name).map(|db| Arc::clone(db.value()))
+    }
+    /// Get every database sorted by name, cloned so the repo is not held while they are used
+    fn databases(&self) -> Vec<(Utf8PathBuf, Arc<TuringDB>)> {
+        let mut databases = self
+            .dbs
+            .iter()
+            .map(|db| (db.key().clone(), Arc::clone(db.value())))
+            .collect::<Vec<(Utf8PathBuf, Arc<TuringDB>)>>();
+
+        databases.sort_by(|(first, _), (second, _)| first.cmp(second));
+
+        databases
+    }
+    /// Load the databases and documents of the repo and verify their integrity manifests
     pub async fn repo_init(&mut self) -> TuringResult<OpsOutcome> {
+        let master_key = self.master_key().await;
         let mut repo = async_fs::read_dir(&self.repo_dir).await?;
 
         while let Some(database_entry) = repo.try_next().await? {
@@ -65,10 +312,28 @@ impl TuringEngine {
 
             if database_entry.file_type().await?.is_dir() {
                 let mut repo = async_fs::read_dir(&database_entry.path()).await?;
-                let mut current_db = TuringDB::new();
+                let database_name: Utf8PathBuf = TuringEngine::to_utf8_path(database_name_raw)?;
+                let mut current_db = TuringDB::new()
+                    .with_name(&database_name)
+                    .with_integrity(IntegrityManifest::new(&self.integrity_key, &database_name))
+                    .with_cdc(CdcLog::new(
+                        &self.repo_dir.join(&database_name),
+                        self.cdc_retention,
+                    ))
+                    .with_history(self.history);
 
                 while let Some(document_entry) = repo.try_next().await? {
-                    if document_entry.file_type().await?.is_dir() {
+                    if document_entry.file_type().await?.is_file() {
+                        let marker = TuringEngine::to_utf8_path(document_entry.file_name())?;
+
+                        if marker.as_str() == DB_ENCRYPTED_MARKER {
+                            current_db.encrypted = true;
+                        } else if marker.extension() == Some(DOCUMENT_ENCRYPTED_MARKER) {
+                            current_db
+                                .encrypted_documents
+                                .insert(marker.with_extension(""));
+                        }
+                    } else if document_entry.file_type().await?.is_dir() {
                         let document_name_raw = document_entry.file_name();
                         let document_name: Utf8PathBuf =
                             TuringEngine::to_utf8_path(document_name_raw)?;
@@ -78,38 +343,164 @@ impl TuringEngine {
                             .create_new(false)
                             .open()?;
 
-                        current_db.list.insert(document_name.into(), db);
+                        current_db.list.insert(document_name, db);
                     }
                 }
 
-                let database_name: Utf8PathBuf = TuringEngine::to_utf8_path(database_name_raw)?;
-                self.dbs
-                    .insert(Utf8PathBuf::from(database_name), current_db);
+                let db_dir = self.repo_dir.join(&database_name);
+                current_db
+                    .load_data_keys(&db_dir, master_key.as_ref())
+                    .await?;
+
+                // Resume a data key rotation that was interrupted by a shutdown
+                if let Some(master_key) = &master_key {
+                    if current_db.sealer.is_rotating()? {
+                        current_db.start_reencryption(&db_dir, master_key)?;
+                    }
+                }
+
+                // Write the changes that were committed but not written to the CDC log before a crash
+                current_db.cdc_recover().await?;
+
+                self.dbs.insert(database_name, Arc::new(current_db));
+            }
+        }
+
+        if let Some(replica) = &self.replica {
+            replica.load().await?;
+        }
+
+        // A log that does not match its head leaves the repo read-only, see `AuditLog::failure()`
+        self.audit_log.resume().await;
+
+        if self.rebuild_manifest {
+            for (_, db) in self.databases() {
+                if let Some(integrity) = &db.integrity {
+                    for (document_name, document) in db.documents() {
+                        integrity.rebuild(&document_name, &document)?;
+                    }
+                }
             }
+
+            self.write_manifest().await?;
+            self.rebuild_manifest = false;
+        } else {
+            self.repo_verify().await?;
         }
 
         Ok(OpsOutcome::RepoInitialized)
     }
+    /// Check the repo against its integrity manifests, reporting every database, document
+    /// and field that was added, removed or modified outside TuringDB
+    pub async fn repo_verify(&self) -> TuringResult<OpsOutcome> {
+        let current = RepoManifest::new(&self.integrity_key, &self.dbs);
+
+        let mut violations = match async_fs::read(self.repo_dir.join(REPO_MANIFEST_FILE)).await {
+            Ok(value) => match RepoManifest::from_bytes(&value) {
+                None => vec![IntegrityViolation::ManifestModified],
+                Some(stored) => stored.compare(&self.integrity_key, &current),
+            },
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                vec![IntegrityViolation::ManifestMissing]
+            }
+            Err(error) => return Err(error.into()),
+        };
+
+        for (_, db) in self.databases() {
+            if let Some(integrity) = &db.integrity {
+                for (document_name, document) in db.documents() {
+                    violations.extend(integrity.verify(&document_name, &document)?);
+                }
+            }
+        }
+
+        if violations.is_empty() {
+            Ok(OpsOutcome::RepoVerified)
+        } else {
+            violations.sort();
+
+            Err(TuringDbError::IntegrityViolation(violations))
+        }
+    }
+
+    pub async fn db_create(&self, ops: TuringDBOps) -> TuringResult<OpsOutcome> {
+        let outcome = self.apply_db_create(&ops).await;
+        let operation = LoggedOperation::DbCreate {
+            db: ops.get_db_name().into_string(),
+            encrypted: ops.is_encrypted(),
+        };
+        self.record(ops.get_actor(), operation, &outcome).await;
+
+        outcome
+    }
 
-    pub async fn db_create(&mut self, ops: TuringDBOps) -> TuringResult<OpsOutcome> {
+    async fn apply_db_create(&self, ops: &TuringDBOps) -> TuringResult<OpsOutcome> {
+        self.writable()?;
+        let _checkpoint = self.checkpoint.read().await;
+
+        self.create_db(ops).await
+    }
+    /// Create a database while `checkpoint` is held
+    async fn create_db(&self, ops: &TuringDBOps) -> TuringResult<OpsOutcome> {
         let db_path = ops.get_db_name();
         let db = TuringDB::new();
 
-        let dbop = db.db_create(&self.repo_dir, &db_path).await?;
+        let master_key = self.master_key().await;
+        if ops.is_encrypted() && master_key.is_none() {
+            return Err(TuringDbError::EncryptionKeyMissing);
+        }
+
+        let dbop = db
+            .db_create(&self.repo_dir, &db_path, ops.is_encrypted())
+            .await?;
+
+        let new_db = TuringDB::new()
+            .with_name(&db_path)
+            .with_integrity(IntegrityManifest::new(&self.integrity_key, &db_path))
+            .with_cdc(CdcLog::new(
+                &self.repo_dir.join(&db_path),
+                self.cdc_retention,
+            ))
+            .with_history(self.history)
+            .with_encryption(ops.is_encrypted());
+
+        if ops.is_encrypted() {
+            new_db
+                .ensure_data_keys(&self.repo_dir.join(&db_path), master_key.as_ref())
+                .await?;
+        }
 
-        self.dbs.insert(db_path.into(), TuringDB::new());
+        self.dbs.insert(db_path, Arc::new(new_db));
+        self.write_manifest().await?;
 
         Ok(dbop)
     }
 
-    pub async fn db_drop(&mut self, ops: TuringDBOps) -> TuringResult<OpsOutcome> {
+    pub async fn db_drop(&self, ops: TuringDBOps) -> TuringResult<OpsOutcome> {
+        let outcome = self.apply_db_drop(&ops).await;
+        let operation = LoggedOperation::DbDrop {
+            db: ops.get_db_name().into_string(),
+        };
+        self.record(ops.get_actor(), operation, &outcome).await;
+
+        outcome
+    }
+
+    async fn apply_db_drop(&self, ops: &TuringDBOps) -> TuringResult<OpsOutcome> {
+        self.writable()?;
+        let _checkpoint = self.checkpoint.read().await;
+
         let db_path = ops.get_db_name();
         let db = TuringDB::new();
 
         let dbop = db.db_drop(&self.repo_dir, &db_path).await?;
 
         match self.dbs.remove(&db_path) {
-            Some(_) => Ok(dbop),
+            Some(_) => {
+                self.write_manifest().await?;
+
+                Ok(dbop)
+            }
             None => Err(TuringDbError::NotFound),
         }
     }
@@ -144,131 +535,2325 @@ impl TuringEngine {
         }
     }
     /// List all the documents in the database in any order
-    pub fn document_list(&mut self, ops: &TuringDBOps) -> TuringResult<OpsOutcome> {
+    pub fn document_list(&self, ops: &TuringDBOps) -> TuringResult<OpsOutcome> {
         let db_name = ops.get_db_name();
 
-        match self.dbs.get(&db_name.to_path_buf()) {
+        match self.db(&db_name) {
             None => Err(TuringDbError::DbNotFound),
             Some(db) => Ok(TuringDB::document_list(&db)),
         }
     }
     /// List all documents in a database sorted alphabetically
-    pub fn document_list_sorted(&mut self, ops: &TuringDBOps) -> TuringResult<OpsOutcome> {
+    pub fn document_list_sorted(&self, ops: &TuringDBOps) -> TuringResult<OpsOutcome> {
         let db_name = ops.get_db_name();
 
-        match self.dbs.get(&db_name.to_path_buf()) {
+        match self.db(&db_name) {
             None => Err(TuringDbError::DbNotFound),
             Some(db) => Ok(TuringDB::document_list_sorted(&db)),
         }
     }
     /// Create a document
-    pub async fn document_create(&mut self, ops: &TuringDBDocumentOps) -> TuringResult<OpsOutcome> {
+    pub async fn document_create(&self, ops: &TuringDBDocumentOps) -> TuringResult<OpsOutcome> {
+        let outcome = self.apply_document_create(ops).await;
+        let operation = LoggedOperation::DocumentCreate {
+            db: ops.get_db_name().into_string(),
+            document: ops.get_document_name().into_string(),
+            encrypted: ops.is_encrypted(),
+        };
+        self.record(ops.get_actor(), operation, &outcome).await;
+
+        outcome
+    }
+
+    async fn apply_document_create(&self, ops: &TuringDBDocumentOps) -> TuringResult<OpsOutcome> {
+        self.writable()?;
+        let _checkpoint = self.checkpoint.read().await;
+
+        self.create_document(ops).await
+    }
+    /// Create a document while `checkpoint` is held
+    async fn create_document(&self, ops: &TuringDBDocumentOps) -> TuringResult<OpsOutcome> {
         let db_name = ops.get_db_name();
 
-        match self.dbs.get_mut(&db_name.to_path_buf()) {
+        let outcome = match self.db(&db_name) {
             None => Err(TuringDbError::DbNotFound),
-            Some(mut db) => {
-                db.document_create(&self.repo_dir, &ops.get_db_name(), &ops.get_document_name())
-                    .await
+            Some(db) => {
+                if ops.is_encrypted() {
+                    db.ensure_data_keys(
+                        &self.repo_dir.join(&db_name),
+                        self.master_key().await.as_ref(),
+                    )
+                    .await?;
+                }
+
+                db.document_create(
+                    &self.repo_dir,
+                    &ops.get_db_name(),
+                    &ops.get_document_name(),
+                    ops.is_encrypted(),
+                )
+                .await
             }
-        }
+        }?;
+
+        self.write_manifest().await?;
+
+        Ok(outcome)
     }
     /// Create a document
-    pub async fn document_drop(&mut self, ops: &TuringDBDocumentOps) -> TuringResult<OpsOutcome> {
+    pub async fn document_drop(&self, ops: &TuringDBDocumentOps) -> TuringResult<OpsOutcome> {
+        let outcome = self.apply_document_drop(ops).await;
+        let operation = LoggedOperation::DocumentDrop {
+            db: ops.get_db_name().into_string(),
+            document: ops.get_document_name().into_string(),
+        };
+        self.record(ops.get_actor(), operation, &outcome).await;
+
+        outcome
+    }
+
+    async fn apply_document_drop(&self, ops: &TuringDBDocumentOps) -> TuringResult<OpsOutcome> {
+        self.writable()?;
+        let _checkpoint = self.checkpoint.read().await;
+
         let db_name = ops.get_db_name();
 
-        match self.dbs.get_mut(&db_name.to_path_buf()) {
+        let outcome = match self.db(&db_name) {
             None => Err(TuringDbError::DbNotFound),
-            Some(mut db) => {
+            Some(db) => {
                 db.document_drop(&self.repo_dir, &ops.get_db_name(), &ops.get_document_name())
                     .await
             }
+        }?;
+
+        self.write_manifest().await?;
+
+        Ok(outcome)
+    }
+    ///Insert a field and its value.
+    /// A field given a `ttl` is hidden from reads once it expires and later removed by `expiry_sweep()`
+    pub async fn field_set(&self, ops: &TuringDBFieldOps) -> TuringResult<OpsOutcome> {
+        let outcome = self.apply_field_set(ops).await;
+        let operation = LoggedOperation::FieldInsert {
+            db: ops.get_db_name().into_string(),
+            document: ops.get_document_name().into_string(),
+            field: ops.get_key(),
+        };
+        self.record(ops.get_actor(), operation, &outcome).await;
+
+        outcome
+    }
+
+    async fn apply_field_set(&self, ops: &TuringDBFieldOps) -> TuringResult<OpsOutcome> {
+        self.writable()?;
+        let _checkpoint = self.checkpoint.read().await;
+
+        let db_name = ops.get_db_name();
+
+        match self.db(&db_name) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => {
+                let cell = TDBCell::new(ops.get_data_type(), &Zeroizing::new(ops.get_value()));
+
+                db.field_set(
+                    &ops.get_document_name(),
+                    IVec::from(ops.get_key()),
+                    &cell.to_bytes(),
+                    ops.get_ttl(),
+                )
+                .await
+            }
+        }
+    }
+    /// Replace the value of a field that already exists.
+    /// A `ttl` replaces the time the field expires, otherwise the field keeps its expiry
+    pub async fn field_modify(&self, ops: &TuringDBFieldOps) -> TuringResult<OpsOutcome> {
+        let outcome = self.apply_field_modify(ops).await;
+        let operation = LoggedOperation::FieldModify {
+            db: ops.get_db_name().into_string(),
+            document: ops.get_document_name().into_string(),
+            field: ops.get_key(),
+        };
+        self.record(ops.get_actor(), operation, &outcome).await;
+
+        outcome
+    }
+
+    async fn apply_field_modify(&self, ops: &TuringDBFieldOps) -> TuringResult<OpsOutcome> {
+        self.writable()?;
+        let _checkpoint = self.checkpoint.read().await;
+
+        match self.db(&ops.get_db_name()) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => {
+                let cell = TDBCell::new(ops.get_data_type(), &Zeroizing::new(ops.get_value()));
+
+                db.field_modify(
+                    &ops.get_document_name(),
+                    &ops.get_key(),
+                    &cell.to_bytes(),
+                    ops.get_ttl(),
+                )
+                .await
+            }
+        }
+    }
+    /// Remove a field
+    pub async fn field_remove(&self, ops: &TuringDBFieldOps) -> TuringResult<OpsOutcome> {
+        let outcome = self.apply_field_remove(ops).await;
+        let operation = LoggedOperation::FieldRemove {
+            db: ops.get_db_name().into_string(),
+            document: ops.get_document_name().into_string(),
+            field: ops.get_key(),
+        };
+        self.record(ops.get_actor(), operation, &outcome).await;
+
+        outcome
+    }
+
+    async fn apply_field_remove(&self, ops: &TuringDBFieldOps) -> TuringResult<OpsOutcome> {
+        self.writable()?;
+        let _checkpoint = self.checkpoint.read().await;
+
+        match self.db(&ops.get_db_name()) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => {
+                db.field_remove(&ops.get_document_name(), &ops.get_key())
+                    .await
+            }
         }
-    } /*
-      ///Insert a field and its value
-      pub async fn field_set(&self, ops: &TuringDBFieldOps) -> TuringResult<OpsOutcome> {
-          let db_name = ops.get_db_name();
-
-          match self.dbs.get_mut(&db_name.to_path_buf()) {
-              None => Err(TuringDbError::DbNotFound),
-              Some(mut db) => {
-                  db.field_set(
-                      &self.repo_dir,
-                      &ops.get_db_name(),
-                      &ops.get_document_name(),
-                      &ops.get_key(),
-                      &ops.get_value(),
-                  )
-                  .await
-              }
-          }
-      }*/
-    /// TODO Document and database stats
+    }
+    /// Apply many field operations across the documents of a database in order,
+    /// returning the result of every operation.
+    /// Without `atomic` every operation is applied on its own and a failed operation does not stop the others.
+    /// With `atomic` the operations are checked against each other first and written only if they all succeed,
+    /// otherwise the batch fails with `TuringDbError::BatchFailed` naming the first operation that failed
+    /// #### Usage
+    /// ```
+    /// let operations = vec![
+    ///     BatchOperation::Set { document: "users".into(), key: b"alice".to_vec(), data_type: 0x28, value: b"admin".to_vec() },
+    ///     BatchOperation::Remove { document: "invites".into(), key: b"alice".to_vec() },
+    /// ];
+    /// engine.batch(Utf8Path::new("db"), &operations, true).await?;
+    /// ```
+    pub async fn batch(
+        &self,
+        db_name: &Utf8Path,
+        operations: &[BatchOperation],
+        atomic: bool,
+    ) -> TuringResult<OpsOutcome> {
+        let outcome = self.apply_batch(db_name, operations, atomic).await;
+        let operation = LoggedOperation::Batch {
+            db: db_name.to_string(),
+            operations: operations.len() as u64,
+            atomic,
+        };
+        self.record(DEFAULT_ACTOR, operation, &outcome).await;
 
-    fn to_utf8_path(value: OsString) -> TuringResult<Utf8PathBuf> {
-        match std::path::PathBuf::from(value).to_str() {
-            None => Err(TuringDbError::PathReadIsNotUtf8Path),
-            Some(path) => Ok(Utf8Path::new(path).to_path_buf()),
+        outcome
+    }
+
+    async fn apply_batch(
+        &self,
+        db_name: &Utf8Path,
+        operations: &[BatchOperation],
+        atomic: bool,
+    ) -> TuringResult<OpsOutcome> {
+        // A batch that only reads fields can run on a replica
+        if operations.iter().any(BatchOperation::is_write) {
+            self.writable()?;
+        }
+        let _checkpoint = self.checkpoint.read().await;
+
+        match self.db(db_name) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) if atomic => Ok(OpsOutcome::BatchApplied(db.batch_atomic(operations).await?)),
+            Some(db) => Ok(OpsOutcome::BatchApplied(db.batch(operations).await)),
         }
     }
-}
+    /// Declare a secondary index of a document on the values of its fields, or on the value at a JSON path
+    /// inside them, and add the fields the document already holds to it.
+    /// Every later write to the document updates the index in the same sled transaction as the field.
+    /// The values of encrypted documents are not indexed. Indexes are part of the CDC log, snapshots, backups and exports,
+    /// and replicas and restored databases build their entries again from the fields.
+    /// A unique index fails with a `UniqueViolation` naming one of the fields if two of them already hold the same value
+    /// #### Usage
+    /// ```
+    /// let users = TuringDBDocumentOps::default()
+    ///     .set_db_name("db")
+    ///     .set_document_name("users");
+    /// engine.index_create(&users, IndexDefinition::new("by_email").path("email").unique()).await?;
+    /// ```
+    pub async fn index_create(
+        &self,
+        ops: &TuringDBDocumentOps,
+        index: IndexDefinition,
+    ) -> TuringResult<OpsOutcome> {
+        let outcome = self.apply_index_create(ops, &index).await;
+        let operation = LoggedOperation::IndexCreate {
+            db: ops.get_db_name().into_string(),
+            document: ops.get_document_name().into_string(),
+            index: index.get_name().to_owned(),
+        };
+        self.record(ops.get_actor(), operation, &outcome).await;
 
-/*//TODO
-// 1. READ THE REPO AND CHECK AGANIST A HMAC FOR TIME AND HASHES
-// 5. APPLY TIMESTAMP AND DATABASE OPS TO ops.log file
-//---------
-/// Read a repo
+        outcome
+    }
 
-pub async fn repo_init(&mut self) -> Result<OpsOutcome, TuringDbError> {
-    let mut repo = match async_fs::read_dir(&self.repo_dir).await {
-        Ok(value) => value,
-        Err(e) => {
-            if e.kind() == ErrorKind::NotFound {
-                return Ok(self);
-            } else {
-                return Err(anyhow::Error::new(e));
+    async fn apply_index_create(
+        &self,
+        ops: &TuringDBDocumentOps,
+        index: &IndexDefinition,
+    ) -> TuringResult<OpsOutcome> {
+        self.writable()?;
+        let _checkpoint = self.checkpoint.read().await;
+
+        match self.db(&ops.get_db_name()) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => db.index_create(&ops.get_document_name(), index).await,
+        }
+    }
+    /// Drop a secondary index of a document
+    pub async fn index_drop(
+        &self,
+        ops: &TuringDBDocumentOps,
+        index: &str,
+    ) -> TuringResult<OpsOutcome> {
+        let outcome = self.apply_index_drop(ops, index).await;
+        let operation = LoggedOperation::IndexDrop {
+            db: ops.get_db_name().into_string(),
+            document: ops.get_document_name().into_string(),
+            index: index.to_owned(),
+        };
+        self.record(ops.get_actor(), operation, &outcome).await;
+
+        outcome
+    }
+
+    async fn apply_index_drop(
+        &self,
+        ops: &TuringDBDocumentOps,
+        index: &str,
+    ) -> TuringResult<OpsOutcome> {
+        self.writable()?;
+        let _checkpoint = self.checkpoint.read().await;
+
+        match self.db(&ops.get_db_name()) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => db.index_drop(&ops.get_document_name(), index).await,
+        }
+    }
+    /// Find the fields of a document whose indexed value equals a value or lies in a range,
+    /// returning their keys and contents in the order of their indexed values
+    /// #### Usage
+    /// ```
+    /// let adults = IndexLookup::Range {
+    ///     from: Some(IndexValue::Integer(18)),
+    ///     to: None,
+    /// };
+    /// engine.find_by_index(Utf8Path::new("db"), Utf8Path::new("users"), "by_age", &adults).await?;
+    /// ```
+    pub async fn find_by_index(
+        &self,
+        db_name: &Utf8Path,
+        document_name: &Utf8Path,
+        index: &str,
+        lookup: &IndexLookup,
+    ) -> TuringResult<OpsOutcome> {
+        match self.db(db_name) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => Ok(OpsOutcome::FieldsFound(
+                db.find_by_index(document_name, index, lookup).await?,
+            )),
+        }
+    }
+    /// List the keys of the fields in a document
+    pub fn field_list(&self, ops: &TuringDBDocumentOps) -> TuringResult<OpsOutcome> {
+        let db_name = ops.get_db_name();
+
+        match self.db(&db_name) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => db.field_list(&ops.get_document_name()),
+        }
+    }
+    /// Get the contents of a field
+    pub async fn field_get(&self, ops: &TuringDBFieldOps) -> TuringResult<OpsOutcome> {
+        let db_name = ops.get_db_name();
+
+        match self.db(&db_name) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => {
+                let field_data = db
+                    .field_get(&ops.get_document_name(), &ops.get_key())
+                    .await?;
+
+                Ok(OpsOutcome::FieldContents(field_data.data().to_vec()))
             }
         }
-    };
+    }
+    /// Get the contents a field held at the time `at`, which needs the engine to keep history
+    /// #### Usage
+    /// ```
+    /// let an_hour_ago = TAI64N::from_system_time(&(SystemTime::now() - Duration::from_secs(3600)));
+    /// engine.field_get_at(&ops, an_hour_ago).await?;
+    /// ```
+    pub async fn field_get_at(
+        &self,
+        ops: &TuringDBFieldOps,
+        at: TAI64N,
+    ) -> TuringResult<OpsOutcome> {
+        let db_name = ops.get_db_name();
 
-    while let Some(database_entry) = repo.try_next().await? {
-        let database_name = database_entry.file_name();
+        match self.db(&db_name) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => {
+                let field_data = db
+                    .field_get_at(&ops.get_document_name(), &ops.get_key(), at)
+                    .await?;
 
-        if database_entry.file_type().await?.is_dir() {
-            let mut repo = async_fs::read_dir(&database_entry.path()).await?;
-            let mut current_db = Tdb::new();
+                Ok(OpsOutcome::FieldContents(field_data.data().to_vec()))
+            }
+        }
+    }
+    /// Get every previous version of a field with the time it was written, the oldest first.
+    /// The current value of the field is read with `field_get()`
+    pub async fn field_history(&self, ops: &TuringDBFieldOps) -> TuringResult<OpsOutcome> {
+        let db_name = ops.get_db_name();
 
-            while let Some(document_entry) = repo.try_next().await? {
-                let mut field_keys = Vec::new();
+        match self.db(&db_name) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => {
+                let versions = db
+                    .field_history(&ops.get_document_name(), &ops.get_key())
+                    .await?;
 
-                if document_entry.file_type().await?.is_dir() {
-                    let document_name = document_entry.file_name();
-                    let db = sled::open(document_entry.path())?;
+                Ok(OpsOutcome::FieldHistory(versions))
+            }
+        }
+    }
+    /// Read up to `max` records of the CDC log of a database starting from the sequence `from`.
+    /// The field values of encrypted documents are opened with the data keys of the database
+    pub async fn cdc_read(
+        &self,
+        db_name: &Utf8Path,
+        from: u64,
+        max: usize,
+    ) -> TuringResult<Vec<CdcRecord>> {
+        match self.db(db_name) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => match &db.cdc {
+                None => Ok(Vec::new()),
+                Some(cdc) => {
+                    let records = cdc.read(from, max).await?;
 
-                    for field_key in db.into_iter().keys() {
-                        field_keys.push(field_key?);
-                    }
+                    Ok(records
+                        .into_iter()
+                        .map(|record| db.cdc_open(record))
+                        .collect())
+                }
+            },
+        }
+    }
+    /// Read up to `max` records of the CDC log of a database from the offset committed by a consumer group.
+    /// The group has to commit the offset after processing the records to move past them
+    /// #### Usage
+    /// ```
+    /// let records = engine.cdc_poll(Utf8Path::new("db"), "indexer", 100).await?;
+    ///
+    /// if let Some(last) = records.last() {
+    ///     engine.cdc_commit(Utf8Path::new("db"), "indexer", last.sequence + 1).await?;
+    /// }
+    /// ```
+    pub async fn cdc_poll(
+        &self,
+        db_name: &Utf8Path,
+        group: &str,
+        max: usize,
+    ) -> TuringResult<Vec<CdcRecord>> {
+        match self.db(db_name) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => match &db.cdc {
+                None => Ok(Vec::new()),
+                Some(cdc) => {
+                    let records = cdc.poll(group, max).await?;
 
-                    let data = field_keys.iter().map(|inner| inner.to_vec()).collect();
+                    Ok(records
+                        .into_iter()
+                        .map(|record| db.cdc_open(record))
+                        .collect())
+                }
+            },
+        }
+    }
+    /// Commit the offset of a consumer group, the sequence of the next record it reads
+    pub async fn cdc_commit(
+        &self,
+        db_name: &Utf8Path,
+        group: &str,
+        offset: u64,
+    ) -> TuringResult<OpsOutcome> {
+        match self.db(db_name) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => match &db.cdc {
+                None => Err(TuringDbError::NotFound),
+                Some(cdc) => {
+                    cdc.commit(group, offset).await?;
 
-                    current_db.list.insert(
-                        document_name,
-                        Document {
-                            fd: Mutex::new(db),
-                            keys: data,
-                        },
-                    );
+                    Ok(OpsOutcome::CdcOffsetCommitted)
                 }
-            }
-            self.dbs.insert(database_name, current_db);
+            },
+        }
+    }
+    /// Watch the fields of a document whose keys start with `key_prefix`.
+    /// An empty prefix watches every field of the document
+    /// #### Usage
+    /// ```
+    /// let mut feed = engine.watch(Utf8Path::new("db"), Utf8Path::new("document"), b"user:")?;
+    ///
+    /// while let Some(event) = feed.next().await {
+    ///     let event = event?;
+    /// }
+    /// ```
+    pub fn watch(
+        &self,
+        db_name: &Utf8Path,
+        document_name: &Utf8Path,
+        key_prefix: &[u8],
+    ) -> TuringResult<ChangeFeed> {
+        match self.db(db_name) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => TuringEngine::feed(&db, document_name, key_prefix),
         }
     }
+    /// Resume watching the fields of a document from the `position` of the last event a client received,
+    /// first reporting every change made to the watched fields after it, removals and expiries included.
+    /// Fails with `CdcOffsetOutOfRange` when the CDC log no longer keeps the changes after `position`
+    /// #### Usage
+    /// ```
+    /// let mut feed = engine
+    ///     .watch_since(Utf8Path::new("db"), Utf8Path::new("document"), b"user:", last.position)
+    ///     .await?;
+    /// ```
+    pub async fn watch_since(
+        &self,
+        db_name: &Utf8Path,
+        document_name: &Utf8Path,
+        key_prefix: &[u8],
+        position: u64,
+    ) -> TuringResult<ChangeFeed> {
+        let db = match self.db(db_name) {
+            None => return Err(TuringDbError::DbNotFound),
+            Some(db) => db,
+        };
 
-    Ok(self)
-}*/
-/*
-/// Drop a repository
-pub async fn repo_drop(&self) -> Result<DbOps> {
-    async_fs::remove_dir_all(REPO_NAME).await?;
-    Ok(DbOps::RepoDropped)
-}*/
+        // Subscribe before reading the log so no change made in between is lost
+        let mut feed = TuringEngine::feed(&db, document_name, key_prefix)?;
+        db.changes_since(&mut feed, position).await?;
+
+        Ok(feed)
+    }
+
+    fn feed(
+        db: &TuringDB,
+        document_name: &Utf8Path,
+        key_prefix: &[u8],
+    ) -> TuringResult<ChangeFeed> {
+        let document = match db.document(document_name) {
+            None => return Err(TuringDbError::DocumentNotFound),
+            Some(document) => document,
+        };
+
+        let sealer = if db.is_encrypted(document_name) {
+            Some(db.sealer.clone())
+        } else {
+            None
+        };
+
+        ChangeFeed::new(document_name, &document, key_prefix, sealer)
+    }
+    /// Insert a JSON value into a field, failing if the field already exists
+    pub async fn json_set(&self, ops: &TuringDBJsonOps) -> TuringResult<OpsOutcome> {
+        let outcome = self.apply_json_set(ops).await;
+        let operation = LoggedOperation::JsonSet {
+            db: ops.get_db_name().into_string(),
+            document: ops.get_document_name().into_string(),
+            field: ops.get_key(),
+        };
+        self.record(ops.get_actor(), operation, &outcome).await;
+
+        outcome
+    }
+
+    async fn apply_json_set(&self, ops: &TuringDBJsonOps) -> TuringResult<OpsOutcome> {
+        self.writable()?;
+        let _checkpoint = self.checkpoint.read().await;
+
+        let db_name = ops.get_db_name();
+
+        let value = match ops.get_value() {
+            None => return Err(TuringDbError::JsonInvalid("JSON value not provided".into())),
+            Some(value) => value,
+        };
+
+        match self.db(&db_name) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => {
+                db.json_set(&ops.get_document_name(), &ops.get_key(), value)
+                    .await
+            }
+        }
+    }
+    /// Get the JSON value at a path inside a field.
+    /// The value is returned as a JSON string
+    pub async fn json_get(&self, ops: &TuringDBJsonOps) -> TuringResult<OpsOutcome> {
+        let db_name = ops.get_db_name();
+        let path = JsonPath::parse(ops.get_path())?;
+
+        match self.db(&db_name) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => {
+                let value = db
+                    .json_get(&ops.get_document_name(), &ops.get_key(), &path)
+                    .await?;
+
+                match serde_json::to_string(&value) {
+                    Ok(json) => Ok(OpsOutcome::JsonContents(json)),
+                    Err(error) => Err(TuringDbError::JsonInvalid(error.to_string())),
+                }
+            }
+        }
+    }
+    /// Set the value at a path inside a JSON field without rewriting the rest of the value
+    pub async fn json_modify(&self, ops: &TuringDBJsonOps) -> TuringResult<OpsOutcome> {
+        let outcome = self.apply_json_modify(ops).await;
+        let operation = LoggedOperation::JsonModify {
+            db: ops.get_db_name().into_string(),
+            document: ops.get_document_name().into_string(),
+            field: ops.get_key(),
+            path: ops.get_path().to_owned(),
+        };
+        self.record(ops.get_actor(), operation, &outcome).await;
+
+        outcome
+    }
+
+    async fn apply_json_modify(&self, ops: &TuringDBJsonOps) -> TuringResult<OpsOutcome> {
+        self.writable()?;
+        let _checkpoint = self.checkpoint.read().await;
+
+        let db_name = ops.get_db_name();
+        let path = JsonPath::parse(ops.get_path())?;
+
+        let value = match ops.get_value() {
+            None => return Err(TuringDbError::JsonInvalid("JSON value not provided".into())),
+            Some(value) => value,
+        };
+
+        match self.db(&db_name) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => {
+                db.json_update(&ops.get_document_name(), &ops.get_key(), |json| {
+                    path.set(json, value.to_owned())
+                })
+                .await
+            }
+        }
+    }
+    /// Remove the value at a path inside a JSON field
+    pub async fn json_remove(&self, ops: &TuringDBJsonOps) -> TuringResult<OpsOutcome> {
+        let outcome = self.apply_json_remove(ops).await;
+        let operation = LoggedOperation::JsonRemove {
+            db: ops.get_db_name().into_string(),
+            document: ops.get_document_name().into_string(),
+            field: ops.get_key(),
+            path: ops.get_path().to_owned(),
+        };
+        self.record(ops.get_actor(), operation, &outcome).await;
+
+        outcome
+    }
+
+    async fn apply_json_remove(&self, ops: &TuringDBJsonOps) -> TuringResult<OpsOutcome> {
+        self.writable()?;
+        let _checkpoint = self.checkpoint.read().await;
+
+        let db_name = ops.get_db_name();
+        let path = JsonPath::parse(ops.get_path())?;
+
+        if path.is_root() {
+            return Err(TuringDbError::JsonPathInvalid(ops.get_path().to_owned()));
+        }
+
+        match self.db(&db_name) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => {
+                db.json_update(&ops.get_document_name(), &ops.get_key(), |json| {
+                    path.remove(json).map(|_| ())
+                })
+                .await
+            }
+        }
+    }
+    // TODO Document and database stats
+
+    /// Replace the data key of an encrypted database and re-encrypt its data in the background.
+    /// Data sealed with the previous data key can still be read until the re-encryption is done.
+    /// Once a re-encryption stopped, see `data_key_rotation_failure()`, calling it again retries the re-encryption
+    pub async fn rotate_data_key(&self, ops: &TuringDBOps) -> TuringResult<OpsOutcome> {
+        let outcome = self.apply_rotate_data_key(ops).await;
+        let operation = LoggedOperation::DataKeyRotate {
+            db: ops.get_db_name().into_string(),
+        };
+        self.record(ops.get_actor(), operation, &outcome).await;
+
+        outcome
+    }
+
+    async fn apply_rotate_data_key(&self, ops: &TuringDBOps) -> TuringResult<OpsOutcome> {
+        self.writable()?;
+        let _checkpoint = self.checkpoint.read().await;
+
+        let db_name = ops.get_db_name();
+        let db_dir = self.repo_dir.join(&db_name);
+
+        let master_key = match self.master_key().await {
+            None => return Err(TuringDbError::EncryptionKeyMissing),
+            Some(master_key) => master_key,
+        };
+
+        match self.db(&db_name) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => {
+                let keys = match db.sealer.keys()? {
+                    None => return Err(TuringDbError::EncryptionKeyMissing),
+                    Some(keys) => keys,
+                };
+
+                // A re-encryption that stopped is retried with the data key it was rotating to
+                if keys.previous.is_some() {
+                    return match db.sealer.failure()? {
+                        None => Err(TuringDbError::KeyRotationInProgress),
+                        Some(_) => {
+                            db.start_reencryption(&db_dir, &master_key)?;
+
+                            Ok(OpsOutcome::DataKeyRotationStarted)
+                        }
+                    };
+                }
+
+                let rotated = keys.rotate(&master_key)?;
+                db.write_data_keys(&db_dir, &rotated, &master_key).await?;
+                db.sealer.set_keys(rotated)?;
+
+                db.start_reencryption(&db_dir, &master_key)?;
+
+                Ok(OpsOutcome::DataKeyRotationStarted)
+            }
+        }
+    }
+    /// Why the re-encryption started by `rotate_data_key()` stopped, leaving the database
+    /// with its previous data key until the rotation is retried. `None` while it runs or once it is done
+    pub fn data_key_rotation_failure(
+        &self,
+        db_name: &Utf8Path,
+    ) -> TuringResult<Option<TuringDbError>> {
+        match self.db(db_name) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => db.sealer.failure(),
+        }
+    }
+    /// Seal the data keys of every database with a new master key.
+    /// The data itself does not need to be re-encrypted since only the data keys are sealed
+    /// with the master key
+    pub async fn rotate_master_key(&self, master_key: Cipher) -> TuringResult<OpsOutcome> {
+        let outcome = self.apply_rotate_master_key(master_key, None).await;
+        self.record(DEFAULT_ACTOR, LoggedOperation::MasterKeyRotate, &outcome)
+            .await;
+
+        outcome
+    }
+    /// The new key files are staged next to the ones in use and only replace them once
+    /// the `MASTER_KEY_CHANGE` marker is stored, so a crash leaves the repo readable
+    /// with either the previous or the new master key, see `recover_master_key_change()`
+    async fn apply_rotate_master_key(
+        &self,
+        master_key: Cipher,
+        key_derivation: Option<KeyDerivation>,
+    ) -> TuringResult<OpsOutcome> {
+        self.writable()?;
+        let _checkpoint = self.checkpoint.write().await;
+
+        let staged = self
+            .stage_master_key(&master_key, key_derivation.as_ref())
+            .await?;
+
+        self.commit_master_key_change(&staged).await?;
+
+        *self.master_key.write().await = Some(master_key);
+        if let Some(key_derivation) = key_derivation {
+            *self.key_derivation.write().await = Some(key_derivation);
+        }
+
+        let repo_dir = self.repo_dir.clone();
+        blocking::unblock(move || TuringEngine::finish_master_key_change(&repo_dir, &staged))
+            .await?;
+
+        Ok(OpsOutcome::MasterKeyRotated)
+    }
+    /// Store the key files sealed with the new master key next to the ones in use.
+    /// Returns the paths of the key files to replace, relative to the repo directory
+    async fn stage_master_key(
+        &self,
+        master_key: &Cipher,
+        key_derivation: Option<&KeyDerivation>,
+    ) -> TuringResult<Vec<String>> {
+        let mut key_files = Vec::new();
+
+        for (db_name, db) in self.databases() {
+            // A database that is being re-encrypted rewrites its data keys with the old master key once done
+            if db.sealer.is_rotating()? {
+                return Err(TuringDbError::KeyRotationInProgress);
+            }
+
+            if let Some(data_keys) = db.rewrap_data_keys(master_key)? {
+                key_files.push((db_name.join(DATA_KEYS_FILE), data_keys));
+            }
+        }
+
+        key_files.push((
+            Utf8PathBuf::from(INTEGRITY_KEY_FILE),
+            self.integrity_key.to_bytes(Some(master_key))?,
+        ));
+        if let Some(key_derivation) = key_derivation {
+            key_files.push((
+                Utf8PathBuf::from(KEY_DERIVATION_FILE),
+                key_derivation.to_bytes()?,
+            ));
+        }
+
+        let mut staged = Vec::new();
+        for (key_file, contents) in key_files {
+            RepoPath::replace(&RepoPath::staged(&self.repo_dir.join(&key_file)), contents).await?;
+            staged.push(key_file.into_string());
+        }
+
+        Ok(staged)
+    }
+    /// The change is made once its marker, listing the staged key files, is stored
+    async fn commit_master_key_change(&self, staged: &[String]) -> TuringResult<()> {
+        let marker = match bincode::serialize(staged) {
+            Ok(marker) => marker,
+            Err(_) => {
+                return Err(TuringDbError::Bug(
+                    "Unable to serialize the master key change".into(),
+                ))
+            }
+        };
+
+        RepoPath::replace(&self.repo_dir.join(MASTER_KEY_CHANGE_FILE), marker).await
+    }
+    /// Finish a master key change whose marker was stored before a crash,
+    /// or drop the key files staged by a change that was not made
+    async fn recover_master_key_change(repo_dir: &Utf8Path) -> TuringResult<()> {
+        let repo_dir = repo_dir.to_path_buf();
+
+        blocking::unblock(move || {
+            let staged = match std::fs::read(repo_dir.join(MASTER_KEY_CHANGE_FILE)) {
+                Ok(marker) => match bincode::deserialize::<Vec<String>>(&marker) {
+                    Ok(staged) => staged,
+                    Err(_) => return Err(TuringDbError::MasterKeyChangeCorrupted),
+                },
+                Err(error) if error.kind() == ErrorKind::NotFound => {
+                    return TuringEngine::drop_staged_keys(&repo_dir)
+                }
+                Err(error) => return Err(error.into()),
+            };
+
+            TuringEngine::finish_master_key_change(&repo_dir, &staged)
+        })
+        .await
+    }
+    /// Move the staged key files in place, then remove the marker of the change
+    fn finish_master_key_change(repo_dir: &Utf8Path, staged: &[String]) -> TuringResult<()> {
+        for key_file in staged {
+            let path = repo_dir.join(key_file);
+
+            match std::fs::rename(RepoPath::staged(&path), &path) {
+                Ok(_) => RepoPath::sync_parent(&path)?,
+                // Moved before a crash
+                Err(error) if error.kind() == ErrorKind::NotFound => (),
+                Err(error) => return Err(error.into()),
+            }
+        }
+
+        let marker = repo_dir.join(MASTER_KEY_CHANGE_FILE);
+        std::fs::remove_file(&marker)?;
+        RepoPath::sync_parent(&marker)
+    }
+
+    fn drop_staged_keys(repo_dir: &Utf8Path) -> TuringResult<()> {
+        let mut key_files = vec![
+            repo_dir.join(INTEGRITY_KEY_FILE),
+            repo_dir.join(KEY_DERIVATION_FILE),
+        ];
+
+        match std::fs::read_dir(repo_dir) {
+            Ok(entries) => {
+                for entry in entries {
+                    let entry = entry?;
+
+                    if entry.file_type()?.is_dir() {
+                        let db_dir = TuringEngine::to_utf8_path(entry.path().into_os_string())?;
+                        key_files.push(db_dir.join(DATA_KEYS_FILE));
+                    }
+                }
+            }
+            // A repo that is not created yet
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(error) => return Err(error.into()),
+        }
+
+        for key_file in key_files {
+            match std::fs::remove_file(RepoPath::staged(&key_file)) {
+                Ok(_) => (),
+                Err(error) if error.kind() == ErrorKind::NotFound => (),
+                Err(error) => return Err(error.into()),
+            }
+        }
+
+        Ok(())
+    }
+    /// Derive a new master key from a new passphrase with a new salt
+    /// and seal the data keys of every database with it
+    pub async fn change_passphrase(&self, passphrase: Secret<String>) -> TuringResult<OpsOutcome> {
+        let outcome = self.apply_change_passphrase(passphrase).await;
+        self.record(DEFAULT_ACTOR, LoggedOperation::MasterKeyRotate, &outcome)
+            .await;
+
+        outcome
+    }
+
+    async fn apply_change_passphrase(
+        &self,
+        passphrase: Secret<String>,
+    ) -> TuringResult<OpsOutcome> {
+        let kind = match self
+            .master_key()
+            .await
+            .and_then(|master_key| master_key.kind())
+        {
+            None => CipherKind::XChaCha20Poly1305,
+            Some(kind) => kind,
+        };
+
+        let key_derivation = KeyDerivation::generate(kind)?;
+        let master_key = key_derivation.derive(&passphrase)?;
+
+        self.apply_rotate_master_key(master_key, Some(key_derivation))
+            .await
+    }
+
+    /// Whether the engine is a read-only replica of a leader
+    pub fn is_replica(&self) -> bool {
+        self.replica.is_some()
+    }
+    /// Copy a database and the position of its CDC log to bootstrap a replica
+    pub async fn replication_snapshot(&self, db_name: &Utf8Path) -> TuringResult<DbSnapshot> {
+        match self.db(db_name) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => db.snapshot(db_name, &self.repo_dir.join(db_name)).await,
+        }
+    }
+    /// Read up to `max` changes of every database from the positions of a replica.
+    /// The field values of encrypted documents are sent sealed
+    pub async fn replication_changes(
+        &self,
+        positions: &BTreeMap<String, u64>,
+        max: usize,
+    ) -> TuringResult<ReplicationBatch> {
+        let mut databases = BTreeMap::new();
+
+        for (db_name, db) in self.databases() {
+            let position = positions.get(db_name.as_str()).copied();
+
+            let changes = db
+                .changes(&self.repo_dir.join(&db_name), position, max)
+                .await?;
+            databases.insert(db_name.into_string(), changes);
+        }
+
+        Ok(ReplicationBatch { databases })
+    }
+    /// The position of every database of the replica, which is sent to the leader to read its changes
+    pub async fn replication_positions(&self) -> TuringResult<BTreeMap<String, u64>> {
+        Ok(self.replica()?.positions().await)
+    }
+    /// How far every database of the replica is behind the leader
+    pub async fn replication_status(&self) -> ReplicationStatus {
+        match &self.replica {
+            None => ReplicationStatus {
+                replica: false,
+                last_contact: None,
+                databases: BTreeMap::new(),
+            },
+            Some(replica) => replica.status().await,
+        }
+    }
+    /// Apply a batch of changes read from the leader and drop the databases the leader no longer has.
+    /// Returns the databases that have to be bootstrapped with `replication_bootstrap()`
+    /// #### Usage
+    /// ```
+    /// let positions = replica.replication_positions().await?;
+    /// let batch = leader.replication_changes(&positions, 1000).await?;
+    ///
+    /// for db in replica.replication_apply(batch).await? {
+    ///     let snapshot = leader.replication_snapshot(Utf8Path::new(&db)).await?;
+    ///     replica.replication_bootstrap(snapshot).await?;
+    /// }
+    /// ```
+    pub async fn replication_apply(&self, batch: ReplicationBatch) -> TuringResult<Vec<String>> {
+        let replica = self.replica()?;
+        let _checkpoint = self.checkpoint.read().await;
+        replica.contacted().await;
+
+        let dropped = self
+            .dbs
+            .iter()
+            .map(|db| db.key().to_string())
+            .filter(|db_name| !batch.databases.contains_key(db_name))
+            .collect::<Vec<String>>();
+
+        for db_name in dropped {
+            let outcome = self.apply_replica_drop(Utf8Path::new(&db_name)).await;
+            let operation = LoggedOperation::ReplicaDrop {
+                db: db_name.clone(),
+            };
+            self.record(REPLICATION_ACTOR, operation, &outcome).await;
+
+            outcome?;
+            replica.set(&db_name, None).await?;
+        }
+
+        let mut bootstrap = Vec::new();
+
+        for (db_name, changes) in batch.databases {
+            let position = match replica.position(&db_name).await {
+                Some(position) if !changes.bootstrap => position,
+                _ => {
+                    bootstrap.push(db_name);
+                    continue;
+                }
+            };
+
+            // A data key rotated on the leader re-encrypts every field so the database is copied again
+            let db_dir = self.repo_dir.join(&db_name);
+            if TuringDB::read_data_keys(&db_dir).await? != changes.data_keys {
+                bootstrap.push(db_name);
+                continue;
+            }
+
+            let (applied, outcome) = self
+                .apply_replica_changes(Utf8Path::new(&db_name), position, &changes)
+                .await;
+
+            if applied > position {
+                let operation = LoggedOperation::ReplicaApply {
+                    db: db_name.clone(),
+                    from: position,
+                    to: applied,
+                };
+                self.record(REPLICATION_ACTOR, operation, &outcome).await;
+            }
+
+            let lag = ReplicaLag {
+                position: applied,
+                leader_next: changes.next,
+            };
+            replica.set(&db_name, Some(lag)).await?;
+
+            match outcome {
+                Ok(_) => (),
+                Err(TuringDbError::DbNotFound) => bootstrap.push(db_name),
+                Err(error) => return Err(error),
+            }
+        }
+
+        Ok(bootstrap)
+    }
+    /// Replace a database of the replica with a snapshot of the leader
+    pub async fn replication_bootstrap(&self, snapshot: DbSnapshot) -> TuringResult<OpsOutcome> {
+        let replica = self.replica()?;
+        let _checkpoint = self.checkpoint.read().await;
+
+        let outcome = self.apply_replication_bootstrap(&snapshot).await;
+        let operation = LoggedOperation::ReplicaBootstrap {
+            db: snapshot.name.clone(),
+            position: snapshot.next,
+        };
+        self.record(REPLICATION_ACTOR, operation, &outcome).await;
+
+        if outcome.is_ok() {
+            let lag = ReplicaLag {
+                position: snapshot.next,
+                leader_next: snapshot.next,
+            };
+            replica.set(&snapshot.name, Some(lag)).await?;
+        }
+
+        outcome
+    }
+    /// Copy every database of the repo, sorted by name.
+    /// Mutations wait until every database is copied so the copies are from the same point in time
+    pub async fn repo_snapshot(&self) -> TuringResult<Vec<DbSnapshot>> {
+        let _checkpoint = self.checkpoint.write().await;
+
+        let mut db_names = self
+            .dbs
+            .iter()
+            .map(|db| db.key().clone())
+            .collect::<Vec<Utf8PathBuf>>();
+        db_names.sort();
+
+        let mut snapshots = Vec::with_capacity(db_names.len());
+        for db_name in db_names {
+            snapshots.push(self.replication_snapshot(&db_name).await?);
+        }
+
+        Ok(snapshots)
+    }
+    /// Replace every database of the repo with the copies taken by `repo_snapshot()`,
+    /// dropping the databases that have no copy
+    pub async fn repo_restore(&self, snapshots: &[DbSnapshot]) -> TuringResult<OpsOutcome> {
+        let outcome = self.apply_repo_restore(snapshots).await;
+        let operation = LoggedOperation::RepoRestore {
+            databases: snapshots
+                .iter()
+                .map(|snapshot| snapshot.name.clone())
+                .collect(),
+        };
+        self.record(DEFAULT_ACTOR, operation, &outcome).await;
+
+        outcome
+    }
+
+    async fn apply_repo_restore(&self, snapshots: &[DbSnapshot]) -> TuringResult<OpsOutcome> {
+        self.writable()?;
+        let _checkpoint = self.checkpoint.read().await;
+
+        let dropped = self
+            .dbs
+            .iter()
+            .map(|db| db.key().to_string())
+            .filter(|db_name| !snapshots.iter().any(|snapshot| &snapshot.name == db_name))
+            .collect::<Vec<String>>();
+
+        for db_name in dropped {
+            self.apply_replica_drop(Utf8Path::new(&db_name)).await?;
+        }
+
+        for snapshot in snapshots {
+            self.apply_replication_bootstrap(snapshot).await?;
+        }
+
+        Ok(OpsOutcome::RepoRestored)
+    }
+    /// Put every field of every database back to the value it held at the time `at`,
+    /// to recover from a bad deploy without restoring a backup.
+    /// Fields created after `at` are removed while databases and documents are kept.
+    /// The values that are replaced stay in the history, so the rewind can be undone
+    /// by rewinding to a time before it
+    /// #### Usage
+    /// ```
+    /// let before_deploy = TAI64N::from_system_time(&deployed_at);
+    /// engine.repo_rewind(before_deploy).await?;
+    /// ```
+    pub async fn repo_rewind(&self, at: TAI64N) -> TuringResult<OpsOutcome> {
+        let outcome = self.apply_repo_rewind(at).await;
+        self.record(DEFAULT_ACTOR, LoggedOperation::RepoRewind { at }, &outcome)
+            .await;
+
+        outcome
+    }
+
+    async fn apply_repo_rewind(&self, at: TAI64N) -> TuringResult<OpsOutcome> {
+        self.writable()?;
+        let _checkpoint = self.checkpoint.read().await;
+
+        if !self.history {
+            return Err(TuringDbError::HistoryDisabled);
+        }
+
+        let mut rewound = 0;
+
+        for (_, db) in self.databases() {
+            rewound += db.rewind(at).await?;
+        }
+
+        Ok(OpsOutcome::RepoRewound(rewound))
+    }
+    /// Set how many previous versions of the fields of a document are kept,
+    /// overriding the retention the engine was built with
+    /// #### Usage
+    /// ```
+    /// let retention = HistoryRetention::new()
+    ///     .max_versions(Some(10))
+    ///     .max_age(Some(Duration::from_secs(30 * 24 * 3600)));
+    /// engine.history_retention_set(&ops, retention).await?;
+    /// ```
+    pub async fn history_retention_set(
+        &self,
+        ops: &TuringDBDocumentOps,
+        retention: HistoryRetention,
+    ) -> TuringResult<OpsOutcome> {
+        let outcome = self.apply_history_retention_set(ops, retention).await;
+        let operation = LoggedOperation::HistoryRetentionSet {
+            db: ops.get_db_name().into_string(),
+            document: ops.get_document_name().into_string(),
+        };
+        self.record(ops.get_actor(), operation, &outcome).await;
+
+        outcome
+    }
+
+    async fn apply_history_retention_set(
+        &self,
+        ops: &TuringDBDocumentOps,
+        retention: HistoryRetention,
+    ) -> TuringResult<OpsOutcome> {
+        self.writable()?;
+        let _checkpoint = self.checkpoint.read().await;
+
+        match self.db(&ops.get_db_name()) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => {
+                db.history_retention_set(&ops.get_document_name(), retention)
+                    .await
+            }
+        }
+    }
+    /// Drop the previous versions of fields that the retention of their document no longer keeps.
+    /// It is meant to be run periodically by a background task while the engine serves queries
+    pub async fn history_compact(&self) -> TuringResult<OpsOutcome> {
+        // A replica drops the versions its leader does
+        if self.is_replica() {
+            return Ok(OpsOutcome::HistoryCompacted(0));
+        }
+        self.writable()?;
+        let _checkpoint = self.checkpoint.read().await;
+
+        let mut dropped = 0;
+
+        for (_, db) in self.databases() {
+            dropped += db.history_compact(self.history_retention)?;
+        }
+
+        Ok(OpsOutcome::HistoryCompacted(dropped))
+    }
+    /// Remove the fields that outlived their time-to-live from every database,
+    /// recording every removal as a `ChangeKind::Expire` change for the watchers of the document.
+    /// Expired fields are already hidden from reads, the sweep reclaims them.
+    /// Expiry times are not part of the CDC log, snapshots or backups
+    /// so a replica removes an expired field when its leader does
+    pub async fn expiry_sweep(&self) -> TuringResult<OpsOutcome> {
+        if self.is_replica() {
+            return Ok(OpsOutcome::FieldsExpired(0));
+        }
+        let _checkpoint = self.checkpoint.read().await;
+
+        let mut expired = 0;
+
+        for (db_name, db) in self.databases() {
+            let outcome = db.expiry_sweep().await.map(OpsOutcome::FieldsExpired);
+            let fields = match &outcome {
+                Ok(OpsOutcome::FieldsExpired(fields)) => *fields,
+                _ => 0,
+            };
+
+            // Sweeps that found nothing to remove are not logged
+            if fields > 0 || outcome.is_err() {
+                let operation = LoggedOperation::FieldsExpire {
+                    db: db_name.to_string(),
+                    fields,
+                };
+                self.record(DEFAULT_ACTOR, operation, &outcome).await;
+            }
+
+            outcome?;
+            expired += fields;
+        }
+
+        Ok(OpsOutcome::FieldsExpired(expired))
+    }
+    /// Run `expiry_sweep()` once every `interval` for as long as the future is polled,
+    /// so it is meant to be spawned as a background task of the executor serving the engine
+    /// #### Usage
+    /// ```
+    /// let engine = Arc::new(engine);
+    /// let sweeper = Arc::clone(&engine);
+    ///
+    /// Task::spawn(async move { sweeper.expiry_sweeper(Duration::from_secs(1)).await }).detach();
+    /// ```
+    pub async fn expiry_sweeper(&self, interval: Duration) {
+        loop {
+            Timer::new(interval).await;
+
+            if let Err(error) = self.expiry_sweep().await {
+                eprintln!("[TuringDB::<ExpirySweep>::(ERROR)-{:?}]", error);
+            }
+        }
+    }
+
+    /// Back up the repo into the new directory `dest`, with a file for every database
+    /// and a manifest holding their checksums.
+    /// The databases are copied at the same point in time while the engine keeps serving queries.
+    /// Encrypted databases stay sealed, so restoring them needs the same master key
+    /// #### Usage
+    /// ```
+    /// let manifest = engine.snapshot(Utf8Path::new("/backups/2021-06-01")).await?;
+    ///
+    /// let engine = TuringEngine::builder().repo_dir(new_repo_dir).build().await?;
+    /// engine.repo_create().await?;
+    /// engine.repo_init().await?;
+    /// engine.restore(Utf8Path::new("/backups/2021-06-01")).await?;
+    /// ```
+    pub async fn snapshot(&self, dest: &Utf8Path) -> TuringResult<BackupManifest> {
+        let contents = self
+            .repo_snapshot()
+            .await?
+            .into_iter()
+            .map(BackupContents::Snapshot)
+            .collect::<Vec<BackupContents>>();
+
+        BackupManifest::write(dest, None, &contents).await
+    }
+    /// Back up the changes made since the backup in `parent` into the new directory `dest`.
+    /// The changes of every database are read from its CDC log, so `parent` has to be recent enough
+    /// for the log to still hold them. A database the log no longer covers, a database created since
+    /// `parent` and a database whose data key was rotated since are copied whole
+    /// #### Usage
+    /// ```
+    /// engine.snapshot(Utf8Path::new("/backups/full")).await?;
+    /// engine.snapshot_incremental(Utf8Path::new("/backups/full"), Utf8Path::new("/backups/1")).await?;
+    /// engine.snapshot_incremental(Utf8Path::new("/backups/1"), Utf8Path::new("/backups/2")).await?;
+    /// ```
+    pub async fn snapshot_incremental(
+        &self,
+        parent: &Utf8Path,
+        dest: &Utf8Path,
+    ) -> TuringResult<BackupManifest> {
+        let parent = BackupManifest::read(parent).await?;
+        let contents = self.backup_changes(&parent).await?;
+
+        BackupManifest::write(dest, Some(parent.checksum()?), &contents).await
+    }
+    /// Check the manifest of the backup in `src` and the checksum of every file it lists
+    pub async fn snapshot_verify(&self, src: &Utf8Path) -> TuringResult<BackupManifest> {
+        let manifest = BackupManifest::read(src).await?;
+        manifest.load(src).await?;
+
+        Ok(manifest)
+    }
+    /// Replace every database of the repo with the databases of the backup in `src`.
+    /// The whole backup is checked before the repo is changed
+    pub async fn restore(&self, src: &Utf8Path) -> TuringResult<OpsOutcome> {
+        self.restore_chain(&[src], None).await
+    }
+    /// Replace every database of the repo with the full backup `backups[0]`
+    /// and then apply the incremental backups made after it, in order.
+    /// With `until` the changes made after that time are left out, restoring the repo as it was then,
+    /// except for a database copied whole by an incremental backup, which is restored as it was
+    /// when that backup was made if it was made before `until`.
+    /// Every backup is checked before the repo is changed
+    /// #### Usage
+    /// ```
+    /// engine
+    ///     .restore_chain(
+    ///         &[
+    ///             Utf8Path::new("/backups/full"),
+    ///             Utf8Path::new("/backups/1"),
+    ///             Utf8Path::new("/backups/2"),
+    ///         ],
+    ///         Some(point_in_time),
+    ///     )
+    ///     .await?;
+    /// ```
+    pub async fn restore_chain(
+        &self,
+        backups: &[&Utf8Path],
+        until: Option<TAI64N>,
+    ) -> TuringResult<OpsOutcome> {
+        let mut chain = Vec::with_capacity(backups.len());
+        let mut parent = None;
+
+        for src in backups {
+            let manifest = BackupManifest::read(src).await?;
+
+            let chained = match (&parent, manifest.parent) {
+                (None, None) => true,
+                (Some(parent), Some(checksum)) => *parent == checksum,
+                _ => false,
+            };
+            let too_recent =
+                chain.is_empty() && until.is_some_and(|until| manifest.created > until);
+
+            if !chained || too_recent {
+                return Err(TuringDbError::BackupChainBroken {
+                    backup: src.to_string(),
+                });
+            }
+
+            parent = Some(manifest.checksum()?);
+            let contents = manifest.load(src).await?;
+            chain.push((manifest, contents));
+        }
+
+        if chain.is_empty() {
+            return Err(TuringDbError::BackupChainBroken {
+                backup: String::new(),
+            });
+        }
+
+        let outcome = self.apply_backup_restore(&chain, until).await;
+        let operation = LoggedOperation::BackupRestore {
+            backups: backups.iter().map(|src| src.to_string()).collect(),
+            until,
+        };
+        self.record(DEFAULT_ACTOR, operation, &outcome).await;
+
+        outcome
+    }
+
+    async fn backup_changes(&self, parent: &BackupManifest) -> TuringResult<Vec<BackupContents>> {
+        let _checkpoint = self.checkpoint.write().await;
+
+        let mut db_names = self
+            .dbs
+            .iter()
+            .map(|db| db.key().clone())
+            .collect::<Vec<Utf8PathBuf>>();
+        db_names.sort();
+
+        let mut contents = Vec::with_capacity(db_names.len());
+        for db_name in db_names {
+            let db = match self.db(&db_name) {
+                None => continue,
+                Some(db) => db,
+            };
+            let db_dir = self.repo_dir.join(&db_name);
+
+            let changes = match parent.database(db_name.as_str()) {
+                None => None,
+                Some(entry) => {
+                    let changes = db.changes(&db_dir, Some(entry.next), usize::MAX).await?;
+
+                    // A rotated data key re-encrypts every field so the database is copied again
+                    if changes.bootstrap || changes.data_keys != entry.data_keys {
+                        None
+                    } else {
+                        Some((entry.next, changes))
+                    }
+                }
+            };
+
+            contents.push(match changes {
+                Some((from, changes)) => BackupContents::Changes {
+                    db: db_name.to_string(),
+                    from,
+                    changes,
+                },
+                None => BackupContents::Snapshot(db.snapshot(&db_name, &db_dir).await?),
+            });
+        }
+
+        Ok(contents)
+    }
+
+    async fn apply_backup_restore(
+        &self,
+        chain: &[(BackupManifest, Vec<BackupContents>)],
+        until: Option<TAI64N>,
+    ) -> TuringResult<OpsOutcome> {
+        self.writable()?;
+        let _checkpoint = self.checkpoint.read().await;
+
+        for (manifest, contents) in chain {
+            // Databases are dropped or copied whole at the time of the backup
+            // while every change carries its own time
+            let reached = until.is_none_or(|until| manifest.created <= until);
+
+            if reached {
+                let dropped = self
+                    .dbs
+                    .iter()
+                    .map(|db| db.key().to_string())
+                    .filter(|db_name| manifest.database(db_name).is_none())
+                    .collect::<Vec<String>>();
+
+                for db_name in dropped {
+                    self.apply_replica_drop(Utf8Path::new(&db_name)).await?;
+                }
+            }
+
+            for db_contents in contents {
+                match db_contents {
+                    BackupContents::Snapshot(snapshot) => {
+                        if reached {
+                            self.apply_replication_bootstrap(snapshot).await?;
+                        }
+                    }
+                    BackupContents::Changes { db, from, changes } => {
+                        let mut changes = changes.clone();
+                        if let Some(until) = until {
+                            changes.records.retain(|record| record.timestamp <= until);
+                        }
+
+                        let (_, outcome) = self
+                            .apply_replica_changes(Utf8Path::new(db), *from, &changes)
+                            .await;
+                        outcome?;
+                    }
+                }
+            }
+
+            // The backups after this one only hold changes made after `until`
+            if !reached {
+                break;
+            }
+        }
+
+        Ok(OpsOutcome::RepoRestored)
+    }
+
+    /// Add a database copied from another repo with `replication_snapshot()`,
+    /// which is how a database moves between the nodes of a sharded cluster
+    pub async fn db_import(&self, snapshot: &DbSnapshot) -> TuringResult<OpsOutcome> {
+        let outcome = self.apply_db_import(snapshot).await;
+        let operation = LoggedOperation::DbImport {
+            db: snapshot.name.clone(),
+        };
+        self.record(DEFAULT_ACTOR, operation, &outcome).await;
+
+        outcome
+    }
+
+    async fn apply_db_import(&self, snapshot: &DbSnapshot) -> TuringResult<OpsOutcome> {
+        self.writable()?;
+        let _checkpoint = self.checkpoint.read().await;
+
+        if self.dbs.contains_key(Utf8Path::new(&snapshot.name)) {
+            return Err(TuringDbError::AlreadyExists);
+        }
+
+        self.apply_replication_bootstrap(snapshot).await?;
+
+        Ok(OpsOutcome::DbImported)
+    }
+    /// Write every document of a database with its fields, their timestamps and `DataType` tags
+    /// to `writer`, so they can be moved to another repo with `import()`.
+    /// The values of encrypted documents are written in the clear
+    /// #### Usage
+    /// ```
+    /// let mut file = async_fs::File::create("users.ndjson").await?;
+    /// engine.export(Utf8Path::new("users"), ExportFormat::Ndjson, &mut file).await?;
+    /// ```
+    pub async fn export<W: AsyncWrite + Unpin>(
+        &self,
+        db_name: &Utf8Path,
+        format: ExportFormat,
+        writer: &mut W,
+    ) -> TuringResult<OpsOutcome> {
+        match self.db(db_name) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => Ok(OpsOutcome::DbExported(db.export(format, writer).await?)),
+        }
+    }
+    /// Add the documents and fields written by `export()` to a database,
+    /// creating the database and its documents if they do not exist.
+    /// The whole export is read and checked before anything is written, and the import fails
+    /// without writing anything if the export holds a field twice or a field that already exists.
+    /// The fields of every document are written in a single transaction, so a field written by
+    /// another change during the import only leaves out the documents that were not written yet
+    pub async fn import<R: AsyncRead + Unpin>(
+        &self,
+        db_name: &Utf8Path,
+        format: ExportFormat,
+        reader: R,
+    ) -> TuringResult<OpsOutcome> {
+        let outcome = self.apply_import(db_name, format, reader).await;
+        let operation = LoggedOperation::Import {
+            db: db_name.to_string(),
+            format,
+        };
+        self.record(DEFAULT_ACTOR, operation, &outcome).await;
+
+        outcome
+    }
+
+    async fn apply_import<R: AsyncRead + Unpin>(
+        &self,
+        db_name: &Utf8Path,
+        format: ExportFormat,
+        reader: R,
+    ) -> TuringResult<OpsOutcome> {
+        self.writable()?;
+        let _checkpoint = self.checkpoint.read().await;
+
+        let mut records = Vec::new();
+        let mut fields: BTreeMap<String, Vec<(Vec<u8>, FieldData)>> = BTreeMap::new();
+        let mut keys = BTreeSet::new();
+
+        for record in ExportRecord::read_all(format, reader).await? {
+            match record {
+                ExportRecord::Field {
+                    document,
+                    key,
+                    data_type,
+                    value,
+                    created,
+                    modified,
+                } => {
+                    if !keys.insert((document.clone(), key.0.clone())) {
+                        return Err(TuringDbError::KeyAlreadyExists);
+                    }
+
+                    let field_data =
+                        ExportRecord::field_data(data_type, &value, &created, &modified);
+                    fields
+                        .entry(document)
+                        .or_default()
+                        .push((key.0, field_data));
+                }
+                record => records.push(record),
+            }
+        }
+
+        if let Some(db) = self.db(db_name) {
+            for (document, document_fields) in fields.iter() {
+                db.import_check(Utf8Path::new(document), document_fields)?;
+            }
+        }
+
+        for record in records {
+            match record {
+                ExportRecord::Database { encrypted } => {
+                    if !self.dbs.contains_key(db_name) {
+                        let ops = TuringDBOps::default()
+                            .set_db_name(db_name.as_str())
+                            .set_encrypted(encrypted);
+                        self.create_db(&ops).await?;
+                    }
+                }
+                ExportRecord::Document { name, encrypted } => {
+                    let exists = match self.db(db_name) {
+                        None => return Err(TuringDbError::DbNotFound),
+                        Some(db) => db.list.contains_key(Utf8Path::new(&name)),
+                    };
+
+                    if !exists {
+                        let ops = TuringDBDocumentOps::default()
+                            .set_db_name(db_name.as_str())
+                            .set_document_name(&name)
+                            .set_encrypted(encrypted);
+                        self.create_document(&ops).await?;
+                    }
+                }
+                ExportRecord::Index {
+                    document,
+                    definition,
+                } => {
+                    let created = match self.db(db_name) {
+                        None => return Err(TuringDbError::DbNotFound),
+                        Some(db) => db.index_create(Utf8Path::new(&document), &definition).await,
+                    };
+
+                    match created {
+                        Ok(_) | Err(TuringDbError::IndexAlreadyExists) => (),
+                        Err(error) => return Err(error),
+                    }
+                }
+                ExportRecord::Field { .. } => (),
+            }
+        }
+
+        let mut imported = 0;
+        for (document, document_fields) in fields {
+            imported += match self.db(db_name) {
+                None => return Err(TuringDbError::DbNotFound),
+                Some(db) => {
+                    db.import_fields(Utf8Path::new(&document), &document_fields)
+                        .await?
+                }
+            };
+        }
+
+        Ok(OpsOutcome::FieldsImported(imported))
+    }
+    /// Write many fields of a database at once for a fast initial ingestion.
+    /// Records are written with sled transactions and the documents are not flushed,
+    /// so call `db_flush()` once the last records of a load were written.
+    /// Records that cannot be written are rejected without failing the others
+    /// and reported in the `OpsOutcome::BulkInserted` progress
+    /// #### Usage
+    /// ```
+    /// let options = BulkOptions::new().check_existing(false);
+    /// let mut progress = BulkProgress::default();
+    ///
+    /// for records in chunks {
+    ///     if let OpsOutcome::BulkInserted(chunk) = engine.bulk_insert(db, &records, &options).await? {
+    ///         progress.extend(chunk);
+    ///     }
+    /// }
+    /// engine.db_flush(db).await?;
+    /// ```
+    pub async fn bulk_insert(
+        &self,
+        db_name: &Utf8Path,
+        records: &[BulkRecord],
+        options: &BulkOptions,
+    ) -> TuringResult<OpsOutcome> {
+        let outcome = self.apply_bulk_insert(db_name, records, options).await;
+        let operation = LoggedOperation::BulkInsert {
+            db: db_name.to_string(),
+            records: records.len() as u64,
+        };
+        self.record(DEFAULT_ACTOR, operation, &outcome).await;
+
+        outcome
+    }
+
+    async fn apply_bulk_insert(
+        &self,
+        db_name: &Utf8Path,
+        records: &[BulkRecord],
+        options: &BulkOptions,
+    ) -> TuringResult<OpsOutcome> {
+        self.writable()?;
+        let _checkpoint = self.checkpoint.read().await;
+
+        match self.db(db_name) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => Ok(OpsOutcome::BulkInserted(
+                db.bulk_insert(records, options).await?,
+            )),
+        }
+    }
+    /// Write the records of a stream with `bulk_insert()`, `batch_size` records at a time,
+    /// so a load larger than memory only ever holds one batch of it.
+    /// The progress covers the whole stream, the index of a rejection being its position in the stream
+    /// #### Usage
+    /// ```
+    /// let records = futures_lite::stream::iter(lines.map(|line| parse_record(&line)));
+    ///
+    /// if let OpsOutcome::BulkInserted(progress) = engine.bulk_insert_stream(db, records, &options).await? {
+    ///     println!("{} of {} records written", progress.inserted, progress.received);
+    /// }
+    /// engine.db_flush(db).await?;
+    /// ```
+    pub async fn bulk_insert_stream<S: Stream<Item = BulkRecord> + Unpin>(
+        &self,
+        db_name: &Utf8Path,
+        mut records: S,
+        options: &BulkOptions,
+    ) -> TuringResult<OpsOutcome> {
+        let mut progress = BulkProgress::default();
+        let mut chunk = Vec::with_capacity(options.get_batch_size());
+
+        while let Some(record) = records.next().await {
+            chunk.push(record);
+
+            if chunk.len() == options.get_batch_size() {
+                self.bulk_insert_chunk(db_name, &mut chunk, options, &mut progress)
+                    .await?;
+            }
+        }
+
+        if !chunk.is_empty() {
+            self.bulk_insert_chunk(db_name, &mut chunk, options, &mut progress)
+                .await?;
+        }
+
+        Ok(OpsOutcome::BulkInserted(progress))
+    }
+    /// Write a chunk of a stream of records, leaving the chunk empty for the next records
+    async fn bulk_insert_chunk(
+        &self,
+        db_name: &Utf8Path,
+        chunk: &mut Vec<BulkRecord>,
+        options: &BulkOptions,
+        progress: &mut BulkProgress,
+    ) -> TuringResult<()> {
+        if let OpsOutcome::BulkInserted(next) = self.bulk_insert(db_name, chunk, options).await? {
+            progress.extend(next);
+        }
+        chunk.clear();
+
+        Ok(())
+    }
+    /// Flush every document of a database to disk
+    pub async fn db_flush(&self, db_name: &Utf8Path) -> TuringResult<OpsOutcome> {
+        match self.db(db_name) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => {
+                db.flush().await?;
+
+                Ok(OpsOutcome::DbFlushed)
+            }
+        }
+    }
+
+    async fn apply_replication_bootstrap(&self, snapshot: &DbSnapshot) -> TuringResult<OpsOutcome> {
+        let db_name = Utf8PathBuf::from(&snapshot.name);
+        let db_dir = self.repo_dir.join(&db_name);
+
+        let master_key = self.master_key().await;
+        if snapshot.data_keys.is_some() && master_key.is_none() {
+            return Err(TuringDbError::EncryptionKeyMissing);
+        }
+
+        // Close the documents before their files are replaced
+        self.dbs.remove(&db_name);
+        match async_fs::remove_dir_all(&db_dir).await {
+            Ok(_) => (),
+            Err(error) if error.kind() == ErrorKind::NotFound => (),
+            Err(error) => return Err(error.into()),
+        }
+
+        let mut new_db = TuringDB::new()
+            .with_name(&db_name)
+            .with_integrity(IntegrityManifest::new(&self.integrity_key, &db_name))
+            .with_cdc(CdcLog::new(&db_dir, self.cdc_retention))
+            .with_history(self.history)
+            .with_encryption(snapshot.encrypted);
+
+        new_db
+            .restore(&db_dir, snapshot, master_key.as_ref())
+            .await?;
+
+        self.dbs.insert(db_name, Arc::new(new_db));
+        self.write_manifest().await?;
+
+        Ok(OpsOutcome::ReplicaBootstrapped)
+    }
+    /// Apply the changes from `position` on, returning the position after the last change applied
+    async fn apply_replica_changes(
+        &self,
+        db_name: &Utf8Path,
+        position: u64,
+        changes: &DbChanges,
+    ) -> (u64, TuringResult<OpsOutcome>) {
+        let mut applied = position;
+
+        // A database the replica does not hold is bootstrapped even if the leader has no new changes
+        if !self.dbs.contains_key(db_name) {
+            return (applied, Err(TuringDbError::DbNotFound));
+        }
+
+        let mut outcome = Ok(OpsOutcome::ReplicaApplied);
+
+        let first = changes
+            .records
+            .iter()
+            .position(|record| record.sequence >= applied)
+            .unwrap_or(changes.records.len());
+        let records = &changes.records[first..];
+        let end = records.last().map(|record| record.sequence);
+
+        // Consecutive changes to the fields of a document are applied together,
+        // so fields the leader traded the values of a unique index between are written at once
+        let runs = records.chunk_by(|previous, record| {
+            previous.document == record.document
+                && matches!(previous.change, CdcChange::Field { .. })
+                && matches!(record.change, CdcChange::Field { .. })
+        });
+
+        for run in runs {
+            let last = run[run.len() - 1].sequence;
+
+            // Only the creation and removal of documents take the database exclusively,
+            // the reads served by the replica go on while the other changes are applied
+            let replayed = match run[0].change {
+                CdcChange::DocumentCreate | CdcChange::DocumentDrop => match self.db(db_name) {
+                    None => Err(TuringDbError::DbNotFound),
+                    Some(db) => db.replay(&self.repo_dir, db_name, &run[0]).await,
+                },
+                CdcChange::Field { .. } => match self.db(db_name) {
+                    None => Err(TuringDbError::DbNotFound),
+                    Some(db) => match db.replay_fields(run, true).await {
+                        // The last changes of a batch can end in the middle of a write of the leader
+                        // whose remaining changes restore the unique indexes
+                        Err(TuringDbError::UniqueViolation { .. })
+                            if Some(last) == end && last + 1 < changes.next =>
+                        {
+                            db.replay_fields(run, false).await
+                        }
+                        replayed => replayed,
+                    },
+                },
+                CdcChange::IndexCreate(_) | CdcChange::IndexDrop { .. } => match self.db(db_name) {
+                    None => Err(TuringDbError::DbNotFound),
+                    Some(db) => db.replay_index(&run[0]).await,
+                },
+            };
+
+            if let Err(error) = replayed {
+                outcome = Err(error);
+                break;
+            }
+
+            applied = last + 1;
+        }
+
+        if applied > position {
+            if let Err(error) = self.write_manifest().await {
+                return (applied, Err(error));
+            }
+        }
+
+        (applied, outcome)
+    }
+
+    async fn apply_replica_drop(&self, db_name: &Utf8Path) -> TuringResult<OpsOutcome> {
+        self.dbs.remove(db_name);
+
+        match async_fs::remove_dir_all(self.repo_dir.join(db_name)).await {
+            Ok(_) => (),
+            Err(error) if error.kind() == ErrorKind::NotFound => (),
+            Err(error) => return Err(error.into()),
+        }
+
+        self.write_manifest().await?;
+
+        Ok(OpsOutcome::ReplicaDropped)
+    }
+
+    /// Read the key derivation parameters of the repo or generate new ones for a new repo
+    async fn load_key_derivation(repo_dir: &Utf8Path) -> TuringResult<KeyDerivation> {
+        match async_fs::read(repo_dir.join(KEY_DERIVATION_FILE)).await {
+            Ok(value) => Ok(KeyDerivation::from_bytes(&value)?),
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                let key_derivation = KeyDerivation::generate(CipherKind::XChaCha20Poly1305)?;
+
+                // A new repo stores the parameters once `repo_create` creates its directory
+                if async_fs::metadata(repo_dir).await.is_ok() {
+                    RepoPath::replace(
+                        &repo_dir.join(KEY_DERIVATION_FILE),
+                        key_derivation.to_bytes()?,
+                    )
+                    .await?;
+                }
+
+                Ok(key_derivation)
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    fn replica(&self) -> TuringResult<&ReplicaState> {
+        match &self.replica {
+            None => Err(TuringDbError::NotReplica),
+            Some(replica) => Ok(replica),
+        }
+    }
+    async fn master_key(&self) -> Option<Cipher> {
+        self.master_key.read().await.clone()
+    }
+    /// Only the changes of the leader are applied to a replica,
+    /// and nothing is changed once the audit log cannot record it
+    fn writable(&self) -> TuringResult<()> {
+        if let Some(failure) = self.audit_log.failure() {
+            return Err(failure);
+        }
+
+        match &self.replica {
+            None => Ok(()),
+            Some(_) => Err(TuringDbError::ReadOnlyReplica),
+        }
+    }
+
+    /// Record a mutation in the ops.log and the audit log
+    async fn record(
+        &self,
+        actor: &str,
+        operation: LoggedOperation,
+        outcome: &TuringResult<OpsOutcome>,
+    ) {
+        self.ops_log.record(actor, operation.clone(), outcome).await;
+        self.audit_log.record(actor, operation, outcome).await;
+    }
+
+    async fn write_manifest(&self) -> TuringResult<()> {
+        let manifest = RepoManifest::new(&self.integrity_key, &self.dbs);
+        async_fs::write(self.repo_dir.join(REPO_MANIFEST_FILE), manifest.to_bytes()?).await?;
+
+        Ok(())
+    }
+
+    async fn write_key_derivation(&self, key_derivation: &KeyDerivation) -> TuringResult<()> {
+        RepoPath::replace(
+            &self.repo_dir.join(KEY_DERIVATION_FILE),
+            key_derivation.to_bytes()?,
+        )
+        .await
+    }
+
+    fn to_utf8_path(value: OsString) -> TuringResult<Utf8PathBuf> {
+        match std::path::PathBuf::from(value).to_str() {
+            None => Err(TuringDbError::PathReadIsNotUtf8Path),
+            Some(path) => Ok(Utf8Path::new(path).to_path_buf()),
+        }
+    }
+}
+
+/*//TODO
+//---------
+/// Read a repo
+
+pub async fn repo_init(&mut self) -> Result<OpsOutcome, TuringDbError> {
+    let mut repo = match async_fs::read_dir(&self.repo_dir).await {
+        Ok(value) => value,
+        Err(e) => {
+            if e.kind() == ErrorKind::NotFound {
+                return Ok(self);
+            } else {
+                return Err(anyhow::Error::new(e));
+            }
+        }
+    };
+
+    while let Some(database_entry) = repo.try_next().await? {
+        let database_name = database_entry.file_name();
+
+        if database_entry.file_type().await?.is_dir() {
+            let mut repo = async_fs::read_dir(&database_entry.path()).await?;
+            let mut current_db = Tdb::new();
+
+            while let Some(document_entry) = repo.try_next().await? {
+                let mut field_keys = Vec::new();
+
+                if document_entry.file_type().await?.is_dir() {
+                    let document_name = document_entry.file_name();
+                    let db = sled::open(document_entry.path())?;
+
+                    for field_key in db.into_iter().keys() {
+                        field_keys.push(field_key?);
+                    }
+
+                    let data = field_keys.iter().map(|inner| inner.to_vec()).collect();
+
+                    current_db.list.insert(
+                        document_name,
+                        Document {
+                            fd: Mutex::new(db),
+                            keys: data,
+                        },
+                    );
+                }
+            }
+            self.dbs.insert(database_name, current_db);
+        }
+    }
+
+    Ok(self)
+}*/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{t_engine::testing::*, Document};
+    use futures_lite::future::{self, block_on, Future};
+
+    const OLD_PASSPHRASE: &str = "old passphrase";
+    const NEW_PASSPHRASE: &str = "new passphrase";
+
+    async fn open(repo: &Utf8Path, passphrase: &str) -> TuringResult<TuringEngine> {
+        let mut engine = TuringEngine::builder()
+            .repo_dir(repo.to_path_buf())
+            .passphrase(Secret::new(passphrase.to_owned()))
+            .build()
+            .await?;
+        engine.repo_init().await?;
+
+        Ok(engine)
+    }
+    /// A repo protected by `OLD_PASSPHRASE` whose database `DB` is encrypted and holds the field `alice`
+    async fn encrypted_engine(repo: &Utf8Path) -> TuringEngine {
+        let engine = TuringEngine::builder()
+            .repo_dir(repo.to_path_buf())
+            .passphrase(Secret::new(OLD_PASSPHRASE.to_owned()))
+            .build()
+            .await
+            .unwrap();
+        engine.repo_create().await.unwrap();
+
+        engine
+            .db_create(TuringDBOps::default().set_db_name(DB).set_encrypted(true))
+            .await
+            .unwrap();
+        engine.document_create(&document_ops()).await.unwrap();
+        field_set(&engine, "alice", "admin").await.unwrap();
+
+        engine
+    }
+    /// Stage a change to `NEW_PASSPHRASE` as a crash would leave it, committed or not
+    async fn interrupted_change(engine: &TuringEngine, committed: bool) -> Vec<String> {
+        let key_derivation = KeyDerivation::generate(CipherKind::XChaCha20Poly1305).unwrap();
+        let master_key = key_derivation
+            .derive(&Secret::new(NEW_PASSPHRASE.to_owned()))
+            .unwrap();
+
+        let staged = engine
+            .stage_master_key(&master_key, Some(&key_derivation))
+            .await
+            .unwrap();
+        if committed {
+            engine.commit_master_key_change(&staged).await.unwrap();
+        }
+
+        staged
+    }
+
+    /// The document `DOCUMENT` as it is stored, bypassing the engine
+    fn stored_document(engine: &TuringEngine) -> Document {
+        engine
+            .db(Utf8Path::new(DB))
+            .unwrap()
+            .document(Utf8Path::new(DOCUMENT))
+            .unwrap()
+    }
+    /// Wait for the re-encryption of `DB` to finish, returning why it stopped if it did not
+    fn wait_for_reencryption(engine: &TuringEngine) -> Option<TuringDbError> {
+        loop {
+            let failure = engine.data_key_rotation_failure(Utf8Path::new(DB)).unwrap();
+            let rotating = engine
+                .dbs
+                .get(Utf8Path::new(DB))
+                .unwrap()
+                .sealer
+                .is_rotating()
+                .unwrap();
+
+            if failure.is_some() || !rotating {
+                return failure;
+            }
+
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn is_staged(repo: &Utf8Path, key_file: &str) -> bool {
+        RepoPath::staged(&repo.join(key_file)).exists()
+    }
+    /// Whether `operation` is still waiting after 100ms
+    async fn waits<F: Future + Unpin>(operation: &mut F) -> bool {
+        let finished = async {
+            operation.await;
+            false
+        };
+        let waiting = async {
+            Timer::new(Duration::from_millis(100)).await;
+            true
+        };
+
+        future::or(finished, waiting).await
+    }
+
+    #[test]
+    fn a_changed_passphrase_opens_the_repo_after_a_restart() {
+        block_on(async {
+            let dir = TestDir::new("passphrase-changed");
+            let repo = dir.path().join("repo");
+            let engine = encrypted_engine(&repo).await;
+
+            assert_eq!(
+                engine
+                    .change_passphrase(Secret::new(NEW_PASSPHRASE.to_owned()))
+                    .await,
+                Ok(OpsOutcome::MasterKeyRotated)
+            );
+            field_set(&engine, "bob", "guest").await.unwrap();
+            drop(engine);
+
+            assert!(open(&repo, OLD_PASSPHRASE).await.is_err());
+
+            let engine = open(&repo, NEW_PASSPHRASE).await.unwrap();
+            assert_eq!(field_value(&engine, "alice").await, Some(b"admin".to_vec()));
+            assert_eq!(field_value(&engine, "bob").await, Some(b"guest".to_vec()));
+            assert!(!repo.join(MASTER_KEY_CHANGE_FILE).exists());
+        })
+    }
+
+    #[test]
+    fn a_change_interrupted_before_its_marker_is_rolled_back_on_restart() {
+        block_on(async {
+            let dir = TestDir::new("passphrase-rolled-back");
+            let repo = dir.path().join("repo");
+            let engine = encrypted_engine(&repo).await;
+
+            let staged = interrupted_change(&engine, false).await;
+            assert!(staged.iter().all(|key_file| is_staged(&repo, key_file)));
+            drop(engine);
+
+            let engine = open(&repo, OLD_PASSPHRASE).await.unwrap();
+            assert_eq!(field_value(&engine, "alice").await, Some(b"admin".to_vec()));
+            assert!(staged.iter().all(|key_file| !is_staged(&repo, key_file)));
+            drop(engine);
+
+            assert!(open(&repo, NEW_PASSPHRASE).await.is_err());
+        })
+    }
+
+    #[test]
+    fn a_change_interrupted_after_its_marker_is_finished_on_restart() {
+        block_on(async {
+            let dir = TestDir::new("passphrase-finished");
+            let repo = dir.path().join("repo");
+            let engine = encrypted_engine(&repo).await;
+
+            let staged = interrupted_change(&engine, true).await;
+            drop(engine);
+
+            // The crash happened once the integrity key was moved in place
+            let integrity_key = repo.join(INTEGRITY_KEY_FILE);
+            std::fs::rename(RepoPath::staged(&integrity_key), &integrity_key).unwrap();
+
+            assert!(open(&repo, OLD_PASSPHRASE).await.is_err());
+
+            let engine = open(&repo, NEW_PASSPHRASE).await.unwrap();
+            assert_eq!(field_value(&engine, "alice").await, Some(b"admin".to_vec()));
+            assert!(staged.iter().all(|key_file| !is_staged(&repo, key_file)));
+            assert!(!repo.join(MASTER_KEY_CHANGE_FILE).exists());
+        })
+    }
+
+    #[test]
+    fn a_rotated_data_key_reseals_every_field_and_its_hash() {
+        block_on(async {
+            let dir = TestDir::new("data-key-rotated");
+            let repo = dir.path().join("repo");
+            let engine = encrypted_engine(&repo).await;
+            field_set(&engine, "bob", "guest").await.unwrap();
+
+            assert_eq!(
+                engine
+                    .rotate_data_key(&TuringDBOps::default().set_db_name(DB))
+                    .await,
+                Ok(OpsOutcome::DataKeyRotationStarted)
+            );
+            assert_eq!(wait_for_reencryption(&engine), None);
+
+            let sealer = engine.dbs.get(Utf8Path::new(DB)).unwrap().sealer.clone();
+            for field in stored_document(&engine).iter() {
+                let (key, value) = field.unwrap();
+                let (_, is_current) = sealer.open(Utf8Path::new(DOCUMENT), &key, &value).unwrap();
+                assert!(is_current);
+            }
+
+            assert_eq!(field_value(&engine, "alice").await, Some(b"admin".to_vec()));
+            assert_eq!(field_value(&engine, "bob").await, Some(b"guest".to_vec()));
+            assert_eq!(engine.repo_verify().await, Ok(OpsOutcome::RepoVerified));
+        })
+    }
+
+    #[test]
+    fn a_stopped_reencryption_is_reported_until_it_is_retried() {
+        block_on(async {
+            let dir = TestDir::new("data-key-stopped");
+            let repo = dir.path().join("repo");
+            let engine = encrypted_engine(&repo).await;
+            let rotate = TuringDBOps::default().set_db_name(DB);
+
+            // A field that cannot be opened stops the re-encryption
+            stored_document(&engine)
+                .insert("broken", b"broken".to_vec())
+                .unwrap();
+            engine.rotate_data_key(&rotate).await.unwrap();
+            assert!(wait_for_reencryption(&engine).is_some());
+            assert_eq!(field_value(&engine, "alice").await, Some(b"admin".to_vec()));
+
+            stored_document(&engine).remove("broken").unwrap();
+            assert_eq!(
+                engine.rotate_data_key(&rotate).await,
+                Ok(OpsOutcome::DataKeyRotationStarted)
+            );
+            assert_eq!(wait_for_reencryption(&engine), None);
+            assert_eq!(field_value(&engine, "alice").await, Some(b"admin".to_vec()));
+        })
+    }
+
+    #[test]
+    fn databases_and_documents_are_not_changed_while_the_checkpoint_is_held() {
+        block_on(async {
+            let dir = TestDir::new("engine-checkpoint");
+            let engine = test_engine(&dir.path().join("repo"), true).await;
+            let document = document_ops();
+            let other = TuringDBDocumentOps::default()
+                .set_db_name(DB)
+                .set_document_name("other");
+
+            let checkpoint = engine.checkpoint.write().await;
+
+            let mut db_create =
+                Box::pin(engine.db_create(TuringDBOps::default().set_db_name("created")));
+            let mut document_create = Box::pin(engine.document_create(&other));
+            let mut document_drop = Box::pin(engine.document_drop(&document));
+            let mut history_compact = Box::pin(engine.history_compact());
+            let mut import =
+                Box::pin(engine.import(Utf8Path::new(DB), ExportFormat::Ndjson, &b""[..]));
+            assert!(waits(&mut db_create).await);
+            assert!(waits(&mut document_create).await);
+            assert!(waits(&mut document_drop).await);
+            assert!(waits(&mut history_compact).await);
+            assert!(waits(&mut import).await);
+            assert!(!engine.dbs.contains_key(Utf8Path::new("created")));
+
+            drop(checkpoint);
+
+            assert_eq!(db_create.await.unwrap(), OpsOutcome::DbCreated);
+            assert_eq!(document_create.await.unwrap(), OpsOutcome::DocumentCreated);
+            assert_eq!(document_drop.await.unwrap(), OpsOutcome::DocumentDropped);
+            assert_eq!(
+                history_compact.await.unwrap(),
+                OpsOutcome::HistoryCompacted(0)
+            );
+            assert_eq!(import.await.unwrap(), OpsOutcome::FieldsImported(0));
+
+            let checkpoint = engine.checkpoint.write().await;
+            let mut db_drop = Box::pin(engine.db_drop(TuringDBOps::default().set_db_name(DB)));
+            assert!(waits(&mut db_drop).await);
+            assert!(engine.dbs.contains_key(Utf8Path::new(DB)));
+
+            drop(checkpoint);
+            assert_eq!(db_drop.await.unwrap(), OpsOutcome::DbDropped);
+        })
+    }
+
+    #[test]
+    fn the_repo_is_read_while_a_document_create_waits_for_the_cdc_log() {
+        block_on(async {
+            let dir = TestDir::new("engine-document-create");
+            let engine = test_engine(&dir.path().join("repo"), false).await;
+            field_set(&engine, "alice", "admin").await.unwrap();
+            let other = TuringDBDocumentOps::default()
+                .set_db_name(DB)
+                .set_document_name("other");
+
+            let db = engine.db(Utf8Path::new(DB)).unwrap();
+            let capture = db.cdc_capture().await.unwrap();
+
+            let mut document_create = Box::pin(engine.document_create(&other));
+            assert!(waits(&mut document_create).await);
+
+            assert_eq!(
+                engine.document_list_sorted(&TuringDBOps::default().set_db_name(DB)),
+                Ok(OpsOutcome::DocumentList(vec![
+                    Utf8PathBuf::from(DOCUMENT),
+                    Utf8PathBuf::from("other")
+                ]))
+            );
+            assert_eq!(field_value(&engine, "alice").await, Some(b"admin".to_vec()));
+
+            drop(capture);
+            assert_eq!(document_create.await.unwrap(), OpsOutcome::DocumentCreated);
+        })
+    }
+}
@@ -0,0 +1,255 @@
+use crate::{ChangeKind, Document, FieldWrite, TuringDB, TuringDbError, TuringResult};
+use camino::Utf8Path;
+use sled::IVec;
+use std::time::Duration;
+use tai64::TAI64N;
+
+/// The sled tree of a document holding the time every field with a time-to-live expires
+pub(crate) const EXPIRY_TREE: &str = "expiry";
+
+/// The time a field given a time-to-live of `ttl` now expires
+pub(crate) fn expires_after(ttl: Duration) -> TAI64N {
+    TAI64N::now() + ttl
+}
+
+impl TuringDB {
+    /// The time a field expires, `None` if it has no time-to-live
+    pub(crate) fn expires(&self, document: &Document, key: &[u8]) -> TuringResult<Option<TAI64N>> {
+        match document.open_tree(EXPIRY_TREE)?.get(key)? {
+            None => Ok(None),
+            Some(expires) => match TAI64N::from_slice(&expires) {
+                Ok(expires) => Ok(Some(expires)),
+                Err(_) => Err(TuringDbError::Bug("Invalid expiry time of a field".into())),
+            },
+        }
+    }
+    /// Check whether a field outlived its time-to-live.
+    /// Expired fields are hidden from reads until they are reclaimed
+    pub(crate) fn is_expired(&self, document: &Document, key: &[u8]) -> TuringResult<bool> {
+        match self.expires(document, key)? {
+            None => Ok(false),
+            Some(expires) => Ok(expires <= TAI64N::now()),
+        }
+    }
+    /// Set the time a field expires, or clear it with `None` so the field is kept until it is removed
+    pub(crate) fn expiry_set(
+        &self,
+        document: &Document,
+        key: &[u8],
+        expires: Option<TAI64N>,
+    ) -> TuringResult<()> {
+        let expiry = document.open_tree(EXPIRY_TREE)?;
+
+        match expires {
+            None => expiry.remove(key)?,
+            Some(expires) => expiry.insert(key, &expires.to_bytes())?,
+        };
+
+        Ok(())
+    }
+    /// Reclaim a field that outlived its time-to-live before a write to it,
+    /// so the write finds the field missing like a read would
+    pub(crate) async fn expire_if_due(
+        &self,
+        document_name: &Utf8Path,
+        key: &[u8],
+    ) -> TuringResult<()> {
+        if let Some(document) = self.document(document_name) {
+            if self.is_expired(&document, key)? {
+                self.expire_field(document_name, key).await?;
+            }
+        }
+
+        Ok(())
+    }
+    /// Remove an expired field, recording the change as `ChangeKind::Expire`.
+    /// Returns `false` if the field is gone or was given a new time-to-live in the meantime
+    pub(crate) async fn expire_field(
+        &self,
+        document_name: &Utf8Path,
+        key: &[u8],
+    ) -> TuringResult<bool> {
+        let document = match self.document(document_name) {
+            None => return Err(TuringDbError::DocumentNotFound),
+            Some(document) => document,
+        };
+
+        // Every write holds the CDC log so the field cannot change until it is removed
+        let _writing = self.write_lock().await;
+        let mut capture = self.cdc_capture().await?;
+
+        let expires = match self.expires(&document, key)? {
+            Some(expires) if expires <= TAI64N::now() => expires,
+            _ => return Ok(false),
+        };
+
+        let old = match document.get(key)? {
+            None => {
+                self.expiry_set(&document, key, None)?;
+
+                return Ok(false);
+            }
+            Some(old) => old,
+        };
+
+        let write = FieldWrite::new(key, None)
+            .expect(Some(&old))
+            .replaced(expires)
+            .kind(ChangeKind::Expire);
+        // A field that was given a new value in the meantime did not expire
+        if !self.field_swap(document_name, &document, write, capture.as_mut())? {
+            return Ok(false);
+        }
+
+        self.cdc_write(capture).await;
+
+        Ok(true)
+    }
+    /// Remove every field of the database that outlived its time-to-live,
+    /// returning the number of fields removed
+    pub(crate) async fn expiry_sweep(&self) -> TuringResult<u64> {
+        let now = TAI64N::now();
+        let mut expired = 0;
+
+        for (document_name, document) in self.documents() {
+            let mut due: Vec<IVec> = Vec::new();
+
+            for entry in document.open_tree(EXPIRY_TREE)?.iter() {
+                let (key, expires) = entry?;
+
+                match TAI64N::from_slice(&expires) {
+                    Ok(expires) if expires <= now => due.push(key),
+                    Ok(_) => (),
+                    Err(_) => {
+                        return Err(TuringDbError::Bug("Invalid expiry time of a field".into()))
+                    }
+                }
+            }
+
+            for key in due {
+                if self.expire_field(&document_name, &key).await? {
+                    expired += 1;
+                }
+            }
+        }
+
+        Ok(expired)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{t_engine::testing::*, OpsOutcome};
+    use async_io::Timer;
+    use futures_lite::future::{self, block_on};
+
+    #[test]
+    fn an_expired_field_is_hidden_from_reads_then_swept() {
+        block_on(async {
+            let dir = TestDir::new("expiry");
+            let engine = test_engine(&dir.path().join("repo"), false).await;
+
+            engine
+                .field_set(&field_ops("session", "token").ttl(Duration::from_millis(50)))
+                .await
+                .unwrap();
+            field_set(&engine, "user", "alice").await.unwrap();
+            assert_eq!(
+                field_value(&engine, "session").await,
+                Some(b"token".to_vec())
+            );
+
+            std::thread::sleep(Duration::from_millis(100));
+
+            assert_eq!(field_value(&engine, "session").await, None);
+            assert_eq!(
+                engine.field_list(&document_ops()).unwrap(),
+                OpsOutcome::FieldList(vec![b"user".to_vec()])
+            );
+            match engine.field_modify(&field_ops("session", "renewed")).await {
+                Err(TuringDbError::FieldNotFound) => (),
+                outcome => panic!("Unexpected outcome {:?}", outcome),
+            }
+
+            // The modification above already reclaimed the field
+            assert_eq!(
+                engine.expiry_sweep().await.unwrap(),
+                OpsOutcome::FieldsExpired(0)
+            );
+
+            engine
+                .field_set(&field_ops("session", "token").ttl(Duration::from_millis(50)))
+                .await
+                .unwrap();
+            std::thread::sleep(Duration::from_millis(100));
+
+            assert_eq!(
+                engine.expiry_sweep().await.unwrap(),
+                OpsOutcome::FieldsExpired(1)
+            );
+            assert_eq!(
+                engine.expiry_sweep().await.unwrap(),
+                OpsOutcome::FieldsExpired(0)
+            );
+            assert_eq!(field_value(&engine, "session").await, None);
+            assert_eq!(field_value(&engine, "user").await, Some(b"alice".to_vec()));
+        })
+    }
+
+    #[test]
+    fn the_sweeper_reclaims_expired_fields_while_it_is_polled() {
+        block_on(async {
+            let dir = TestDir::new("expiry-sweeper");
+            let engine = test_engine(&dir.path().join("repo"), false).await;
+
+            engine
+                .field_set(&field_ops("session", "token").ttl(Duration::from_millis(50)))
+                .await
+                .unwrap();
+
+            future::or(engine.expiry_sweeper(Duration::from_millis(20)), async {
+                Timer::new(Duration::from_millis(300)).await;
+            })
+            .await;
+
+            assert_eq!(
+                engine.expiry_sweep().await.unwrap(),
+                OpsOutcome::FieldsExpired(0)
+            );
+            assert_eq!(
+                engine.field_list(&document_ops()).unwrap(),
+                OpsOutcome::DocumentEmpty
+            );
+        })
+    }
+
+    #[test]
+    fn a_field_set_again_loses_its_time_to_live() {
+        block_on(async {
+            let dir = TestDir::new("expiry-cleared");
+            let engine = test_engine(&dir.path().join("repo"), false).await;
+
+            engine
+                .field_set(&field_ops("session", "token").ttl(Duration::from_millis(50)))
+                .await
+                .unwrap();
+            engine
+                .field_remove(&field_ops("session", ""))
+                .await
+                .unwrap();
+            field_set(&engine, "session", "kept").await.unwrap();
+
+            std::thread::sleep(Duration::from_millis(100));
+
+            assert_eq!(
+                engine.expiry_sweep().await.unwrap(),
+                OpsOutcome::FieldsExpired(0)
+            );
+            assert_eq!(
+                field_value(&engine, "session").await,
+                Some(b"kept".to_vec())
+            );
+        })
+    }
+}
@@ -0,0 +1,516 @@
+use crate::{
+    DataType, Expiry, FieldData, FieldWrite, IndexDefinition, TDBCell, TuringDB, TuringDbError,
+    TuringResult,
+};
+use camino::Utf8Path;
+use futures_lite::io::{
+    AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{convert::TryFrom, fmt};
+use tai64::TAI64N;
+use zeroize::Zeroizing;
+
+/// The size of the chunks read while importing a CBOR export
+const CBOR_READ_CHUNK: usize = 64 * 1024;
+
+/// The formats written by `TuringEngine::export()` and read by `TuringEngine::import()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExportFormat {
+    /// A JSON object on every line. Field keys and values are written as text when they are
+    /// valid UTF-8 and as base64 otherwise, and timestamps as TAI64N labels
+    Ndjson,
+    /// A sequence of CBOR items with field keys and values as byte strings
+    Cbor,
+}
+
+/// A record of an export. The first record describes the database
+/// and every document comes before its indexes, which come before its fields
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ExportRecord {
+    Database {
+        encrypted: bool,
+    },
+    Document {
+        name: String,
+        encrypted: bool,
+    },
+    Index {
+        document: String,
+        definition: IndexDefinition,
+    },
+    Field {
+        document: String,
+        key: ExportBytes,
+        data_type: DataType,
+        value: ExportBytes,
+        created: ExportTime,
+        modified: ExportTime,
+    },
+}
+
+impl ExportRecord {
+    /// Read the field of a document as a record, with its value opened if the document is encrypted
+    fn field(
+        db: &TuringDB,
+        document_name: &Utf8Path,
+        key: &[u8],
+        value: &[u8],
+    ) -> TuringResult<Self> {
+        let field_data = db.unseal(document_name, key, value)?;
+        let cell = TDBCell::from_bytes(field_data.data())?;
+
+        Ok(ExportRecord::Field {
+            document: document_name.to_string(),
+            key: ExportBytes(key.to_vec()),
+            data_type: cell.get_data_type(),
+            value: ExportBytes(cell.get_data().to_vec()),
+            created: ExportTime(field_data.created()),
+            modified: ExportTime(field_data.modified()),
+        })
+    }
+    /// Write the record in the `format` of the export
+    async fn write<W: AsyncWrite + Unpin>(
+        &self,
+        format: ExportFormat,
+        writer: &mut W,
+    ) -> TuringResult<()> {
+        let bytes = match format {
+            ExportFormat::Ndjson => match serde_json::to_vec(self) {
+                Ok(mut bytes) => {
+                    bytes.push(b'\n');
+                    bytes
+                }
+                Err(_) => return Err(TuringDbError::Bug("Unable to serialize an export".into())),
+            },
+            ExportFormat::Cbor => match serde_cbor::to_vec(self) {
+                Ok(bytes) => bytes,
+                Err(_) => return Err(TuringDbError::Bug("Unable to serialize an export".into())),
+            },
+        };
+
+        writer.write_all(&Zeroizing::new(bytes)).await?;
+
+        Ok(())
+    }
+    /// Read every record of an export in the `format`
+    pub(crate) async fn read_all<R: AsyncRead + Unpin>(
+        format: ExportFormat,
+        reader: R,
+    ) -> TuringResult<Vec<ExportRecord>> {
+        match format {
+            ExportFormat::Ndjson => ExportRecord::read_ndjson(reader).await,
+            ExportFormat::Cbor => ExportRecord::read_cbor(reader).await,
+        }
+    }
+    /// The `FieldData` of a field record, which keeps the timestamps of the export
+    pub(crate) fn field_data(
+        data_type: DataType,
+        value: &ExportBytes,
+        created: &ExportTime,
+        modified: &ExportTime,
+    ) -> FieldData {
+        let cell = TDBCell::new(data_type, &value.0);
+
+        FieldData::with_timestamps(&cell.to_bytes(), created.0, modified.0)
+    }
+
+    async fn read_ndjson<R: AsyncRead + Unpin>(reader: R) -> TuringResult<Vec<ExportRecord>> {
+        let mut lines = BufReader::new(reader).lines();
+        let mut records = Vec::new();
+        let mut line_number = 0;
+
+        while let Some(line) = futures_lite::StreamExt::next(&mut lines).await {
+            let line = line?;
+            line_number += 1;
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            match serde_json::from_str::<ExportRecord>(&line) {
+                Ok(record) => records.push(record),
+                Err(error) => {
+                    return Err(TuringDbError::ImportInvalid {
+                        record: line_number,
+                        error: error.to_string(),
+                    })
+                }
+            }
+        }
+
+        Ok(records)
+    }
+
+    async fn read_cbor<R: AsyncRead + Unpin>(mut reader: R) -> TuringResult<Vec<ExportRecord>> {
+        let mut buffer = Vec::new();
+        let mut chunk = vec![0; CBOR_READ_CHUNK];
+        let mut records = Vec::new();
+
+        loop {
+            let bytes_read = reader.read(&mut chunk).await?;
+            buffer.extend_from_slice(&chunk[..bytes_read]);
+
+            // Decode every complete record and keep the rest for the next chunk
+            let mut stream =
+                serde_cbor::Deserializer::from_slice(&buffer).into_iter::<ExportRecord>();
+
+            loop {
+                match stream.next() {
+                    None => break,
+                    Some(Ok(record)) => records.push(record),
+                    Some(Err(error)) if error.is_eof() && bytes_read > 0 => break,
+                    Some(Err(error)) => {
+                        return Err(TuringDbError::ImportInvalid {
+                            record: records.len() as u64 + 1,
+                            error: error.to_string(),
+                        })
+                    }
+                }
+            }
+
+            let consumed = stream.byte_offset();
+            buffer.drain(..consumed);
+
+            if bytes_read == 0 {
+                return Ok(records);
+            }
+        }
+    }
+}
+
+impl TuringDB {
+    /// Write every document of the database with its fields to `writer`,
+    /// opening the values of encrypted documents. Returns the number of fields written
+    pub(crate) async fn export<W: AsyncWrite + Unpin>(
+        &self,
+        format: ExportFormat,
+        writer: &mut W,
+    ) -> TuringResult<u64> {
+        ExportRecord::Database {
+            encrypted: self.encrypted,
+        }
+        .write(format, writer)
+        .await?;
+
+        let mut exported = 0;
+
+        for (document_name, document) in self.documents() {
+            ExportRecord::Document {
+                name: document_name.to_string(),
+                encrypted: self.encrypted_documents.contains(&document_name),
+            }
+            .write(format, writer)
+            .await?;
+
+            for definition in self.index_definitions(&document)? {
+                ExportRecord::Index {
+                    document: document_name.to_string(),
+                    definition,
+                }
+                .write(format, writer)
+                .await?;
+            }
+
+            for field in document.iter() {
+                let (key, value) = field?;
+
+                // An expired field is no longer part of the database even before it is reclaimed
+                if self.is_expired(&document, &key)? {
+                    continue;
+                }
+
+                ExportRecord::field(self, &document_name, &key, &value)?
+                    .write(format, writer)
+                    .await?;
+                exported += 1;
+            }
+        }
+
+        writer.flush().await?;
+
+        Ok(exported)
+    }
+    /// Fail with `KeyAlreadyExists` if a field of an export is already held by the document,
+    /// ignoring the fields that expired. A document that does not exist holds no field
+    pub(crate) fn import_check(
+        &self,
+        document_name: &Utf8Path,
+        fields: &[(Vec<u8>, FieldData)],
+    ) -> TuringResult<()> {
+        let document = match self.document(document_name) {
+            None => return Ok(()),
+            Some(document) => document,
+        };
+
+        for (key, _) in fields {
+            if document.contains_key(key)? && !self.is_expired(&document, key)? {
+                return Err(TuringDbError::KeyAlreadyExists);
+            }
+        }
+
+        Ok(())
+    }
+    /// Write the fields of an export to a document in a single transaction,
+    /// writing none of them if one of them is already held by the document.
+    /// Returns the number of fields written
+    pub(crate) async fn import_fields(
+        &self,
+        document_name: &Utf8Path,
+        fields: &[(Vec<u8>, FieldData)],
+    ) -> TuringResult<u64> {
+        let document = match self.document(document_name) {
+            None => return Err(TuringDbError::DocumentNotFound),
+            Some(document) => document,
+        };
+
+        for (key, _) in fields {
+            self.expire_if_due(document_name, key).await?;
+        }
+
+        let mut sealed = Vec::with_capacity(fields.len());
+        for (key, field_data) in fields {
+            sealed.push(self.seal(document_name, key, field_data)?);
+        }
+
+        let _writing = self.write_lock().await;
+        let mut capture = self.cdc_capture().await?;
+
+        let writes: Vec<FieldWrite> = fields
+            .iter()
+            .zip(sealed.iter())
+            .map(|((key, _), sealed)| {
+                FieldWrite::new(key, Some(sealed))
+                    .expect(None)
+                    .expiry(Expiry::Clear)
+            })
+            .collect();
+        let written = self.field_apply(document_name, &document, &writes, true, capture.as_mut());
+        self.cdc_write(capture).await;
+
+        match written? {
+            true => Ok(fields.len() as u64),
+            false => Err(TuringDbError::KeyAlreadyExists),
+        }
+    }
+}
+
+/// Bytes written as a byte string in CBOR and, in NDJSON,
+/// as `{"text": ...}` when they are valid UTF-8 or as `{"base64": ...}` otherwise
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct ExportBytes(pub(crate) Vec<u8>);
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ExportText {
+    Text(String),
+    Base64(String),
+}
+
+impl Serialize for ExportBytes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if !serializer.is_human_readable() {
+            return serializer.serialize_bytes(&self.0);
+        }
+
+        match std::str::from_utf8(&self.0) {
+            Ok(text) => ExportText::Text(text.to_owned()).serialize(serializer),
+            Err(_) => ExportText::Base64(base64::encode(&self.0)).serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ExportBytes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if !deserializer.is_human_readable() {
+            return deserializer.deserialize_byte_buf(BytesVisitor);
+        }
+
+        match ExportText::deserialize(deserializer)? {
+            ExportText::Text(text) => Ok(ExportBytes(text.into_bytes())),
+            ExportText::Base64(encoded) => match base64::decode(&encoded) {
+                Ok(bytes) => Ok(ExportBytes(bytes)),
+                Err(error) => Err(de::Error::custom(error)),
+            },
+        }
+    }
+}
+
+struct BytesVisitor;
+
+impl<'de> de::Visitor<'de> for BytesVisitor {
+    type Value = ExportBytes;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a byte string")
+    }
+
+    fn visit_bytes<E: de::Error>(self, value: &[u8]) -> Result<Self::Value, E> {
+        Ok(ExportBytes(value.to_vec()))
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, value: Vec<u8>) -> Result<Self::Value, E> {
+        Ok(ExportBytes(value))
+    }
+}
+
+/// A timestamp written as a byte string in CBOR and as a TAI64N label like
+/// `@4000000060b5c6e21c7a8f40` in NDJSON
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct ExportTime(pub(crate) TAI64N);
+
+impl Serialize for ExportTime {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let bytes = self.0.to_bytes();
+
+        if !serializer.is_human_readable() {
+            return serializer.serialize_bytes(&bytes);
+        }
+
+        let label = bytes
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+
+        serializer.serialize_str(&format!("@{}", label))
+    }
+}
+
+impl<'de> Deserialize<'de> for ExportTime {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bytes = if deserializer.is_human_readable() {
+            let label = String::deserialize(deserializer)?;
+
+            match label.strip_prefix('@') {
+                Some(hex) if hex.len() == 24 && hex.is_ascii() => (0..hex.len())
+                    .step_by(2)
+                    .map(|index| u8::from_str_radix(&hex[index..index + 2], 16))
+                    .collect::<Result<Vec<u8>, _>>()
+                    .map_err(de::Error::custom)?,
+                _ => return Err(de::Error::custom("invalid TAI64N label")),
+            }
+        } else {
+            deserializer.deserialize_byte_buf(BytesVisitor)?.0
+        };
+
+        match TAI64N::try_from(bytes.as_slice()) {
+            Ok(timestamp) => Ok(ExportTime(timestamp)),
+            Err(_) => Err(de::Error::custom("invalid TAI64N timestamp")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{t_engine::testing::*, OpsOutcome, TuringDBDocumentOps, TuringDBOps, TuringEngine};
+    use camino::Utf8PathBuf;
+    use futures_lite::future::block_on;
+
+    /// A repo whose database `DB` holds the field `alice` in `DOCUMENT` and the field `bob` in `other`
+    async fn exported_engine(dir: &TestDir) -> TuringEngine {
+        let engine = test_engine(&dir.path().join("exported"), false).await;
+        field_set(&engine, "alice", "admin").await.unwrap();
+
+        let other = TuringDBDocumentOps::default()
+            .set_db_name(DB)
+            .set_document_name("other");
+        engine.document_create(&other).await.unwrap();
+        engine
+            .field_set(&field_ops("bob", "user").document("other"))
+            .await
+            .unwrap();
+
+        engine
+    }
+
+    async fn export(engine: &TuringEngine, format: ExportFormat) -> Vec<u8> {
+        let mut exported = Vec::new();
+        engine
+            .export(Utf8Path::new(DB), format, &mut exported)
+            .await
+            .unwrap();
+
+        exported
+    }
+
+    fn documents(engine: &TuringEngine) -> Vec<Utf8PathBuf> {
+        match engine.document_list_sorted(&TuringDBOps::default().set_db_name(DB)) {
+            Ok(OpsOutcome::DocumentList(documents)) => documents,
+            outcome => panic!("Unexpected outcome {:?}", outcome),
+        }
+    }
+
+    #[test]
+    fn an_export_is_imported_into_another_repo_in_both_formats() {
+        block_on(async {
+            let dir = TestDir::new("export-round-trip");
+            let exported = exported_engine(&dir).await;
+
+            for format in [ExportFormat::Ndjson, ExportFormat::Cbor] {
+                let records = export(&exported, format).await;
+
+                let repo = dir.path().join(format!("{:?}", format));
+                let mut engine = TuringEngine::builder()
+                    .repo_dir(repo)
+                    .build()
+                    .await
+                    .unwrap();
+                engine.repo_create().await.unwrap();
+                engine.repo_init().await.unwrap();
+
+                assert_eq!(
+                    engine
+                        .import(Utf8Path::new(DB), format, records.as_slice())
+                        .await,
+                    Ok(OpsOutcome::FieldsImported(2))
+                );
+                assert_eq!(
+                    documents(&engine),
+                    vec![Utf8PathBuf::from(DOCUMENT), Utf8PathBuf::from("other")]
+                );
+                assert_eq!(field_value(&engine, "alice").await, Some(b"admin".to_vec()));
+                assert_eq!(
+                    document_value(&engine, "other", "bob").await,
+                    Some(b"user".to_vec())
+                );
+                assert_eq!(engine.repo_verify().await, Ok(OpsOutcome::RepoVerified));
+            }
+        })
+    }
+
+    #[test]
+    fn an_import_holding_an_existing_field_writes_nothing() {
+        block_on(async {
+            let dir = TestDir::new("export-conflict");
+            let exported = exported_engine(&dir).await;
+            let records = export(&exported, ExportFormat::Ndjson).await;
+
+            let engine = test_engine(&dir.path().join("imported"), false).await;
+            field_set(&engine, "alice", "guest").await.unwrap();
+
+            assert_eq!(
+                engine
+                    .import(Utf8Path::new(DB), ExportFormat::Ndjson, records.as_slice())
+                    .await,
+                Err(TuringDbError::KeyAlreadyExists)
+            );
+            assert_eq!(documents(&engine), vec![Utf8PathBuf::from(DOCUMENT)]);
+            assert_eq!(field_value(&engine, "alice").await, Some(b"guest".to_vec()));
+
+            // The same field twice in an export is a conflict as well
+            let mut twice = records.clone();
+            twice.extend_from_slice(&records);
+            let engine = test_engine(&dir.path().join("twice"), false).await;
+
+            assert_eq!(
+                engine
+                    .import(Utf8Path::new(DB), ExportFormat::Ndjson, twice.as_slice())
+                    .await,
+                Err(TuringDbError::KeyAlreadyExists)
+            );
+            assert_eq!(field_value(&engine, "alice").await, None);
+        })
+    }
+}
@@ -1,24 +1,27 @@
+use crate::{TuringDbError, TuringResult};
 use serde::{Deserialize, Serialize};
 use tai64::TAI64N;
+use zeroize::Zeroize;
 
 /// Contains the structure of a value represented by a key
 ///
-/// `Warning:` This is serialized using bincode so deserialization should be done using same version of bincode
+/// `Warning:` This is serialized using bincode so deserialization should be done using same version of bincode.
+/// The data is wiped from memory when the `FieldData` is dropped or updated
 /// ```
-/// #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+/// #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Serialize, Deserialize)]
 /// pub struct FieldData {
 ///     data: Vec<u8>,
 ///     created: TAI64N,
 ///     modified: TAI64N,
 /// }
 /// ```
-#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Serialize, Deserialize)]
 pub struct FieldData {
     data: Vec<u8>,
     created: TAI64N,
     modified: TAI64N,
 }
-/*
+
 impl FieldData {
     /// Initializes a new `FieldData` struct
     pub fn new(value: &[u8]) -> FieldData {
@@ -32,13 +35,63 @@ impl FieldData {
     }
     /// Updates a `FieldData` by modifying its time with a new `TAI64N` timestamp
     pub fn update(&mut self, value: &[u8]) -> &FieldData {
+        self.data.zeroize();
         self.data = value.into();
         self.modified = TAI64N::now();
 
         self
     }
+    /// Initializes a `FieldData` with the timestamps it had in another repo
+    pub(crate) fn with_timestamps(value: &[u8], created: TAI64N, modified: TAI64N) -> FieldData {
+        Self {
+            data: value.into(),
+            created,
+            modified,
+        }
+    }
+    /// Replace the data held by the field without changing its timestamps
+    pub(crate) fn with_data(&self, value: &[u8]) -> FieldData {
+        Self {
+            data: value.into(),
+            created: self.created,
+            modified: self.modified,
+        }
+    }
+    /// Get the data held by the field
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+    /// The time the field was first inserted
+    pub fn created(&self) -> TAI64N {
+        self.created
+    }
+    /// The time the field was last modified
+    pub fn modified(&self) -> TAI64N {
+        self.modified
+    }
+    /// Serialize the `FieldData` into the bytes stored in a sled document
+    pub fn to_bytes(&self) -> TuringResult<Vec<u8>> {
+        match bincode::serialize::<FieldData>(self) {
+            Ok(bytes) => Ok(bytes),
+            Err(_) => Err(TuringDbError::FieldDataCorrupted),
+        }
+    }
+    /// Deserialize the bytes stored in a sled document into a `FieldData`
+    pub fn from_bytes(value: &[u8]) -> TuringResult<FieldData> {
+        match bincode::deserialize::<FieldData>(value) {
+            Ok(field_data) => Ok(field_data),
+            Err(_) => Err(TuringDbError::FieldDataCorrupted),
+        }
+    }
 }
 
+impl Drop for FieldData {
+    fn drop(&mut self) {
+        self.data.zeroize();
+    }
+}
+/*
+
     /// List all fields in a document
     pub async fn field_list(&self, db_name: &Path, doc_name: &Path) -> DbOps {
         if self.dbs.is_empty() {
@@ -0,0 +1,641 @@
+use crate::{
+    Document, FieldData, FieldSealer, FieldWrite, OpsOutcome, TuringDB, TuringDbError, TuringResult,
+};
+use camino::Utf8Path;
+use serde::{Deserialize, Serialize};
+use sled::{
+    transaction::{ConflictableTransactionError, ConflictableTransactionResult, TransactionalTree},
+    IVec,
+};
+use std::{collections::BTreeSet, convert::TryInto, time::Duration};
+use tai64::TAI64N;
+use zeroize::Zeroizing;
+
+/// The sled tree of a document holding the previous versions of its fields
+pub(crate) const HISTORY_TREE: &str = "history";
+/// The sled tree of a document holding its `HistoryRetention`
+const RETENTION_TREE: &str = "history_retention";
+const RETENTION_KEY: &[u8] = b"retention";
+
+/// How many previous versions of every field of a document are kept by `history_compact()`.
+/// A version is dropped once it is not one of the newest `max_versions` versions of its field
+/// or once it was replaced more than `max_age` ago. The default keeps every version
+/// ```
+/// #[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+/// pub struct HistoryRetention {
+///     max_versions: Option<usize>,
+///     max_age: Option<Duration>,
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct HistoryRetention {
+    max_versions: Option<usize>,
+    max_age: Option<Duration>,
+}
+
+impl HistoryRetention {
+    /// Keep every previous version
+    pub fn new() -> Self {
+        HistoryRetention::default()
+    }
+    /// Keep the newest `max_versions` previous versions of every field, `None` keeps any number
+    pub fn max_versions(mut self, max_versions: Option<usize>) -> Self {
+        self.max_versions = max_versions;
+
+        self
+    }
+    /// Drop the versions replaced more than `max_age` ago, `None` keeps versions of any age
+    pub fn max_age(mut self, max_age: Option<Duration>) -> Self {
+        self.max_age = max_age;
+
+        self
+    }
+
+    fn keeps_all(&self) -> bool {
+        self.max_versions.is_none() && self.max_age.is_none()
+    }
+    /// Check whether the version at `position`, counted from the newest, is kept at the time `now`
+    fn keeps(&self, position: usize, version: &FieldVersion, now: TAI64N) -> bool {
+        let too_many = match self.max_versions {
+            Some(max_versions) => position >= max_versions,
+            None => false,
+        };
+        let too_old = match (self.max_age, now.duration_since(&version.replaced)) {
+            (Some(max_age), Ok(age)) => age > max_age,
+            _ => false,
+        };
+
+        !too_many && !too_old
+    }
+
+    fn to_bytes(self) -> TuringResult<Vec<u8>> {
+        match bincode::serialize::<HistoryRetention>(&self) {
+            Ok(bytes) => Ok(bytes),
+            Err(_) => Err(TuringDbError::Bug(
+                "Unable to serialize a history retention".into(),
+            )),
+        }
+    }
+
+    fn from_bytes(value: &[u8]) -> TuringResult<HistoryRetention> {
+        match bincode::deserialize::<HistoryRetention>(value) {
+            Ok(retention) => Ok(retention),
+            Err(_) => Err(TuringDbError::Bug(
+                "Invalid history retention of a document".into(),
+            )),
+        }
+    }
+}
+
+/// A previous version of a field kept in the history of its document.
+/// `value` holds the bytes that were stored in sled, so the versions of encrypted documents stay sealed,
+/// and `replaced` is the time the version was overwritten or removed
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub(crate) struct FieldVersion {
+///     value: Vec<u8>,
+///     replaced: TAI64N,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct FieldVersion {
+    value: Vec<u8>,
+    replaced: TAI64N,
+}
+
+impl FieldVersion {
+    fn to_bytes(&self) -> TuringResult<Vec<u8>> {
+        match bincode::serialize::<FieldVersion>(self) {
+            Ok(bytes) => Ok(bytes),
+            Err(_) => Err(TuringDbError::FieldDataCorrupted),
+        }
+    }
+
+    fn from_bytes(value: &[u8]) -> TuringResult<FieldVersion> {
+        match bincode::deserialize::<FieldVersion>(value) {
+            Ok(version) => Ok(version),
+            Err(_) => Err(TuringDbError::FieldDataCorrupted),
+        }
+    }
+}
+
+impl TuringDB {
+    /// Get the `FieldData` a field held at the time `at`
+    pub(crate) async fn field_get_at(
+        &self,
+        document_name: &Utf8Path,
+        key: &[u8],
+        at: TAI64N,
+    ) -> TuringResult<FieldData> {
+        if !self.history {
+            return Err(TuringDbError::HistoryDisabled);
+        }
+
+        match self.document(document_name) {
+            None => Err(TuringDbError::DocumentNotFound),
+            Some(document) => match TuringDB::field_version_at(&document, key, at)? {
+                None => Err(TuringDbError::FieldNotFound),
+                Some(value) => self.unseal(document_name, key, &value),
+            },
+        }
+    }
+    /// Get every previous version of a field, the oldest first, without its current value
+    pub(crate) async fn field_history(
+        &self,
+        document_name: &Utf8Path,
+        key: &[u8],
+    ) -> TuringResult<Vec<FieldData>> {
+        if !self.history {
+            return Err(TuringDbError::HistoryDisabled);
+        }
+
+        let document = match self.document(document_name) {
+            None => return Err(TuringDbError::DocumentNotFound),
+            Some(document) => document,
+        };
+
+        let mut versions = Vec::new();
+
+        for entry in document
+            .open_tree(HISTORY_TREE)?
+            .scan_prefix(history_prefix(key))
+        {
+            let version = FieldVersion::from_bytes(&entry?.1)?;
+            versions.push(self.unseal(document_name, key, &version.value)?);
+        }
+
+        if versions.is_empty() && !document.contains_key(key)? {
+            return Err(TuringDbError::FieldNotFound);
+        }
+
+        Ok(versions)
+    }
+    /// Set how many previous versions of the fields of a document are kept,
+    /// instead of the retention of the engine
+    pub(crate) async fn history_retention_set(
+        &self,
+        document_name: &Utf8Path,
+        retention: HistoryRetention,
+    ) -> TuringResult<OpsOutcome> {
+        if !self.history {
+            return Err(TuringDbError::HistoryDisabled);
+        }
+
+        match self.document(document_name) {
+            None => Err(TuringDbError::DocumentNotFound),
+            Some(document) => {
+                let retention_tree = document.open_tree(RETENTION_TREE)?;
+                retention_tree.insert(RETENTION_KEY, retention.to_bytes()?)?;
+                retention_tree.flush_async().await?;
+
+                Ok(OpsOutcome::HistoryRetentionSet)
+            }
+        }
+    }
+    /// Drop the previous versions of the fields of every document that its retention
+    /// no longer keeps, using `retention` for the documents without one of their own.
+    /// Returns the number of versions dropped
+    pub(crate) fn history_compact(&self, retention: HistoryRetention) -> TuringResult<u64> {
+        if !self.history {
+            return Ok(0);
+        }
+
+        let now = TAI64N::now();
+        let mut dropped = 0;
+
+        for (_, document) in self.documents() {
+            let retention = match document.open_tree(RETENTION_TREE)?.get(RETENTION_KEY)? {
+                None => retention,
+                Some(document_retention) => HistoryRetention::from_bytes(&document_retention)?,
+            };
+
+            if retention.keeps_all() {
+                continue;
+            }
+
+            let history = document.open_tree(HISTORY_TREE)?;
+            let mut field: Vec<(IVec, IVec)> = Vec::new();
+
+            for entry in history.iter() {
+                let (history_key, value) = entry?;
+
+                if let Some((previous_key, _)) = field.first() {
+                    if field_key(previous_key)? != field_key(&history_key)? {
+                        dropped += TuringDB::compact_field(&history, &field, retention, now)?;
+                        field.clear();
+                    }
+                }
+
+                field.push((history_key, value));
+            }
+
+            dropped += TuringDB::compact_field(&history, &field, retention, now)?;
+        }
+
+        Ok(dropped)
+    }
+    /// Drop the versions of a single field, which are sorted from the oldest
+    fn compact_field(
+        history: &sled::Tree,
+        versions: &[(IVec, IVec)],
+        retention: HistoryRetention,
+        now: TAI64N,
+    ) -> TuringResult<u64> {
+        let mut dropped = 0;
+
+        for (position, (history_key, value)) in versions.iter().rev().enumerate() {
+            let version = FieldVersion::from_bytes(value)?;
+
+            if retention.keeps(position, &version, now) {
+                continue;
+            }
+
+            // A version re-encrypted in the meantime is dropped on the next compaction
+            if history
+                .compare_and_swap(history_key, Some(value), None as Option<IVec>)?
+                .is_ok()
+            {
+                dropped += 1;
+            }
+        }
+
+        Ok(dropped)
+    }
+    /// Put every field of the database back to the value it held at the time `at`,
+    /// removing the fields that did not exist then. The values that are replaced are kept
+    /// in the history, so a rewind can itself be undone. Returns the number of fields changed
+    pub(crate) async fn rewind(&self, at: TAI64N) -> TuringResult<u64> {
+        if !self.history {
+            return Err(TuringDbError::HistoryDisabled);
+        }
+
+        let mut rewound = 0;
+
+        for (document_name, document) in self.documents() {
+            let mut keys = BTreeSet::new();
+
+            for key in document.iter().keys() {
+                keys.insert(key?.to_vec());
+            }
+
+            for history_key in document.open_tree(HISTORY_TREE)?.iter().keys() {
+                keys.insert(field_key(&history_key?)?.to_vec());
+            }
+
+            for key in keys {
+                if self
+                    .field_rewind(&document_name, &document, &key, at)
+                    .await?
+                {
+                    rewound += 1;
+                }
+            }
+        }
+
+        Ok(rewound)
+    }
+
+    async fn field_rewind(
+        &self,
+        document_name: &Utf8Path,
+        document: &Document,
+        key: &[u8],
+        at: TAI64N,
+    ) -> TuringResult<bool> {
+        let _writing = self.write_lock().await;
+        let mut capture = self.cdc_capture().await?;
+
+        loop {
+            let current = document.get(key)?;
+            let past = TuringDB::field_version_at(document, key, at)?;
+
+            if current.as_deref() == past.as_deref() {
+                return Ok(false);
+            }
+
+            // The restored value starts a new version so reads between `at` and now
+            // still see the values that were stored then
+            let (restored, replaced) = match past {
+                None => (None, TAI64N::now()),
+                Some(past) => {
+                    let mut field_data = self.unseal(document_name, key, &past)?;
+
+                    // The field was already put back by an earlier rewind
+                    if let Some(current) = &current {
+                        if self.unseal(document_name, key, current)?.data() == field_data.data() {
+                            return Ok(false);
+                        }
+                    }
+
+                    let data = Zeroizing::new(field_data.data().to_vec());
+                    field_data.update(&data);
+
+                    (
+                        Some(self.seal(document_name, key, &field_data)?),
+                        field_data.modified(),
+                    )
+                }
+            };
+
+            let write = FieldWrite::new(key, restored.as_deref())
+                .expect(current.as_deref())
+                .replaced(replaced);
+
+            if self.field_swap(document_name, document, write, capture.as_mut())? {
+                self.cdc_write(capture).await;
+
+                return Ok(true);
+            }
+        }
+    }
+    /// The bytes stored in sled for a field at the time `at`, `None` if the field did not exist then
+    fn field_version_at(
+        document: &Document,
+        key: &[u8],
+        at: TAI64N,
+    ) -> TuringResult<Option<Vec<u8>>> {
+        if let Some(current) = document.get(key)? {
+            if FieldData::from_bytes(&current)?.modified() <= at {
+                return Ok(Some(current.to_vec()));
+            }
+        }
+
+        // The last version written before `at`, unless it was replaced before `at` too
+        let history = document.open_tree(HISTORY_TREE)?;
+        let mut versions = history.range(history_prefix(key)..=history_key(key, at));
+
+        match versions.next_back() {
+            None => Ok(None),
+            Some(version) => {
+                let version = FieldVersion::from_bytes(&version?.1)?;
+
+                if version.replaced > at {
+                    Ok(Some(version.value))
+                } else {
+                    Ok(None)
+                }
+            }
+        }
+    }
+}
+
+impl FieldSealer {
+    /// Re-encrypt the previous versions of the fields of a document
+    /// that are still sealed with the previous data key
+    pub(crate) fn reencrypt_history(
+        &self,
+        document_name: &Utf8Path,
+        document: &Document,
+    ) -> TuringResult<()> {
+        let history = document.open_tree(HISTORY_TREE)?;
+
+        for entry in history.iter() {
+            let (history_key, value) = entry?;
+            let mut version = FieldVersion::from_bytes(&value)?;
+            let key = field_key(&history_key)?;
+
+            let (field_data, is_current) = self.open(document_name, key, &version.value)?;
+
+            if is_current {
+                continue;
+            }
+
+            version.value = self.seal(document_name, key, &field_data)?;
+
+            // A version removed while it was being re-encrypted stays removed
+            let _ =
+                history.compare_and_swap(&history_key, Some(&value), Some(version.to_bytes()?))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Keep the value a field held before it was overwritten or removed at the time `replaced`,
+/// inside the transaction writing the field
+pub(crate) fn history_insert(
+    history: &TransactionalTree,
+    key: &[u8],
+    old: &[u8],
+    replaced: TAI64N,
+) -> ConflictableTransactionResult<(), TuringDbError> {
+    let version = FieldVersion {
+        value: old.to_vec(),
+        replaced,
+    };
+    let modified = match FieldData::from_bytes(old) {
+        Ok(field_data) => field_data.modified(),
+        Err(error) => return Err(ConflictableTransactionError::Abort(error)),
+    };
+    let version = match version.to_bytes() {
+        Ok(version) => version,
+        Err(error) => return Err(ConflictableTransactionError::Abort(error)),
+    };
+
+    history.insert(history_key(key, modified), version)?;
+
+    Ok(())
+}
+
+/// The versions of a field are stored under its length-prefixed key,
+/// so the versions of every field sort together by the time they were written
+fn history_prefix(key: &[u8]) -> Vec<u8> {
+    let mut prefix = Vec::with_capacity(4 + key.len());
+    prefix.extend_from_slice(&(key.len() as u32).to_be_bytes());
+    prefix.extend_from_slice(key);
+
+    prefix
+}
+
+fn history_key(key: &[u8], modified: TAI64N) -> Vec<u8> {
+    let mut history_key = history_prefix(key);
+    history_key.extend_from_slice(&modified.to_bytes());
+
+    history_key
+}
+
+fn field_key(history_key: &[u8]) -> TuringResult<&[u8]> {
+    let corrupted = || TuringDbError::Bug("Invalid key in the history of a document".into());
+
+    let length = match history_key.get(..4) {
+        None => return Err(corrupted()),
+        Some(length) => u32::from_be_bytes(length.try_into().unwrap_or_default()) as usize,
+    };
+
+    match history_key.get(4..4 + length) {
+        None => Err(corrupted()),
+        Some(key) => Ok(key),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{t_engine::testing::*, TDBCell, TuringEngine};
+    use futures_lite::future::block_on;
+
+    /// The value the field `key` held at the time `at`, `None` if it did not exist then
+    async fn value_at(engine: &TuringEngine, key: &str, at: TAI64N) -> Option<Vec<u8>> {
+        match engine.field_get_at(&field_ops(key, ""), at).await {
+            Ok(OpsOutcome::FieldContents(contents)) => {
+                Some(TDBCell::from_bytes(&contents).unwrap().get_data().to_vec())
+            }
+            Err(TuringDbError::FieldNotFound) => None,
+            outcome => panic!("Unexpected outcome {:?}", outcome),
+        }
+    }
+
+    /// The values of the previous versions of a field
+    fn versions(outcome: OpsOutcome) -> Vec<Vec<u8>> {
+        match outcome {
+            OpsOutcome::FieldHistory(versions) => versions
+                .iter()
+                .map(|version| {
+                    TDBCell::from_bytes(version.data())
+                        .unwrap()
+                        .get_data()
+                        .to_vec()
+                })
+                .collect(),
+            outcome => panic!("Unexpected outcome {:?}", outcome),
+        }
+    }
+
+    /// The time between two writes
+    fn pause() -> TAI64N {
+        std::thread::sleep(Duration::from_millis(10));
+        let now = TAI64N::now();
+        std::thread::sleep(Duration::from_millis(10));
+
+        now
+    }
+
+    #[test]
+    fn fields_are_read_and_rewound_as_they_were_at_a_time() {
+        block_on(async {
+            let dir = TestDir::new("history-rewind");
+            let engine = test_engine(&dir.path().join("repo"), true).await;
+
+            let before = pause();
+            field_set(&engine, "balance", "10").await.unwrap();
+            field_set(&engine, "invite", "pending").await.unwrap();
+            let deployed = pause();
+            engine
+                .field_modify(&field_ops("balance", "0"))
+                .await
+                .unwrap();
+            engine.field_remove(&field_ops("invite", "")).await.unwrap();
+            field_set(&engine, "spam", "spam").await.unwrap();
+            let broken = pause();
+
+            assert_eq!(value_at(&engine, "balance", before).await, None);
+            assert_eq!(
+                value_at(&engine, "balance", deployed).await,
+                Some(b"10".to_vec())
+            );
+            assert_eq!(
+                value_at(&engine, "balance", broken).await,
+                Some(b"0".to_vec())
+            );
+            assert_eq!(
+                value_at(&engine, "invite", deployed).await,
+                Some(b"pending".to_vec())
+            );
+            assert_eq!(value_at(&engine, "invite", broken).await, None);
+            assert_eq!(value_at(&engine, "spam", deployed).await, None);
+
+            assert_eq!(
+                engine.repo_rewind(deployed).await.unwrap(),
+                OpsOutcome::RepoRewound(3)
+            );
+            assert_eq!(field_value(&engine, "balance").await, Some(b"10".to_vec()));
+            assert_eq!(
+                field_value(&engine, "invite").await,
+                Some(b"pending".to_vec())
+            );
+            assert_eq!(field_value(&engine, "spam").await, None);
+            // Reads of the time between the deploy and the rewind still see the values stored then
+            assert_eq!(
+                value_at(&engine, "balance", broken).await,
+                Some(b"0".to_vec())
+            );
+            assert_eq!(
+                engine.repo_rewind(deployed).await.unwrap(),
+                OpsOutcome::RepoRewound(0)
+            );
+
+            // The rewind is undone by rewinding to a time before it
+            assert_eq!(
+                engine.repo_rewind(broken).await.unwrap(),
+                OpsOutcome::RepoRewound(3)
+            );
+            assert_eq!(field_value(&engine, "balance").await, Some(b"0".to_vec()));
+            assert_eq!(field_value(&engine, "invite").await, None);
+            assert_eq!(field_value(&engine, "spam").await, Some(b"spam".to_vec()));
+        })
+    }
+
+    #[test]
+    fn rewinds_need_history() {
+        block_on(async {
+            let dir = TestDir::new("history-disabled");
+            let engine = test_engine(&dir.path().join("repo"), false).await;
+            field_set(&engine, "balance", "10").await.unwrap();
+
+            match engine.repo_rewind(TAI64N::now()).await {
+                Err(TuringDbError::HistoryDisabled) => (),
+                outcome => panic!("Unexpected outcome {:?}", outcome),
+            }
+            match engine
+                .field_get_at(&field_ops("balance", ""), TAI64N::now())
+                .await
+            {
+                Err(TuringDbError::HistoryDisabled) => (),
+                outcome => panic!("Unexpected outcome {:?}", outcome),
+            }
+        })
+    }
+
+    #[test]
+    fn previous_versions_are_kept_until_the_retention_drops_them() {
+        block_on(async {
+            let dir = TestDir::new("history-retention");
+            let engine = test_engine(&dir.path().join("repo"), true).await;
+
+            field_set(&engine, "balance", "1").await.unwrap();
+            for balance in &["2", "3", "4"] {
+                engine
+                    .field_modify(&field_ops("balance", balance))
+                    .await
+                    .unwrap();
+            }
+
+            let history = engine.field_history(&field_ops("balance", "")).await;
+            assert_eq!(
+                versions(history.unwrap()),
+                vec![b"1".to_vec(), b"2".to_vec(), b"3".to_vec()]
+            );
+            assert_eq!(
+                engine.history_compact().await.unwrap(),
+                OpsOutcome::HistoryCompacted(0)
+            );
+
+            let retention = HistoryRetention::new().max_versions(Some(2));
+            engine
+                .history_retention_set(&document_ops(), retention)
+                .await
+                .unwrap();
+            assert_eq!(
+                engine.history_compact().await.unwrap(),
+                OpsOutcome::HistoryCompacted(1)
+            );
+
+            let history = engine.field_history(&field_ops("balance", "")).await;
+            assert_eq!(
+                versions(history.unwrap()),
+                vec![b"2".to_vec(), b"3".to_vec()]
+            );
+            assert_eq!(field_value(&engine, "balance").await, Some(b"4".to_vec()));
+        })
+    }
+}
@@ -0,0 +1,782 @@
+use crate::{
+    CdcChange, DataType, Document, FieldData, FieldKey, JsonPath, OpsOutcome, TDBCell, TuringDB,
+    TuringDbError, TuringResult,
+};
+use camino::Utf8Path;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sled::{
+    transaction::{ConflictableTransactionError, ConflictableTransactionResult, TransactionalTree},
+    Batch, IVec, Transactional, Tree,
+};
+use std::{collections::BTreeMap, convert::TryInto};
+use tai64::{TAI64, TAI64N};
+
+/// The sled tree of a document holding the `IndexDefinition` of each of its indexes
+const INDEX_DEFINITIONS_TREE: &str = "index_definitions";
+/// The sled tree of a document holding the entries of all its indexes.
+/// An entry maps the name of the index, the indexed value and the key of a field to the key of the field.
+/// The entries of a unique index leave out the key so a value has a single entry
+pub(crate) const INDEX_ENTRIES_TREE: &str = "index_entries";
+
+/// A secondary index of a document on the decoded values of its fields,
+/// or on the value found at a JSON `path` inside the JSON values of its fields.
+/// Fields without an `IndexValue` there are left out of the index.
+/// No two fields of the document can hold the same value of a `unique` index
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct IndexDefinition {
+///     name: String,
+///     path: Option<String>,
+///     unique: bool,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexDefinition {
+    name: String,
+    path: Option<String>,
+    unique: bool,
+}
+
+/// A value held by a secondary index, decoded from a field by the `DataType` it was stored with.
+/// Every integer type is read from its little-endian bytes as an `Integer`, `F32` and `F64` as a `Float`,
+/// `STRING` as a `Text`, `TAI64` and `TAI64N` as a `Time` and any other data type is kept as `Bytes`.
+/// Inside a JSON value only booleans, numbers and strings are indexed.
+/// Values of different variants are never equal, so a `Float` does not find an `Integer`
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// pub enum IndexValue {
+///     Boolean(bool),
+///     Integer(i128),
+///     Float(f64),
+///     Text(String),
+///     Time(TAI64N),
+///     Bytes(Vec<u8>),
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum IndexValue {
+    Boolean(bool),
+    Integer(i128),
+    Float(f64),
+    Text(String),
+    Time(TAI64N),
+    Bytes(Vec<u8>),
+}
+
+/// The fields looked up by `TuringEngine::find_by_index()`, those whose indexed value is `Value`
+/// or lies in a `Range` including both bounds.
+/// The bounds of a range must be of the same variant and a missing bound leaves the range open
+/// up to the values of the variant of the other bound. A range without bounds finds every indexed field
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// pub enum IndexLookup {
+///     Value(IndexValue),
+///     Range { from: Option<IndexValue>, to: Option<IndexValue> },
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum IndexLookup {
+    Value(IndexValue),
+    Range {
+        from: Option<IndexValue>,
+        to: Option<IndexValue>,
+    },
+}
+
+impl IndexDefinition {
+    /// An index named `name` on the whole value of every field
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            path: None,
+            unique: false,
+        }
+    }
+    /// Index the value at `path` inside the JSON value of every field, like `user.email`
+    pub fn path(mut self, path: &str) -> Self {
+        self.path = Some(path.to_owned());
+
+        self
+    }
+    /// Refuse any write that leaves two fields holding the same indexed value
+    /// with a `TuringDbError::UniqueViolation` naming the field already holding it
+    pub fn unique(mut self) -> Self {
+        self.unique = true;
+
+        self
+    }
+    /// The name of the index
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+    /// The JSON path the index reads, `None` if it indexes the whole value
+    pub fn get_path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+    /// Check whether the index allows a value to be held by a single field
+    pub fn is_unique(&self) -> bool {
+        self.unique
+    }
+    /// The value of a field held by the index, `None` if the field has nothing to index
+    fn value_of(&self, field_data: &FieldData) -> TuringResult<Option<IndexValue>> {
+        let cell = TDBCell::from_bytes(field_data.data())?;
+
+        if cell.get_data_type() == DataType::JSON {
+            let json = match serde_json::from_slice::<Value>(cell.get_data()) {
+                Ok(json) => json,
+                Err(_) => return Ok(None),
+            };
+            let path = JsonPath::parse(self.path.as_deref().unwrap_or_default())?;
+
+            return Ok(path.get(&json).and_then(IndexValue::from_json));
+        }
+
+        match self.path {
+            Some(_) => Ok(None),
+            None => Ok(IndexValue::from_cell(cell.get_data_type(), cell.get_data())),
+        }
+    }
+    /// The first bytes of every entry of the index
+    fn prefix(&self) -> Vec<u8> {
+        let mut prefix = Vec::with_capacity(self.name.len() + 2);
+        escape(&mut prefix, self.name.as_bytes());
+
+        prefix
+    }
+    /// The first bytes of the entries of the fields holding `value`
+    fn value_prefix(&self, value: &IndexValue) -> Vec<u8> {
+        let mut prefix = self.prefix();
+        value.encode(&mut prefix);
+
+        prefix
+    }
+    /// The first bytes of the entries of the fields holding a value of the same variant as `value`
+    fn variant_prefix(&self, value: &IndexValue) -> Vec<u8> {
+        let mut prefix = self.prefix();
+        prefix.push(value.tag());
+
+        prefix
+    }
+    /// The entry of the field `key` holding `value`
+    fn entry(&self, value: &IndexValue, key: &[u8]) -> Vec<u8> {
+        let mut entry = self.value_prefix(value);
+        if !self.unique {
+            entry.extend_from_slice(key);
+        }
+
+        entry
+    }
+    /// The first entry that can be in the range of a lookup and the prefix of the last entries in that range
+    fn bounds(&self, lookup: &IndexLookup) -> TuringResult<(Vec<u8>, Vec<u8>)> {
+        let bounds = match lookup {
+            IndexLookup::Value(value) => (self.value_prefix(value), self.value_prefix(value)),
+            IndexLookup::Range { from, to } => match (from, to) {
+                (Some(from), Some(to)) if from.tag() != to.tag() => {
+                    return Err(TuringDbError::InvalidInput)
+                }
+                (Some(from), Some(to)) => (self.value_prefix(from), self.value_prefix(to)),
+                (Some(from), None) => (self.value_prefix(from), self.variant_prefix(from)),
+                (None, Some(to)) => (self.variant_prefix(to), self.value_prefix(to)),
+                (None, None) => (self.prefix(), self.prefix()),
+            },
+        };
+
+        Ok(bounds)
+    }
+    /// The keys of the fields found by a lookup in the entries of the index, in the order of their values
+    fn lookup(&self, entries: &Tree, lookup: &IndexLookup) -> TuringResult<Vec<IVec>> {
+        let (start, end) = self.bounds(lookup)?;
+        let mut keys = Vec::new();
+
+        for entry in entries.range(start.as_slice()..) {
+            let (entry, key) = entry?;
+
+            if !within(&entry, &start, &end) {
+                break;
+            }
+
+            keys.push(key);
+        }
+
+        Ok(keys)
+    }
+
+    fn to_bytes(&self) -> TuringResult<Vec<u8>> {
+        match bincode::serialize::<IndexDefinition>(self) {
+            Ok(bytes) => Ok(bytes),
+            Err(_) => Err(TuringDbError::Bug(
+                "Unable to serialize an index definition".into(),
+            )),
+        }
+    }
+
+    fn from_bytes(value: &[u8]) -> TuringResult<IndexDefinition> {
+        match bincode::deserialize::<IndexDefinition>(value) {
+            Ok(definition) => Ok(definition),
+            Err(_) => Err(TuringDbError::Bug("Invalid index definition".into())),
+        }
+    }
+}
+
+impl IndexValue {
+    /// Decode the data of a field by its data type, `None` if the data does not fit the data type
+    fn from_cell(data_type: DataType, data: &[u8]) -> Option<IndexValue> {
+        let value = match data_type {
+            DataType::Boolean => match data {
+                [value] => IndexValue::Boolean(*value != 0),
+                _ => return None,
+            },
+            DataType::U8 => IndexValue::Integer(u8::from_le_bytes(data.try_into().ok()?).into()),
+            DataType::I8 => IndexValue::Integer(i8::from_le_bytes(data.try_into().ok()?).into()),
+            DataType::U16 => IndexValue::Integer(u16::from_le_bytes(data.try_into().ok()?).into()),
+            DataType::I16 => IndexValue::Integer(i16::from_le_bytes(data.try_into().ok()?).into()),
+            DataType::U32 => IndexValue::Integer(u32::from_le_bytes(data.try_into().ok()?).into()),
+            DataType::I32 => IndexValue::Integer(i32::from_le_bytes(data.try_into().ok()?).into()),
+            DataType::U64 => IndexValue::Integer(u64::from_le_bytes(data.try_into().ok()?).into()),
+            DataType::I64 => IndexValue::Integer(i64::from_le_bytes(data.try_into().ok()?).into()),
+            // Values above `i128::MAX` are left out of the index
+            DataType::U128 => {
+                IndexValue::Integer(u128::from_le_bytes(data.try_into().ok()?).try_into().ok()?)
+            }
+            DataType::I128 => IndexValue::Integer(i128::from_le_bytes(data.try_into().ok()?)),
+            DataType::F32 => IndexValue::Float(f32::from_le_bytes(data.try_into().ok()?).into()),
+            DataType::F64 => IndexValue::Float(f64::from_le_bytes(data.try_into().ok()?)),
+            DataType::STRING => IndexValue::Text(String::from_utf8(data.to_vec()).ok()?),
+            DataType::TAI64 => IndexValue::Time(TAI64N(TAI64::from_slice(data).ok()?, 0)),
+            DataType::TAI64N => IndexValue::Time(TAI64N::from_slice(data).ok()?),
+            _ => IndexValue::Bytes(data.to_vec()),
+        };
+
+        match value {
+            IndexValue::Float(float) if float.is_nan() => None,
+            value => Some(value),
+        }
+    }
+
+    fn from_json(value: &Value) -> Option<IndexValue> {
+        match value {
+            Value::Bool(value) => Some(IndexValue::Boolean(*value)),
+            Value::Number(number) => match (number.as_i64(), number.as_u64()) {
+                (Some(integer), _) => Some(IndexValue::Integer(integer.into())),
+                (None, Some(integer)) => Some(IndexValue::Integer(integer.into())),
+                (None, None) => number.as_f64().map(IndexValue::Float),
+            },
+            Value::String(text) => Some(IndexValue::Text(text.to_owned())),
+            _ => None,
+        }
+    }
+
+    fn tag(&self) -> u8 {
+        match self {
+            IndexValue::Boolean(_) => 0x01,
+            IndexValue::Integer(_) => 0x02,
+            IndexValue::Float(_) => 0x03,
+            IndexValue::Text(_) => 0x04,
+            IndexValue::Time(_) => 0x05,
+            IndexValue::Bytes(_) => 0x06,
+        }
+    }
+    /// Append the tag of the variant and the value, encoded so the bytes of the values
+    /// of a variant sort in the order of the values
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(self.tag());
+
+        match self {
+            IndexValue::Boolean(value) => out.push(*value as u8),
+            IndexValue::Integer(value) => {
+                out.extend_from_slice(&((*value as u128) ^ (1 << 127)).to_be_bytes())
+            }
+            IndexValue::Float(value) => {
+                // Adding zero turns `-0.0` into `0.0` so both are the same value
+                let bits = (value + 0.0).to_bits();
+                let bits = if bits >> 63 == 1 {
+                    !bits
+                } else {
+                    bits | (1 << 63)
+                };
+
+                out.extend_from_slice(&bits.to_be_bytes())
+            }
+            IndexValue::Text(value) => escape(out, value.as_bytes()),
+            IndexValue::Time(value) => out.extend_from_slice(&value.to_bytes()),
+            IndexValue::Bytes(value) => escape(out, value),
+        }
+    }
+}
+
+impl TuringDB {
+    /// Declare an index of a document and add the fields the document already holds to it
+    pub(crate) async fn index_create(
+        &self,
+        document_name: &Utf8Path,
+        definition: &IndexDefinition,
+    ) -> TuringResult<OpsOutcome> {
+        let document = match self.document(document_name) {
+            None => return Err(TuringDbError::DocumentNotFound),
+            Some(document) => document,
+        };
+
+        // The entries of an index hold the values of the fields unsealed
+        if self.is_encrypted(document_name) {
+            return Err(TuringDbError::IndexOnEncryptedDocument);
+        }
+
+        if definition.get_name().is_empty() {
+            return Err(TuringDbError::InvalidInput);
+        }
+
+        if let Some(path) = definition.get_path() {
+            JsonPath::parse(path)?;
+        }
+
+        // Hold the database so no field changes before its entries are written
+        let _writing = self.write_lock_all().await;
+        let capture = self.cdc_capture().await?;
+
+        if document
+            .open_tree(INDEX_DEFINITIONS_TREE)?
+            .contains_key(definition.get_name())?
+        {
+            return Err(TuringDbError::IndexAlreadyExists);
+        }
+
+        self.index_build(document_name, &document, std::slice::from_ref(definition))?;
+
+        if let Some(capture) = capture {
+            capture
+                .append(
+                    document_name,
+                    self.is_encrypted(document_name),
+                    CdcChange::IndexCreate(definition.clone()),
+                )
+                .await;
+        }
+
+        Ok(OpsOutcome::IndexCreated)
+    }
+    /// Declare indexes of a document and write the entries of the fields it already holds,
+    /// each index with its entries in a single transaction
+    pub(crate) fn index_build(
+        &self,
+        document_name: &Utf8Path,
+        document: &Document,
+        definitions: &[IndexDefinition],
+    ) -> TuringResult<()> {
+        let definitions_tree = document.open_tree(INDEX_DEFINITIONS_TREE)?;
+        let entries = document.open_tree(INDEX_ENTRIES_TREE)?;
+
+        for definition in definitions {
+            let batch = self.index_scan(document_name, document, definition)?;
+            let definition_bytes = definition.to_bytes()?;
+
+            (&definitions_tree, &entries).transaction(|(definitions, entries)| {
+                entries.apply_batch(&batch)?;
+                definitions.insert(definition.get_name(), definition_bytes.as_slice())?;
+
+                Ok(())
+            })?;
+        }
+
+        Ok(())
+    }
+    /// The entries of an index for the fields a document holds
+    fn index_scan(
+        &self,
+        document_name: &Utf8Path,
+        document: &Document,
+        definition: &IndexDefinition,
+    ) -> TuringResult<Batch> {
+        let mut added = BTreeMap::new();
+        for field in document.iter() {
+            let (key, value) = field?;
+            let field_data = self.unseal(document_name, &key, &value)?;
+
+            if let Some(value) = definition.value_of(&field_data)? {
+                // Only the entries of a unique index can be shared by two fields
+                if let Some(owner) = added.insert(definition.entry(&value, &key), key) {
+                    return Err(TuringDbError::UniqueViolation {
+                        index: definition.get_name().to_owned(),
+                        key: owner.to_vec(),
+                    });
+                }
+            }
+        }
+
+        let mut batch = Batch::default();
+        for (entry, key) in added {
+            batch.insert(entry, key);
+        }
+
+        Ok(batch)
+    }
+    /// Drop an index of a document and all its entries
+    pub(crate) async fn index_drop(
+        &self,
+        document_name: &Utf8Path,
+        name: &str,
+    ) -> TuringResult<OpsOutcome> {
+        let document = match self.document(document_name) {
+            None => return Err(TuringDbError::DocumentNotFound),
+            Some(document) => document,
+        };
+
+        let _writing = self.write_lock_all().await;
+        let capture = self.cdc_capture().await?;
+
+        let definitions = document.open_tree(INDEX_DEFINITIONS_TREE)?;
+        let definition = match definitions.get(name)? {
+            None => return Err(TuringDbError::IndexNotFound),
+            Some(definition) => IndexDefinition::from_bytes(&definition)?,
+        };
+
+        let entries = document.open_tree(INDEX_ENTRIES_TREE)?;
+
+        let mut batch = Batch::default();
+        for entry in entries.scan_prefix(definition.prefix()) {
+            let (entry, _) = entry?;
+            batch.remove(entry);
+        }
+
+        (&definitions, &entries).transaction(|(definitions, entries)| {
+            entries.apply_batch(&batch)?;
+            definitions.remove(name)?;
+
+            Ok(())
+        })?;
+
+        if let Some(capture) = capture {
+            capture
+                .append(
+                    document_name,
+                    self.is_encrypted(document_name),
+                    CdcChange::IndexDrop {
+                        name: name.to_owned(),
+                    },
+                )
+                .await;
+        }
+
+        Ok(OpsOutcome::IndexDropped)
+    }
+    /// The fields of a document found by a lookup in one of its indexes, in the order of their indexed values.
+    /// Expired fields are left out like they are from reads
+    pub(crate) async fn find_by_index(
+        &self,
+        document_name: &Utf8Path,
+        name: &str,
+        lookup: &IndexLookup,
+    ) -> TuringResult<Vec<(FieldKey, FieldData)>> {
+        let document = match self.document(document_name) {
+            None => return Err(TuringDbError::DocumentNotFound),
+            Some(document) => document,
+        };
+
+        let definition = match document.open_tree(INDEX_DEFINITIONS_TREE)?.get(name)? {
+            None => return Err(TuringDbError::IndexNotFound),
+            Some(definition) => IndexDefinition::from_bytes(&definition)?,
+        };
+
+        let entries = document.open_tree(INDEX_ENTRIES_TREE)?;
+        let (start, end) = definition.bounds(lookup)?;
+        let mut fields = Vec::new();
+
+        for key in definition.lookup(&entries, lookup)? {
+            let field_data = match self.field_get(document_name, &key).await {
+                Ok(field_data) => field_data,
+                Err(TuringDbError::FieldNotFound) => continue,
+                Err(error) => return Err(error),
+            };
+
+            // The field may have changed after its entry was read
+            let found = match definition.value_of(&field_data)? {
+                None => false,
+                Some(value) => within(&definition.entry(&value, &key), &start, &end),
+            };
+
+            if found {
+                fields.push((key.to_vec(), field_data));
+            }
+        }
+
+        Ok(fields)
+    }
+    /// The indexes declared on a document
+    pub(crate) fn index_definitions(
+        &self,
+        document: &Document,
+    ) -> TuringResult<Vec<IndexDefinition>> {
+        let mut definitions = Vec::new();
+
+        for definition in document.open_tree(INDEX_DEFINITIONS_TREE)?.iter() {
+            let (_, definition) = definition?;
+            definitions.push(IndexDefinition::from_bytes(&definition)?);
+        }
+
+        Ok(definitions)
+    }
+    /// The entries of the indexes of a field holding the bytes `value` stored in sled
+    pub(crate) fn index_entries<'d>(
+        &self,
+        definitions: &'d [IndexDefinition],
+        document_name: &Utf8Path,
+        key: &[u8],
+        value: Option<&[u8]>,
+    ) -> TuringResult<Vec<IndexEntry<'d>>> {
+        let field_data = match value {
+            None => return Ok(Vec::new()),
+            Some(value) => self.unseal(document_name, key, value)?,
+        };
+
+        let mut entries = Vec::new();
+        for definition in definitions {
+            if let Some(indexed) = definition.value_of(&field_data)? {
+                entries.push(IndexEntry {
+                    definition,
+                    entry: definition.entry(&indexed, key),
+                });
+            }
+        }
+
+        Ok(entries)
+    }
+    /// Fail with a `UniqueViolation` if a field whose entry of a unique index was taken over by a write
+    /// still holds the value of that entry once the write is done
+    pub(crate) fn index_check_unique(
+        &self,
+        definitions: &[IndexDefinition],
+        document_name: &Utf8Path,
+        fields: &TransactionalTree,
+        taken: &[TakenEntry],
+    ) -> ConflictableTransactionResult<(), TuringDbError> {
+        for taken in taken {
+            let value = fields.get(&taken.owner)?;
+            let held = match self.index_entries(
+                definitions,
+                document_name,
+                &taken.owner,
+                value.as_deref(),
+            ) {
+                Ok(held) => held,
+                Err(error) => return Err(ConflictableTransactionError::Abort(error)),
+            };
+
+            if held.iter().any(|held| held.entry == taken.entry) {
+                return Err(ConflictableTransactionError::Abort(
+                    TuringDbError::UniqueViolation {
+                        index: taken.index.to_owned(),
+                        key: taken.owner.to_vec(),
+                    },
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// An entry of a field in one of the indexes of its document
+pub(crate) struct IndexEntry<'d> {
+    definition: &'d IndexDefinition,
+    entry: Vec<u8>,
+}
+
+/// An entry of the unique index `index` a write moved away from the field `owner`
+pub(crate) struct TakenEntry<'d> {
+    index: &'d str,
+    entry: Vec<u8>,
+    owner: IVec,
+}
+
+/// Replace the entries `removed` of the field `key` with the entries `added` inside a transaction.
+/// An entry of a unique index is only removed while it belongs to the field,
+/// and the entries of unique indexes moved away from other fields are collected into `taken`
+pub(crate) fn index_move<'d>(
+    entries: &TransactionalTree,
+    key: &[u8],
+    removed: &[IndexEntry<'d>],
+    added: &[IndexEntry<'d>],
+    taken: &mut Vec<TakenEntry<'d>>,
+) -> ConflictableTransactionResult<(), TuringDbError> {
+    for removed in removed {
+        if removed.definition.unique && entries.get(&removed.entry)?.as_deref() != Some(key) {
+            continue;
+        }
+
+        entries.remove(removed.entry.as_slice())?;
+    }
+
+    for added in added {
+        if let Some(owner) = entries.insert(added.entry.as_slice(), key)? {
+            if owner.as_ref() != key {
+                taken.push(TakenEntry {
+                    index: added.definition.get_name(),
+                    entry: added.entry.clone(),
+                    owner,
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Append bytes of any length so that no encoding is the start of another
+/// and the encodings sort like the bytes: every `0x00` is followed by `0xff` and `0x00 0x00` marks the end
+fn escape(out: &mut Vec<u8>, bytes: &[u8]) {
+    for byte in bytes {
+        out.push(*byte);
+
+        if *byte == 0x00 {
+            out.push(0xff);
+        }
+    }
+
+    out.extend_from_slice(&[0x00, 0x00]);
+}
+
+/// Check whether an entry lies between the first entry `start` of a range
+/// and the last entries of the range, which start with `end`
+fn within(entry: &[u8], start: &[u8], end: &[u8]) -> bool {
+    entry >= start && (entry <= end || entry.starts_with(end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{t_engine::testing::*, TuringEngine};
+    use futures_lite::future::block_on;
+
+    async fn user_set(engine: &TuringEngine, key: &str, user: &str) -> TuringResult<OpsOutcome> {
+        engine
+            .field_set(&field_ops(key, user).data_type(DataType::JSON))
+            .await
+    }
+
+    async fn find(engine: &TuringEngine, index: &str, lookup: IndexLookup) -> Vec<FieldKey> {
+        match engine
+            .find_by_index(Utf8Path::new(DB), Utf8Path::new(DOCUMENT), index, &lookup)
+            .await
+            .unwrap()
+        {
+            OpsOutcome::FieldsFound(fields) => fields.into_iter().map(|(key, _)| key).collect(),
+            outcome => panic!("Unexpected outcome {:?}", outcome),
+        }
+    }
+
+    #[test]
+    fn fields_are_found_by_the_value_at_a_json_path() {
+        block_on(async {
+            let dir = TestDir::new("index");
+            let engine = test_engine(&dir.path().join("repo"), false).await;
+
+            user_set(&engine, "alice", r#"{"age": 34}"#).await.unwrap();
+            user_set(&engine, "bob", r#"{"age": 17}"#).await.unwrap();
+            engine
+                .index_create(&document_ops(), IndexDefinition::new("by_age").path("age"))
+                .await
+                .unwrap();
+            user_set(&engine, "carol", r#"{"age": 52}"#).await.unwrap();
+            user_set(&engine, "dave", r#"{"name": "dave"}"#)
+                .await
+                .unwrap();
+
+            let adults = IndexLookup::Range {
+                from: Some(IndexValue::Integer(18)),
+                to: None,
+            };
+            assert_eq!(
+                find(&engine, "by_age", adults.clone()).await,
+                vec![b"alice".to_vec(), b"carol".to_vec()]
+            );
+            assert_eq!(
+                find(
+                    &engine,
+                    "by_age",
+                    IndexLookup::Value(IndexValue::Integer(17))
+                )
+                .await,
+                vec![b"bob".to_vec()]
+            );
+
+            engine
+                .field_modify(&field_ops("bob", r#"{"age": 18}"#).data_type(DataType::JSON))
+                .await
+                .unwrap();
+            engine.field_remove(&field_ops("carol", "")).await.unwrap();
+
+            assert_eq!(
+                find(&engine, "by_age", adults).await,
+                vec![b"bob".to_vec(), b"alice".to_vec()]
+            );
+
+            engine.index_drop(&document_ops(), "by_age").await.unwrap();
+            match engine
+                .find_by_index(
+                    Utf8Path::new(DB),
+                    Utf8Path::new(DOCUMENT),
+                    "by_age",
+                    &IndexLookup::Value(IndexValue::Integer(18)),
+                )
+                .await
+            {
+                Err(TuringDbError::IndexNotFound) => (),
+                outcome => panic!("Unexpected outcome {:?}", outcome),
+            }
+        })
+    }
+
+    #[test]
+    fn a_unique_index_names_the_field_already_holding_a_value() {
+        block_on(async {
+            let dir = TestDir::new("index-unique");
+            let engine = test_engine(&dir.path().join("repo"), false).await;
+            let by_email = IndexDefinition::new("by_email").path("email").unique();
+
+            user_set(&engine, "alice", r#"{"email": "a@example.com"}"#)
+                .await
+                .unwrap();
+            engine
+                .index_create(&document_ops(), by_email.clone())
+                .await
+                .unwrap();
+
+            match user_set(&engine, "bob", r#"{"email": "a@example.com"}"#).await {
+                Err(TuringDbError::UniqueViolation { index, key }) => {
+                    assert_eq!(index, "by_email");
+                    assert_eq!(key, b"alice".to_vec());
+                }
+                outcome => panic!("Unexpected outcome {:?}", outcome),
+            }
+            assert_eq!(field_value(&engine, "bob").await, None);
+
+            // The value is free again once its field holds another one
+            engine
+                .field_modify(
+                    &field_ops("alice", r#"{"email": "alice@example.com"}"#)
+                        .data_type(DataType::JSON),
+                )
+                .await
+                .unwrap();
+            user_set(&engine, "bob", r#"{"email": "a@example.com"}"#)
+                .await
+                .unwrap();
+
+            // A unique index cannot be created over fields already sharing a value
+            engine
+                .index_drop(&document_ops(), "by_email")
+                .await
+                .unwrap();
+            user_set(&engine, "carol", r#"{"email": "a@example.com"}"#)
+                .await
+                .unwrap();
+            match engine.index_create(&document_ops(), by_email).await {
+                Err(TuringDbError::UniqueViolation { index, key }) => {
+                    assert_eq!(index, "by_email");
+                    assert!(key == b"bob".to_vec() || key == b"carol".to_vec());
+                }
+                outcome => panic!("Unexpected outcome {:?}", outcome),
+            }
+        })
+    }
+}
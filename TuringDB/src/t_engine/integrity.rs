@@ -0,0 +1,866 @@
+use crate::{
+    Cipher, CipherOps, Document, IntegrityViolation, RepoPath, TuringDB, TuringDbError,
+    TuringResult, INTEGRITY_KEY_FILE,
+};
+use camino::{Utf8Path, Utf8PathBuf};
+use dashmap::DashMap;
+use secrecy::{ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+use sled::transaction::{ConflictableTransactionResult, TransactionalTree};
+use std::{
+    collections::{btree_map::Entry, BTreeMap, BTreeSet},
+    io::ErrorKind,
+    sync::{Arc, Mutex, MutexGuard},
+};
+use zeroize::Zeroize;
+
+/// The sled tree of a document holding the hash of every field under its bucket and key
+pub(crate) const LEAVES_TREE: &[u8] = b"turingdb::integrity::leaves";
+/// The sled tree of a document holding the nodes of the Merkle tree over its buckets
+pub(crate) const NODES_TREE: &[u8] = b"turingdb::integrity::nodes";
+/// The sled tree of a document holding the Merkle root of its fields
+pub(crate) const ROOT_TREE: &[u8] = b"turingdb::integrity::root";
+const ROOT_KEY: &[u8] = b"root";
+const INTEGRITY_KEY_CONTEXT: &[u8] = b"TuringDB integrity key";
+/// The depth of the Merkle tree, whose leaves are the `2^BUCKET_BITS` buckets of field hashes
+const BUCKET_BITS: u8 = 16;
+
+const LEAF_DOMAIN: u8 = 0x00;
+const NODE_DOMAIN: u8 = 0x01;
+const ROOT_DOMAIN: u8 = 0x02;
+const MANIFEST_DOMAIN: u8 = 0x03;
+const BUCKET_DOMAIN: u8 = 0x04;
+const PATH_DOMAIN: u8 = 0x05;
+
+/// The key used to compute the keyed BLAKE3 hashes of the integrity manifests.
+/// It is stored in the repo sealed with the master key when there is one,
+/// so without a master key the manifests only detect changes made by someone
+/// who cannot read the repo directory
+/// ```
+/// #[derive(Clone)]
+/// pub(crate) struct IntegrityKey(Arc<Secret<[u8; 32]>>);
+/// ```
+#[derive(Clone)]
+pub(crate) struct IntegrityKey(Arc<Secret<[u8; 32]>>);
+
+impl std::fmt::Debug for IntegrityKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("IntegrityKey([REDACTED])")
+    }
+}
+
+/// The integrity key as stored in the `INTEGRITY_KEY` file
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredIntegrityKey {
+    sealed: bool,
+    key: Vec<u8>,
+}
+
+impl IntegrityKey {
+    /// Read the integrity key of the repo or generate one for a new repo
+    pub(crate) async fn load(
+        repo_dir: &Utf8Path,
+        master_key: Option<&Cipher>,
+    ) -> TuringResult<IntegrityKey> {
+        let stored = match async_fs::read(repo_dir.join(INTEGRITY_KEY_FILE)).await {
+            Ok(value) => match bincode::deserialize::<StoredIntegrityKey>(&value) {
+                Ok(stored) => stored,
+                Err(_) => return Err(TuringDbError::IntegrityKeyCorrupted),
+            },
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                let integrity_key = IntegrityKey::generate()?;
+
+                // A new repo stores the key once `repo_create` creates its directory
+                if async_fs::metadata(repo_dir).await.is_ok() {
+                    integrity_key.write(repo_dir, master_key).await?;
+                }
+
+                return Ok(integrity_key);
+            }
+            Err(error) => return Err(error.into()),
+        };
+
+        let integrity_key = match (stored.sealed, master_key) {
+            (false, _) => IntegrityKey::from_slice(&stored.key)?,
+            (true, None) => return Err(TuringDbError::EncryptionKeyMissing),
+            (true, Some(master_key)) => {
+                let key = master_key.decrypt(&stored.key, INTEGRITY_KEY_CONTEXT)?;
+
+                IntegrityKey::from_slice(key.expose_secret())?
+            }
+        };
+
+        // Seal a key stored before the repo had a master key
+        if !stored.sealed && master_key.is_some() {
+            integrity_key.write(repo_dir, master_key).await?;
+        }
+
+        Ok(integrity_key)
+    }
+    /// Store the integrity key in the repo, sealed with the master key if there is one
+    pub(crate) async fn write(
+        &self,
+        repo_dir: &Utf8Path,
+        master_key: Option<&Cipher>,
+    ) -> TuringResult<()> {
+        RepoPath::replace(
+            &repo_dir.join(INTEGRITY_KEY_FILE),
+            self.to_bytes(master_key)?,
+        )
+        .await
+    }
+    /// The integrity key as it is stored in the repo, sealed with the master key if there is one
+    pub(crate) fn to_bytes(&self, master_key: Option<&Cipher>) -> TuringResult<Vec<u8>> {
+        let stored = match master_key {
+            None => StoredIntegrityKey {
+                sealed: false,
+                key: self.0.expose_secret().to_vec(),
+            },
+            Some(master_key) => StoredIntegrityKey {
+                sealed: true,
+                key: master_key.encrypt(
+                    Secret::new(self.0.expose_secret().to_vec()),
+                    INTEGRITY_KEY_CONTEXT,
+                )?,
+            },
+        };
+
+        match bincode::serialize(&stored) {
+            Ok(bytes) => Ok(bytes),
+            Err(_) => Err(TuringDbError::IntegrityKeyCorrupted),
+        }
+    }
+
+    /// Derive a key for another purpose from the integrity key
+    pub(crate) fn derive(&self, context: &str) -> Secret<[u8; 32]> {
+        Secret::new(blake3::derive_key(context, self.0.expose_secret()))
+    }
+
+    fn generate() -> TuringResult<IntegrityKey> {
+        let mut key = [0u8; 32];
+        if getrandom::getrandom(&mut key).is_err() {
+            return Err(crate::CipherErrors::RandomnessUnavailable.into());
+        }
+
+        let integrity_key = IntegrityKey(Arc::new(Secret::new(key)));
+        key.zeroize();
+
+        Ok(integrity_key)
+    }
+
+    fn from_slice(value: &[u8]) -> TuringResult<IntegrityKey> {
+        if value.len() != 32 {
+            return Err(TuringDbError::IntegrityKeyCorrupted);
+        }
+
+        let mut key = [0u8; 32];
+        key.copy_from_slice(value);
+
+        let integrity_key = IntegrityKey(Arc::new(Secret::new(key)));
+        key.zeroize();
+
+        Ok(integrity_key)
+    }
+
+    fn hasher(&self, domain: u8) -> blake3::Hasher {
+        let mut hasher = blake3::Hasher::new_keyed(self.0.expose_secret());
+        hasher.update(&[domain]);
+
+        hasher
+    }
+}
+
+/// A keyed BLAKE3 Merkle tree over the fields of each document of a database.
+/// The hash of every field is kept in a separate sled tree of the document, in one of
+/// `2^BUCKET_BITS` buckets picked by a keyed hash of its key. The buckets are the leaves
+/// of a Merkle tree of fixed depth whose nodes are kept in another sled tree,
+/// so a write only hashes the buckets it changes and the nodes above them.
+/// Nodes over empty buckets are not stored
+/// ```
+/// #[derive(Debug, Clone)]
+/// pub(crate) struct IntegrityManifest {
+///     key: IntegrityKey,
+///     db_name: Utf8PathBuf,
+///     empty: Arc<Vec<[u8; 32]>>,
+///     lock: Arc<Mutex<()>>,
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub(crate) struct IntegrityManifest {
+    key: IntegrityKey,
+    db_name: Utf8PathBuf,
+    /// The node over empty buckets at every level of the tree, the root level first
+    empty: Arc<Vec<[u8; 32]>>,
+    lock: Arc<Mutex<()>>,
+}
+
+impl IntegrityManifest {
+    pub(crate) fn new(key: &IntegrityKey, db_name: &Utf8Path) -> Self {
+        let mut manifest = Self {
+            key: key.clone(),
+            db_name: db_name.to_path_buf(),
+            empty: Arc::new(Vec::new()),
+            lock: Arc::new(Mutex::new(())),
+        };
+
+        let mut empty = vec![manifest.bucket_hash(std::iter::empty())];
+        for _ in 0..BUCKET_BITS {
+            let child = empty[0];
+            empty.insert(0, manifest.node(&child, &child));
+        }
+        manifest.empty = Arc::new(empty);
+
+        manifest
+    }
+    /// Hash every field of a document and replace the stored hashes with them.
+    /// This trusts the current contents of the document
+    pub(crate) fn rebuild(
+        &self,
+        document_name: &Utf8Path,
+        document: &Document,
+    ) -> TuringResult<()> {
+        let _guard = self.lock()?;
+        let leaves = document.open_tree(LEAVES_TREE)?;
+        let nodes = document.open_tree(NODES_TREE)?;
+        leaves.clear()?;
+        nodes.clear()?;
+
+        for field in document.iter() {
+            let (key, value) = field?;
+            leaves.insert(self.leaf_key(&key), &self.leaf(document_name, &key, &value))?;
+        }
+
+        let tree = self.tree(&leaves)?;
+        for ((level, index), node) in &tree {
+            nodes.insert(IntegrityManifest::node_key(*level, *index), node)?;
+        }
+
+        let root = self.root_of(document_name, &self.node_at(&tree, 0, 0));
+        document.open_tree(ROOT_TREE)?.insert(ROOT_KEY, &root)?;
+
+        Ok(())
+    }
+    /// Compare the fields of a document with their stored hashes
+    /// and the stored hashes with their Merkle root, which is computed again from all of them
+    pub(crate) fn verify(
+        &self,
+        document_name: &Utf8Path,
+        document: &Document,
+    ) -> TuringResult<Vec<IntegrityViolation>> {
+        let _guard = self.lock()?;
+        let leaves = document.open_tree(LEAVES_TREE)?;
+        let mut violations = Vec::new();
+
+        for field in document.iter() {
+            let (key, value) = field?;
+
+            match leaves.get(self.leaf_key(&key))? {
+                None => violations.push(IntegrityViolation::FieldAdded {
+                    db: self.db_name.clone(),
+                    document: document_name.to_path_buf(),
+                    field: key.to_vec(),
+                }),
+                Some(leaf) => {
+                    if !self.matches(&leaf, &self.leaf(document_name, &key, &value)) {
+                        violations.push(IntegrityViolation::FieldModified {
+                            db: self.db_name.clone(),
+                            document: document_name.to_path_buf(),
+                            field: key.to_vec(),
+                        })
+                    }
+                }
+            }
+        }
+
+        for leaf in leaves.iter() {
+            let (leaf_key, _) = leaf?;
+            let key = &leaf_key[2..];
+
+            if !document.contains_key(key)? {
+                violations.push(IntegrityViolation::FieldRemoved {
+                    db: self.db_name.clone(),
+                    document: document_name.to_path_buf(),
+                    field: key.to_vec(),
+                })
+            }
+        }
+
+        // The field hashes themselves were changed
+        let tree = self.tree(&leaves)?;
+        let root = self.root_of(document_name, &self.node_at(&tree, 0, 0));
+        let root_matches = match document.open_tree(ROOT_TREE)?.get(ROOT_KEY)? {
+            None => false,
+            Some(stored) => self.matches(&stored, &root),
+        };
+
+        if !root_matches {
+            violations.push(IntegrityViolation::DocumentModified {
+                db: self.db_name.clone(),
+                document: document_name.to_path_buf(),
+            })
+        }
+
+        Ok(violations)
+    }
+    /// Hash the values some fields are about to be written with, `None` for a field being removed,
+    /// and compute the buckets they change, the nodes above them and the Merkle root of their document
+    /// once they are written
+    pub(crate) fn stage(
+        &self,
+        document_name: &Utf8Path,
+        document: &Document,
+        fields: &[(&[u8], Option<&[u8]>)],
+    ) -> TuringResult<StagedIntegrity<'_>> {
+        let guard = self.lock()?;
+        let leaves = document.open_tree(LEAVES_TREE)?;
+        let stored_nodes = document.open_tree(NODES_TREE)?;
+
+        let mut staged = BTreeMap::new();
+        for (key, value) in fields {
+            let leaf = value.map(|value| self.leaf(document_name, key, value));
+            staged.insert(self.leaf_key(key), leaf);
+        }
+
+        let mut buckets: BTreeMap<u32, BTreeMap<Vec<u8>, [u8; 32]>> = BTreeMap::new();
+        for (leaf_key, leaf) in &staged {
+            let bucket = u16::from_be_bytes([leaf_key[0], leaf_key[1]]);
+
+            let bucket_leaves = match buckets.entry(bucket as u32) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let mut bucket_leaves = BTreeMap::new();
+                    for stored in leaves.scan_prefix(bucket.to_be_bytes()) {
+                        let (stored_key, stored_leaf) = stored?;
+                        bucket_leaves.insert(stored_key.to_vec(), self.to_hash(&stored_leaf));
+                    }
+
+                    entry.insert(bucket_leaves)
+                }
+            };
+
+            match leaf {
+                None => bucket_leaves.remove(leaf_key),
+                Some(leaf) => bucket_leaves.insert(leaf_key.to_owned(), *leaf),
+            };
+        }
+
+        let mut nodes: BTreeMap<(u8, u32), [u8; 32]> = buckets
+            .into_iter()
+            .map(|(bucket, bucket_leaves)| {
+                let hash = self.bucket_hash(bucket_leaves.values());
+
+                ((BUCKET_BITS, bucket), hash)
+            })
+            .collect();
+
+        for level in (0..BUCKET_BITS).rev() {
+            let parents = nodes
+                .range((level + 1, 0)..=(level + 1, u32::MAX))
+                .map(|((_, index), _)| index / 2)
+                .collect::<BTreeSet<u32>>();
+
+            for parent in parents {
+                let mut children = [[0u8; 32]; 2];
+                for (side, child) in children.iter_mut().enumerate() {
+                    let index = parent * 2 + side as u32;
+
+                    *child = match nodes.get(&(level + 1, index)) {
+                        Some(node) => *node,
+                        None => {
+                            match stored_nodes.get(IntegrityManifest::node_key(level + 1, index))? {
+                                Some(node) => self.to_hash(&node),
+                                None => self.empty[(level + 1) as usize],
+                            }
+                        }
+                    };
+                }
+
+                nodes.insert((level, parent), self.node(&children[0], &children[1]));
+            }
+        }
+
+        let root = self.root_of(document_name, &self.node_at(&nodes, 0, 0));
+
+        Ok(StagedIntegrity {
+            _guard: guard,
+            leaves: staged,
+            nodes: nodes
+                .into_iter()
+                .map(|((level, index), node)| {
+                    let node = match node == self.empty[level as usize] {
+                        true => None,
+                        false => Some(node),
+                    };
+
+                    (IntegrityManifest::node_key(level, index), node)
+                })
+                .collect(),
+            root,
+        })
+    }
+    /// Every node of the Merkle tree over the stored field hashes that is not over empty buckets
+    fn tree(&self, leaves: &sled::Tree) -> TuringResult<BTreeMap<(u8, u32), [u8; 32]>> {
+        let mut buckets: BTreeMap<u32, Vec<[u8; 32]>> = BTreeMap::new();
+
+        // The hashes are stored in the order of their buckets, then of their keys
+        for leaf in leaves.iter() {
+            let (leaf_key, leaf) = leaf?;
+            let bucket = u16::from_be_bytes([leaf_key[0], leaf_key[1]]);
+
+            buckets
+                .entry(bucket as u32)
+                .or_default()
+                .push(self.to_hash(&leaf));
+        }
+
+        let mut tree: BTreeMap<(u8, u32), [u8; 32]> = buckets
+            .into_iter()
+            .map(|(bucket, bucket_leaves)| {
+                (
+                    (BUCKET_BITS, bucket),
+                    self.bucket_hash(bucket_leaves.iter()),
+                )
+            })
+            .collect();
+
+        for level in (0..BUCKET_BITS).rev() {
+            let parents = tree
+                .range((level + 1, 0)..=(level + 1, u32::MAX))
+                .map(|((_, index), _)| index / 2)
+                .collect::<BTreeSet<u32>>();
+
+            for parent in parents {
+                let left = self.node_at(&tree, level + 1, parent * 2);
+                let right = self.node_at(&tree, level + 1, parent * 2 + 1);
+
+                tree.insert((level, parent), self.node(&left, &right));
+            }
+        }
+
+        Ok(tree)
+    }
+    /// A node of `nodes`, or the node over empty buckets at its level
+    fn node_at(&self, nodes: &BTreeMap<(u8, u32), [u8; 32]>, level: u8, index: u32) -> [u8; 32] {
+        match nodes.get(&(level, index)) {
+            Some(node) => *node,
+            None => self.empty[level as usize],
+        }
+    }
+    /// The Merkle root over the root node of the tree bound to the database and document names
+    fn root_of(&self, document_name: &Utf8Path, node: &[u8; 32]) -> [u8; 32] {
+        let mut hasher = self.key.hasher(ROOT_DOMAIN);
+        update_prefixed(&mut hasher, self.db_name.as_str().as_bytes());
+        update_prefixed(&mut hasher, document_name.as_str().as_bytes());
+        update_prefixed(&mut hasher, node);
+
+        *hasher.finalize().as_bytes()
+    }
+
+    fn node(&self, left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+        let mut hasher = self.key.hasher(NODE_DOMAIN);
+        hasher.update(left);
+        hasher.update(right);
+
+        *hasher.finalize().as_bytes()
+    }
+    /// The hash of the field hashes of a bucket, in the order of their keys
+    fn bucket_hash<'l>(&self, leaves: impl Iterator<Item = &'l [u8; 32]>) -> [u8; 32] {
+        let mut hasher = self.key.hasher(BUCKET_DOMAIN);
+        for leaf in leaves {
+            hasher.update(leaf);
+        }
+
+        *hasher.finalize().as_bytes()
+    }
+    /// The key a field hash is stored under, prefixed with the big-endian bucket of the field
+    fn leaf_key(&self, key: &[u8]) -> Vec<u8> {
+        let mut hasher = self.key.hasher(PATH_DOMAIN);
+        update_prefixed(&mut hasher, key);
+
+        let mut leaf_key = hasher.finalize().as_bytes()[..2].to_vec();
+        leaf_key.extend_from_slice(key);
+
+        leaf_key
+    }
+
+    fn node_key(level: u8, index: u32) -> [u8; 5] {
+        let mut node_key = [level, 0, 0, 0, 0];
+        node_key[1..].copy_from_slice(&index.to_be_bytes());
+
+        node_key
+    }
+
+    fn leaf(&self, document_name: &Utf8Path, key: &[u8], value: &[u8]) -> [u8; 32] {
+        let mut hasher = self.key.hasher(LEAF_DOMAIN);
+        update_prefixed(&mut hasher, self.db_name.as_str().as_bytes());
+        update_prefixed(&mut hasher, document_name.as_str().as_bytes());
+        update_prefixed(&mut hasher, key);
+        update_prefixed(&mut hasher, value);
+
+        *hasher.finalize().as_bytes()
+    }
+    /// A stored hash, which no longer matches anything once it does not have 32 bytes
+    fn to_hash(&self, stored: &[u8]) -> [u8; 32] {
+        let mut hash = [0u8; 32];
+
+        if stored.len() == hash.len() {
+            hash.copy_from_slice(stored);
+        }
+
+        hash
+    }
+    /// Compare hashes in constant-time
+    fn matches(&self, stored: &[u8], expected: &[u8; 32]) -> bool {
+        let mut stored_hash = [0u8; 32];
+
+        if stored.len() != stored_hash.len() {
+            return false;
+        }
+        stored_hash.copy_from_slice(stored);
+
+        blake3::Hash::from(stored_hash) == blake3::Hash::from(*expected)
+    }
+
+    fn lock(&self) -> TuringResult<MutexGuard<'_, ()>> {
+        match self.lock.lock() {
+            Ok(guard) => Ok(guard),
+            Err(_) => Err(TuringDbError::Bug(
+                "Integrity manifest lock poisoned".into(),
+            )),
+        }
+    }
+}
+
+/// The hashes of fields about to be written together, the nodes of the Merkle tree above them
+/// and the Merkle root of their document once they are, written in the same transaction as the fields.
+/// The manifest stays locked until it is dropped so no other write changes the hashes in the meantime
+pub(crate) struct StagedIntegrity<'m> {
+    _guard: MutexGuard<'m, ()>,
+    leaves: BTreeMap<Vec<u8>, Option<[u8; 32]>>,
+    nodes: BTreeMap<[u8; 5], Option<[u8; 32]>>,
+    root: [u8; 32],
+}
+
+impl StagedIntegrity<'_> {
+    /// Write the hashes, the nodes and the Merkle root inside the transaction writing the fields
+    pub(crate) fn write(
+        &self,
+        leaves: &TransactionalTree,
+        nodes: &TransactionalTree,
+        root: &TransactionalTree,
+    ) -> ConflictableTransactionResult<(), TuringDbError> {
+        for (key, leaf) in &self.leaves {
+            match leaf {
+                None => leaves.remove(key.as_slice())?,
+                Some(leaf) => leaves.insert(key.as_slice(), leaf)?,
+            };
+        }
+        for (key, node) in &self.nodes {
+            match node {
+                None => nodes.remove(&key[..])?,
+                Some(node) => nodes.insert(&key[..], node)?,
+            };
+        }
+        root.insert(ROOT_KEY, &self.root)?;
+
+        Ok(())
+    }
+}
+
+/// The databases and documents of the repo together with a keyed BLAKE3 hash over them,
+/// stored in the `MANIFEST` file of the repo
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub(crate) struct RepoManifest {
+///     databases: BTreeMap<String, BTreeSet<String>>,
+///     tag: [u8; 32],
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct RepoManifest {
+    databases: BTreeMap<String, BTreeSet<String>>,
+    tag: [u8; 32],
+}
+
+impl RepoManifest {
+    /// Create the manifest of the databases and documents currently in the repo
+    pub(crate) fn new(key: &IntegrityKey, dbs: &DashMap<Utf8PathBuf, Arc<TuringDB>>) -> Self {
+        let databases = dbs
+            .iter()
+            .map(|db| {
+                let db_name = db.key();
+                let documents = db
+                    .documents()
+                    .into_iter()
+                    .map(|(document_name, _)| document_name.into_string())
+                    .collect::<BTreeSet<String>>();
+
+                (db_name.as_str().to_owned(), documents)
+            })
+            .collect::<BTreeMap<String, BTreeSet<String>>>();
+
+        let tag = RepoManifest::tag(key, &databases);
+
+        Self { databases, tag }
+    }
+    /// Report the databases and documents that were added or removed compared to `current`.
+    /// Everything is reported as modified if the manifest itself was changed
+    pub(crate) fn compare(
+        &self,
+        key: &IntegrityKey,
+        current: &RepoManifest,
+    ) -> Vec<IntegrityViolation> {
+        if blake3::Hash::from(self.tag)
+            != blake3::Hash::from(RepoManifest::tag(key, &self.databases))
+        {
+            return vec![IntegrityViolation::ManifestModified];
+        }
+
+        let mut violations = Vec::new();
+
+        for (db_name, documents) in &current.databases {
+            match self.databases.get(db_name) {
+                None => violations.push(IntegrityViolation::DatabaseAdded(db_name.into())),
+                Some(expected) => documents.difference(expected).for_each(|document_name| {
+                    violations.push(IntegrityViolation::DocumentAdded {
+                        db: db_name.into(),
+                        document: document_name.into(),
+                    })
+                }),
+            }
+        }
+
+        for (db_name, expected) in &self.databases {
+            match current.databases.get(db_name) {
+                None => violations.push(IntegrityViolation::DatabaseRemoved(db_name.into())),
+                Some(documents) => expected.difference(documents).for_each(|document_name| {
+                    violations.push(IntegrityViolation::DocumentRemoved {
+                        db: db_name.into(),
+                        document: document_name.into(),
+                    })
+                }),
+            }
+        }
+
+        violations
+    }
+    pub(crate) fn to_bytes(&self) -> TuringResult<Vec<u8>> {
+        match bincode::serialize::<RepoManifest>(self) {
+            Ok(bytes) => Ok(bytes),
+            Err(_) => Err(TuringDbError::Bug(
+                "Unable to serialize the repo manifest".into(),
+            )),
+        }
+    }
+
+    pub(crate) fn from_bytes(value: &[u8]) -> Option<RepoManifest> {
+        bincode::deserialize::<RepoManifest>(value).ok()
+    }
+
+    fn tag(key: &IntegrityKey, databases: &BTreeMap<String, BTreeSet<String>>) -> [u8; 32] {
+        let mut hasher = key.hasher(MANIFEST_DOMAIN);
+
+        for (db_name, documents) in databases {
+            update_prefixed(&mut hasher, db_name.as_bytes());
+            hasher.update(&(documents.len() as u64).to_le_bytes());
+
+            for document_name in documents {
+                update_prefixed(&mut hasher, document_name.as_bytes());
+            }
+        }
+
+        *hasher.finalize().as_bytes()
+    }
+}
+
+/// Each part is prefixed with its length to keep the encoding unambiguous
+fn update_prefixed(hasher: &mut blake3::Hasher, part: &[u8]) {
+    hasher.update(&(part.len() as u64).to_le_bytes());
+    hasher.update(part);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{t_engine::testing::*, OpsOutcome, TuringEngine};
+    use futures_lite::future::block_on;
+
+    async fn open(repo: &Utf8Path) -> TuringResult<TuringEngine> {
+        let mut engine = TuringEngine::builder()
+            .repo_dir(repo.to_path_buf())
+            .build()
+            .await?;
+        engine.repo_init().await?;
+
+        Ok(engine)
+    }
+    /// A repo whose document holds the fields `alice` and `bob`, closed so it can be changed outside TuringDB
+    async fn closed_repo(dir: &TestDir) -> Utf8PathBuf {
+        let repo = dir.path().join("repo");
+        let engine = test_engine(&repo, false).await;
+        field_set(&engine, "alice", "admin").await.unwrap();
+        field_set(&engine, "bob", "user").await.unwrap();
+
+        repo
+    }
+
+    fn stored_document(repo: &Utf8Path) -> sled::Db {
+        sled::open(repo.join(DB).join(DOCUMENT)).unwrap()
+    }
+    /// The key the hash of the field `key` is stored under
+    fn stored_leaf_key(document: &sled::Db, key: &[u8]) -> sled::IVec {
+        document
+            .open_tree(LEAVES_TREE)
+            .unwrap()
+            .iter()
+            .keys()
+            .map(|leaf_key| leaf_key.unwrap())
+            .find(|leaf_key| &leaf_key[2..] == key)
+            .unwrap()
+    }
+
+    async fn violations(repo: &Utf8Path) -> Vec<IntegrityViolation> {
+        match open(repo).await {
+            Err(TuringDbError::IntegrityViolation(violations)) => violations,
+            outcome => panic!("Unexpected outcome {:?}", outcome.map(|_| ())),
+        }
+    }
+
+    fn document_modified() -> IntegrityViolation {
+        IntegrityViolation::DocumentModified {
+            db: DB.into(),
+            document: DOCUMENT.into(),
+        }
+    }
+
+    #[test]
+    fn the_root_updated_on_every_write_matches_the_root_of_all_the_fields() {
+        block_on(async {
+            let dir = TestDir::new("integrity-incremental");
+            let engine = test_engine(&dir.path().join("repo"), false).await;
+
+            for index in 0..64 {
+                field_set(&engine, &format!("field-{}", index), "before")
+                    .await
+                    .unwrap();
+            }
+            for index in (0..64).step_by(3) {
+                engine
+                    .field_modify(&field_ops(&format!("field-{}", index), "after"))
+                    .await
+                    .unwrap();
+            }
+            for index in (0..64).step_by(5) {
+                engine
+                    .field_remove(&field_ops(&format!("field-{}", index), ""))
+                    .await
+                    .unwrap();
+            }
+
+            assert_eq!(engine.repo_verify().await, Ok(OpsOutcome::RepoVerified));
+        })
+    }
+
+    #[test]
+    fn fields_modified_added_and_removed_outside_turingdb_are_reported() {
+        block_on(async {
+            let dir = TestDir::new("integrity-fields");
+            let repo = closed_repo(&dir).await;
+
+            let document = stored_document(&repo);
+            let bob = document.get("bob").unwrap().unwrap();
+            document.insert("alice", bob.clone()).unwrap();
+            document.insert("carol", bob).unwrap();
+            document.remove("bob").unwrap();
+            document.flush().unwrap();
+            drop(document);
+
+            let mut expected = vec![
+                IntegrityViolation::FieldModified {
+                    db: DB.into(),
+                    document: DOCUMENT.into(),
+                    field: b"alice".to_vec(),
+                },
+                IntegrityViolation::FieldAdded {
+                    db: DB.into(),
+                    document: DOCUMENT.into(),
+                    field: b"carol".to_vec(),
+                },
+                IntegrityViolation::FieldRemoved {
+                    db: DB.into(),
+                    document: DOCUMENT.into(),
+                    field: b"bob".to_vec(),
+                },
+            ];
+            expected.sort();
+            assert_eq!(violations(&repo).await, expected);
+        })
+    }
+
+    #[test]
+    fn a_field_removed_together_with_its_hash_is_reported_as_a_modified_document() {
+        block_on(async {
+            let dir = TestDir::new("integrity-hash-removed");
+            let repo = closed_repo(&dir).await;
+
+            let document = stored_document(&repo);
+            let leaf_key = stored_leaf_key(&document, b"bob");
+            document.remove("bob").unwrap();
+            document
+                .open_tree(LEAVES_TREE)
+                .unwrap()
+                .remove(leaf_key)
+                .unwrap();
+            document.flush().unwrap();
+            drop(document);
+
+            assert_eq!(violations(&repo).await, vec![document_modified()]);
+        })
+    }
+
+    #[test]
+    fn a_modified_document_root_is_reported() {
+        block_on(async {
+            let dir = TestDir::new("integrity-root");
+            let repo = closed_repo(&dir).await;
+
+            let document = stored_document(&repo);
+            document
+                .open_tree(ROOT_TREE)
+                .unwrap()
+                .insert(ROOT_KEY, &[0u8; 32])
+                .unwrap();
+            document.flush().unwrap();
+            drop(document);
+
+            assert_eq!(violations(&repo).await, vec![document_modified()]);
+        })
+    }
+
+    #[test]
+    fn a_lost_integrity_key_is_only_replaced_when_the_manifests_are_rebuilt() {
+        block_on(async {
+            let dir = TestDir::new("integrity-key-lost");
+            let repo = closed_repo(&dir).await;
+            std::fs::remove_file(repo.join(INTEGRITY_KEY_FILE)).unwrap();
+
+            assert_eq!(
+                open(&repo).await.err(),
+                Some(TuringDbError::IntegrityKeyMissing)
+            );
+
+            let mut engine = TuringEngine::builder()
+                .repo_dir(repo.to_path_buf())
+                .rebuild_manifests(true)
+                .build()
+                .await
+                .unwrap();
+            engine.repo_init().await.unwrap();
+            assert_eq!(field_value(&engine, "bob").await, Some(b"user".to_vec()));
+            drop(engine);
+
+            let engine = open(&repo).await.unwrap();
+            assert_eq!(engine.repo_verify().await, Ok(OpsOutcome::RepoVerified));
+        })
+    }
+}
@@ -0,0 +1,260 @@
+use crate::{
+    DataType, FieldData, FieldWrite, OpsOutcome, TDBCell, TuringDB, TuringDbError, TuringResult,
+};
+use camino::Utf8Path;
+use serde_json::Value;
+use sled::IVec;
+use zeroize::Zeroizing;
+
+/// A single step into a JSON value, either an object key or an array index
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JsonPathSegment {
+    Key(String),
+    Index(usize),
+}
+
+/// A parsed path into a JSON value like `user.address.city` or `user.phones[1]`.
+/// An empty path points to the whole JSON value
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq)]
+/// pub struct JsonPath(Vec<JsonPathSegment>);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonPath(Vec<JsonPathSegment>);
+
+impl JsonPath {
+    /// Parse a path made up of keys separated by `.` with optional `[index]` suffixes
+    pub fn parse(path: &str) -> TuringResult<JsonPath> {
+        let mut segments = Vec::new();
+
+        if path.is_empty() {
+            return Ok(JsonPath(segments));
+        }
+
+        for part in path.split('.') {
+            let (key, mut indices) = match part.find('[') {
+                Some(position) => (&part[..position], &part[position..]),
+                None => (part, ""),
+            };
+
+            if key.is_empty() && indices.is_empty() {
+                return Err(TuringDbError::JsonPathInvalid(path.to_owned()));
+            }
+
+            if !key.is_empty() {
+                segments.push(JsonPathSegment::Key(key.to_owned()));
+            }
+
+            while !indices.is_empty() {
+                let end = match (indices.starts_with('['), indices.find(']')) {
+                    (true, Some(end)) => end,
+                    _ => return Err(TuringDbError::JsonPathInvalid(path.to_owned())),
+                };
+
+                match indices[1..end].parse::<usize>() {
+                    Ok(index) => segments.push(JsonPathSegment::Index(index)),
+                    Err(_) => return Err(TuringDbError::JsonPathInvalid(path.to_owned())),
+                }
+
+                indices = &indices[end + 1..];
+            }
+        }
+
+        Ok(JsonPath(segments))
+    }
+    /// Check whether the path points to the whole JSON value
+    pub fn is_root(&self) -> bool {
+        self.0.is_empty()
+    }
+    /// Get a reference to the value the path points to
+    pub fn get<'v>(&self, root: &'v Value) -> Option<&'v Value> {
+        self.0
+            .iter()
+            .try_fold(root, |current, segment| match segment {
+                JsonPathSegment::Key(key) => current.get(key),
+                JsonPathSegment::Index(index) => current.get(index),
+            })
+    }
+    /// Replace the value the path points to. Missing object keys are created along the way
+    /// and an index equal to the length of an array appends to that array
+    pub fn set(&self, root: &mut Value, new_value: Value) -> TuringResult<()> {
+        let mut current = root;
+
+        for segment in &self.0 {
+            current = match segment {
+                JsonPathSegment::Key(key) => {
+                    if current.is_null() {
+                        *current = Value::Object(serde_json::Map::new());
+                    }
+
+                    match current {
+                        Value::Object(map) => map.entry(key.to_owned()).or_insert(Value::Null),
+                        _ => return Err(TuringDbError::JsonPathNotFound(self.to_string())),
+                    }
+                }
+                JsonPathSegment::Index(index) => match current {
+                    Value::Array(array) => {
+                        if *index == array.len() {
+                            array.push(Value::Null);
+                        }
+
+                        match array.get_mut(*index) {
+                            Some(value) => value,
+                            None => return Err(TuringDbError::JsonPathNotFound(self.to_string())),
+                        }
+                    }
+                    _ => return Err(TuringDbError::JsonPathNotFound(self.to_string())),
+                },
+            };
+        }
+
+        *current = new_value;
+
+        Ok(())
+    }
+    /// Remove the value the path points to and return it
+    pub fn remove(&self, root: &mut Value) -> TuringResult<Value> {
+        let (last, parents) = match self.0.split_last() {
+            None => return Err(TuringDbError::JsonPathInvalid(self.to_string())),
+            Some(split) => split,
+        };
+
+        let parent = JsonPath(parents.to_vec());
+        let parent_value = match parent.get_mut(root) {
+            None => return Err(TuringDbError::JsonPathNotFound(self.to_string())),
+            Some(value) => value,
+        };
+
+        let removed = match (last, parent_value) {
+            (JsonPathSegment::Key(key), Value::Object(map)) => map.remove(key),
+            (JsonPathSegment::Index(index), Value::Array(array)) if *index < array.len() => {
+                Some(array.remove(*index))
+            }
+            _ => None,
+        };
+
+        match removed {
+            Some(value) => Ok(value),
+            None => Err(TuringDbError::JsonPathNotFound(self.to_string())),
+        }
+    }
+
+    fn get_mut<'v>(&self, root: &'v mut Value) -> Option<&'v mut Value> {
+        self.0
+            .iter()
+            .try_fold(root, |current, segment| match segment {
+                JsonPathSegment::Key(key) => current.get_mut(key),
+                JsonPathSegment::Index(index) => current.get_mut(index),
+            })
+    }
+}
+
+impl std::fmt::Display for JsonPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (position, segment) in self.0.iter().enumerate() {
+            match segment {
+                JsonPathSegment::Key(key) if position == 0 => write!(f, "{}", key)?,
+                JsonPathSegment::Key(key) => write!(f, ".{}", key)?,
+                JsonPathSegment::Index(index) => write!(f, "[{}]", index)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl TuringDB {
+    /// Insert a JSON value into a field, failing if the field already exists
+    pub(crate) async fn json_set(
+        &self,
+        document_name: &Utf8Path,
+        key: &[u8],
+        value: &Value,
+    ) -> TuringResult<OpsOutcome> {
+        let cell = TDBCell::new(DataType::JSON, &TuringDB::json_to_bytes(value)?);
+
+        self.field_set(document_name, IVec::from(key), &cell.to_bytes(), None)
+            .await
+    }
+    /// Read the JSON value found at `path` inside a field
+    pub(crate) async fn json_get(
+        &self,
+        document_name: &Utf8Path,
+        key: &[u8],
+        path: &JsonPath,
+    ) -> TuringResult<Value> {
+        let field_data = self.field_get(document_name, key).await?;
+        let json = TuringDB::json_from_field(&field_data)?;
+
+        match path.get(&json) {
+            Some(value) => Ok(value.to_owned()),
+            None => Err(TuringDbError::JsonPathNotFound(path.to_string())),
+        }
+    }
+    /// Apply `change` to the JSON value stored in a field without the caller reading the
+    /// whole value first. The write is a compare-and-swap so concurrent partial updates
+    /// to the same field are retried instead of overwriting each other
+    pub(crate) async fn json_update<F>(
+        &self,
+        document_name: &Utf8Path,
+        key: &[u8],
+        change: F,
+    ) -> TuringResult<OpsOutcome>
+    where
+        F: Fn(&mut Value) -> TuringResult<()>,
+    {
+        let sled_db = match self.document(document_name) {
+            None => return Err(TuringDbError::DocumentNotFound),
+            Some(sled_db) => sled_db,
+        };
+
+        self.expire_if_due(document_name, key).await?;
+        let _writing = self.write_lock().await;
+        let mut capture = self.cdc_capture().await?;
+
+        loop {
+            let current = match sled_db.get(key)? {
+                None => return Err(TuringDbError::FieldNotFound),
+                Some(current) => current,
+            };
+
+            let mut field_data = self.unseal(document_name, key, &current)?;
+            let mut json = TuringDB::json_from_field(&field_data)?;
+            change(&mut json)?;
+
+            let cell = TDBCell::new(DataType::JSON, &TuringDB::json_to_bytes(&json)?);
+            field_data.update(&cell.to_bytes());
+            let sealed = self.seal(document_name, key, &field_data)?;
+
+            let write = FieldWrite::new(key, Some(&sealed))
+                .expect(Some(&current))
+                .replaced(field_data.modified());
+
+            if self.field_swap(document_name, &sled_db, write, capture.as_mut())? {
+                self.cdc_write(capture).await;
+
+                return Ok(OpsOutcome::FieldModified);
+            }
+        }
+    }
+
+    fn json_from_field(field_data: &FieldData) -> TuringResult<Value> {
+        let cell = TDBCell::from_bytes(field_data.data())?;
+
+        if cell.get_data_type() != DataType::JSON {
+            return Err(TuringDbError::FieldNotJson);
+        }
+
+        match serde_json::from_slice::<Value>(cell.get_data()) {
+            Ok(value) => Ok(value),
+            Err(error) => Err(TuringDbError::JsonInvalid(error.to_string())),
+        }
+    }
+
+    fn json_to_bytes(value: &Value) -> TuringResult<Zeroizing<Vec<u8>>> {
+        match serde_json::to_vec(value) {
+            Ok(bytes) => Ok(Zeroizing::new(bytes)),
+            Err(error) => Err(TuringDbError::JsonInvalid(error.to_string())),
+        }
+    }
+}
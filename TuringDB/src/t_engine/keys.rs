@@ -0,0 +1,336 @@
+use crate::{
+    Cipher, CipherErrors, CipherOps, DataKeys, Document, FieldData, FieldWrite, RepoPath, TuringDB,
+    TuringDbError, TuringResult, WrappedDataKeys, DATA_KEYS_FILE,
+};
+use camino::{Utf8Path, Utf8PathBuf};
+use secrecy::{ExposeSecret, Secret};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Seals and opens the field values of the encrypted documents of a database using its data keys.
+/// It is cheap to clone so that a background key rotation can share the keys with the database.
+/// `failure` holds why the last re-encryption of the database stopped
+/// ```
+/// #[derive(Debug, Clone)]
+/// pub(crate) struct FieldSealer {
+///     db_name: Utf8PathBuf,
+///     keys: Arc<RwLock<Option<DataKeys>>>,
+///     failure: Arc<RwLock<Option<TuringDbError>>>,
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub(crate) struct FieldSealer {
+    db_name: Utf8PathBuf,
+    keys: Arc<RwLock<Option<DataKeys>>>,
+    failure: Arc<RwLock<Option<TuringDbError>>>,
+}
+
+impl FieldSealer {
+    pub(crate) fn new(db_name: &Utf8Path) -> Self {
+        Self {
+            db_name: db_name.to_path_buf(),
+            keys: Arc::new(RwLock::new(None)),
+            failure: Arc::new(RwLock::new(None)),
+        }
+    }
+    /// Check whether the data keys of the database have been loaded
+    pub(crate) fn has_keys(&self) -> TuringResult<bool> {
+        Ok(self.read_keys()?.is_some())
+    }
+    /// Check whether the data of the database is being re-encrypted with a new data key
+    pub(crate) fn is_rotating(&self) -> TuringResult<bool> {
+        match &*self.read_keys()? {
+            Some(keys) => Ok(keys.previous.is_some()),
+            None => Ok(false),
+        }
+    }
+
+    /// Why the last re-encryption stopped before every field was sealed with the current data key
+    pub(crate) fn failure(&self) -> TuringResult<Option<TuringDbError>> {
+        match self.failure.read() {
+            Ok(failure) => Ok(failure.clone()),
+            Err(_) => Err(TuringDbError::Bug(
+                "Key rotation failure lock poisoned".into(),
+            )),
+        }
+    }
+
+    fn set_failure(&self, error: Option<TuringDbError>) -> TuringResult<()> {
+        match self.failure.write() {
+            Ok(mut failure) => {
+                *failure = error;
+
+                Ok(())
+            }
+            Err(_) => Err(TuringDbError::Bug(
+                "Key rotation failure lock poisoned".into(),
+            )),
+        }
+    }
+
+    pub(crate) fn keys(&self) -> TuringResult<Option<DataKeys>> {
+        Ok(self.read_keys()?.clone())
+    }
+
+    pub(crate) fn set_keys(&self, keys: DataKeys) -> TuringResult<()> {
+        *self.write_keys()? = Some(keys);
+
+        Ok(())
+    }
+    /// Encrypt the data of a `FieldData` with the current data key
+    pub(crate) fn seal(
+        &self,
+        document_name: &Utf8Path,
+        key: &[u8],
+        field_data: &FieldData,
+    ) -> TuringResult<Vec<u8>> {
+        let keys = self.read_keys()?;
+
+        match &*keys {
+            None => Err(TuringDbError::EncryptionKeyMissing),
+            Some(keys) => {
+                let associated_data = self.associated_data(document_name, key);
+                let ciphertext = keys
+                    .current
+                    .encrypt(Secret::new(field_data.data().to_vec()), &associated_data)?;
+
+                field_data.with_data(&ciphertext).to_bytes()
+            }
+        }
+    }
+    /// Decrypt the data of a `FieldData`, falling back to the previous data key
+    /// while a key rotation is in progress
+    pub(crate) fn unseal(
+        &self,
+        document_name: &Utf8Path,
+        key: &[u8],
+        value: &[u8],
+    ) -> TuringResult<FieldData> {
+        self.open(document_name, key, value)
+            .map(|(field_data, _)| field_data)
+    }
+
+    /// Returns the opened `FieldData` and whether it was sealed with the current data key
+    pub(crate) fn open(
+        &self,
+        document_name: &Utf8Path,
+        key: &[u8],
+        value: &[u8],
+    ) -> TuringResult<(FieldData, bool)> {
+        let field_data = FieldData::from_bytes(value)?;
+        let keys = self.read_keys()?;
+
+        let keys = match &*keys {
+            None => return Err(TuringDbError::EncryptionKeyMissing),
+            Some(keys) => keys,
+        };
+
+        let associated_data = self.associated_data(document_name, key);
+
+        match (
+            keys.current.decrypt(field_data.data(), &associated_data),
+            &keys.previous,
+        ) {
+            (Ok(plaintext), _) => Ok((field_data.with_data(plaintext.expose_secret()), true)),
+            (Err(CipherErrors::DecryptionError), Some(previous)) => {
+                let plaintext = previous.decrypt(field_data.data(), &associated_data)?;
+
+                Ok((field_data.with_data(plaintext.expose_secret()), false))
+            }
+            (Err(error), _) => Err(error.into()),
+        }
+    }
+    /// Re-encrypt every field that is still sealed with the previous data key.
+    /// Each field is written through `writer` so its hash in the integrity manifest changes with it
+    pub(crate) fn reencrypt(
+        &self,
+        writer: &TuringDB,
+        documents: &[(Utf8PathBuf, Document)],
+    ) -> TuringResult<()> {
+        for (document_name, document) in documents {
+            for field in document.iter() {
+                let (key, mut value) = field?;
+
+                loop {
+                    let (field_data, is_current) = self.open(document_name, &key, &value)?;
+
+                    if is_current {
+                        break;
+                    }
+
+                    let sealed = self.seal(document_name, &key, &field_data)?;
+                    let write = FieldWrite::new(&key, Some(&sealed)).expect(Some(&value));
+
+                    if writer.field_swap(document_name, document, write, None)? {
+                        break;
+                    }
+
+                    // The field was changed or removed while it was being re-encrypted
+                    match document.get(&key)? {
+                        None => break,
+                        Some(current) => value = current,
+                    }
+                }
+            }
+
+            self.reencrypt_history(document_name, document)?;
+        }
+
+        Ok(())
+    }
+    /// Forget the previous data key once all data is sealed with the current one
+    pub(crate) fn finish_rotation(
+        &self,
+        db_dir: &Utf8Path,
+        master_key: &Cipher,
+    ) -> TuringResult<()> {
+        let mut keys = self.write_keys()?;
+
+        if let Some(keys) = &mut *keys {
+            let current = DataKeys {
+                current: keys.current.clone(),
+                previous: None,
+            };
+
+            let wrapped = current.wrap(master_key, self.db_name.as_str())?;
+            RepoPath::replace_file(&db_dir.join(DATA_KEYS_FILE), &wrapped.to_bytes()?)?;
+
+            keys.previous = None;
+        }
+
+        Ok(())
+    }
+
+    /// Binds a sealed value to the database, document and field it was written to
+    /// so that it cannot be copied to another field and still decrypt.
+    /// Each name is prefixed with its length to keep the encoding unambiguous
+    fn associated_data(&self, document_name: &Utf8Path, key: &[u8]) -> Vec<u8> {
+        let mut associated_data = Vec::new();
+
+        for part in &[
+            self.db_name.as_str().as_bytes(),
+            document_name.as_str().as_bytes(),
+            key,
+        ] {
+            associated_data.extend_from_slice(&(part.len() as u64).to_le_bytes());
+            associated_data.extend_from_slice(part);
+        }
+
+        associated_data
+    }
+
+    fn read_keys(&self) -> TuringResult<RwLockReadGuard<'_, Option<DataKeys>>> {
+        match self.keys.read() {
+            Ok(keys) => Ok(keys),
+            Err(_) => Err(TuringDbError::Bug("Data keys lock poisoned".into())),
+        }
+    }
+
+    fn write_keys(&self) -> TuringResult<RwLockWriteGuard<'_, Option<DataKeys>>> {
+        match self.keys.write() {
+            Ok(keys) => Ok(keys),
+            Err(_) => Err(TuringDbError::Bug("Data keys lock poisoned".into())),
+        }
+    }
+}
+
+impl TuringDB {
+    /// Read and unwrap the data keys of a database if it has any
+    pub(crate) async fn load_data_keys(
+        &self,
+        db_dir: &Utf8Path,
+        master_key: Option<&Cipher>,
+    ) -> TuringResult<()> {
+        let wrapped = match async_fs::read(db_dir.join(DATA_KEYS_FILE)).await {
+            Ok(wrapped) => WrappedDataKeys::from_bytes(&wrapped)?,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(error) => return Err(error.into()),
+        };
+
+        match master_key {
+            // The database can still be listed but its encrypted documents cannot be read
+            None => Ok(()),
+            Some(master_key) => {
+                let keys = wrapped.unwrap(master_key, self.sealer.db_name.as_str())?;
+
+                self.sealer.set_keys(keys)
+            }
+        }
+    }
+    /// Generate and store the data keys of a database the first time it needs them
+    pub(crate) async fn ensure_data_keys(
+        &self,
+        db_dir: &Utf8Path,
+        master_key: Option<&Cipher>,
+    ) -> TuringResult<()> {
+        if self.sealer.has_keys()? {
+            return Ok(());
+        }
+
+        let master_key = match master_key {
+            None => return Err(TuringDbError::EncryptionKeyMissing),
+            Some(master_key) => master_key,
+        };
+
+        let keys = DataKeys::generate(master_key)?;
+        self.write_data_keys(db_dir, &keys, master_key).await?;
+
+        self.sealer.set_keys(keys)
+    }
+    /// Seal the data keys with the master key and store them in the database directory
+    pub(crate) async fn write_data_keys(
+        &self,
+        db_dir: &Utf8Path,
+        keys: &DataKeys,
+        master_key: &Cipher,
+    ) -> TuringResult<()> {
+        let wrapped = keys.wrap(master_key, self.sealer.db_name.as_str())?;
+        RepoPath::replace(&db_dir.join(DATA_KEYS_FILE), wrapped.to_bytes()?).await
+    }
+    /// The data keys sealed with the new master key of a master key change,
+    /// `None` if the database has no data keys
+    pub(crate) fn rewrap_data_keys(&self, master_key: &Cipher) -> TuringResult<Option<Vec<u8>>> {
+        match self.sealer.keys()? {
+            None => Ok(None),
+            Some(keys) => Ok(Some(
+                keys.wrap(master_key, self.sealer.db_name.as_str())?
+                    .to_bytes()?,
+            )),
+        }
+    }
+    /// Re-encrypt all the encrypted documents with the current data key on a background thread
+    pub(crate) fn start_reencryption(
+        &self,
+        db_dir: &Utf8Path,
+        master_key: &Cipher,
+    ) -> TuringResult<()> {
+        self.sealer.set_failure(None)?;
+
+        let sealer = self.sealer.clone();
+        let db_dir = db_dir.to_path_buf();
+        let master_key = master_key.clone();
+        // A re-encrypted field holds the same value so no previous version is kept for it
+        let writer = TuringDB::new().with_history(false);
+        let writer = match &self.integrity {
+            None => writer,
+            Some(integrity) => writer.with_integrity(integrity.clone()),
+        };
+        let documents = self
+            .documents()
+            .into_iter()
+            .filter(|(document_name, _)| self.is_encrypted(document_name))
+            .collect::<Vec<(Utf8PathBuf, Document)>>();
+
+        std::thread::spawn(move || {
+            let outcome = sealer
+                .reencrypt(&writer, &documents)
+                .and_then(|_| sealer.finish_rotation(&db_dir, &master_key));
+
+            // Kept for `TuringEngine::data_key_rotation_failure()` until the rotation is retried
+            if let Err(error) = outcome {
+                let _ = sealer.set_failure(Some(error));
+            }
+        });
+
+        Ok(())
+    }
+}
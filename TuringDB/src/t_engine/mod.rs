@@ -3,3 +3,50 @@ pub(crate) use database::TuringDB;
 mod engine;
 pub use engine::*;
 mod fields;
+pub use fields::FieldData;
+mod json;
+pub use json::*;
+mod integrity;
+pub(crate) use integrity::{IntegrityKey, IntegrityManifest, RepoManifest, LEAVES_TREE, NODES_TREE, ROOT_TREE};
+mod keys;
+pub(crate) use keys::FieldSealer;
+mod ops_log;
+pub use ops_log::*;
+mod audit;
+pub use audit::*;
+mod changefeed;
+pub use changefeed::*;
+mod cdc;
+pub use cdc::*;
+mod replication;
+pub use replication::*;
+mod history;
+pub use history::HistoryRetention;
+pub(crate) use history::{history_insert, HISTORY_TREE};
+mod expiry;
+pub(crate) use expiry::{expires_after, EXPIRY_TREE};
+mod export;
+pub use export::ExportFormat;
+pub(crate) use export::ExportRecord;
+mod bulk;
+pub use bulk::{BulkOptions, BulkProgress, BulkRecord, BulkRejection};
+mod batch;
+pub use batch::{BatchOperation, BatchResult};
+mod index;
+pub(crate) use index::{index_move, INDEX_ENTRIES_TREE};
+pub use index::{IndexDefinition, IndexLookup, IndexValue};
+mod write;
+pub(crate) use write::{Expiry, FieldWrite};
+mod backup;
+pub(crate) use backup::BackupContents;
+pub use backup::{BackupEntry, BackupManifest};
+mod raft;
+pub use raft::*;
+mod raft_log;
+pub(crate) use raft_log::RaftStorage;
+mod consensus;
+pub use consensus::*;
+mod raft_cluster;
+pub use raft_cluster::*;
+#[cfg(test)]
+mod testing;
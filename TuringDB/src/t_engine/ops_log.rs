@@ -0,0 +1,438 @@
+use crate::{ExportFormat, OpsOutcome, TuringDbError, TuringResult, OPS_LOG_FILE};
+use async_fs::{File, OpenOptions};
+use async_lock::Mutex;
+use camino::{Utf8Path, Utf8PathBuf};
+use futures_lite::io::AsyncWriteExt;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{convert::TryInto, io::ErrorKind};
+use tai64::{TAI64, TAI64N};
+
+/// Rotate the ops.log once it grows past 16MiB
+const DEFAULT_MAX_SIZE: u64 = 16 * 1024 * 1024;
+/// Keep `ops.log.1` to `ops.log.8` after rotating
+const DEFAULT_MAX_FILES: usize = 8;
+/// Every record starts with the length of the entry and its seahash checksum
+const RECORD_HEADER_LEN: usize = 4 + 8;
+
+/// A mutation applied to the repo.
+/// Field values are never logged so the ops.log does not leak the contents of encrypted documents
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LoggedOperation {
+    RepoCreate,
+    DbCreate {
+        db: String,
+        encrypted: bool,
+    },
+    DbDrop {
+        db: String,
+    },
+    DocumentCreate {
+        db: String,
+        document: String,
+        encrypted: bool,
+    },
+    DocumentDrop {
+        db: String,
+        document: String,
+    },
+    FieldInsert {
+        db: String,
+        document: String,
+        field: Vec<u8>,
+    },
+    FieldModify {
+        db: String,
+        document: String,
+        field: Vec<u8>,
+    },
+    FieldRemove {
+        db: String,
+        document: String,
+        field: Vec<u8>,
+    },
+    JsonSet {
+        db: String,
+        document: String,
+        field: Vec<u8>,
+    },
+    JsonModify {
+        db: String,
+        document: String,
+        field: Vec<u8>,
+        path: String,
+    },
+    JsonRemove {
+        db: String,
+        document: String,
+        field: Vec<u8>,
+        path: String,
+    },
+    DataKeyRotate {
+        db: String,
+    },
+    MasterKeyRotate,
+    ReplicaBootstrap {
+        db: String,
+        position: u64,
+    },
+    ReplicaApply {
+        db: String,
+        from: u64,
+        to: u64,
+    },
+    ReplicaDrop {
+        db: String,
+    },
+    RepoRestore {
+        databases: Vec<String>,
+    },
+    DbImport {
+        db: String,
+    },
+    BackupRestore {
+        backups: Vec<String>,
+        until: Option<TAI64N>,
+    },
+    RepoRewind {
+        at: TAI64N,
+    },
+    HistoryRetentionSet {
+        db: String,
+        document: String,
+    },
+    Import {
+        db: String,
+        format: ExportFormat,
+    },
+    BulkInsert {
+        db: String,
+        records: u64,
+    },
+    Batch {
+        db: String,
+        operations: u64,
+        atomic: bool,
+    },
+    FieldsExpire {
+        db: String,
+        fields: u64,
+    },
+    IndexCreate {
+        db: String,
+        document: String,
+        index: String,
+    },
+    IndexDrop {
+        db: String,
+        document: String,
+        index: String,
+    },
+}
+
+/// Whether a logged mutation succeeded, with the error if it failed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LoggedOutcome {
+    Success,
+    Failure(String),
+}
+
+impl From<&TuringResult<OpsOutcome>> for LoggedOutcome {
+    fn from(outcome: &TuringResult<OpsOutcome>) -> Self {
+        match outcome {
+            Ok(_) => LoggedOutcome::Success,
+            Err(error) => LoggedOutcome::Failure(format!("{:?}", error)),
+        }
+    }
+}
+
+/// A single record of the ops.log
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct OpsLogEntry {
+///     pub timestamp: TAI64N,
+///     pub actor: String,
+///     pub operation: LoggedOperation,
+///     pub outcome: LoggedOutcome,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpsLogEntry {
+    pub timestamp: TAI64N,
+    pub actor: String,
+    pub operation: LoggedOperation,
+    pub outcome: LoggedOutcome,
+}
+
+impl OpsLogEntry {
+    /// Encode the entry as `length || seahash checksum || entry`
+    fn to_record(&self) -> TuringResult<Vec<u8>> {
+        encode_record(self, "Unable to serialize an ops.log entry")
+    }
+}
+
+/// Encode an entry as `length || seahash checksum || entry`, the record layout
+/// shared by the ops.log and the CDC logs
+pub(crate) fn encode_record<T: Serialize>(entry: &T, bug: &str) -> TuringResult<Vec<u8>> {
+    let entry = match bincode::serialize::<T>(entry) {
+        Ok(entry) => entry,
+        Err(_) => return Err(TuringDbError::Bug(bug.into())),
+    };
+
+    let mut record = Vec::with_capacity(RECORD_HEADER_LEN + entry.len());
+    record.extend_from_slice(&(entry.len() as u32).to_le_bytes());
+    record.extend_from_slice(&seahash::hash(&entry).to_le_bytes());
+    record.extend_from_slice(&entry);
+
+    Ok(record)
+}
+
+/// Decode the records of a log file, returning them with the length of the complete records.
+/// An incomplete record at the end of the file is the result of a crash while writing
+/// and is skipped, any other damaged record is reported using `corrupted` with its offset
+pub(crate) fn decode_records<T, F>(contents: &[u8], corrupted: F) -> TuringResult<(Vec<T>, usize)>
+where
+    T: DeserializeOwned,
+    F: Fn(u64) -> TuringDbError,
+{
+    let mut entries = Vec::new();
+    let mut offset = 0usize;
+
+    while contents.len() - offset >= RECORD_HEADER_LEN {
+        let header = &contents[offset..offset + RECORD_HEADER_LEN];
+        let len = match header[..4].try_into() {
+            Ok(len) => u32::from_le_bytes(len) as usize,
+            Err(_) => return Err(corrupted(offset as u64)),
+        };
+        let checksum = match header[4..].try_into() {
+            Ok(checksum) => u64::from_le_bytes(checksum),
+            Err(_) => return Err(corrupted(offset as u64)),
+        };
+
+        let start = offset + RECORD_HEADER_LEN;
+        if contents.len() - start < len {
+            break;
+        }
+
+        let entry = &contents[start..start + len];
+        if seahash::hash(entry) != checksum {
+            return Err(corrupted(offset as u64));
+        }
+
+        match bincode::deserialize::<T>(entry) {
+            Ok(entry) => entries.push(entry),
+            Err(_) => return Err(corrupted(offset as u64)),
+        }
+
+        offset = start + len;
+    }
+
+    Ok((entries, offset))
+}
+
+#[derive(Debug)]
+struct OpsLogWriter {
+    file: Option<File>,
+    size: u64,
+}
+
+/// An append-only log of every mutation of the repo, rotated by size.
+/// The current log is `ops.log` in the repo directory and rotated logs are
+/// `ops.log.1` (the newest) to `ops.log.<max_files>` (the oldest)
+/// ```
+/// #[derive(Debug)]
+/// pub struct OpsLog {
+///     dir: Utf8PathBuf,
+///     max_size: u64,
+///     max_files: usize,
+///     writer: Mutex<OpsLogWriter>,
+/// }
+/// ```
+#[derive(Debug)]
+pub struct OpsLog {
+    dir: Utf8PathBuf,
+    max_size: u64,
+    max_files: usize,
+    writer: Mutex<OpsLogWriter>,
+}
+
+impl OpsLog {
+    /// Open the ops.log in a repo directory. The file is created on the first write
+    pub fn new(dir: &Utf8Path) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            max_size: DEFAULT_MAX_SIZE,
+            max_files: DEFAULT_MAX_FILES,
+            writer: Mutex::new(OpsLogWriter {
+                file: None,
+                size: 0,
+            }),
+        }
+    }
+    /// Set the size in bytes after which the ops.log is rotated
+    /// and how many rotated logs are kept
+    pub fn with_rotation(mut self, max_size: u64, max_files: usize) -> Self {
+        self.max_size = max_size;
+        self.max_files = max_files;
+
+        self
+    }
+    /// Append an entry and wait for it to reach the disk
+    pub async fn append(&self, entry: &OpsLogEntry) -> TuringResult<()> {
+        let record = entry.to_record()?;
+        let mut writer = self.writer.lock().await;
+
+        if writer.file.is_none() {
+            writer.size = self.repair().await?;
+            writer.file = Some(self.open_current().await?);
+        }
+
+        if writer.size > 0 && writer.size + record.len() as u64 > self.max_size {
+            writer.file = None;
+            self.rotate().await?;
+
+            writer.file = Some(self.open_current().await?);
+            writer.size = 0;
+        }
+
+        if let Some(file) = &mut writer.file {
+            file.write_all(&record).await?;
+            file.sync_data().await?;
+        }
+        writer.size += record.len() as u64;
+
+        Ok(())
+    }
+    /// Record a mutation together with its outcome
+    pub(crate) async fn record(
+        &self,
+        actor: &str,
+        operation: LoggedOperation,
+        outcome: &TuringResult<OpsOutcome>,
+    ) {
+        let entry = OpsLogEntry {
+            timestamp: TAI64N::now(),
+            actor: actor.to_owned(),
+            operation,
+            outcome: outcome.into(),
+        };
+
+        // The mutation has already been applied so a failure to log it is only reported
+        if let Err(error) = self.append(&entry).await {
+            eprintln!("[TuringDB::<OpsLog>::(ERROR)-{:?}]", error);
+        }
+    }
+    /// The last `count` entries, oldest first
+    pub async fn tail(&self, count: usize) -> TuringResult<Vec<OpsLogEntry>> {
+        let mut entries = Vec::new();
+        self.replay(TAI64N(TAI64(0), 0), |entry| {
+            entries.push(entry);
+
+            Ok(())
+        })
+        .await?;
+
+        let skip = entries.len().saturating_sub(count);
+
+        Ok(entries.split_off(skip))
+    }
+    /// Pass every entry recorded at or after `since` to `apply`, oldest first.
+    /// Reading stops at the first error returned by `apply`
+    pub async fn replay<F>(&self, since: TAI64N, mut apply: F) -> TuringResult<()>
+    where
+        F: FnMut(OpsLogEntry) -> TuringResult<()>,
+    {
+        // Hold the lock so a rotation cannot rename the files while they are read
+        let _writer = self.writer.lock().await;
+
+        for path in self.log_files() {
+            let contents = match async_fs::read(&path).await {
+                Ok(contents) => contents,
+                Err(error) if error.kind() == ErrorKind::NotFound => continue,
+                Err(error) => return Err(error.into()),
+            };
+
+            let (entries, _) = OpsLog::decode(&path, &contents)?;
+
+            for entry in entries {
+                if entry.timestamp >= since {
+                    apply(entry)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn open_current(&self) -> TuringResult<File> {
+        Ok(OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join(OPS_LOG_FILE))
+            .await?)
+    }
+    /// Cut off a record left incomplete by a crash so new records are not appended after it.
+    /// Returns the size of the ops.log
+    async fn repair(&self) -> TuringResult<u64> {
+        let path = self.dir.join(OPS_LOG_FILE);
+
+        let contents = match async_fs::read(&path).await {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(0),
+            Err(error) => return Err(error.into()),
+        };
+
+        let (_, valid_len) = OpsLog::decode(&path, &contents)?;
+
+        if valid_len < contents.len() {
+            let file = OpenOptions::new().write(true).open(&path).await?;
+            file.set_len(valid_len as u64).await?;
+            file.sync_all().await?;
+        }
+
+        Ok(valid_len as u64)
+    }
+    /// Shift every rotated log up by one, dropping the oldest, and move the ops.log to `ops.log.1`
+    async fn rotate(&self) -> TuringResult<()> {
+        for index in (1..=self.max_files).rev() {
+            let from = self.rotated_path(index);
+
+            if async_fs::metadata(&from).await.is_err() {
+                continue;
+            }
+
+            if index == self.max_files {
+                async_fs::remove_file(&from).await?;
+            } else {
+                async_fs::rename(&from, self.rotated_path(index + 1)).await?;
+            }
+        }
+
+        if self.max_files > 0 {
+            async_fs::rename(self.dir.join(OPS_LOG_FILE), self.rotated_path(1)).await?;
+        } else {
+            async_fs::remove_file(self.dir.join(OPS_LOG_FILE)).await?;
+        }
+
+        Ok(())
+    }
+    /// The rotated logs from the oldest to the newest followed by the current ops.log
+    fn log_files(&self) -> Vec<Utf8PathBuf> {
+        let mut files = (1..=self.max_files)
+            .rev()
+            .map(|index| self.rotated_path(index))
+            .collect::<Vec<Utf8PathBuf>>();
+        files.push(self.dir.join(OPS_LOG_FILE));
+
+        files
+    }
+
+    fn rotated_path(&self, index: usize) -> Utf8PathBuf {
+        self.dir.join(format!("{}.{}", OPS_LOG_FILE, index))
+    }
+    fn decode(path: &Utf8Path, contents: &[u8]) -> TuringResult<(Vec<OpsLogEntry>, usize)> {
+        decode_records(contents, |offset| TuringDbError::OpsLogCorrupted {
+            file: path.to_string(),
+            offset,
+        })
+    }
+}
@@ -0,0 +1,1033 @@
+use crate::{TuringDbError, TuringResult};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The identifier of a node of a Raft cluster
+pub type NodeId = u64;
+
+/// The timing and batching settings of a Raft node.
+/// Time is counted in ticks of the clock that drives the node through `RaftNode::tick()`
+/// ```
+/// #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// pub struct RaftConfig {
+///     election_ticks: u64,
+///     heartbeat_ticks: u64,
+///     max_entries: usize,
+///     snapshot_entries: u64,
+///     seed: u64,
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RaftConfig {
+    election_ticks: u64,
+    heartbeat_ticks: u64,
+    max_entries: usize,
+    snapshot_entries: u64,
+    seed: u64,
+}
+
+impl Default for RaftConfig {
+    fn default() -> Self {
+        Self {
+            election_ticks: 10,
+            heartbeat_ticks: 2,
+            max_entries: 64,
+            snapshot_entries: 1024,
+            seed: 0,
+        }
+    }
+}
+
+impl RaftConfig {
+    /// An election every 10 to 20 ticks without a leader, a heartbeat every 2 ticks,
+    /// at most 64 entries per message and a snapshot every 1024 applied entries
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// A follower starts an election after a random number of ticks between
+    /// `election_ticks` and twice `election_ticks` without hearing from a leader
+    pub fn election_ticks(mut self, election_ticks: u64) -> Self {
+        self.election_ticks = election_ticks.max(1);
+
+        self
+    }
+    /// The number of ticks between two heartbeats of the leader, which has to be less than `election_ticks`
+    pub fn heartbeat_ticks(mut self, heartbeat_ticks: u64) -> Self {
+        self.heartbeat_ticks = heartbeat_ticks.max(1);
+
+        self
+    }
+    /// The most entries sent to a follower in a single message
+    pub fn max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries.max(1);
+
+        self
+    }
+    /// Replace the applied entries with a snapshot of the engine once this many entries have been applied
+    pub fn snapshot_entries(mut self, snapshot_entries: u64) -> Self {
+        self.snapshot_entries = snapshot_entries.max(1);
+
+        self
+    }
+    /// Seed the random election timeouts so that a run of a cluster can be repeated
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+
+        self
+    }
+
+    pub(crate) fn get_snapshot_entries(&self) -> u64 {
+        self.snapshot_entries
+    }
+}
+
+/// What an entry of the Raft log holds
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RaftEntryKind {
+    /// Appended by a new leader to commit the entries of the previous terms
+    Noop,
+    /// A mutation serialized by the caller
+    Command(Vec<u8>),
+    /// The members of the cluster from this entry on
+    Membership(BTreeSet<NodeId>),
+}
+
+/// An entry of the Raft log
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct RaftEntry {
+///     pub term: u64,
+///     pub index: u64,
+///     pub kind: RaftEntryKind,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RaftEntry {
+    pub term: u64,
+    pub index: u64,
+    pub kind: RaftEntryKind,
+}
+
+/// A change of the members of a cluster. Only one node is added or removed at a time
+/// so the majorities of the old and the new members always overlap
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MembershipChange {
+    AddNode(NodeId),
+    RemoveNode(NodeId),
+}
+
+/// The state of the cluster up to and including `index`, which replaces the entries it covers.
+/// `data` is produced by the caller, for a `RaftEngine` it is a copy of every database
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct RaftSnapshot {
+///     pub index: u64,
+///     pub term: u64,
+///     pub members: BTreeSet<NodeId>,
+///     pub data: Vec<u8>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RaftSnapshot {
+    pub index: u64,
+    pub term: u64,
+    pub members: BTreeSet<NodeId>,
+    pub data: Vec<u8>,
+}
+
+/// The state a node has to keep on disk before it answers any message
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HardState {
+    pub term: u64,
+    pub voted_for: Option<NodeId>,
+    pub commit: u64,
+}
+
+/// The messages exchanged by the nodes of a cluster
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RaftMessageKind {
+    RequestVote {
+        last_log_index: u64,
+        last_log_term: u64,
+    },
+    Vote {
+        granted: bool,
+    },
+    AppendEntries {
+        prev_log_index: u64,
+        prev_log_term: u64,
+        entries: Vec<RaftEntry>,
+        leader_commit: u64,
+    },
+    /// `match_index` is the last entry the follower shares with the leader when `success` is set,
+    /// otherwise it is a hint of where the logs may start to match
+    AppendResponse {
+        success: bool,
+        match_index: u64,
+    },
+    InstallSnapshot {
+        snapshot: RaftSnapshot,
+    },
+}
+
+/// A message from one node of a cluster to another
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct RaftMessage {
+///     pub from: NodeId,
+///     pub to: NodeId,
+///     pub term: u64,
+///     pub kind: RaftMessageKind,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RaftMessage {
+    pub from: NodeId,
+    pub to: NodeId,
+    pub term: u64,
+    pub kind: RaftMessageKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RaftRole {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+/// Everything a node produced since the last call to `RaftNode::ready()`.
+/// The caller persists `snapshot`, `hard_state` and `entries` in that order, where an entry
+/// replaces any stored entry at the same or a later index, then applies the `committed`
+/// entries and only then sends the `messages`
+/// ```
+/// #[derive(Debug, Default)]
+/// pub struct RaftReady {
+///     pub snapshot: Option<RaftSnapshot>,
+///     pub hard_state: Option<HardState>,
+///     pub entries: Vec<RaftEntry>,
+///     pub committed: Vec<RaftEntry>,
+///     pub messages: Vec<RaftMessage>,
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub struct RaftReady {
+    pub snapshot: Option<RaftSnapshot>,
+    pub hard_state: Option<HardState>,
+    pub entries: Vec<RaftEntry>,
+    pub committed: Vec<RaftEntry>,
+    pub messages: Vec<RaftMessage>,
+}
+
+/// The state of a node read from disk when it restarts
+#[derive(Debug, Default)]
+pub(crate) struct RaftState {
+    pub(crate) hard_state: HardState,
+    pub(crate) snapshot: Option<RaftSnapshot>,
+    pub(crate) entries: Vec<RaftEntry>,
+    pub(crate) applied: u64,
+}
+
+/// A snapshot of the state of a node
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RaftStatus {
+    pub id: NodeId,
+    pub role: RaftRole,
+    pub term: u64,
+    pub leader: Option<NodeId>,
+    pub members: BTreeSet<NodeId>,
+    pub commit: u64,
+    pub applied: u64,
+    pub last_index: u64,
+}
+
+/// A node of a Raft cluster that elects a leader, replicates the entries proposed to the leader
+/// and commits them once a majority of the members stored them.
+///
+/// The node does no I/O and reads no clock, so the same messages, ticks and seed always lead
+/// to the same state. The caller drives it with `tick()`, `step()` and `propose()`
+/// and carries out the work handed out by `ready()`
+#[derive(Debug)]
+pub struct RaftNode {
+    id: NodeId,
+    config: RaftConfig,
+    role: RaftRole,
+    term: u64,
+    voted_for: Option<NodeId>,
+    leader: Option<NodeId>,
+    snapshot: RaftSnapshot,
+    entries: Vec<RaftEntry>,
+    members: BTreeSet<NodeId>,
+    commit: u64,
+    applied: u64,
+    votes: BTreeSet<NodeId>,
+    next_index: BTreeMap<NodeId, u64>,
+    match_index: BTreeMap<NodeId, u64>,
+    election_elapsed: u64,
+    election_timeout: u64,
+    heartbeat_elapsed: u64,
+    rng: u64,
+    persisted: HardState,
+    unstable: Vec<RaftEntry>,
+    received_snapshot: Option<RaftSnapshot>,
+    messages: Vec<RaftMessage>,
+}
+
+impl RaftNode {
+    /// Start a node of a new cluster made up of `members`.
+    /// A node joining an existing cluster starts with no members and waits for the leader to reach it
+    pub fn new(id: NodeId, config: RaftConfig, members: BTreeSet<NodeId>) -> Self {
+        let snapshot = RaftSnapshot {
+            index: 0,
+            term: 0,
+            members,
+            data: Vec::new(),
+        };
+
+        Self::restore(
+            id,
+            config,
+            RaftState {
+                snapshot: Some(snapshot),
+                ..RaftState::default()
+            },
+        )
+    }
+
+    pub(crate) fn restore(id: NodeId, config: RaftConfig, state: RaftState) -> Self {
+        let snapshot = match state.snapshot {
+            Some(snapshot) => snapshot,
+            None => RaftSnapshot {
+                index: 0,
+                term: 0,
+                members: BTreeSet::new(),
+                data: Vec::new(),
+            },
+        };
+        let applied = state.applied.max(snapshot.index);
+
+        let mut node = Self {
+            id,
+            config,
+            role: RaftRole::Follower,
+            term: state.hard_state.term,
+            voted_for: state.hard_state.voted_for,
+            leader: None,
+            commit: state.hard_state.commit.max(applied),
+            applied,
+            members: BTreeSet::new(),
+            snapshot,
+            entries: state.entries,
+            votes: BTreeSet::new(),
+            next_index: BTreeMap::new(),
+            match_index: BTreeMap::new(),
+            election_elapsed: 0,
+            election_timeout: 0,
+            heartbeat_elapsed: 0,
+            rng: config.seed ^ id.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1,
+            persisted: state.hard_state,
+            unstable: Vec::new(),
+            received_snapshot: None,
+            messages: Vec::new(),
+        };
+
+        node.recompute_members();
+        node.reset_election_timer();
+
+        node
+    }
+
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    pub fn role(&self) -> RaftRole {
+        self.role
+    }
+
+    pub fn term(&self) -> u64 {
+        self.term
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.role == RaftRole::Leader
+    }
+    /// The leader of the current term if the node knows it
+    pub fn leader(&self) -> Option<NodeId> {
+        self.leader
+    }
+
+    pub fn status(&self) -> RaftStatus {
+        RaftStatus {
+            id: self.id,
+            role: self.role,
+            term: self.term,
+            leader: self.leader,
+            members: self.members.clone(),
+            commit: self.commit,
+            applied: self.applied,
+            last_index: self.last_index(),
+        }
+    }
+
+    pub(crate) fn hard_state(&self) -> HardState {
+        HardState {
+            term: self.term,
+            voted_for: self.voted_for,
+            commit: self.commit,
+        }
+    }
+
+    pub(crate) fn snapshot_index(&self) -> u64 {
+        self.snapshot.index
+    }
+
+    pub(crate) fn applied(&self) -> u64 {
+        self.applied
+    }
+
+    pub(crate) fn log_entries(&self) -> &[RaftEntry] {
+        &self.entries
+    }
+    /// Advance the clock of the node by one tick
+    pub fn tick(&mut self) {
+        match self.role {
+            RaftRole::Leader => {
+                self.heartbeat_elapsed += 1;
+
+                if self.heartbeat_elapsed >= self.config.heartbeat_ticks {
+                    self.heartbeat_elapsed = 0;
+                    self.broadcast_append();
+                }
+            }
+            RaftRole::Follower | RaftRole::Candidate => {
+                self.election_elapsed += 1;
+
+                if self.election_elapsed >= self.election_timeout {
+                    self.campaign();
+                }
+            }
+        }
+    }
+    /// Append a command to the log of the leader, returning its index.
+    /// The command is applied by every node once it is committed
+    pub fn propose(&mut self, command: Vec<u8>) -> TuringResult<u64> {
+        if self.role != RaftRole::Leader {
+            return Err(TuringDbError::RaftNotLeader {
+                leader: self.leader,
+            });
+        }
+
+        let index = self.append_local(RaftEntryKind::Command(command));
+        self.broadcast_append();
+        self.maybe_commit();
+
+        Ok(index)
+    }
+    /// Add or remove a single member, returning the index of the membership entry.
+    /// The new members take effect as soon as the entry is appended and a second change
+    /// is refused until the first one is committed
+    pub fn propose_membership(&mut self, change: MembershipChange) -> TuringResult<u64> {
+        if self.role != RaftRole::Leader {
+            return Err(TuringDbError::RaftNotLeader {
+                leader: self.leader,
+            });
+        }
+
+        // A leader only changes the members once an entry of its own term is committed
+        let pending = self.entries.iter().any(|entry| {
+            entry.index > self.commit && matches!(entry.kind, RaftEntryKind::Membership(_))
+        });
+        if pending || self.term_at(self.commit) != Some(self.term) {
+            return Err(TuringDbError::RaftMembershipChangePending);
+        }
+
+        let mut members = self.members.clone();
+        match change {
+            MembershipChange::AddNode(id) => {
+                if !members.insert(id) {
+                    return Err(TuringDbError::AlreadyExists);
+                }
+            }
+            MembershipChange::RemoveNode(id) => {
+                if !members.remove(&id) {
+                    return Err(TuringDbError::NotFound);
+                }
+
+                if members.is_empty() {
+                    return Err(TuringDbError::InvalidInput);
+                }
+            }
+        }
+
+        let index = self.append_local(RaftEntryKind::Membership(members));
+        self.broadcast_append();
+        self.maybe_commit();
+
+        Ok(index)
+    }
+    /// Handle a message from another node
+    pub fn step(&mut self, message: RaftMessage) {
+        if message.term > self.term {
+            let leader = match message.kind {
+                RaftMessageKind::AppendEntries { .. } | RaftMessageKind::InstallSnapshot { .. } => {
+                    Some(message.from)
+                }
+                _ => None,
+            };
+
+            self.become_follower(message.term, leader);
+        } else if message.term < self.term {
+            // Let a stale leader or candidate know about the newer term
+            match message.kind {
+                RaftMessageKind::RequestVote { .. } => {
+                    self.send(message.from, RaftMessageKind::Vote { granted: false })
+                }
+                RaftMessageKind::AppendEntries { .. } | RaftMessageKind::InstallSnapshot { .. } => {
+                    self.send(
+                        message.from,
+                        RaftMessageKind::AppendResponse {
+                            success: false,
+                            match_index: self.last_index(),
+                        },
+                    )
+                }
+                _ => (),
+            }
+
+            return;
+        }
+
+        match message.kind {
+            RaftMessageKind::RequestVote {
+                last_log_index,
+                last_log_term,
+            } => self.handle_request_vote(message.from, last_log_index, last_log_term),
+            RaftMessageKind::Vote { granted } => self.handle_vote(message.from, granted),
+            RaftMessageKind::AppendEntries {
+                prev_log_index,
+                prev_log_term,
+                entries,
+                leader_commit,
+            } => {
+                self.follow(message.from);
+                self.handle_append(
+                    message.from,
+                    prev_log_index,
+                    prev_log_term,
+                    entries,
+                    leader_commit,
+                );
+            }
+            RaftMessageKind::AppendResponse {
+                success,
+                match_index,
+            } => self.handle_append_response(message.from, success, match_index),
+            RaftMessageKind::InstallSnapshot { snapshot } => {
+                self.follow(message.from);
+                self.handle_snapshot(message.from, snapshot);
+            }
+        }
+    }
+    /// Hand out the work the caller has to carry out
+    pub fn ready(&mut self) -> RaftReady {
+        let hard_state = self.hard_state();
+        let hard_state = if hard_state != self.persisted {
+            self.persisted = hard_state;
+
+            Some(hard_state)
+        } else {
+            None
+        };
+
+        let committed = if self.commit > self.applied {
+            let start = (self.applied - self.snapshot.index) as usize;
+            let end = (self.commit - self.snapshot.index) as usize;
+            self.applied = self.commit;
+
+            self.entries[start..end].to_vec()
+        } else {
+            Vec::new()
+        };
+
+        RaftReady {
+            snapshot: self.received_snapshot.take(),
+            hard_state,
+            entries: std::mem::take(&mut self.unstable),
+            committed,
+            messages: std::mem::take(&mut self.messages),
+        }
+    }
+    /// Replace the entries up to and including `index`, which have to be applied,
+    /// with a snapshot holding the `data` of the caller
+    pub fn compact(&mut self, index: u64, data: Vec<u8>) -> TuringResult<RaftSnapshot> {
+        if index > self.applied || index <= self.snapshot.index {
+            return Err(TuringDbError::InvalidInput);
+        }
+
+        let term = match self.term_at(index) {
+            None => return Err(TuringDbError::Bug("Compacted entry missing".into())),
+            Some(term) => term,
+        };
+
+        let members = self
+            .entries
+            .iter()
+            .take_while(|entry| entry.index <= index)
+            .filter_map(|entry| match &entry.kind {
+                RaftEntryKind::Membership(members) => Some(members.clone()),
+                _ => None,
+            })
+            .last()
+            .unwrap_or_else(|| self.snapshot.members.clone());
+
+        self.entries.drain(..(index - self.snapshot.index) as usize);
+        self.snapshot = RaftSnapshot {
+            index,
+            term,
+            members,
+            data,
+        };
+
+        Ok(self.snapshot.clone())
+    }
+
+    fn last_index(&self) -> u64 {
+        match self.entries.last() {
+            Some(entry) => entry.index,
+            None => self.snapshot.index,
+        }
+    }
+
+    fn last_term(&self) -> u64 {
+        match self.entries.last() {
+            Some(entry) => entry.term,
+            None => self.snapshot.term,
+        }
+    }
+
+    fn term_at(&self, index: u64) -> Option<u64> {
+        if index == self.snapshot.index {
+            return Some(self.snapshot.term);
+        }
+
+        if index < self.snapshot.index {
+            return None;
+        }
+
+        self.entries
+            .get((index - self.snapshot.index - 1) as usize)
+            .map(|entry| entry.term)
+    }
+
+    fn quorum(&self) -> usize {
+        self.members.len() / 2 + 1
+    }
+    /// The members are set by the last membership entry in the log, committed or not
+    fn recompute_members(&mut self) {
+        self.members = self
+            .entries
+            .iter()
+            .rev()
+            .find_map(|entry| match &entry.kind {
+                RaftEntryKind::Membership(members) => Some(members.clone()),
+                _ => None,
+            })
+            .unwrap_or_else(|| self.snapshot.members.clone());
+
+        if self.role == RaftRole::Leader {
+            let next = self.last_index() + 1;
+            let id = self.id;
+
+            for member in self.members.iter().filter(|member| **member != id) {
+                self.next_index.entry(*member).or_insert(next);
+                self.match_index.entry(*member).or_insert(0);
+            }
+
+            let members = &self.members;
+            self.next_index.retain(|member, _| members.contains(member));
+            self.match_index
+                .retain(|member, _| members.contains(member));
+        }
+    }
+
+    fn random(&mut self) -> u64 {
+        // xorshift64*
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+
+        self.rng.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn reset_election_timer(&mut self) {
+        self.election_elapsed = 0;
+        self.election_timeout =
+            self.config.election_ticks + self.random() % self.config.election_ticks;
+    }
+
+    fn send(&mut self, to: NodeId, kind: RaftMessageKind) {
+        self.messages.push(RaftMessage {
+            from: self.id,
+            to,
+            term: self.term,
+            kind,
+        });
+    }
+
+    fn campaign(&mut self) {
+        self.reset_election_timer();
+
+        // A node that is not a member never disrupts the cluster
+        if !self.members.contains(&self.id) {
+            return;
+        }
+
+        self.role = RaftRole::Candidate;
+        self.term += 1;
+        self.voted_for = Some(self.id);
+        self.leader = None;
+        self.votes = BTreeSet::new();
+        self.votes.insert(self.id);
+
+        if self.votes.len() >= self.quorum() {
+            self.become_leader();
+            return;
+        }
+
+        let (last_log_index, last_log_term) = (self.last_index(), self.last_term());
+        let peers = self
+            .members
+            .iter()
+            .copied()
+            .filter(|member| *member != self.id)
+            .collect::<Vec<NodeId>>();
+
+        for peer in peers {
+            self.send(
+                peer,
+                RaftMessageKind::RequestVote {
+                    last_log_index,
+                    last_log_term,
+                },
+            );
+        }
+    }
+
+    fn become_follower(&mut self, term: u64, leader: Option<NodeId>) {
+        if term != self.term {
+            self.term = term;
+            self.voted_for = None;
+        }
+
+        self.role = RaftRole::Follower;
+        self.leader = leader;
+        self.votes.clear();
+        self.reset_election_timer();
+    }
+
+    fn become_leader(&mut self) {
+        self.role = RaftRole::Leader;
+        self.leader = Some(self.id);
+        self.heartbeat_elapsed = 0;
+        self.next_index.clear();
+        self.match_index.clear();
+        self.recompute_members();
+
+        self.append_local(RaftEntryKind::Noop);
+        self.broadcast_append();
+        self.maybe_commit();
+    }
+    /// A candidate that hears from the leader of its term steps down
+    fn follow(&mut self, leader: NodeId) {
+        if self.role != RaftRole::Follower {
+            self.role = RaftRole::Follower;
+            self.votes.clear();
+        }
+
+        self.leader = Some(leader);
+        self.reset_election_timer();
+    }
+
+    fn append_local(&mut self, kind: RaftEntryKind) -> u64 {
+        let membership = matches!(kind, RaftEntryKind::Membership(_));
+        let entry = RaftEntry {
+            term: self.term,
+            index: self.last_index() + 1,
+            kind,
+        };
+        let index = entry.index;
+
+        self.entries.push(entry.clone());
+        self.unstable.push(entry);
+
+        if membership {
+            self.recompute_members();
+        }
+
+        index
+    }
+
+    fn broadcast_append(&mut self) {
+        let peers = self.next_index.keys().copied().collect::<Vec<NodeId>>();
+
+        for peer in peers {
+            self.send_append(peer);
+        }
+    }
+
+    fn send_append(&mut self, to: NodeId) {
+        let next = match self.next_index.get(&to) {
+            None => return,
+            Some(next) => *next,
+        };
+
+        if next <= self.snapshot.index {
+            let snapshot = self.snapshot.clone();
+            self.next_index.insert(to, snapshot.index + 1);
+            self.send(to, RaftMessageKind::InstallSnapshot { snapshot });
+
+            return;
+        }
+
+        let prev_log_index = next - 1;
+        let prev_log_term = self.term_at(prev_log_index).unwrap_or(0);
+        let entries = self
+            .entries
+            .iter()
+            .skip((next - self.snapshot.index - 1) as usize)
+            .take(self.config.max_entries)
+            .cloned()
+            .collect::<Vec<RaftEntry>>();
+        let leader_commit = self.commit;
+
+        self.send(
+            to,
+            RaftMessageKind::AppendEntries {
+                prev_log_index,
+                prev_log_term,
+                entries,
+                leader_commit,
+            },
+        );
+    }
+    /// Commit the last entry of the current term stored by a majority of the members
+    fn maybe_commit(&mut self) {
+        if self.role != RaftRole::Leader {
+            return;
+        }
+
+        let last_index = self.last_index();
+        let mut matched = self
+            .members
+            .iter()
+            .map(|member| {
+                if *member == self.id {
+                    last_index
+                } else {
+                    self.match_index.get(member).copied().unwrap_or(0)
+                }
+            })
+            .collect::<Vec<u64>>();
+        matched.sort_unstable_by(|first, second| second.cmp(first));
+
+        let candidate = match matched.get(self.quorum() - 1) {
+            None => return,
+            Some(candidate) => *candidate,
+        };
+
+        if candidate > self.commit && self.term_at(candidate) == Some(self.term) {
+            self.commit = candidate;
+            self.broadcast_append();
+        }
+
+        // A leader that removed itself steps down once the removal is committed
+        if !self.members.contains(&self.id) {
+            let membership = self
+                .entries
+                .iter()
+                .rev()
+                .find(|entry| matches!(entry.kind, RaftEntryKind::Membership(_)))
+                .map(|entry| entry.index)
+                .unwrap_or(0);
+
+            if membership <= self.commit {
+                let term = self.term;
+                self.become_follower(term, None);
+            }
+        }
+    }
+
+    fn handle_request_vote(&mut self, from: NodeId, last_log_index: u64, last_log_term: u64) {
+        let up_to_date = last_log_term > self.last_term()
+            || (last_log_term == self.last_term() && last_log_index >= self.last_index());
+        let granted = up_to_date
+            && self.role == RaftRole::Follower
+            && (self.voted_for.is_none() || self.voted_for == Some(from));
+
+        if granted {
+            self.voted_for = Some(from);
+            self.reset_election_timer();
+        }
+
+        self.send(from, RaftMessageKind::Vote { granted });
+    }
+
+    fn handle_vote(&mut self, from: NodeId, granted: bool) {
+        if self.role != RaftRole::Candidate || !granted || !self.members.contains(&from) {
+            return;
+        }
+
+        self.votes.insert(from);
+
+        if self.votes.len() >= self.quorum() {
+            self.become_leader();
+        }
+    }
+
+    fn handle_append(
+        &mut self,
+        from: NodeId,
+        mut prev_log_index: u64,
+        mut prev_log_term: u64,
+        mut entries: Vec<RaftEntry>,
+        leader_commit: u64,
+    ) {
+        // Entries covered by the snapshot are committed so they match the leader
+        if prev_log_index < self.snapshot.index {
+            let snapshot_index = self.snapshot.index;
+            entries.retain(|entry| entry.index > snapshot_index);
+            prev_log_index = self.snapshot.index;
+            prev_log_term = self.snapshot.term;
+        }
+
+        match self.term_at(prev_log_index) {
+            None => {
+                let match_index = self.last_index();
+                self.send(
+                    from,
+                    RaftMessageKind::AppendResponse {
+                        success: false,
+                        match_index,
+                    },
+                );
+
+                return;
+            }
+            Some(term) if term != prev_log_term => {
+                let match_index = (prev_log_index - 1).max(self.commit);
+                self.send(
+                    from,
+                    RaftMessageKind::AppendResponse {
+                        success: false,
+                        match_index,
+                    },
+                );
+
+                return;
+            }
+            Some(_) => (),
+        }
+
+        let last_new = prev_log_index + entries.len() as u64;
+        let mut membership = false;
+
+        for entry in entries {
+            match self.term_at(entry.index) {
+                Some(term) if term == entry.term => continue,
+                Some(_) => {
+                    self.entries
+                        .truncate((entry.index - self.snapshot.index - 1) as usize);
+                    membership = true;
+                }
+                None => (),
+            }
+
+            membership |= matches!(entry.kind, RaftEntryKind::Membership(_));
+            self.entries.push(entry.clone());
+            self.unstable.push(entry);
+        }
+
+        if membership {
+            self.recompute_members();
+        }
+
+        if leader_commit > self.commit {
+            self.commit = leader_commit.min(last_new).max(self.commit);
+        }
+
+        self.send(
+            from,
+            RaftMessageKind::AppendResponse {
+                success: true,
+                match_index: last_new,
+            },
+        );
+    }
+
+    fn handle_append_response(&mut self, from: NodeId, success: bool, match_index: u64) {
+        if self.role != RaftRole::Leader {
+            return;
+        }
+
+        let next = match self.next_index.get(&from) {
+            None => return,
+            Some(next) => *next,
+        };
+
+        if success {
+            let matched = self.match_index.entry(from).or_insert(0);
+            *matched = (*matched).max(match_index);
+            let matched = *matched;
+
+            self.next_index.insert(from, matched + 1);
+            self.maybe_commit();
+
+            if matched < self.last_index() {
+                self.send_append(from);
+            }
+        } else {
+            let next = next.saturating_sub(1).min(match_index + 1).max(1);
+            self.next_index.insert(from, next);
+            self.send_append(from);
+        }
+    }
+
+    fn handle_snapshot(&mut self, from: NodeId, snapshot: RaftSnapshot) {
+        if snapshot.index <= self.commit {
+            let match_index = self.commit;
+            self.send(
+                from,
+                RaftMessageKind::AppendResponse {
+                    success: true,
+                    match_index,
+                },
+            );
+
+            return;
+        }
+
+        // Keep the entries after the snapshot if the log agrees with it
+        if self.term_at(snapshot.index) == Some(snapshot.term) {
+            self.entries
+                .drain(..(snapshot.index - self.snapshot.index) as usize);
+        } else {
+            self.entries.clear();
+        }
+
+        let index = snapshot.index;
+        self.unstable.retain(|entry| entry.index > index);
+        self.commit = index;
+        self.applied = index;
+        self.snapshot = snapshot.clone();
+        self.received_snapshot = Some(snapshot);
+        self.recompute_members();
+
+        self.send(
+            from,
+            RaftMessageKind::AppendResponse {
+                success: true,
+                match_index: index,
+            },
+        );
+    }
+}
@@ -0,0 +1,534 @@
+use crate::{
+    MembershipChange, NodeId, OpsOutcome, RaftCommand, RaftConfig, RaftEngine, RaftMessage,
+    RaftRole, TuringDbError, TuringEngine, TuringResult,
+};
+use camino::{Utf8Path, Utf8PathBuf};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+/// The most ticks `RaftCluster::propose()` waits for a proposal to be applied
+const PROPOSAL_TICKS: u64 = 1000;
+
+/// #### An in-process Raft cluster driven by a simulated clock and network
+/// Every node keeps its repo in its own directory under `dir` and all messages go through
+/// a single queue, so a run only depends on the seed of the `RaftConfig`.
+/// Messages can be dropped at random, nodes crashed and restarted from their files
+/// and the network split into partitions to test the cluster
+/// ```
+/// #[derive(Debug)]
+/// pub struct RaftCluster {
+///     dir: Utf8PathBuf,
+///     config: RaftConfig,
+///     nodes: BTreeMap<NodeId, RaftEngine>,
+///     crashed: BTreeSet<NodeId>,
+///     network: VecDeque<RaftMessage>,
+///     partitions: BTreeMap<NodeId, usize>,
+///     drop_rate: u64,
+///     rng: u64,
+/// }
+/// ```
+/// #### Usage
+/// ```
+/// let mut cluster = RaftCluster::new(dir, &[1, 2, 3], RaftConfig::new().seed(7)).await?;
+/// let leader = cluster.elect(100).await?;
+///
+/// cluster.propose(DEFAULT_ACTOR, RaftCommand::DbCreate { db, encrypted: false }).await?;
+///
+/// cluster.crash(leader);
+/// cluster.elect(100).await?;
+/// cluster.restart(leader).await?;
+/// ```
+#[derive(Debug)]
+pub struct RaftCluster {
+    dir: Utf8PathBuf,
+    config: RaftConfig,
+    nodes: BTreeMap<NodeId, RaftEngine>,
+    crashed: BTreeSet<NodeId>,
+    network: VecDeque<RaftMessage>,
+    partitions: BTreeMap<NodeId, usize>,
+    drop_rate: u64,
+    rng: u64,
+}
+
+impl RaftCluster {
+    /// Start a new cluster made up of `members` with their repos in `dir`
+    pub async fn new(dir: &Utf8Path, members: &[NodeId], config: RaftConfig) -> TuringResult<Self> {
+        let mut cluster = Self {
+            dir: dir.to_path_buf(),
+            config,
+            nodes: BTreeMap::new(),
+            crashed: BTreeSet::new(),
+            network: VecDeque::new(),
+            partitions: BTreeMap::new(),
+            drop_rate: 0,
+            rng: 0x853c_49e6_748f_ea9b,
+        };
+
+        let members = members.iter().copied().collect::<BTreeSet<NodeId>>();
+        for id in members.iter() {
+            cluster.start(*id, members.clone()).await?;
+        }
+
+        Ok(cluster)
+    }
+    /// Drop `percent` of the messages at random
+    pub fn drop_rate(mut self, percent: u64) -> Self {
+        self.drop_rate = percent.min(100);
+
+        self
+    }
+    /// The running node `id`
+    pub fn node(&self, id: NodeId) -> Option<&RaftEngine> {
+        self.nodes.get(&id)
+    }
+    /// The running nodes
+    pub fn nodes(&self) -> impl Iterator<Item = (&NodeId, &RaftEngine)> {
+        self.nodes.iter()
+    }
+    /// The running leader with the highest term
+    pub fn leader(&self) -> Option<NodeId> {
+        self.nodes
+            .iter()
+            .filter(|(_, node)| node.status().role == RaftRole::Leader)
+            .max_by_key(|(_, node)| node.status().term)
+            .map(|(id, _)| *id)
+    }
+    /// Advance the clock of every running node by one tick and deliver messages until none is left
+    pub async fn tick(&mut self) -> TuringResult<()> {
+        for node in self.nodes.values_mut() {
+            node.tick();
+        }
+
+        self.deliver().await
+    }
+    /// Advance the clock by `ticks` ticks
+    pub async fn run(&mut self, ticks: u64) -> TuringResult<()> {
+        for _ in 0..ticks {
+            self.tick().await?;
+        }
+
+        Ok(())
+    }
+    /// Tick until a leader is elected, failing after `max_ticks`
+    pub async fn elect(&mut self, max_ticks: u64) -> TuringResult<NodeId> {
+        for _ in 0..max_ticks {
+            if let Some(leader) = self.leader() {
+                return Ok(leader);
+            }
+
+            self.tick().await?;
+        }
+
+        match self.leader() {
+            None => Err(TuringDbError::TimedOut),
+            Some(leader) => Ok(leader),
+        }
+    }
+    /// Propose a mutation to the leader and tick until it is applied
+    pub async fn propose(&mut self, actor: &str, command: RaftCommand) -> TuringResult<OpsOutcome> {
+        let leader = self.elect(PROPOSAL_TICKS).await?;
+
+        let index = match self.nodes.get_mut(&leader) {
+            None => return Err(TuringDbError::RaftNotLeader { leader: None }),
+            Some(node) => node.propose(actor, command)?,
+        };
+
+        self.wait(leader, index).await
+    }
+    /// Add a node to the cluster or remove one from it and tick until the change is applied.
+    /// A node that is added is started without members and catches up from the leader
+    pub async fn change_membership(
+        &mut self,
+        change: MembershipChange,
+    ) -> TuringResult<OpsOutcome> {
+        let leader = self.elect(PROPOSAL_TICKS).await?;
+
+        if let MembershipChange::AddNode(id) = change {
+            if !self.nodes.contains_key(&id) && !self.crashed.contains(&id) {
+                self.start(id, BTreeSet::new()).await?;
+            }
+        }
+
+        let index = match self.nodes.get_mut(&leader) {
+            None => return Err(TuringDbError::RaftNotLeader { leader: None }),
+            Some(node) => node.propose_membership(change)?,
+        };
+
+        self.wait(leader, index).await
+    }
+    /// Stop a node, losing everything it did not store on disk
+    pub fn crash(&mut self, id: NodeId) {
+        if self.nodes.remove(&id).is_some() {
+            self.crashed.insert(id);
+        }
+
+        self.network
+            .retain(|message| message.from != id && message.to != id);
+    }
+    /// Start a crashed node again from its files
+    pub async fn restart(&mut self, id: NodeId) -> TuringResult<()> {
+        if self.crashed.remove(&id) {
+            self.start(id, BTreeSet::new()).await?;
+        }
+
+        Ok(())
+    }
+    /// Split the network so that only the nodes of the same group reach each other.
+    /// A node missing from every group can only reach the other missing nodes
+    pub fn partition(&mut self, groups: &[&[NodeId]]) {
+        self.partitions = groups
+            .iter()
+            .enumerate()
+            .flat_map(|(group, ids)| ids.iter().map(move |id| (*id, group + 1)))
+            .collect();
+    }
+    /// Let every node reach every other node again
+    pub fn heal(&mut self) {
+        self.partitions.clear();
+    }
+
+    async fn start(&mut self, id: NodeId, members: BTreeSet<NodeId>) -> TuringResult<()> {
+        let repo_dir = self.dir.join(format!("node-{}", id));
+
+        let mut engine = TuringEngine::builder()
+            .repo_dir(repo_dir.clone())
+            .build()
+            .await?;
+        if async_fs::metadata(&repo_dir).await.is_err() {
+            engine.repo_create().await?;
+        }
+        engine.repo_init().await?;
+
+        let node = RaftEngine::open(id, engine, self.config, members).await?;
+        self.nodes.insert(id, node);
+
+        Ok(())
+    }
+
+    async fn wait(&mut self, leader: NodeId, index: u64) -> TuringResult<OpsOutcome> {
+        for _ in 0..PROPOSAL_TICKS {
+            match self.nodes.get_mut(&leader) {
+                None => return Err(TuringDbError::RaftProposalDropped),
+                Some(node) => {
+                    if let Some(outcome) = node.outcome(index) {
+                        return outcome;
+                    }
+                }
+            }
+
+            self.tick().await?;
+        }
+
+        Err(TuringDbError::TimedOut)
+    }
+
+    async fn deliver(&mut self) -> TuringResult<()> {
+        loop {
+            for node in self.nodes.values_mut() {
+                let messages = node.process().await?;
+                self.network.extend(messages);
+            }
+
+            if self.network.is_empty() {
+                return Ok(());
+            }
+
+            while let Some(message) = self.network.pop_front() {
+                if !self.reachable(message.from, message.to) || self.dropped() {
+                    continue;
+                }
+
+                if let Some(node) = self.nodes.get_mut(&message.to) {
+                    node.step(message);
+                }
+            }
+        }
+    }
+
+    fn reachable(&self, from: NodeId, to: NodeId) -> bool {
+        self.partitions.get(&from).copied().unwrap_or(0)
+            == self.partitions.get(&to).copied().unwrap_or(0)
+    }
+
+    fn dropped(&mut self) -> bool {
+        if self.drop_rate == 0 {
+            return false;
+        }
+
+        // xorshift64*
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+
+        self.rng.wrapping_mul(0x2545_f491_4f6c_dd1d) % 100 < self.drop_rate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{t_engine::testing::*, DataType, TDBCell, DEFAULT_ACTOR};
+    use futures_lite::future::block_on;
+
+    /// A cluster of `members` in a directory of its own, removed when the test is done
+    struct TestCluster {
+        cluster: RaftCluster,
+        _dir: TestDir,
+    }
+
+    impl TestCluster {
+        async fn new(name: &str, members: &[NodeId], config: RaftConfig) -> Self {
+            let dir = TestDir::new(&format!("raft-{}", name));
+
+            let mut cluster = RaftCluster::new(dir.path(), members, config).await.unwrap();
+            cluster.elect(200).await.unwrap();
+
+            cluster
+                .propose(
+                    DEFAULT_ACTOR,
+                    RaftCommand::DbCreate {
+                        db: DB.into(),
+                        encrypted: false,
+                    },
+                )
+                .await
+                .unwrap();
+            cluster
+                .propose(
+                    DEFAULT_ACTOR,
+                    RaftCommand::DocumentCreate {
+                        db: DB.into(),
+                        document: DOCUMENT.into(),
+                        encrypted: false,
+                    },
+                )
+                .await
+                .unwrap();
+
+            Self { cluster, _dir: dir }
+        }
+    }
+
+    fn set(key: &str, value: &str) -> RaftCommand {
+        RaftCommand::FieldSet {
+            db: DB.into(),
+            document: DOCUMENT.into(),
+            key: key.as_bytes().to_vec(),
+            value: value.as_bytes().to_vec(),
+            data_type: DataType::BINARY as u8,
+            expires: None,
+        }
+    }
+
+    /// The value of a field on node `id`, `None` if the node does not hold it
+    async fn value(cluster: &RaftCluster, id: NodeId, key: &str) -> Option<Vec<u8>> {
+        match cluster
+            .node(id)?
+            .engine()
+            .field_get(&field_ops(key, ""))
+            .await
+        {
+            Ok(OpsOutcome::FieldContents(contents)) => {
+                Some(TDBCell::from_bytes(&contents).unwrap().get_data().to_vec())
+            }
+            _ => None,
+        }
+    }
+
+    fn leaders(cluster: &RaftCluster) -> Vec<NodeId> {
+        cluster
+            .nodes()
+            .filter(|(_, node)| node.is_leader())
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    #[test]
+    fn a_single_leader_is_elected_and_commands_reach_every_node() {
+        block_on(async {
+            let mut test =
+                TestCluster::new("election", &[1, 2, 3], RaftConfig::new().seed(1)).await;
+            let cluster = &mut test.cluster;
+
+            let leader = cluster.leader().unwrap();
+            assert_eq!(leaders(cluster), vec![leader]);
+
+            let term = cluster.node(leader).unwrap().status().term;
+            for (_, node) in cluster.nodes() {
+                assert_eq!(node.status().term, term);
+                assert_eq!(node.status().leader, Some(leader));
+            }
+
+            cluster
+                .propose(DEFAULT_ACTOR, set("key", "one"))
+                .await
+                .unwrap();
+            cluster.run(10).await.unwrap();
+
+            for id in 1..=3 {
+                assert_eq!(value(cluster, id, "key").await, Some(b"one".to_vec()));
+            }
+        });
+    }
+
+    #[test]
+    fn a_partitioned_leader_is_replaced_and_catches_up_once_healed() {
+        block_on(async {
+            let mut test =
+                TestCluster::new("partition", &[1, 2, 3], RaftConfig::new().seed(2)).await;
+            let cluster = &mut test.cluster;
+
+            let old_leader = cluster.leader().unwrap();
+            let old_term = cluster.node(old_leader).unwrap().status().term;
+            let others = (1..=3)
+                .filter(|id| *id != old_leader)
+                .collect::<Vec<NodeId>>();
+
+            cluster.partition(&[&[old_leader], &others]);
+            cluster.run(100).await.unwrap();
+
+            let new_leader = cluster.leader().unwrap();
+            assert_ne!(new_leader, old_leader);
+            assert!(cluster.node(new_leader).unwrap().status().term > old_term);
+
+            cluster
+                .propose(DEFAULT_ACTOR, set("key", "majority"))
+                .await
+                .unwrap();
+            cluster.run(10).await.unwrap();
+            assert_eq!(value(cluster, old_leader, "key").await, None);
+
+            cluster.heal();
+            cluster.run(100).await.unwrap();
+
+            assert_eq!(leaders(cluster), vec![new_leader]);
+            for id in 1..=3 {
+                assert_eq!(value(cluster, id, "key").await, Some(b"majority".to_vec()));
+            }
+        });
+    }
+
+    #[test]
+    fn a_crashed_leader_fails_over_and_rejoins_as_a_follower() {
+        block_on(async {
+            let mut test =
+                TestCluster::new("failover", &[1, 2, 3], RaftConfig::new().seed(3)).await;
+            let cluster = &mut test.cluster;
+
+            cluster
+                .propose(DEFAULT_ACTOR, set("key", "before"))
+                .await
+                .unwrap();
+
+            let old_leader = cluster.leader().unwrap();
+            cluster.crash(old_leader);
+
+            let new_leader = cluster.elect(200).await.unwrap();
+            assert_ne!(new_leader, old_leader);
+
+            // The write acknowledged by the crashed leader survives the failover
+            let survivor = (1..=3).find(|id| *id != old_leader).unwrap();
+            assert_eq!(
+                value(cluster, survivor, "key").await,
+                Some(b"before".to_vec())
+            );
+
+            cluster
+                .propose(DEFAULT_ACTOR, set("other", "after"))
+                .await
+                .unwrap();
+
+            cluster.restart(old_leader).await.unwrap();
+            cluster.run(100).await.unwrap();
+
+            assert_eq!(leaders(cluster), vec![new_leader]);
+            assert_eq!(
+                value(cluster, old_leader, "key").await,
+                Some(b"before".to_vec())
+            );
+            assert_eq!(
+                value(cluster, old_leader, "other").await,
+                Some(b"after".to_vec())
+            );
+        });
+    }
+
+    #[test]
+    fn nodes_are_added_and_removed() {
+        block_on(async {
+            let mut test =
+                TestCluster::new("membership", &[1, 2, 3], RaftConfig::new().seed(4)).await;
+            let cluster = &mut test.cluster;
+
+            cluster
+                .propose(DEFAULT_ACTOR, set("key", "one"))
+                .await
+                .unwrap();
+
+            cluster
+                .change_membership(MembershipChange::AddNode(4))
+                .await
+                .unwrap();
+            cluster.run(100).await.unwrap();
+
+            let leader = cluster.leader().unwrap();
+            let members = cluster.node(leader).unwrap().status().members;
+            assert_eq!(members, [1, 2, 3, 4].iter().copied().collect());
+            assert_eq!(value(cluster, 4, "key").await, Some(b"one".to_vec()));
+
+            let removed = (1..=3).find(|id| *id != leader).unwrap();
+            cluster
+                .change_membership(MembershipChange::RemoveNode(removed))
+                .await
+                .unwrap();
+            cluster.run(10).await.unwrap();
+
+            let members = cluster.node(leader).unwrap().status().members;
+            assert!(!members.contains(&removed));
+            assert_eq!(members.len(), 3);
+
+            // The removed node no longer receives the commands of the cluster
+            cluster
+                .propose(DEFAULT_ACTOR, set("other", "two"))
+                .await
+                .unwrap();
+            cluster.run(10).await.unwrap();
+
+            assert_eq!(value(cluster, 4, "other").await, Some(b"two".to_vec()));
+            assert_eq!(value(cluster, removed, "other").await, None);
+        });
+    }
+
+    #[test]
+    fn a_node_far_behind_installs_a_snapshot_of_the_leader() {
+        block_on(async {
+            let config = RaftConfig::new().seed(5).snapshot_entries(4);
+            let mut test = TestCluster::new("snapshot", &[1, 2, 3], config).await;
+            let cluster = &mut test.cluster;
+
+            let leader = cluster.leader().unwrap();
+            let lagging = (1..=3).find(|id| *id != leader).unwrap();
+            cluster.crash(lagging);
+
+            for index in 0..20 {
+                cluster
+                    .propose(DEFAULT_ACTOR, set(&format!("key-{}", index), "value"))
+                    .await
+                    .unwrap();
+            }
+
+            cluster.restart(lagging).await.unwrap();
+            cluster.run(100).await.unwrap();
+
+            let leader_status = cluster.node(leader).unwrap().status();
+            let lagging_status = cluster.node(lagging).unwrap().status();
+            assert_eq!(lagging_status.applied, leader_status.applied);
+
+            for index in 0..20 {
+                assert_eq!(
+                    value(cluster, lagging, &format!("key-{}", index)).await,
+                    Some(b"value".to_vec())
+                );
+            }
+        });
+    }
+}
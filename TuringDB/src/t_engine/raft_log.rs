@@ -0,0 +1,200 @@
+use crate::{
+    decode_records, encode_record, HardState, RaftEntry, RaftSnapshot, RaftState, TuringDbError,
+    TuringResult, RAFT_LOG_FILE, RAFT_SNAPSHOT_FILE,
+};
+use async_fs::{File, OpenOptions};
+use camino::{Utf8Path, Utf8PathBuf};
+use futures_lite::io::AsyncWriteExt;
+use serde::{Deserialize, Serialize};
+use std::io::ErrorKind;
+
+/// A record of the raft.log. The log is only appended to, an entry replaces
+/// any entry stored before it at the same or a later index
+#[derive(Debug, Serialize, Deserialize)]
+enum RaftLogRecord {
+    HardState(HardState),
+    Entries(Vec<RaftEntry>),
+    /// Every committed entry up to this index has been applied to the engine
+    Applied(u64),
+}
+
+/// Keeps the state of a Raft node on disk.
+/// The raft.log holds the term, the vote and the entries after the snapshot
+/// while RAFT_SNAPSHOT holds the latest snapshot, which is written before the log is compacted
+/// ```
+/// #[derive(Debug)]
+/// pub(crate) struct RaftStorage {
+///     dir: Utf8PathBuf,
+///     file: Option<File>,
+/// }
+/// ```
+#[derive(Debug)]
+pub(crate) struct RaftStorage {
+    dir: Utf8PathBuf,
+    file: Option<File>,
+}
+
+impl RaftStorage {
+    pub(crate) fn new(dir: &Utf8Path) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            file: None,
+        }
+    }
+    /// Read the state of the node, returning `None` for a node that has never been started
+    pub(crate) async fn load(&mut self) -> TuringResult<Option<RaftState>> {
+        let snapshot = match async_fs::read(self.dir.join(RAFT_SNAPSHOT_FILE)).await {
+            Ok(contents) => {
+                let corrupted = |offset| TuringDbError::RaftLogCorrupted {
+                    file: RAFT_SNAPSHOT_FILE.to_owned(),
+                    offset,
+                };
+
+                match decode_records::<RaftSnapshot, _>(&contents, corrupted)? {
+                    (mut snapshot, valid_len)
+                        if snapshot.len() == 1 && valid_len == contents.len() =>
+                    {
+                        snapshot.remove(0)
+                    }
+                    _ => return Err(corrupted(0)),
+                }
+            }
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error.into()),
+        };
+
+        let path = self.dir.join(RAFT_LOG_FILE);
+        let contents = match async_fs::read(&path).await {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(error) => return Err(error.into()),
+        };
+
+        let corrupted = |offset| TuringDbError::RaftLogCorrupted {
+            file: RAFT_LOG_FILE.to_owned(),
+            offset,
+        };
+        let (records, valid_len) = decode_records::<RaftLogRecord, _>(&contents, corrupted)?;
+
+        // Cut off a record left incomplete by a crash so new records are not appended after it
+        if valid_len < contents.len() {
+            let file = OpenOptions::new().write(true).open(&path).await?;
+            file.set_len(valid_len as u64).await?;
+            file.sync_all().await?;
+        }
+
+        let mut state = RaftState::default();
+
+        for record in records {
+            match record {
+                RaftLogRecord::HardState(hard_state) => state.hard_state = hard_state,
+                RaftLogRecord::Applied(applied) => state.applied = state.applied.max(applied),
+                RaftLogRecord::Entries(entries) => {
+                    for entry in entries {
+                        if entry.index <= snapshot.index {
+                            continue;
+                        }
+
+                        let position = (entry.index - snapshot.index - 1) as usize;
+                        if position > state.entries.len() {
+                            return Err(corrupted(0));
+                        }
+
+                        state.entries.truncate(position);
+                        state.entries.push(entry);
+                    }
+                }
+            }
+        }
+
+        state.snapshot = Some(snapshot);
+
+        Ok(Some(state))
+    }
+    /// Store the term, the vote and the new entries and wait for them to reach the disk
+    pub(crate) async fn append(
+        &mut self,
+        hard_state: Option<HardState>,
+        entries: Vec<RaftEntry>,
+    ) -> TuringResult<()> {
+        let mut records = Vec::new();
+
+        if let Some(hard_state) = hard_state {
+            records.push(RaftLogRecord::HardState(hard_state));
+        }
+
+        if !entries.is_empty() {
+            records.push(RaftLogRecord::Entries(entries));
+        }
+
+        self.write(&records).await
+    }
+    /// Store the index of the last entry applied to the engine
+    pub(crate) async fn applied(&mut self, applied: u64) -> TuringResult<()> {
+        self.write(&[RaftLogRecord::Applied(applied)]).await
+    }
+    /// Replace the snapshot and drop the entries it covers from the raft.log.
+    /// Both files are replaced through a rename so a crash leaves either the old or the new file
+    pub(crate) async fn compact(
+        &mut self,
+        snapshot: &RaftSnapshot,
+        hard_state: HardState,
+        entries: &[RaftEntry],
+        applied: u64,
+    ) -> TuringResult<()> {
+        let record = encode_record(snapshot, "Unable to serialize the Raft snapshot")?;
+        self.replace(RAFT_SNAPSHOT_FILE, &record).await?;
+
+        let mut contents = Vec::new();
+        let records = [
+            RaftLogRecord::HardState(hard_state),
+            RaftLogRecord::Entries(entries.to_vec()),
+            RaftLogRecord::Applied(applied),
+        ];
+        for record in records.iter() {
+            contents.extend(encode_record(record, "Unable to serialize the Raft log")?);
+        }
+
+        self.file = None;
+        self.replace(RAFT_LOG_FILE, &contents).await
+    }
+
+    async fn write(&mut self, records: &[RaftLogRecord]) -> TuringResult<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+
+        let mut contents = Vec::new();
+        for record in records {
+            contents.extend(encode_record(record, "Unable to serialize the Raft log")?);
+        }
+
+        if self.file.is_none() {
+            self.file = Some(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(self.dir.join(RAFT_LOG_FILE))
+                    .await?,
+            );
+        }
+
+        if let Some(file) = &mut self.file {
+            file.write_all(&contents).await?;
+            file.sync_data().await?;
+        }
+
+        Ok(())
+    }
+
+    async fn replace(&self, name: &str, contents: &[u8]) -> TuringResult<()> {
+        let temporary = self.dir.join(format!("{}.tmp", name));
+        let mut file = File::create(&temporary).await?;
+        file.write_all(contents).await?;
+        file.sync_all().await?;
+
+        async_fs::rename(&temporary, self.dir.join(name)).await?;
+
+        Ok(())
+    }
+}
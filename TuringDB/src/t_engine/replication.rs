@@ -0,0 +1,455 @@
+use crate::{
+    decode_records, encode_record, CdcChange, CdcRecord, Cipher, FieldWrite, IndexDefinition,
+    TuringDB, TuringDbError, TuringResult, DATA_KEYS_FILE, DB_ENCRYPTED_MARKER,
+    REPLICA_POSITIONS_FILE,
+};
+use async_fs::{DirBuilder, File};
+use async_lock::Mutex;
+use camino::{Utf8Path, Utf8PathBuf};
+use futures_lite::io::AsyncWriteExt;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, io::ErrorKind};
+use tai64::TAI64N;
+
+/// The fields of a document exactly as they are stored, so the values of encrypted documents stay sealed,
+/// with the definitions of its indexes whose entries are built again from the fields on restore
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct DocumentSnapshot {
+///     pub name: String,
+///     pub encrypted: bool,
+///     pub fields: Vec<(Vec<u8>, Vec<u8>)>,
+///     pub indexes: Vec<IndexDefinition>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DocumentSnapshot {
+    pub name: String,
+    pub encrypted: bool,
+    pub fields: Vec<(Vec<u8>, Vec<u8>)>,
+    pub indexes: Vec<IndexDefinition>,
+}
+
+/// A consistent copy of a database used to bootstrap a replica.
+/// `next` is the sequence of the first change of the CDC log that is not part of the snapshot
+/// and `data_keys` holds the data keys of the database sealed with the master key,
+/// so the replica has to use the same master key as the leader
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct DbSnapshot {
+///     pub name: String,
+///     pub encrypted: bool,
+///     pub data_keys: Option<Vec<u8>>,
+///     pub next: u64,
+///     pub documents: Vec<DocumentSnapshot>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DbSnapshot {
+    pub name: String,
+    pub encrypted: bool,
+    pub data_keys: Option<Vec<u8>>,
+    pub next: u64,
+    pub documents: Vec<DocumentSnapshot>,
+}
+
+/// The changes to a database since the position of a replica.
+/// `bootstrap` is set when the replica has no position or its position is no longer kept
+/// by the CDC log of the leader, in which case it has to start over from a snapshot
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct DbChanges {
+///     pub data_keys: Option<Vec<u8>>,
+///     pub bootstrap: bool,
+///     pub next: u64,
+///     pub records: Vec<CdcRecord>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DbChanges {
+    pub data_keys: Option<Vec<u8>>,
+    pub bootstrap: bool,
+    pub next: u64,
+    pub records: Vec<CdcRecord>,
+}
+
+/// The changes to every database of the leader. A database missing from the batch
+/// no longer exists on the leader
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct ReplicationBatch {
+///     pub databases: BTreeMap<String, DbChanges>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplicationBatch {
+    pub databases: BTreeMap<String, DbChanges>,
+}
+
+/// How far a database of the replica is behind the leader.
+/// `position` is the sequence of the next change the replica applies
+/// and `leader_next` the sequence of the next change on the leader when it was last contacted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplicaLag {
+    pub position: u64,
+    pub leader_next: u64,
+}
+
+impl ReplicaLag {
+    /// The number of changes the replica has not applied yet
+    pub fn changes_behind(&self) -> u64 {
+        self.leader_next.saturating_sub(self.position)
+    }
+}
+
+/// The state of replication on a replica
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct ReplicationStatus {
+///     pub replica: bool,
+///     pub last_contact: Option<TAI64N>,
+///     pub databases: BTreeMap<String, ReplicaLag>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplicationStatus {
+    pub replica: bool,
+    pub last_contact: Option<TAI64N>,
+    pub databases: BTreeMap<String, ReplicaLag>,
+}
+
+/// The position of every database of a replica, kept in the `REPLICA_POSITIONS` file
+/// of the repo so the replica resumes from it after a restart
+#[derive(Debug)]
+pub(crate) struct ReplicaState {
+    dir: Utf8PathBuf,
+    databases: Mutex<BTreeMap<String, ReplicaLag>>,
+    last_contact: Mutex<Option<TAI64N>>,
+}
+
+impl ReplicaState {
+    pub(crate) fn new(dir: &Utf8Path) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            databases: Mutex::new(BTreeMap::new()),
+            last_contact: Mutex::new(None),
+        }
+    }
+    /// Load the positions saved before the last shutdown
+    pub(crate) async fn load(&self) -> TuringResult<()> {
+        let path = self.dir.join(REPLICA_POSITIONS_FILE);
+
+        let contents = match async_fs::read(&path).await {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(error) => return Err(error.into()),
+        };
+
+        let corrupted = |_| TuringDbError::ReplicaPositionsCorrupted;
+
+        let positions = match decode_records::<BTreeMap<String, u64>, _>(&contents, corrupted)? {
+            (mut positions, valid_len) if positions.len() == 1 && valid_len == contents.len() => {
+                positions.remove(0)
+            }
+            _ => return Err(TuringDbError::ReplicaPositionsCorrupted),
+        };
+
+        *self.databases.lock().await = positions
+            .into_iter()
+            .map(|(db, position)| {
+                (
+                    db,
+                    ReplicaLag {
+                        position,
+                        leader_next: position,
+                    },
+                )
+            })
+            .collect();
+
+        Ok(())
+    }
+
+    pub(crate) async fn positions(&self) -> BTreeMap<String, u64> {
+        self.databases
+            .lock()
+            .await
+            .iter()
+            .map(|(db, lag)| (db.clone(), lag.position))
+            .collect()
+    }
+
+    pub(crate) async fn position(&self, db: &str) -> Option<u64> {
+        self.databases.lock().await.get(db).map(|lag| lag.position)
+    }
+    /// Save the position of a database, or forget it when `lag` is `None`
+    pub(crate) async fn set(&self, db: &str, lag: Option<ReplicaLag>) -> TuringResult<()> {
+        let mut databases = self.databases.lock().await;
+
+        let previous = match lag {
+            Some(lag) => databases.insert(db.to_owned(), lag),
+            None => databases.remove(db),
+        };
+
+        if previous.map(|previous| previous.position) == lag.map(|lag| lag.position) {
+            return Ok(());
+        }
+
+        let positions = databases
+            .iter()
+            .map(|(db, lag)| (db.clone(), lag.position))
+            .collect::<BTreeMap<String, u64>>();
+        let record = encode_record(&positions, "Unable to serialize the replica positions")?;
+
+        // Replace the positions through a rename so a crash never leaves them partly written
+        let temporary = self.dir.join(format!("{}.tmp", REPLICA_POSITIONS_FILE));
+        let mut file = File::create(&temporary).await?;
+        file.write_all(&record).await?;
+        file.sync_all().await?;
+
+        async_fs::rename(&temporary, self.dir.join(REPLICA_POSITIONS_FILE)).await?;
+
+        Ok(())
+    }
+
+    pub(crate) async fn contacted(&self) {
+        *self.last_contact.lock().await = Some(TAI64N::now());
+    }
+
+    pub(crate) async fn status(&self) -> ReplicationStatus {
+        ReplicationStatus {
+            replica: true,
+            last_contact: *self.last_contact.lock().await,
+            databases: self.databases.lock().await.clone(),
+        }
+    }
+}
+
+impl TuringDB {
+    /// Copy the database while holding it so no change is applied during the copy
+    pub(crate) async fn snapshot(
+        &self,
+        db_name: &Utf8Path,
+        db_dir: &Utf8Path,
+    ) -> TuringResult<DbSnapshot> {
+        let _writing = self.write_lock_all().await;
+        let capture = self.cdc_capture().await?;
+
+        let mut documents = Vec::with_capacity(self.list.len());
+        for (document_name, document) in self.documents() {
+            let mut fields = Vec::new();
+
+            for field in document.iter() {
+                let (key, value) = field?;
+                fields.push((key.to_vec(), value.to_vec()));
+            }
+
+            documents.push(DocumentSnapshot {
+                name: document_name.to_string(),
+                encrypted: self.encrypted_documents.contains(&document_name),
+                fields,
+                indexes: self.index_definitions(&document)?,
+            });
+        }
+        documents.sort_by(|first, second| first.name.cmp(&second.name));
+
+        Ok(DbSnapshot {
+            name: db_name.to_string(),
+            encrypted: self.encrypted,
+            data_keys: TuringDB::read_data_keys(db_dir).await?,
+            next: capture.map(|capture| capture.next()).unwrap_or(0),
+            documents,
+        })
+    }
+    /// The changes from `position` on, or a request to bootstrap
+    /// when `position` is unknown or no longer kept
+    pub(crate) async fn changes(
+        &self,
+        db_dir: &Utf8Path,
+        position: Option<u64>,
+        max: usize,
+    ) -> TuringResult<DbChanges> {
+        let (first, next) = match &self.cdc {
+            None => (0, 0),
+            Some(cdc) => cdc.bounds().await?,
+        };
+
+        let records = match (&self.cdc, position) {
+            (Some(cdc), Some(position)) if position >= first && position <= next => {
+                Some(cdc.read(position, max).await?)
+            }
+            _ => None,
+        };
+
+        Ok(DbChanges {
+            data_keys: TuringDB::read_data_keys(db_dir).await?,
+            bootstrap: records.is_none(),
+            next,
+            records: records.unwrap_or_default(),
+        })
+    }
+    /// Write the contents of a snapshot into a new database directory
+    pub(crate) async fn restore(
+        &mut self,
+        db_dir: &Utf8Path,
+        snapshot: &DbSnapshot,
+        master_key: Option<&Cipher>,
+    ) -> TuringResult<()> {
+        DirBuilder::new().recursive(true).create(db_dir).await?;
+
+        if snapshot.encrypted {
+            async_fs::write(db_dir.join(DB_ENCRYPTED_MARKER), &[]).await?;
+        }
+
+        if let Some(data_keys) = &snapshot.data_keys {
+            async_fs::write(db_dir.join(DATA_KEYS_FILE), data_keys).await?;
+            self.load_data_keys(db_dir, master_key).await?;
+        }
+
+        for document_snapshot in &snapshot.documents {
+            let document_name = Utf8PathBuf::from(&document_snapshot.name);
+            let path = db_dir.join(&document_name);
+
+            let document = sled::Config::default().path(&path).open()?;
+
+            let mut batch = sled::Batch::default();
+            for (key, value) in &document_snapshot.fields {
+                batch.insert(key.as_slice(), value.as_slice());
+            }
+            document.apply_batch(batch)?;
+            document.flush_async().await?;
+
+            if document_snapshot.encrypted {
+                async_fs::write(TuringDB::document_marker_path(&path), &[]).await?;
+                self.encrypted_documents.insert(document_name.clone());
+            }
+
+            self.index_build(&document_name, &document, &document_snapshot.indexes)?;
+
+            if let Some(integrity) = &self.integrity {
+                integrity.rebuild(&document_name, &document)?;
+            }
+
+            self.list.insert(document_name, document);
+        }
+
+        Ok(())
+    }
+    /// Apply a change of the leader. Applying the same change twice leaves the document
+    /// as it was so a replica that crashed before saving its position can apply it again
+    pub(crate) async fn replay(
+        &self,
+        repo_dir: &Utf8Path,
+        db_name: &Utf8Path,
+        record: &CdcRecord,
+    ) -> TuringResult<()> {
+        let document_name = Utf8PathBuf::from(&record.document);
+
+        match &record.change {
+            CdcChange::DocumentCreate => {
+                // Every document of an encrypted database is sealed without its own marker
+                let encrypted = record.sealed && !self.encrypted;
+
+                if !self.list.contains_key(&document_name) {
+                    self.document_create(repo_dir, db_name, &document_name, encrypted)
+                        .await?;
+                }
+
+                Ok(())
+            }
+            CdcChange::DocumentDrop => {
+                if self.list.contains_key(&document_name) {
+                    self.document_drop(repo_dir, db_name, &document_name)
+                        .await?;
+                }
+
+                Ok(())
+            }
+            CdcChange::Field { .. } => self.replay_fields(std::slice::from_ref(record), true).await,
+            CdcChange::IndexCreate(_) | CdcChange::IndexDrop { .. } => {
+                self.replay_index(record).await
+            }
+        }
+    }
+    /// Apply the creation or removal of an index by the leader,
+    /// which unlike the changes to documents does not need the database exclusively
+    pub(crate) async fn replay_index(&self, record: &CdcRecord) -> TuringResult<()> {
+        let document_name = Utf8Path::new(&record.document);
+
+        match &record.change {
+            CdcChange::IndexCreate(definition) => {
+                match self.index_create(document_name, definition).await {
+                    Ok(_) | Err(TuringDbError::IndexAlreadyExists) => Ok(()),
+                    Err(error) => Err(error),
+                }
+            }
+            CdcChange::IndexDrop { name } => match self.index_drop(document_name, name).await {
+                Ok(_) | Err(TuringDbError::IndexNotFound) => Ok(()),
+                Err(error) => Err(error),
+            },
+            _ => Err(TuringDbError::Bug(
+                "The change is not about an index".into(),
+            )),
+        }
+    }
+    /// Apply consecutive changes of the leader to the fields of a single document in a single transaction,
+    /// so with `check_unique` the unique indexes hold over the fields once all of them are written
+    /// like they held over the write of the leader that made the changes
+    pub(crate) async fn replay_fields(
+        &self,
+        records: &[CdcRecord],
+        check_unique: bool,
+    ) -> TuringResult<()> {
+        let document_name = match records.first() {
+            None => return Ok(()),
+            Some(record) => Utf8PathBuf::from(&record.document),
+        };
+        let document = match self.document(&document_name) {
+            None => return Err(TuringDbError::DocumentNotFound),
+            Some(document) => document,
+        };
+
+        let _writing = self.write_lock().await;
+        let mut capture = self.cdc_capture().await?;
+
+        let mut fields = Vec::with_capacity(records.len());
+        for record in records {
+            if let CdcChange::Field { kind, key, new, .. } = &record.change {
+                let new = match new {
+                    None => None,
+                    Some(new) => Some(new.to_bytes()?),
+                };
+
+                fields.push((record.timestamp, *kind, key.as_slice(), new));
+            }
+        }
+
+        let writes: Vec<FieldWrite> = fields
+            .iter()
+            .map(|(timestamp, kind, key, new)| {
+                FieldWrite::new(key, new.as_deref())
+                    .replaced(*timestamp)
+                    .kind(*kind)
+            })
+            .collect();
+        self.field_apply(
+            &document_name,
+            &document,
+            &writes,
+            check_unique,
+            capture.as_mut(),
+        )?;
+
+        self.cdc_write(capture).await;
+
+        Ok(())
+    }
+    /// The data keys of a database sealed with the master key, as stored in its directory
+    pub(crate) async fn read_data_keys(db_dir: &Utf8Path) -> TuringResult<Option<Vec<u8>>> {
+        match async_fs::read(db_dir.join(DATA_KEYS_FILE)).await {
+            Ok(data_keys) => Ok(Some(data_keys)),
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error.into()),
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use crate::{
+    DataType, OpsOutcome, TDBCell, TuringDBDocumentOps, TuringDBFieldOps, TuringDBOps,
+    TuringDbError, TuringEngine, TuringResult,
+};
+use camino::{Utf8Path, Utf8PathBuf};
+
+/// The database every test repo starts with
+pub(crate) const DB: &str = "db";
+/// The document of `DB` every test repo starts with
+pub(crate) const DOCUMENT: &str = "document";
+
+/// A directory of the system temporary directory used by a single test, removed once the test is done
+#[derive(Debug)]
+pub(crate) struct TestDir {
+    path: Utf8PathBuf,
+}
+
+impl TestDir {
+    pub(crate) fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("turingdb-{}-{}", name, std::process::id()));
+        let path = Utf8PathBuf::from_path_buf(path).unwrap();
+
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+
+        Self { path }
+    }
+
+    pub(crate) fn path(&self) -> &Utf8Path {
+        &self.path
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Create and load a repo in `repo_dir` holding the document `DOCUMENT` of the database `DB`
+pub(crate) async fn test_engine(repo_dir: &Utf8Path, history: bool) -> TuringEngine {
+    let mut engine = TuringEngine::builder()
+        .repo_dir(repo_dir.to_path_buf())
+        .history(history)
+        .build()
+        .await
+        .unwrap();
+    engine.repo_create().await.unwrap();
+    engine.repo_init().await.unwrap();
+
+    engine
+        .db_create(TuringDBOps::default().set_db_name(DB))
+        .await
+        .unwrap();
+    engine.document_create(&document_ops()).await.unwrap();
+
+    engine
+}
+
+pub(crate) fn document_ops() -> TuringDBDocumentOps {
+    TuringDBDocumentOps::default()
+        .set_db_name(DB)
+        .set_document_name(DOCUMENT)
+}
+
+/// The operation on the field `key` of `DOCUMENT` holding `value` as `DataType::BINARY`
+pub(crate) fn field_ops(key: &str, value: &str) -> TuringDBFieldOps {
+    TuringDBFieldOps::default()
+        .db(DB)
+        .document(DOCUMENT)
+        .key(key.as_bytes())
+        .value(value.as_bytes())
+        .data_type(DataType::BINARY)
+}
+
+pub(crate) async fn field_set(
+    engine: &TuringEngine,
+    key: &str,
+    value: &str,
+) -> TuringResult<OpsOutcome> {
+    engine.field_set(&field_ops(key, value)).await
+}
+
+/// The value of the field `key` of `DOCUMENT`, `None` if it does not exist
+pub(crate) async fn field_value(engine: &TuringEngine, key: &str) -> Option<Vec<u8>> {
+    document_value(engine, DOCUMENT, key).await
+}
+
+/// The value of the field `key` of `document`, `None` if it does not exist
+pub(crate) async fn document_value(
+    engine: &TuringEngine,
+    document: &str,
+    key: &str,
+) -> Option<Vec<u8>> {
+    match engine
+        .field_get(&field_ops(key, "").document(document))
+        .await
+    {
+        Ok(OpsOutcome::FieldContents(contents)) => {
+            Some(TDBCell::from_bytes(&contents).unwrap().get_data().to_vec())
+        }
+        Err(TuringDbError::FieldNotFound) => None,
+        outcome => panic!("Unexpected outcome {:?}", outcome),
+    }
+}
@@ -0,0 +1,324 @@
+use crate::{
+    history_insert, index_move, CdcCapture, ChangeKind, Document, TuringDB, TuringDbError,
+    TuringResult, CDC_OUTBOX_TREE, EXPIRY_TREE, HISTORY_TREE, INDEX_ENTRIES_TREE, LEAVES_TREE,
+    NODES_TREE, ROOT_TREE,
+};
+use camino::Utf8Path;
+use sled::{
+    transaction::{ConflictableTransactionError, TransactionError, TransactionalTree},
+    Transactional, Tree,
+};
+use tai64::TAI64N;
+
+/// How a write changes the time a field expires
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Expiry {
+    /// The field keeps the time it expires, if it has one
+    Keep,
+    /// The field no longer expires
+    Clear,
+    /// The field expires at the given time
+    At(TAI64N),
+}
+
+/// A field written by `TuringDB::field_apply()`, holding the bytes stored in sled.
+/// A `value` of `None` removes the field. With `expected` the write only happens
+/// if the field still holds the expected bytes, `None` expecting the field to be missing.
+/// `replaced` is the time the previous value of the field is kept in the history under
+/// and `kind` the change captured in the CDC log, found from the value the field held when it is `None`
+/// ```
+/// pub(crate) struct FieldWrite<'w> {
+///     key: &'w [u8],
+///     value: Option<&'w [u8]>,
+///     expected: Option<Option<&'w [u8]>>,
+///     expiry: Expiry,
+///     replaced: TAI64N,
+///     kind: Option<ChangeKind>,
+/// }
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct FieldWrite<'w> {
+    key: &'w [u8],
+    value: Option<&'w [u8]>,
+    expected: Option<Option<&'w [u8]>>,
+    expiry: Expiry,
+    replaced: TAI64N,
+    kind: Option<ChangeKind>,
+}
+
+impl<'w> FieldWrite<'w> {
+    /// Write `value` to a field whatever it holds, keeping the time it expires.
+    /// A removed field no longer expires
+    pub(crate) fn new(key: &'w [u8], value: Option<&'w [u8]>) -> Self {
+        let expiry = match value {
+            Some(_) => Expiry::Keep,
+            None => Expiry::Clear,
+        };
+
+        FieldWrite {
+            key,
+            value,
+            expected: None,
+            expiry,
+            replaced: TAI64N::now(),
+            kind: None,
+        }
+    }
+    /// Only write the field if it holds `current`, `None` if it must be missing
+    pub(crate) fn expect(mut self, current: Option<&'w [u8]>) -> Self {
+        self.expected = Some(current);
+
+        self
+    }
+    /// Change the time the field expires
+    pub(crate) fn expiry(mut self, expiry: Expiry) -> Self {
+        self.expiry = expiry;
+
+        self
+    }
+    /// Keep the previous value of the field in the history as replaced at the time `replaced`
+    pub(crate) fn replaced(mut self, replaced: TAI64N) -> Self {
+        self.replaced = replaced;
+
+        self
+    }
+    /// Capture the write in the CDC log as a change of this kind
+    pub(crate) fn kind(mut self, kind: ChangeKind) -> Self {
+        self.kind = Some(kind);
+
+        self
+    }
+    /// The change captured in the CDC log when the field held `old`, `None` if the field is left as it was
+    fn change_kind(&self, old: Option<&[u8]>) -> Option<ChangeKind> {
+        match (self.kind, old, self.value) {
+            (Some(kind), _, _) => Some(kind),
+            (None, None, None) => None,
+            (None, None, Some(_)) => Some(ChangeKind::Insert),
+            (None, Some(_), None) => Some(ChangeKind::Remove),
+            (None, Some(_), Some(_)) => Some(ChangeKind::Modify),
+        }
+    }
+}
+
+impl TuringDB {
+    /// Write a field if it holds what the write expects, see `TuringDB::field_apply()`
+    pub(crate) fn field_swap(
+        &self,
+        document_name: &Utf8Path,
+        document: &Document,
+        write: FieldWrite,
+        capture: Option<&mut CdcCapture<'_>>,
+    ) -> TuringResult<bool> {
+        self.field_apply(document_name, document, &[write], true, capture)
+    }
+    /// Write many fields of a document in a single transaction together with everything kept about them:
+    /// the entries of the indexes of the document, the time the fields expire,
+    /// the previous values in the history, the hashes of the integrity manifest
+    /// and, with a `capture` of the CDC log, the records of the changes in the outbox of the document.
+    /// Returns `false` without writing anything if a field does not hold what its write expects.
+    /// With `check_unique` nothing is written if the fields end up sharing the value of a unique index
+    /// with each other or with the other fields of the document
+    pub(crate) fn field_apply(
+        &self,
+        document_name: &Utf8Path,
+        document: &Document,
+        writes: &[FieldWrite],
+        check_unique: bool,
+        capture: Option<&mut CdcCapture<'_>>,
+    ) -> TuringResult<bool> {
+        self.field_transaction(document_name, document, writes, check_unique, capture, true)
+    }
+    /// Check whether `field_apply()` would write the fields, failing with a `UniqueViolation` where it would,
+    /// in a transaction that is rolled back
+    pub(crate) fn field_check(
+        &self,
+        document_name: &Utf8Path,
+        document: &Document,
+        writes: &[FieldWrite],
+    ) -> TuringResult<bool> {
+        self.field_transaction(document_name, document, writes, true, None, false)
+    }
+
+    fn field_transaction(
+        &self,
+        document_name: &Utf8Path,
+        document: &Document,
+        writes: &[FieldWrite],
+        check_unique: bool,
+        mut capture: Option<&mut CdcCapture<'_>>,
+        commit: bool,
+    ) -> TuringResult<bool> {
+        let definitions = self.index_definitions(document)?;
+
+        let mut added = Vec::with_capacity(writes.len());
+        for write in writes {
+            added.push(self.index_entries(&definitions, document_name, write.key, write.value)?);
+        }
+
+        // The manifest stays locked until the transaction is done so its root covers these writes
+        let staged = match &self.integrity {
+            Some(integrity) if commit => {
+                let fields: Vec<(&[u8], Option<&[u8]>)> = writes
+                    .iter()
+                    .map(|write| (write.key, write.value))
+                    .collect();
+
+                Some(integrity.stage(document_name, document, &fields)?)
+            }
+            _ => None,
+        };
+
+        let entries = document.open_tree(INDEX_ENTRIES_TREE)?;
+        let expiry = document.open_tree(EXPIRY_TREE)?;
+        let history = match self.history {
+            true => Some(document.open_tree(HISTORY_TREE)?),
+            false => None,
+        };
+        let outbox = match (&capture, commit) {
+            (Some(capture), true) => Some((document.open_tree(CDC_OUTBOX_TREE)?, capture.next())),
+            _ => None,
+        };
+        let integrity = match &staged {
+            None => None,
+            Some(_) => Some((
+                document.open_tree(LEAVES_TREE)?,
+                document.open_tree(NODES_TREE)?,
+                document.open_tree(ROOT_TREE)?,
+            )),
+        };
+
+        // The trees a document does not use are left out of the transaction instead of being created
+        let mut trees: Vec<&Tree> = vec![&**document, &entries, &expiry];
+        trees.extend(history.as_ref());
+        trees.extend(outbox.as_ref().map(|(outbox, _)| outbox));
+        if let Some((leaves, nodes, root)) = &integrity {
+            trees.push(leaves);
+            trees.push(nodes);
+            trees.push(root);
+        }
+
+        let apply = |trees: &Vec<TransactionalTree>| {
+            let (tx_fields, tx_entries, tx_expiry) = (&trees[0], &trees[1], &trees[2]);
+            let tx_history = history.as_ref().map(|_| &trees[3]);
+            let tx_outbox = outbox
+                .as_ref()
+                .map(|(_, next)| (&trees[3 + tx_history.iter().count()], *next));
+
+            // Every expectation is checked before anything is written, so a failed one leaves the document untouched
+            for write in writes {
+                if let Some(expected) = write.expected {
+                    if tx_fields.get(write.key)?.as_deref() != expected {
+                        return Ok(None);
+                    }
+                }
+            }
+
+            let mut taken = Vec::new();
+            let mut records = Vec::new();
+
+            for (write, added) in writes.iter().zip(added.iter()) {
+                // The value the field holds before this write, which may be an earlier write of the same field
+                let old = tx_fields.get(write.key)?;
+                let removed = match self.index_entries(
+                    &definitions,
+                    document_name,
+                    write.key,
+                    old.as_deref(),
+                ) {
+                    Ok(removed) => removed,
+                    Err(error) => return Err(ConflictableTransactionError::Abort(error)),
+                };
+
+                match write.value {
+                    Some(value) => tx_fields.insert(write.key, value)?,
+                    None => tx_fields.remove(write.key)?,
+                };
+                index_move(tx_entries, write.key, &removed, added, &mut taken)?;
+
+                match write.expiry {
+                    Expiry::Keep => (),
+                    Expiry::Clear => {
+                        tx_expiry.remove(write.key)?;
+                    }
+                    Expiry::At(expires) => {
+                        tx_expiry.insert(write.key, &expires.to_bytes())?;
+                    }
+                }
+
+                if let (Some(tx_history), Some(old)) = (tx_history, &old) {
+                    history_insert(tx_history, write.key, old, write.replaced)?;
+                }
+
+                if let (Some((tx_outbox, next)), Some(kind)) =
+                    (tx_outbox, write.change_kind(old.as_deref()))
+                {
+                    let sequence = next + records.len() as u64;
+                    let record = self.cdc_field_record(
+                        sequence,
+                        document_name,
+                        kind,
+                        write.key,
+                        old.as_deref(),
+                        write.value,
+                    );
+                    let record = match record {
+                        Ok(record) => record,
+                        Err(error) => return Err(ConflictableTransactionError::Abort(error)),
+                    };
+                    let bytes = match bincode::serialize(&record) {
+                        Ok(bytes) => bytes,
+                        Err(_) => {
+                            return Err(ConflictableTransactionError::Abort(TuringDbError::Bug(
+                                "Unable to serialize a CDC record".into(),
+                            )))
+                        }
+                    };
+
+                    tx_outbox.insert(&sequence.to_be_bytes(), bytes)?;
+                    records.push(record);
+                }
+            }
+
+            // The fields are checked once all of them are written so they can trade values
+            if check_unique {
+                self.index_check_unique(&definitions, document_name, tx_fields, &taken)?;
+            }
+
+            if let Some(staged) = &staged {
+                let count = trees.len();
+                staged.write(&trees[count - 3], &trees[count - 2], &trees[count - 1])?;
+            }
+
+            Ok(Some(records))
+        };
+
+        // A check is rolled back by aborting with no error once the fields are written
+        let written = trees.as_slice().transaction(|trees| match apply(trees) {
+            Ok(Some(_)) if !commit => Err(ConflictableTransactionError::Abort(None)),
+            Ok(written) => Ok(written),
+            Err(ConflictableTransactionError::Abort(error)) => {
+                Err(ConflictableTransactionError::Abort(Some(error)))
+            }
+            Err(ConflictableTransactionError::Conflict) => {
+                Err(ConflictableTransactionError::Conflict)
+            }
+            Err(ConflictableTransactionError::Storage(error)) => {
+                Err(ConflictableTransactionError::Storage(error))
+            }
+        });
+
+        match written {
+            Ok(None) => Ok(false),
+            Ok(Some(records)) => {
+                if let (Some(capture), Some((outbox, _))) = (capture.as_mut(), &outbox) {
+                    capture.committed(records, outbox);
+                }
+
+                Ok(true)
+            }
+            Err(TransactionError::Abort(None)) => Ok(true),
+            Err(TransactionError::Abort(Some(error))) => Err(error),
+            Err(TransactionError::Storage(error)) => Err(error.into()),
+        }
+    }
+}